@@ -1,6 +1,7 @@
-use aya_gen::btf_types;
+use aya::linker::Linker;
+use aya_gen::{btf_types, btfgen, skeleton};
 
-use std::{path::PathBuf, process::exit};
+use std::{fs, path::PathBuf, process::exit};
 
 use structopt::StructOpt;
 #[derive(StructOpt)]
@@ -19,6 +20,29 @@ enum Command {
         probe_read_getters: bool,
         names: Vec<String>,
     },
+    /// Links multiple eBPF object files into one
+    #[structopt(name = "link")]
+    Link {
+        #[structopt(short, long)]
+        output: PathBuf,
+        #[structopt(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+    /// Generates a typed skeleton for an eBPF object file
+    #[structopt(name = "skeleton")]
+    Skeleton { object: PathBuf },
+    /// Generates minimized kernel BTF containing only the types used by eBPF object files
+    #[structopt(name = "btfgen")]
+    Btfgen {
+        /// The directory containing the BTF files of the kernels
+        #[structopt(long)]
+        btf_dir: PathBuf,
+        /// The directory to write the minimized BTF files to
+        #[structopt(short, long)]
+        output: PathBuf,
+        #[structopt(required = true)]
+        objects: Vec<PathBuf>,
+    },
 }
 
 fn main() {
@@ -39,6 +63,25 @@ fn try_main() -> Result<(), anyhow::Error> {
             let bindings = btf_types::generate(&btf, &names, probe_read_getters)?;
             println!("{}", bindings);
         }
+        Command::Link { output, inputs } => {
+            let mut linker = Linker::new();
+            for input in inputs {
+                linker.add_file(input)?;
+            }
+            fs::write(&output, linker.link()?)?;
+        }
+        Command::Skeleton { object } => {
+            println!("{}", skeleton::generate(&object)?);
+        }
+        Command::Btfgen {
+            btf_dir,
+            output,
+            objects,
+        } => {
+            for path in btfgen::generate(&objects, &btf_dir, &output)? {
+                println!("{}", path.display());
+            }
+        }
     };
 
     Ok(())
@@ -53,7 +53,7 @@ pub fn generate<T: AsRef<str>>(
     Ok(bindings)
 }
 
-fn c_header_from_btf(path: &Path) -> Result<String, Error> {
+pub(crate) fn c_header_from_btf(path: &Path) -> Result<String, Error> {
     let output = Command::new("bpftool")
         .args(&["btf", "dump", "file"])
         .arg(path)
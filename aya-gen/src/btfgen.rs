@@ -0,0 +1,153 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use aya::{btf::BtfBuilder, inspect::Object, BpfError, Btf, BtfError, Endianness};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("error reading `{}`", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+
+    #[error("error writing `{}`", path.display())]
+    Write {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+
+    #[error("error parsing the object file `{}`", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        error: BpfError,
+    },
+
+    #[error("error parsing the BTF file `{}`", path.display())]
+    Btf {
+        path: PathBuf,
+        #[source]
+        error: BtfError,
+    },
+
+    #[error("error resolving the CO-RE relocations of `{}` against `{}`", object.display(), btf.display())]
+    Relocation {
+        object: PathBuf,
+        btf: PathBuf,
+        #[source]
+        error: BpfError,
+    },
+
+    #[error("error generating the minimized BTF for `{}`", path.display())]
+    Generate {
+        path: PathBuf,
+        #[source]
+        error: BtfError,
+    },
+}
+
+/// Generates minimized BTF for the eBPF object files `objects`, for each kernel BTF file in
+/// `btf_dir`.
+///
+/// The CO-RE relocations of the objects are resolved against each kernel BTF, and only the types
+/// and the struct and union members they use are kept. The result is written to `output_dir`
+/// with the same file name, and can be passed to [`BpfLoader::btf`] to load the objects on
+/// kernels that don't expose their BTF. The kernel BTF files must be uncompressed.
+///
+/// Returns the paths of the generated files.
+///
+/// [`BpfLoader::btf`]: aya::BpfLoader::btf
+pub fn generate<P: AsRef<Path>>(
+    objects: &[P],
+    btf_dir: &Path,
+    output_dir: &Path,
+) -> Result<Vec<PathBuf>, Error> {
+    let objects = objects
+        .iter()
+        .map(|path| {
+            let path = path.as_ref();
+            let data = fs::read(path).map_err(|error| Error::Read {
+                path: path.to_owned(),
+                error,
+            })?;
+            let obj = Object::parse(&data).map_err(|error| Error::Parse {
+                path: path.to_owned(),
+                error,
+            })?;
+            Ok((path, obj))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let read_dir_error = |error| Error::Read {
+        path: btf_dir.to_owned(),
+        error,
+    };
+    let mut btf_files = fs::read_dir(btf_dir)
+        .map_err(read_dir_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read_dir_error)?;
+    btf_files.retain(|path| path.is_file());
+    btf_files.sort();
+
+    fs::create_dir_all(output_dir).map_err(|error| Error::Write {
+        path: output_dir.to_owned(),
+        error,
+    })?;
+
+    let mut outputs = Vec::new();
+    for btf_file in btf_files {
+        let btf =
+            Btf::parse_file(&btf_file, Endianness::default()).map_err(|error| Error::Btf {
+                path: btf_file.clone(),
+                error,
+            })?;
+
+        let mut type_ids = BTreeSet::new();
+        let mut members = HashSet::new();
+        for (path, obj) in &objects {
+            let targets = obj
+                .core_relocation_targets(&btf)
+                .map_err(|error| Error::Relocation {
+                    object: path.to_path_buf(),
+                    btf: btf_file.clone(),
+                    error,
+                })?;
+            for target in targets {
+                type_ids.insert(target.type_id);
+                members.extend(target.members);
+            }
+        }
+
+        let output = output_dir.join(btf_file.file_name().unwrap());
+        let generate_error = |error| Error::Generate {
+            path: btf_file.clone(),
+            error,
+        };
+        let mut builder = BtfBuilder::new();
+        builder
+            .add_pruned_types_from(
+                &btf,
+                &type_ids.into_iter().collect::<Vec<_>>(),
+                |type_id, index| members.contains(&(type_id, index)),
+            )
+            .map_err(generate_error)?;
+        let data = builder
+            .to_bytes(Endianness::default())
+            .map_err(generate_error)?;
+        fs::write(&output, data).map_err(|error| Error::Write {
+            path: output.clone(),
+            error,
+        })?;
+        outputs.push(output);
+    }
+
+    Ok(outputs)
+}
@@ -6,8 +6,10 @@ use std::{
 
 pub mod bindgen;
 pub mod btf_types;
+pub mod btfgen;
 pub mod getters;
 pub mod rustfmt;
+pub mod skeleton;
 
 pub fn write_to_file<T: AsRef<Path>>(path: T, code: &str) -> Result<(), io::Error> {
     let mut file = File::create(path)?;
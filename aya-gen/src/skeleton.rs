@@ -0,0 +1,477 @@
+use std::{collections::HashSet, fs, io, path::Path};
+
+use ::bindgen::EnumVariation;
+use aya::{
+    inspect::{Map, Object, ProgramSection},
+    BpfError, BtfError,
+};
+use proc_macro2::{Ident, Literal, Span, TokenStream};
+use quote::quote;
+use thiserror::Error;
+
+use crate::{
+    bindgen,
+    btf_types::{self, c_header_from_btf},
+    rustfmt,
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("error reading the object file")]
+    Io(#[source] io::Error),
+
+    #[error("error parsing the object file")]
+    Parse(#[source] BpfError),
+
+    #[error("error reading the object BTF")]
+    Btf(#[from] BtfError),
+
+    #[error("error dumping the object BTF")]
+    BtfTypes(#[from] btf_types::Error),
+
+    #[error("bindgen failed")]
+    Bindgen,
+
+    #[error("rustfmt failed")]
+    Rustfmt(#[source] io::Error),
+}
+
+// the map types that have a typed wrapper in aya::maps
+const BPF_MAP_TYPE_HASH: u32 = 1;
+const BPF_MAP_TYPE_ARRAY: u32 = 2;
+const BPF_MAP_TYPE_PROG_ARRAY: u32 = 3;
+const BPF_MAP_TYPE_PERF_EVENT_ARRAY: u32 = 4;
+const BPF_MAP_TYPE_PERCPU_HASH: u32 = 5;
+const BPF_MAP_TYPE_PERCPU_ARRAY: u32 = 6;
+const BPF_MAP_TYPE_STACK_TRACE: u32 = 7;
+const BPF_MAP_TYPE_LRU_HASH: u32 = 9;
+const BPF_MAP_TYPE_LRU_PERCPU_HASH: u32 = 10;
+const BPF_MAP_TYPE_SOCKMAP: u32 = 15;
+const BPF_MAP_TYPE_SOCKHASH: u32 = 18;
+const BPF_MAP_TYPE_QUEUE: u32 = 22;
+const BPF_MAP_TYPE_STACK: u32 = 23;
+
+/// Generates a skeleton for the eBPF object file at `object_file`.
+///
+/// The skeleton is a module named after the file containing a struct that loads the embedded
+/// object and exposes its programs and maps with their concrete types. Map keys and values, and
+/// the `.rodata` globals that can be set before loading, are typed from the object BTF when it's
+/// available.
+pub fn generate(object_file: &Path) -> Result<String, Error> {
+    let data = fs::read(object_file).map_err(Error::Io)?;
+    let obj = Object::parse(&data).map_err(Error::Parse)?;
+
+    let stem = object_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let module = ident(&stem);
+    let skeleton = Ident::new(&camel_case(&stem), Span::call_site());
+    let builder = Ident::new(&format!("{}Builder", skeleton), Span::call_site());
+
+    // C typedefs for the types of keys, values and globals, to be converted by bindgen
+    let mut typedefs = Vec::new();
+    let mut type_names = Vec::new();
+    let mut typedef = |type_id: u32, name: String| -> Result<TokenStream, Error> {
+        let btf = obj.btf().unwrap();
+        typedefs.push(format!("typedef {};", btf.c_declaration(type_id, &name)?));
+        let ty = ident(&name);
+        type_names.push(name);
+        Ok(quote!(#ty))
+    };
+
+    let mut programs = obj.programs().collect::<Vec<_>>();
+    programs.sort_by_key(|(name, _)| *name);
+    let mut method_names = ["load", "bpf", "bpf_mut"]
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<HashSet<_>>();
+    let mut accessors = Vec::new();
+    for (name, section) in programs {
+        let method = ident(name);
+        method_names.insert(method.to_string());
+        let ty = program_type(section);
+        accessors.push(quote! {
+            pub fn #method(&mut self) -> &mut aya::programs::#ty {
+                self.bpf.program_mut(#name).unwrap().try_into().unwrap()
+            }
+        });
+    }
+
+    let mut maps = obj
+        .maps()
+        .filter(|(_, map)| !map.is_global_data())
+        .collect::<Vec<_>>();
+    maps.sort_by_key(|(name, _)| *name);
+    for (name, map) in maps {
+        let mut method_name = ident(&name.to_lowercase()).to_string();
+        if method_names.contains(&method_name) {
+            method_name.push_str("_map");
+        }
+        let method = ident(&method_name);
+        method_names.insert(method_name);
+
+        let mut key = || match map.key_type_id() {
+            Some(type_id) => typedef(type_id, format!("{}_key", name)),
+            None => Ok(sized_type(map.key_size())),
+        };
+        let ty = match map.map_type() {
+            BPF_MAP_TYPE_HASH | BPF_MAP_TYPE_LRU_HASH => {
+                let key = key()?;
+                let value = map_value(map, name, &mut typedef)?;
+                quote!(HashMap<MapRefMut, #key, #value>)
+            }
+            BPF_MAP_TYPE_PERCPU_HASH | BPF_MAP_TYPE_LRU_PERCPU_HASH => {
+                let key = key()?;
+                let value = map_value(map, name, &mut typedef)?;
+                quote!(PerCpuHashMap<MapRefMut, #key, #value>)
+            }
+            BPF_MAP_TYPE_ARRAY => {
+                let value = map_value(map, name, &mut typedef)?;
+                quote!(Array<MapRefMut, #value>)
+            }
+            BPF_MAP_TYPE_PERCPU_ARRAY => {
+                let value = map_value(map, name, &mut typedef)?;
+                quote!(PerCpuArray<MapRefMut, #value>)
+            }
+            BPF_MAP_TYPE_QUEUE => {
+                let value = map_value(map, name, &mut typedef)?;
+                quote!(Queue<MapRefMut, #value>)
+            }
+            BPF_MAP_TYPE_STACK => {
+                let value = map_value(map, name, &mut typedef)?;
+                quote!(Stack<MapRefMut, #value>)
+            }
+            BPF_MAP_TYPE_SOCKHASH => {
+                let key = key()?;
+                quote!(SockHash<MapRefMut, #key>)
+            }
+            BPF_MAP_TYPE_PROG_ARRAY => quote!(ProgramArray<MapRefMut>),
+            BPF_MAP_TYPE_PERF_EVENT_ARRAY => quote!(PerfEventArray<MapRefMut>),
+            BPF_MAP_TYPE_STACK_TRACE => quote!(StackTraceMap<MapRefMut>),
+            BPF_MAP_TYPE_SOCKMAP => quote!(SockMap<MapRefMut>),
+            _ => {
+                // no typed wrapper, return the map as is
+                accessors.push(quote! {
+                    pub fn #method(&self) -> Result<aya::maps::MapRefMut, aya::maps::MapError> {
+                        self.bpf.map_mut(#name)
+                    }
+                });
+                continue;
+            }
+        };
+        accessors.push(quote! {
+            pub fn #method(&self) -> Result<aya::maps::#ty, aya::maps::MapError> {
+                TryFrom::try_from(self.bpf.map_mut(#name)?)
+            }
+        });
+    }
+
+    let mut globals = obj
+        .globals()?
+        .into_iter()
+        .filter(|global| global.section == ".rodata")
+        .collect::<Vec<_>>();
+    globals.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut global_names = Vec::new();
+    let mut global_fields = Vec::new();
+    let mut global_setters = Vec::new();
+    let mut global_types = Vec::new();
+    for global in &globals {
+        global_names.push(global.name.as_str());
+        global_fields.push(ident(&global.name));
+        global_setters.push(ident(&format!("set_{}", global.name)));
+        global_types.push(typedef(global.type_id, format!("{}_t", global.name))?);
+    }
+
+    let types = if typedefs.is_empty() {
+        TokenStream::new()
+    } else {
+        generate_types(object_file, &typedefs, &type_names)?
+    };
+
+    let object = Literal::byte_string(&data);
+    let code = quote! {
+        pub mod #module {
+            #![allow(
+                dead_code,
+                non_camel_case_types,
+                non_snake_case,
+                non_upper_case_globals,
+                unused_imports,
+                unused_mut
+            )]
+
+            use std::convert::{TryFrom, TryInto};
+
+            use aya::{maps::MapRefMut, Bpf, BpfError, BpfLoader};
+
+            /// The object code the skeleton was generated from.
+            pub static OBJECT: &[u8] = #object;
+
+            #types
+
+            /// Loads the object, optionally setting the values of its read-only globals first.
+            #[derive(Default)]
+            pub struct #builder {
+                #(#global_fields: Option<#global_types>,)*
+            }
+
+            impl #builder {
+                pub fn new() -> #builder {
+                    #builder::default()
+                }
+
+                #(
+                    pub fn #global_setters(&mut self, value: #global_types) -> &mut #builder {
+                        self.#global_fields = Some(value);
+                        self
+                    }
+                )*
+
+                pub fn load(&self) -> Result<#skeleton, BpfError> {
+                    let mut loader = BpfLoader::new();
+                    #(
+                        if let Some(value) = &self.#global_fields {
+                            loader.set_global(#global_names, value);
+                        }
+                    )*
+                    Ok(#skeleton {
+                        bpf: loader.load(OBJECT)?,
+                    })
+                }
+            }
+
+            /// The loaded object.
+            pub struct #skeleton {
+                bpf: Bpf,
+            }
+
+            impl #skeleton {
+                pub fn load() -> Result<#skeleton, BpfError> {
+                    #builder::new().load()
+                }
+
+                pub fn bpf(&self) -> &Bpf {
+                    &self.bpf
+                }
+
+                pub fn bpf_mut(&mut self) -> &mut Bpf {
+                    &mut self.bpf
+                }
+
+                #(#accessors)*
+            }
+        }
+    };
+
+    rustfmt::format(&code.to_string()).map_err(Error::Rustfmt)
+}
+
+fn map_value(
+    map: &Map,
+    name: &str,
+    typedef: &mut impl FnMut(u32, String) -> Result<TokenStream, Error>,
+) -> Result<TokenStream, Error> {
+    match map.value_type_id() {
+        Some(type_id) => typedef(type_id, format!("{}_value", name)),
+        None => Ok(sized_type(map.value_size())),
+    }
+}
+
+// Runs bindgen on the object BTF, generating the types named by `typedefs` and everything they
+// depend on. Structs and unions get a Pod impl so they can be used as keys and values.
+fn generate_types(
+    object_file: &Path,
+    typedefs: &[String],
+    type_names: &[String],
+) -> Result<TokenStream, Error> {
+    let header = format!(
+        "{}\n{}\n",
+        c_header_from_btf(object_file)?,
+        typedefs.join("\n")
+    );
+    let mut bindgen = bindgen::user_builder()
+        .clang_arg("-Wno-unknown-attributes")
+        .default_enum_style(EnumVariation::Consts)
+        .header_contents("skeleton.h", &header);
+    for name in type_names {
+        bindgen = bindgen.allowlist_type(name);
+    }
+    let bindings = bindgen.generate().or(Err(Error::Bindgen))?.to_string();
+
+    let tree = syn::parse_str::<syn::File>(&bindings).unwrap();
+    let pod_impls = tree.items.iter().filter_map(|item| {
+        let ident = match item {
+            syn::Item::Struct(s) if s.generics.params.is_empty() => &s.ident,
+            syn::Item::Union(u) if u.generics.params.is_empty() => &u.ident,
+            _ => return None,
+        };
+        Some(quote!(unsafe impl aya::Pod for #ident {}))
+    });
+
+    Ok(quote! {
+        #tree
+        #(#pod_impls)*
+    })
+}
+
+fn program_type(section: &ProgramSection) -> TokenStream {
+    use ProgramSection::*;
+    match section {
+        KProbe { .. } | KRetProbe { .. } => quote!(KProbe),
+        UProbe { .. } | URetProbe { .. } => quote!(UProbe),
+        TracePoint { .. } => quote!(TracePoint),
+        SocketFilter { .. } => quote!(SocketFilter),
+        Xdp { .. } => quote!(Xdp),
+        SkMsg { .. } => quote!(SkMsg),
+        SkSkbStreamParser { .. } | SkSkbStreamVerdict { .. } => quote!(SkSkb),
+        SockOps { .. } => quote!(SockOps),
+        SchedClassifier { .. } => quote!(SchedClassifier),
+        CgroupSkbIngress { .. } | CgroupSkbEgress { .. } => quote!(CgroupSkb),
+        LircMode2 { .. } => quote!(LircMode2),
+        PerfEvent { .. } => quote!(PerfEvent),
+        RawTracePoint { .. } => quote!(RawTracePoint),
+        Lsm { .. } => quote!(Lsm),
+        BtfTracePoint { .. } => quote!(BtfTracePoint),
+        FEntry { .. } => quote!(FEntry),
+        FExit { .. } => quote!(FExit),
+    }
+}
+
+// The type used for keys and values of maps defined without BTF.
+fn sized_type(size: u32) -> TokenStream {
+    match size {
+        1 => quote!(u8),
+        2 => quote!(u16),
+        4 => quote!(u32),
+        8 => quote!(u64),
+        size => {
+            let size = size as usize;
+            quote!([u8; #size])
+        }
+    }
+}
+
+fn ident(name: &str) -> Ident {
+    let mut name = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    if syn::parse_str::<syn::Ident>(&name).is_err() {
+        // keywords
+        name.push('_');
+    }
+    Ident::new(&name, Span::call_site())
+}
+
+fn camel_case(name: &str) -> String {
+    let name = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            std::iter::once(first).chain(chars).collect::<String>()
+        })
+        .collect::<String>();
+    ident(&name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use object::{
+        write::{Object as ElfWriter, Symbol, SymbolSection},
+        Architecture, BinaryFormat, Endianness, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+    };
+
+    use super::*;
+
+    fn add_section(obj: &mut ElfWriter, name: &str, kind: SectionKind, data: &[u8], symbol: &str) {
+        let section = obj.add_section(Vec::new(), name.as_bytes().to_vec(), kind);
+        obj.set_section_data(section, data.to_vec(), 8);
+        obj.add_symbol(Symbol {
+            name: symbol.as_bytes().to_vec(),
+            value: 0,
+            size: data.len() as u64,
+            kind: if kind == SectionKind::Text {
+                SymbolKind::Text
+            } else {
+                SymbolKind::Data
+            },
+            scope: SymbolScope::Dynamic,
+            weak: false,
+            section: SymbolSection::Section(section),
+            flags: SymbolFlags::None,
+        });
+    }
+
+    #[test]
+    fn test_generate() {
+        let mut obj = ElfWriter::new(BinaryFormat::Elf, Architecture::Bpf, Endianness::Little);
+        let license = obj.add_section(Vec::new(), b"license".to_vec(), SectionKind::ReadOnlyData);
+        obj.set_section_data(license, b"GPL\x00".to_vec(), 1);
+        // mov r0, 2; exit
+        let pass = [[0xb7, 0, 0, 0, 2, 0, 0, 0], [0x95, 0, 0, 0, 0, 0, 0, 0]].concat();
+        add_section(&mut obj, "xdp/pass", SectionKind::Text, &pass, "pass");
+        // a hash map with u32 keys and u64 values, defined without BTF
+        let map_def = [
+            [1, 0, 0, 0],
+            [4, 0, 0, 0],
+            [8, 0, 0, 0],
+            [0, 4, 0, 0],
+            [0; 4],
+        ]
+        .concat();
+        add_section(
+            &mut obj,
+            "maps/counters",
+            SectionKind::Data,
+            &map_def,
+            "counters",
+        );
+        // a map that has the same name as a method of the skeleton
+        let map_def = [
+            [2, 0, 0, 0],
+            [4, 0, 0, 0],
+            [4, 0, 0, 0],
+            [1, 0, 0, 0],
+            [0; 4],
+        ]
+        .concat();
+        add_section(&mut obj, "maps/load", SectionKind::Data, &map_def, "load");
+
+        let dir = env::temp_dir().join(format!("aya-gen-skeleton-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("probe.o");
+        fs::write(&path, obj.write().unwrap()).unwrap();
+        let code = generate(&path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // compare without whitespace, which is up to rustfmt
+        let code = code.unwrap().split_whitespace().collect::<String>();
+        for expected in [
+            "pubmodprobe{",
+            "pubstructProbeBuilder{}",
+            "pubstructProbe{bpf:Bpf,}",
+            "pubfnpass(&mutself)->&mutaya::programs::Xdp{",
+            "self.bpf.program_mut(\"pass\")",
+            "pubfncounters(",
+            "Result<aya::maps::HashMap<MapRefMut,u32,u64>,aya::maps::MapError>",
+            "pubfnload_map(&self)->Result<aya::maps::Array<MapRefMut,u32>,aya::maps::MapError>",
+            "self.bpf.map_mut(\"load\")",
+        ] {
+            assert!(
+                code.contains(expected),
+                "{} not found in {}",
+                expected,
+                code
+            );
+        }
+    }
+}
@@ -1,32 +1,41 @@
 use std::{
-    borrow::Cow,
     collections::HashMap,
+    convert::TryFrom,
     error::Error,
     ffi::CString,
-    fs, io,
-    os::{raw::c_int, unix::io::RawFd},
+    fmt, fs, io,
+    os::{
+        raw::c_int,
+        unix::io::{BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    },
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
+use log::debug;
 use thiserror::Error;
 
 use crate::{
+    features::features,
     generated::{
-        bpf_map_type::BPF_MAP_TYPE_PERF_EVENT_ARRAY, AYA_PERF_EVENT_IOC_DISABLE,
-        AYA_PERF_EVENT_IOC_ENABLE, AYA_PERF_EVENT_IOC_SET_BPF,
+        bpf_map_type::{self, BPF_MAP_TYPE_PERF_EVENT_ARRAY},
+        AYA_PERF_EVENT_IOC_DISABLE, AYA_PERF_EVENT_IOC_ENABLE, AYA_PERF_EVENT_IOC_SET_BPF,
+        BPF_F_MMAPABLE,
     },
+    global::{DataMap, Global, GlobalError, GlobalVar},
     maps::{Map, MapError, MapLock, MapRef, MapRefMut},
     obj::{
         btf::{Btf, BtfError},
-        MapKind, Object, ParseError, ProgramSection,
+        ExternError, MapKind, Object, ParseError, ProgramSection,
     },
     programs::{
-        BtfTracePoint, CgroupSkb, CgroupSkbAttachType, FEntry, FExit, KProbe, LircMode2, Lsm,
-        PerfEvent, ProbeKind, Program, ProgramData, ProgramError, RawTracePoint, SchedClassifier,
-        SkMsg, SkSkb, SkSkbKind, SockOps, SocketFilter, TracePoint, UProbe, Xdp,
+        utils::module_btf_fd, BtfTracePoint, CgroupSkb, CgroupSkbAttachType, FEntry, FExit, KProbe,
+        LircMode2, Lsm, PerfEvent, ProbeKind, Program, ProgramData, ProgramError, RawTracePoint,
+        SchedClassifier, SkMsg, SkSkb, SkSkbKind, SockOps, SocketFilter, SourceMap, TracePoint,
+        UProbe, VerifierLog, VerifierLogLevel, Xdp,
     },
-    sys::{bpf_map_freeze, bpf_map_update_elem_ptr},
-    util::{bytes_of, possible_cpus, POSSIBLE_CPUS},
+    sys::{bpf_load_btf, bpf_map_freeze, bpf_map_update_elem_ptr, kernel_version},
+    util::{bytes_of, kernel_config, kernel_symbols, possible_cpus, POSSIBLE_CPUS},
 };
 
 pub(crate) const BPF_OBJ_NAME_LEN: usize = 16;
@@ -48,6 +57,8 @@ macro_rules! unsafe_impl_pod {
 
 unsafe_impl_pod!(i8, u8, i16, u16, i32, u32, i64, u64, u128, i128);
 
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
 #[allow(non_camel_case_types)]
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
@@ -67,7 +78,6 @@ pub(crate) struct bpf_map_def {
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub(crate) enum PinningType {
     None = 0,
-    #[allow(dead_code)] // ByName is constructed from the BPF side
     ByName = 1,
 }
 
@@ -77,6 +87,63 @@ impl Default for PinningType {
     }
 }
 
+/// The definition of a map, which can be changed at load time with
+/// [`BpfLoader::map_def_override`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MapDefinition {
+    /// The type of the map, one of the `BPF_MAP_TYPE_*` constants. It can't be changed.
+    pub map_type: u32,
+    /// The size of the keys.
+    pub key_size: u32,
+    /// The size of the values.
+    pub value_size: u32,
+    /// The maximum number of entries.
+    pub max_entries: u32,
+    /// The `BPF_F_*` map flags.
+    pub map_flags: u32,
+}
+
+impl MapDefinition {
+    fn new(def: &bpf_map_def) -> MapDefinition {
+        MapDefinition {
+            map_type: def.map_type,
+            key_size: def.key_size,
+            value_size: def.value_size,
+            max_entries: def.max_entries,
+            map_flags: def.map_flags,
+        }
+    }
+}
+
+/// What to do when a pinned map doesn't match the map defined in the object, see
+/// [`BpfLoader::pinned_map_policy`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PinnedMapPolicy {
+    /// Fail loading with [`MapError::IncompatibleMap`].
+    Error,
+    /// Remove the pinned map, then create and pin a new map. The programs that still use the old
+    /// map keep using it.
+    Recreate,
+    /// Use the pinned map anyway.
+    Accept,
+}
+
+impl Default for PinnedMapPolicy {
+    fn default() -> Self {
+        PinnedMapPolicy::Error
+    }
+}
+
+type MapDefOverrideFn = dyn FnMut(&str, &mut MapDefinition);
+
+struct MapDefOverride(Box<MapDefOverrideFn>);
+
+impl fmt::Debug for MapDefOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MapDefOverride")
+    }
+}
+
 /// Builder style API for advanced loading of eBPF programs.
 ///
 /// Loading eBPF code involves a few steps, including loading maps and applying
@@ -100,26 +167,56 @@ impl Default for PinningType {
 /// ```
 #[derive(Debug)]
 pub struct BpfLoader<'a> {
-    btf: Option<Cow<'a, Btf>>,
+    btf: Option<&'a Btf>,
+    // Whether to find the BTF of the running kernel when loading, which is used when no BTF is
+    // set with BpfLoader::btf. It's also the base of the split BTF of kernel modules, which is
+    // searched for CO-RE relocations.
+    use_kernel_btf: bool,
+    btf_search_paths: Vec<PathBuf>,
+    // The BTF of the running kernel and the path it was parsed from, found by the first load and
+    // reused by the following ones.
+    kernel_btf: Option<(Arc<Btf>, PathBuf)>,
     map_pin_path: Option<PathBuf>,
+    pinned_map_policy: PinnedMapPolicy,
     globals: HashMap<&'a str, &'a [u8]>,
+    map_fds: HashMap<&'a str, RawFd>,
+    max_entries: HashMap<&'a str, u32>,
+    map_flags: HashMap<&'a str, u32>,
+    map_def_override: Option<MapDefOverride>,
+    programs: Option<Vec<&'a str>>,
+    autoload: HashMap<&'a str, bool>,
+    verifier_log_level: Option<VerifierLogLevel>,
 }
 
 impl<'a> BpfLoader<'a> {
     /// Creates a new loader instance.
     pub fn new() -> BpfLoader<'a> {
         BpfLoader {
-            btf: Btf::from_sys_fs().ok().map(Cow::Owned),
+            btf: None,
+            use_kernel_btf: true,
+            btf_search_paths: Vec::new(),
+            kernel_btf: None,
             map_pin_path: None,
+            pinned_map_policy: PinnedMapPolicy::default(),
             globals: HashMap::new(),
+            map_fds: HashMap::new(),
+            max_entries: HashMap::new(),
+            map_flags: HashMap::new(),
+            map_def_override: None,
+            programs: None,
+            autoload: HashMap::new(),
+            verifier_log_level: None,
         }
     }
 
     /// Sets the target [BTF](Btf) info.
     ///
-    /// The loader defaults to loading `BTF` info using [Btf::from_sys_fs].
+    /// The loader defaults to loading `BTF` info using [Btf::from_kernel], and
+    /// to also searching the `BTF` of the loaded kernel modules in
+    /// `/sys/kernel/btf` when applying relocations.
     /// Use this method if you want to load `BTF` from a custom location or
-    /// pass `None` to disable `BTF` relocations entirely.
+    /// pass `None` to disable `BTF` relocations entirely. Kernel module `BTF`
+    /// is not searched when a custom `BTF` is set.
     /// # Example
     ///
     /// ```no_run
@@ -133,13 +230,43 @@ impl<'a> BpfLoader<'a> {
     /// # Ok::<(), aya::BpfError>(())
     /// ```
     pub fn btf(&mut self, btf: Option<&'a Btf>) -> &mut BpfLoader<'a> {
-        self.btf = btf.map(Cow::Borrowed);
+        self.btf = btf;
+        self.use_kernel_btf = false;
+        self
+    }
+
+    /// Adds a location to search for the [BTF](Btf) of the running kernel.
+    ///
+    /// When `/sys/kernel/btf/vmlinux` doesn't exist, the paths added with this method are
+    /// searched before the usual locations of vmlinux images, see [Btf::from_kernel]. `path` can
+    /// be a file containing raw `BTF` or a vmlinux image, or a directory containing
+    /// `vmlinux-$(uname -r)` or `$(uname -r).btf`. The path the `BTF` was loaded from is
+    /// returned by [Bpf::kernel_btf_path].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .btf_search_path("/opt/btfhub")
+    ///     .load_file("file.o")?;
+    /// if let Some(path) = bpf.kernel_btf_path() {
+    ///     println!("loaded kernel BTF from {}", path.display());
+    /// }
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn btf_search_path<P: AsRef<Path>>(&mut self, path: P) -> &mut BpfLoader<'a> {
+        self.btf_search_paths.push(path.as_ref().to_owned());
+        // the new path may come before the one the cached BTF was found at
+        self.kernel_btf = None;
         self
     }
 
     /// Sets the base directory path for pinned maps.
     ///
-    /// Pinned maps will be loaded from `path/MAP_NAME`.
+    /// Pinned maps will be loaded from `path/MAP_NAME`. Maps that are pinned but don't match the
+    /// object are handled according to the [pinned map policy](Self::pinned_map_policy).
     ///
     /// # Example
     ///
@@ -157,6 +284,31 @@ impl<'a> BpfLoader<'a> {
         self
     }
 
+    /// Sets what to do when a map pinned in the [map pin path](Self::map_pin_path) doesn't match
+    /// the map defined in the object.
+    ///
+    /// A pinned map matches when it has the same type, key size, value size, maximum number of
+    /// entries and flags as the map in the object, after the overrides set on the loader have been
+    /// applied. Maps pinned by a different version of the object may not match. By default loading
+    /// fails with [`MapError::IncompatibleMap`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{BpfLoader, PinnedMapPolicy};
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .map_pin_path("/sys/fs/bpf/my-program")
+    ///     .pinned_map_policy(PinnedMapPolicy::Recreate)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn pinned_map_policy(&mut self, policy: PinnedMapPolicy) -> &mut BpfLoader<'a> {
+        self.pinned_map_policy = policy;
+        self
+    }
+
     /// Sets the value of a global variable
     ///
     /// From Rust eBPF, a global variable would be constructed as follows:
@@ -187,6 +339,248 @@ impl<'a> BpfLoader<'a> {
         self
     }
 
+    /// Uses an existing map for the map called `name` instead of creating it.
+    ///
+    /// The map must have the same type, key size and value size as the map defined in the object,
+    /// otherwise loading fails with [`MapError::IncompatibleMap`]. The programs of the object then
+    /// read and write the existing map, which makes it possible to share state between
+    /// independently loaded objects without pinning.
+    ///
+    /// `fd` is duplicated when loading, so it remains owned by the caller and must stay open until
+    /// the object is loaded.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    /// use std::os::unix::io::RawFd;
+    ///
+    /// # let counters_fd: RawFd = 0;
+    /// let bpf = BpfLoader::new()
+    ///     .map_fd("COUNTERS", counters_fd)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn map_fd(&mut self, name: &'a str, fd: RawFd) -> &mut BpfLoader<'a> {
+        self.map_fds.insert(name, fd);
+        self
+    }
+
+    /// Uses a map of an already loaded object for the map called `name`.
+    ///
+    /// This is the same as [map_fd](Self::map_fd), using the file descriptor of `map`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{Bpf, BpfLoader};
+    ///
+    /// let global = Bpf::load_file("global.o")?;
+    /// let counters = global.map("COUNTERS")?;
+    /// let tenant = BpfLoader::new()
+    ///     .reuse_map("COUNTERS", &counters)?
+    ///     .load_file("tenant.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn reuse_map(&mut self, name: &'a str, map: &Map) -> Result<&mut BpfLoader<'a>, MapError> {
+        let fd = map.fd_or_err()?;
+        Ok(self.map_fd(name, fd))
+    }
+
+    /// Sets the maximum number of entries of the map called `name`.
+    ///
+    /// This overrides the `max_entries` value the map was compiled with, so that the same object
+    /// can be sized differently depending on where it's deployed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .set_max_entries("CONNECTIONS", 1 << 20)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn set_max_entries(&mut self, name: &'a str, max_entries: u32) -> &mut BpfLoader<'a> {
+        self.max_entries.insert(name, max_entries);
+        self
+    }
+
+    /// Sets the `BPF_F_*` flags of the map called `name`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// const BPF_F_NO_PREALLOC: u32 = 1;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .set_map_flags("CONNECTIONS", BPF_F_NO_PREALLOC)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn set_map_flags(&mut self, name: &'a str, flags: u32) -> &mut BpfLoader<'a> {
+        self.map_flags.insert(name, flags);
+        self
+    }
+
+    /// Sets a callback that can change the definition of maps before they are created.
+    ///
+    /// The callback is called with the name and the [definition](MapDefinition) of each map, after
+    /// the values set with [set_max_entries](Self::set_max_entries) and
+    /// [set_map_flags](Self::set_map_flags) have been applied. The changes are validated against
+    /// the map type: the type itself can't be changed, nor the key and value sizes of maps whose
+    /// layout is fixed by the kernel, like perf event arrays. Invalid changes make loading fail with
+    /// [`MapError::InvalidDefinition`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .map_def_override(|name, def| {
+    ///         if name.starts_with("CONN_") {
+    ///             def.max_entries *= 4;
+    ///         }
+    ///     })
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn map_def_override<F>(&mut self, callback: F) -> &mut BpfLoader<'a>
+    where
+        F: FnMut(&str, &mut MapDefinition) + 'static,
+    {
+        self.map_def_override = Some(MapDefOverride(Box::new(callback)));
+        self
+    }
+
+    fn override_map_def(
+        &mut self,
+        name: &str,
+        kind: &MapKind,
+        def: &mut bpf_map_def,
+    ) -> Result<(), MapError> {
+        let mut new_def = MapDefinition::new(def);
+        if let Some(max_entries) = self.max_entries.get(name) {
+            new_def.max_entries = *max_entries;
+        }
+        if let Some(flags) = self.map_flags.get(name) {
+            new_def.map_flags = *flags;
+        }
+        if let Some(MapDefOverride(callback)) = &mut self.map_def_override {
+            callback(name, &mut new_def);
+        }
+
+        let old_def = MapDefinition::new(def);
+        if new_def != old_def {
+            validate_map_def(name, kind, &old_def, &new_def)?;
+            def.key_size = new_def.key_size;
+            def.value_size = new_def.value_size;
+            def.max_entries = new_def.max_entries;
+            def.map_flags = new_def.map_flags;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the programs to prepare when loading the object.
+    ///
+    /// By default all the programs included in the object are relocated and linked. When this
+    /// method is called, only the given programs are prepared and returned by
+    /// [Bpf::programs], and the others are discarded. This avoids the cost of
+    /// preparing programs that are never used.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .programs(&["ingress", "egress"])
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn programs(&mut self, names: &[&'a str]) -> &mut BpfLoader<'a> {
+        self.programs = Some(names.to_vec());
+        self
+    }
+
+    /// Sets whether the program called `name` is prepared when loading the object.
+    ///
+    /// Programs that are not prepared are discarded. This takes precedence over the programs
+    /// selected with [programs](Self::programs).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::BpfLoader;
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .autoload("debug_probe", false)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn autoload(&mut self, name: &'a str, autoload: bool) -> &mut BpfLoader<'a> {
+        self.autoload.insert(name, autoload);
+        self
+    }
+
+    /// Sets the level of the verifier log of all the programs.
+    ///
+    /// When set, the log is captured even when the programs are loaded successfully and can be
+    /// inspected with [Program::verifier_output](crate::programs::Program::verifier_output). The
+    /// level can also be set for a single program with
+    /// [Program::set_verifier_log_level](crate::programs::Program::set_verifier_log_level).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::{BpfLoader, programs::VerifierLogLevel};
+    ///
+    /// let bpf = BpfLoader::new()
+    ///     .verifier_log_level(VerifierLogLevel::DEBUG | VerifierLogLevel::STATS)
+    ///     .load_file("file.o")?;
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    ///
+    pub fn verifier_log_level(&mut self, level: VerifierLogLevel) -> &mut BpfLoader<'a> {
+        self.verifier_log_level = Some(level);
+        self
+    }
+
+    fn is_autoloaded(&self, name: &str) -> bool {
+        match self.autoload.get(name) {
+            Some(autoload) => *autoload,
+            None => self
+                .programs
+                .as_ref()
+                .map(|names| names.contains(&name))
+                .unwrap_or(true),
+        }
+    }
+
+    fn kernel_btf(&mut self) -> Option<(Arc<Btf>, PathBuf)> {
+        if !self.use_kernel_btf {
+            return None;
+        }
+        if self.kernel_btf.is_none() {
+            self.kernel_btf = Btf::from_kernel(&self.btf_search_paths)
+                .ok()
+                .map(|(btf, path)| (Arc::new(btf), path));
+        }
+        self.kernel_btf.clone()
+    }
+
     /// Loads eBPF bytecode from a file.
     ///
     /// # Examples
@@ -220,13 +614,130 @@ impl<'a> BpfLoader<'a> {
     pub fn load(&mut self, data: &[u8]) -> Result<Bpf, BpfError> {
         let mut obj = Object::parse(data)?;
         obj.patch_map_data(self.globals.clone())?;
+        // must be done before the BTF is sanitized, which can remove the DATASEC types
+        let globals = obj
+            .globals()?
+            .into_iter()
+            .map(|global| {
+                (
+                    global.name,
+                    GlobalVar {
+                        section: global.section,
+                        offset: global.offset as usize,
+                        size: global.size as usize,
+                    },
+                )
+            })
+            .collect();
+
+        if let Some(name) = self
+            .map_fds
+            .keys()
+            .chain(self.max_entries.keys())
+            .chain(self.map_flags.keys())
+            .find(|name| !obj.maps.contains_key(**name))
+        {
+            return Err(MapError::MapNotFound {
+                name: name.to_string(),
+            }
+            .into());
+        }
+        if let Some(name) = self
+            .programs
+            .iter()
+            .flatten()
+            .chain(self.autoload.keys())
+            .find(|name| !obj.programs.contains_key(**name))
+        {
+            return Err(BpfError::ProgramNotFound {
+                name: name.to_string(),
+            });
+        }
+        let (kernel_btf, kernel_btf_path) = match self.kernel_btf() {
+            Some((btf, path)) => (Some(btf), Some(path)),
+            None => (None, None),
+        };
+        let module_btfs = match &kernel_btf {
+            Some(kernel_btf) if obj.has_btf_relocations() || obj.has_kfunc_externs() => {
+                Btf::modules_from_sys_fs(Arc::clone(kernel_btf))?
+            }
+            _ => Vec::new(),
+        };
+        let (mut core_relocations, core_relocation_errors) =
+            match self.btf.or(kernel_btf.as_deref()) {
+                Some(btf) => obj.relocate_btf(btf, &module_btfs)?,
+                None => (HashMap::new(), HashMap::new()),
+            };
+        // programs that aren't loaded are removed once the CO-RE relocations, which reference
+        // them by section, have been applied
+        obj.programs.retain(|name, _| self.is_autoloaded(name));
 
-        if let Some(btf) = &self.btf {
-            obj.relocate_btf(btf)?;
+        if obj.has_kconfig_externs() {
+            // options that can't be found are reported when resolving the externs
+            let config = kernel_config().unwrap_or_default();
+            let version = match kernel_version() {
+                Ok((major, minor, patch)) => (major << 16) + (minor << 8) + patch.min(255),
+                Err(_) => 0,
+            };
+            obj.resolve_kconfig(&config, version)?;
+        }
+        let mut fd_array = Vec::new();
+        if obj.has_ksym_externs() {
+            let symbols = if obj.has_typeless_ksym_externs() {
+                kernel_symbols()
+                    .map_err(|error| BpfError::FileError {
+                        path: PathBuf::from("/proc/kallsyms"),
+                        error,
+                    })?
+                    .into_iter()
+                    .map(|(addr, name)| (name, addr))
+                    .collect()
+            } else {
+                HashMap::new()
+            };
+            let modules =
+                obj.resolve_ksyms(self.btf.or(kernel_btf.as_deref()), &module_btfs, &symbols)?;
+            // kfuncs defined by modules are referenced by their index in the fd_array, which is
+            // built when loading the programs
+            for module in modules {
+                let fd =
+                    module_btf_fd(&module).map_err(|io_error| ExternError::ModuleBtfError {
+                        module: module.clone(),
+                        io_error,
+                    })?;
+                fd_array.push(Arc::new(fd));
+            }
         }
 
+        // Load the BTF of the object so that it can be referenced by the programs. BTF is
+        // optional: kernels that don't support BPF_BTF_LOAD, or that reject the sanitized BTF,
+        // can still load the programs without it.
+        let btf_fd = match &features().btf {
+            Some(btf_features) if obj.btf.is_some() => {
+                obj.fixup_and_sanitize_btf(btf_features)?;
+                obj.btf
+                    .as_ref()
+                    .and_then(|btf| match load_btf(btf.to_bytes()) {
+                        Ok(fd) => Some(Arc::new(fd)),
+                        Err(e) => {
+                            debug!("loading the programs without BTF: {}", e);
+                            None
+                        }
+                    })
+            }
+            _ => None,
+        };
+
         let mut maps = HashMap::new();
+        let mut data_maps = HashMap::new();
         for (name, mut obj) in obj.maps.drain() {
+            self.override_map_def(&name, &obj.kind, &mut obj.def)?;
+            if obj.kind != MapKind::Other
+                && features().mmapable_array()
+                && !self.map_fds.contains_key(name.as_str())
+            {
+                obj.def.map_flags |= BPF_F_MMAPABLE;
+            }
             if obj.def.map_type == BPF_MAP_TYPE_PERF_EVENT_ARRAY as u32 && obj.def.max_entries == 0
             {
                 obj.def.max_entries = possible_cpus()
@@ -241,28 +752,57 @@ impl<'a> BpfLoader<'a> {
                 fd: None,
                 pinned: false,
             };
-            let fd = match map.obj.def.pinning {
-                PinningType::ByName => {
+            let reused_fd = self.map_fds.get(name.as_str()).copied();
+            let fd = match (reused_fd, map.obj.def.pinning) {
+                (Some(fd), _) => map.reuse(&name, fd)?,
+                (None, PinningType::ByName) => {
                     let path = match &self.map_pin_path {
                         Some(p) => p,
                         None => return Err(BpfError::NoPinPath),
                     };
                     // try to open map in case it's already pinned
-                    match map.from_pinned(&name, path) {
-                        Ok(fd) => {
+                    let pinned_fd = match map.from_pinned(&name, path) {
+                        Ok(fd) => match map.check_compatible(&name, fd, true) {
+                            Ok(()) => Some(fd),
+                            Err(MapError::IncompatibleMap { .. })
+                                if self.pinned_map_policy == PinnedMapPolicy::Accept =>
+                            {
+                                Some(fd)
+                            }
+                            Err(MapError::IncompatibleMap { .. })
+                                if self.pinned_map_policy == PinnedMapPolicy::Recreate =>
+                            {
+                                map.fd = None;
+                                let map_path = path.join(&name);
+                                fs::remove_file(&map_path).map_err(|error| {
+                                    BpfError::FileError {
+                                        path: map_path,
+                                        error,
+                                    }
+                                })?;
+                                None
+                            }
+                            Err(e) => return Err(e.into()),
+                        },
+                        Err(_) => None,
+                    };
+                    match pinned_fd {
+                        Some(fd) => {
                             map.pinned = true;
-                            fd as RawFd
+                            fd
                         }
-                        Err(_) => {
+                        None => {
                             let fd = map.create(&name)?;
                             map.pin(&name, path)?;
                             fd
                         }
                     }
                 }
-                PinningType::None => map.create(&name)?,
+                (None, PinningType::None) => map.create(&name)?,
             };
-            if !map.obj.data.is_empty() && map.obj.kind != MapKind::Bss {
+            // reused maps have already been initialized by their owner
+            let initialize = reused_fd.is_none();
+            if initialize && !map.obj.data.is_empty() && map.obj.kind != MapKind::Bss {
                 bpf_map_update_elem_ptr(fd, &0 as *const _, map.obj.data.as_mut_ptr(), 0).map_err(
                     |(code, io_error)| MapError::SyscallError {
                         call: "bpf_map_update_elem".to_owned(),
@@ -271,23 +811,41 @@ impl<'a> BpfLoader<'a> {
                     },
                 )?;
             }
-            if map.obj.kind == MapKind::Rodata {
+            if initialize && map.obj.kind == MapKind::Rodata {
                 bpf_map_freeze(fd).map_err(|(code, io_error)| MapError::SyscallError {
                     call: "bpf_map_freeze".to_owned(),
                     code,
                     io_error,
                 })?;
             }
+            if map.obj.kind != MapKind::Other {
+                let writable = map.obj.kind != MapKind::Rodata;
+                // Safety: fd is owned by the map
+                let data_fd = unsafe { BorrowedFd::borrow_raw(fd) }
+                    .try_clone_to_owned()
+                    .map_err(|io_error| MapError::SyscallError {
+                        call: "dup".to_owned(),
+                        code: -1,
+                        io_error,
+                    })?;
+                data_maps.insert(
+                    name.clone(),
+                    DataMap::new(data_fd, map.obj.def.value_size as usize, writable),
+                );
+            }
             maps.insert(name, map);
         }
 
-        obj.relocate_maps(maps.iter().map(|(name, map)| (name.as_str(), map)))?;
-        obj.relocate_calls()?;
+        let mut relocation_errors =
+            obj.relocate_programs(maps.iter().map(|(name, map)| (name.as_str(), map)));
+        relocation_errors.extend(core_relocation_errors);
 
+        let btf = obj.btf.as_ref();
         let programs = obj
             .programs
             .drain()
             .map(|(name, obj)| {
+                let source_map = SourceMap::new(&obj.function, btf);
                 let data = ProgramData {
                     obj,
                     fd: None,
@@ -295,6 +853,13 @@ impl<'a> BpfLoader<'a> {
                     expected_attach_type: None,
                     attach_btf_obj_fd: None,
                     attach_btf_id: None,
+                    btf_fd: btf_fd.clone(),
+                    relocation_error: relocation_errors.remove(&name),
+                    verifier_log_level: self.verifier_log_level,
+                    verifier_output: None,
+                    source_map,
+                    fd_array: fd_array.clone(),
+                    core_relocations: core_relocations.remove(&name).unwrap_or_default(),
                 };
                 let program = match &data.obj.section {
                     ProgramSection::KProbe { .. } => Program::KProbe(KProbe {
@@ -365,7 +930,94 @@ impl<'a> BpfLoader<'a> {
             .drain()
             .map(|(name, map)| (name, MapLock::new(map)))
             .collect();
-        Ok(Bpf { maps, programs })
+        Ok(Bpf {
+            maps,
+            programs,
+            globals,
+            data_maps,
+            kernel_btf_path,
+        })
+    }
+}
+
+fn validate_map_def(
+    name: &str,
+    kind: &MapKind,
+    old_def: &MapDefinition,
+    new_def: &MapDefinition,
+) -> Result<(), MapError> {
+    use bpf_map_type::*;
+
+    let invalid = |reason: &str| {
+        Err(MapError::InvalidDefinition {
+            name: name.to_owned(),
+            reason: reason.to_owned(),
+        })
+    };
+
+    if new_def.map_type != old_def.map_type {
+        return invalid("the map type can't be changed");
+    }
+    let map_type = bpf_map_type::try_from(new_def.map_type).unwrap_or(BPF_MAP_TYPE_UNSPEC);
+    if *kind != MapKind::Other {
+        // global data is stored in the value of the only entry of the map
+        if new_def.key_size != old_def.key_size
+            || new_def.value_size != old_def.value_size
+            || new_def.max_entries != old_def.max_entries
+        {
+            return invalid("only the flags of global data maps can be changed");
+        }
+        return Ok(());
+    }
+
+    let fixed_layout = matches!(
+        map_type,
+        BPF_MAP_TYPE_PROG_ARRAY
+            | BPF_MAP_TYPE_PERF_EVENT_ARRAY
+            | BPF_MAP_TYPE_CGROUP_ARRAY
+            | BPF_MAP_TYPE_ARRAY_OF_MAPS
+            | BPF_MAP_TYPE_HASH_OF_MAPS
+            | BPF_MAP_TYPE_DEVMAP
+            | BPF_MAP_TYPE_DEVMAP_HASH
+            | BPF_MAP_TYPE_SOCKMAP
+            | BPF_MAP_TYPE_SOCKHASH
+            | BPF_MAP_TYPE_CPUMAP
+            | BPF_MAP_TYPE_XSKMAP
+            | BPF_MAP_TYPE_REUSEPORT_SOCKARRAY
+            | BPF_MAP_TYPE_RINGBUF
+    );
+    if fixed_layout
+        && (new_def.key_size != old_def.key_size || new_def.value_size != old_def.value_size)
+    {
+        return invalid("the key and value sizes of this map type can't be changed");
+    }
+    // perf event arrays default to one entry per CPU
+    if new_def.max_entries == 0 && map_type != BPF_MAP_TYPE_PERF_EVENT_ARRAY {
+        return invalid("max_entries can't be 0");
+    }
+    if map_type == BPF_MAP_TYPE_RINGBUF && !new_def.max_entries.is_power_of_two() {
+        return invalid("the size of ring buffers must be a power of two");
+    }
+
+    Ok(())
+}
+
+fn load_btf(raw_btf: Vec<u8>) -> Result<OwnedFd, BtfError> {
+    let mut log_buf = VerifierLog::new();
+    log_buf.grow();
+    match bpf_load_btf(raw_btf.as_slice(), &mut log_buf) {
+        // Safety: the fd was just returned by the kernel and nothing else owns it
+        Ok(fd) => Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) }),
+        Err((_, io_error)) => {
+            log_buf.truncate();
+            Err(BtfError::LoadError {
+                io_error,
+                verifier_log: log_buf
+                    .as_c_str()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "[none]".to_owned()),
+            })
+        }
     }
 }
 
@@ -380,6 +1032,9 @@ impl<'a> Default for BpfLoader<'a> {
 pub struct Bpf {
     maps: HashMap<String, MapLock>,
     programs: HashMap<String, Program>,
+    globals: HashMap<String, GlobalVar>,
+    data_maps: HashMap<String, DataMap>,
+    kernel_btf_path: Option<PathBuf>,
 }
 
 impl Bpf {
@@ -387,7 +1042,7 @@ impl Bpf {
     ///
     /// Parses the given object code file and initializes the [maps](crate::maps) defined in it. If
     /// the kernel supports [BTF](Btf) debug info, it is automatically loaded from
-    /// `/sys/kernel/btf/vmlinux`.
+    /// `/sys/kernel/btf/vmlinux`, or from a vmlinux image, see [Btf::from_kernel].
     ///
     /// For more loading options, see [BpfLoader].
     ///
@@ -400,23 +1055,22 @@ impl Bpf {
     /// # Ok::<(), aya::BpfError>(())
     /// ```
     pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Bpf, BpfError> {
-        BpfLoader::new()
-            .btf(Btf::from_sys_fs().ok().as_ref())
-            .load_file(path)
+        BpfLoader::new().load_file(path)
     }
 
     /// Loads eBPF bytecode from a buffer.
     ///
     /// Parses the object code contained in `data` and initializes the
     /// [maps](crate::maps) defined in it. If the kernel supports [BTF](Btf)
-    /// debug info, it is automatically loaded from `/sys/kernel/btf/vmlinux`.
+    /// debug info, it is automatically loaded from `/sys/kernel/btf/vmlinux`,
+    /// or from a vmlinux image, see [Btf::from_kernel].
     ///
     /// For more loading options, see [BpfLoader].
     ///
     /// # Examples
     ///
     /// ```no_run
-    /// use aya::{Bpf, Btf};
+    /// use aya::Bpf;
     /// use std::fs;
     ///
     /// let data = fs::read("file.o").unwrap();
@@ -425,9 +1079,15 @@ impl Bpf {
     /// # Ok::<(), aya::BpfError>(())
     /// ```
     pub fn load(data: &[u8]) -> Result<Bpf, BpfError> {
-        BpfLoader::new()
-            .btf(Btf::from_sys_fs().ok().as_ref())
-            .load(data)
+        BpfLoader::new().load(data)
+    }
+
+    /// Returns the path the [BTF](Btf) of the running kernel was loaded from.
+    ///
+    /// Returns `None` if the kernel `BTF` couldn't be found, or if a custom `BTF` was set with
+    /// [BpfLoader::btf].
+    pub fn kernel_btf_path(&self) -> Option<&Path> {
+        self.kernel_btf_path.as_deref()
     }
 
     /// Returns a reference to the map with the given name.
@@ -480,6 +1140,49 @@ impl Bpf {
             })
     }
 
+    /// Removes the map with the given name from the object and returns it.
+    ///
+    /// The returned reference is the only owner of the map, which is no longer returned by
+    /// [map](Self::map), [map_mut](Self::map_mut) and [maps](Self::maps). It can be converted to a
+    /// [typed map](crate::maps) and moved to another thread, independently of the object. The
+    /// programs of the object keep using the map, and the map is closed when the reference is
+    /// dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MapError::MapNotFound`] if the map does not exist, and [`MapError::BorrowError`]
+    /// if the map is borrowed with [map](Self::map) or [map_mut](Self::map_mut).
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # let mut bpf = aya::Bpf::load(&[])?;
+    /// use aya::maps::HashMap;
+    /// use std::{convert::TryFrom, thread};
+    ///
+    /// let mut redirect_ports = HashMap::<_, u16, u16>::try_from(bpf.take_map("REDIRECT_PORTS")?)?;
+    /// thread::spawn(move || {
+    ///     redirect_ports.insert(80, 8080, 0).unwrap();
+    /// });
+    /// # Ok::<(), aya::BpfError>(())
+    /// ```
+    pub fn take_map(&mut self, name: &str) -> Result<MapRefMut, MapError> {
+        let lock = self
+            .maps
+            .remove(name)
+            .ok_or_else(|| MapError::MapNotFound {
+                name: name.to_owned(),
+            })?;
+        match lock.try_write() {
+            Ok(map) => Ok(map),
+            Err(_) => {
+                self.maps.insert(name.to_owned(), lock);
+                Err(MapError::BorrowError {
+                    name: name.to_owned(),
+                })
+            }
+        }
+    }
+
     /// An iterator over all the maps.
     ///
     /// # Examples
@@ -505,6 +1208,39 @@ impl Bpf {
         ret
     }
 
+    /// Returns the global variable with the given name.
+    ///
+    /// Globals defined in `.data` and `.bss` can be read and changed while the programs of the
+    /// object are running. Globals defined in `.rodata` can only be read, use
+    /// [`BpfLoader::set_global`] to set them before loading.
+    ///
+    /// The variable is found using the BTF of the object, so objects compiled without BTF have no
+    /// globals.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GlobalError::NotFound`] if the global does not exist and
+    /// [`GlobalError::InvalidSize`] if the size of `T` is not the size of the global.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let bpf = aya::Bpf::load(&[])?;
+    /// // declared in the eBPF program as `volatile u64 packets = 0;`
+    /// let packets = bpf.global::<u64>("packets")?;
+    /// println!("{} packets so far", packets.get()?);
+    /// packets.set(0)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn global<T: Pod>(&self, name: &str) -> Result<Global<'_, T>, GlobalError> {
+        let not_found = || GlobalError::NotFound {
+            name: name.to_owned(),
+        };
+        let (name, var) = self.globals.get_key_value(name).ok_or_else(not_found)?;
+        let map = self.data_maps.get(&var.section).ok_or_else(not_found)?;
+        Global::new(name, var, map)
+    }
+
     /// Returns a reference to the program with the given name.
     ///
     /// You can use this to inspect a program and its properties. To load and attach a program, use
@@ -606,6 +1342,12 @@ pub enum BpfError {
     #[error("BTF error")]
     BtfError(#[from] BtfError),
 
+    #[error("error resolving extern")]
+    ExternError(#[from] ExternError),
+
+    #[error("program `{name}` not found")]
+    ProgramNotFound { name: String },
+
     #[error("error relocating `{function}`")]
     RelocationError {
         function: String,
@@ -619,3 +1361,414 @@ pub enum BpfError {
     #[error("program error")]
     ProgramError(#[from] ProgramError),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, env, os::unix::io::IntoRawFd, process};
+
+    use object::{
+        write, Architecture, BinaryFormat, Endianness, SectionKind, SymbolFlags, SymbolKind,
+    };
+
+    use super::*;
+    use crate::{
+        generated::{bpf_cmd, bpf_map_info},
+        maps::MapLock,
+        obj,
+        obj::MapKind,
+        programs::{LinkRef, PinnedLink},
+        sys::{fake_fd, override_syscall, SysResult, Syscall},
+    };
+
+    fn new_object() -> write::Object<'static> {
+        let mut obj = write::Object::new(BinaryFormat::Elf, Architecture::Bpf, Endianness::Little);
+        let license = obj.add_section(Vec::new(), b"license".to_vec(), SectionKind::ReadOnlyData);
+        obj.set_section_data(license, b"GPL\x00".to_vec(), 1);
+        obj
+    }
+
+    // adds a section defining `symbol`, a program if `kind` is SectionKind::Text
+    fn add_section(
+        obj: &mut write::Object,
+        name: &str,
+        kind: SectionKind,
+        data: &[u8],
+        symbol: &str,
+    ) {
+        let section = obj.add_section(Vec::new(), name.as_bytes().to_vec(), kind);
+        obj.set_section_data(section, data.to_vec(), 8);
+        obj.add_symbol(write::Symbol {
+            name: symbol.as_bytes().to_vec(),
+            value: 0,
+            size: data.len() as u64,
+            kind: if kind == SectionKind::Text {
+                SymbolKind::Text
+            } else {
+                SymbolKind::Data
+            },
+            scope: object::SymbolScope::Dynamic,
+            weak: false,
+            section: write::SymbolSection::Section(section),
+            flags: SymbolFlags::None,
+        });
+    }
+
+    fn hash_map_def() -> bpf_map_def {
+        bpf_map_def {
+            map_type: bpf_map_type::BPF_MAP_TYPE_HASH as u32,
+            key_size: 4,
+            value_size: 8,
+            max_entries: 1024,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_override_map_def() {
+        let mut loader = BpfLoader::new();
+        loader
+            .set_max_entries("foo", 4096)
+            .set_map_flags("bar", 1)
+            .map_def_override(|name, def| {
+                if name == "bar" {
+                    def.value_size = 16;
+                }
+            });
+
+        let mut def = hash_map_def();
+        loader
+            .override_map_def("foo", &MapKind::Other, &mut def)
+            .unwrap();
+        assert_eq!(
+            def,
+            bpf_map_def {
+                max_entries: 4096,
+                ..hash_map_def()
+            }
+        );
+
+        let mut def = hash_map_def();
+        loader
+            .override_map_def("bar", &MapKind::Other, &mut def)
+            .unwrap();
+        assert_eq!(
+            def,
+            bpf_map_def {
+                value_size: 16,
+                map_flags: 1,
+                ..hash_map_def()
+            }
+        );
+    }
+
+    #[test]
+    fn test_override_map_def_invalid() {
+        let mut loader = BpfLoader::new();
+        loader.map_def_override(|name, def| match name {
+            "type" => def.map_type = bpf_map_type::BPF_MAP_TYPE_ARRAY as u32,
+            "zero" => def.max_entries = 0,
+            _ => def.key_size = 8,
+        });
+
+        for name in &["type", "zero"] {
+            let mut def = hash_map_def();
+            assert!(matches!(
+                loader.override_map_def(name, &MapKind::Other, &mut def),
+                Err(MapError::InvalidDefinition { .. })
+            ));
+            assert_eq!(def, hash_map_def());
+        }
+
+        let mut def = bpf_map_def {
+            map_type: BPF_MAP_TYPE_PERF_EVENT_ARRAY as u32,
+            key_size: 4,
+            value_size: 4,
+            ..Default::default()
+        };
+        assert!(matches!(
+            loader.override_map_def("perf", &MapKind::Other, &mut def),
+            Err(MapError::InvalidDefinition { .. })
+        ));
+
+        let mut def = hash_map_def();
+        assert!(matches!(
+            loader.override_map_def(".data", &MapKind::Data, &mut def),
+            Err(MapError::InvalidDefinition { .. })
+        ));
+    }
+
+    #[test]
+    fn test_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Bpf>();
+        assert_send::<Program>();
+        assert_send::<LinkRef>();
+        assert_send::<PinnedLink>();
+        assert_send::<MapRefMut>();
+    }
+
+    #[test]
+    fn test_take_map() {
+        let map = Map {
+            obj: obj::Map {
+                def: hash_map_def(),
+                section_index: 0,
+                symbol_index: None,
+                data: Vec::new(),
+                kind: MapKind::Other,
+                btf_def: None,
+            },
+            fd: Some(fake_fd()),
+            pinned: false,
+        };
+        let mut bpf = Bpf {
+            maps: vec![("foo".to_owned(), MapLock::new(map))]
+                .into_iter()
+                .collect(),
+            programs: HashMap::new(),
+            globals: HashMap::new(),
+            data_maps: HashMap::new(),
+            kernel_btf_path: None,
+        };
+
+        let borrowed = bpf.map("foo").unwrap();
+        assert!(matches!(
+            bpf.take_map("foo"),
+            Err(MapError::BorrowError { .. })
+        ));
+        drop(borrowed);
+
+        let map = bpf.take_map("foo").unwrap();
+        assert!(map.fd.is_some());
+        assert!(matches!(bpf.map("foo"), Err(MapError::MapNotFound { .. })));
+    }
+
+    #[test]
+    fn test_load_deselected_program_with_core_relocations() {
+        use std::convert::TryInto;
+
+        use crate::{
+            generated::{BTF_KIND_INT, BTF_KIND_STRUCT},
+            obj::btf::btf_bytes,
+            programs::Xdp,
+        };
+
+        let kind = |kind: u32, vlen: u32| kind << 24 | vlen;
+        let words = |words: &[u32]| {
+            words
+                .iter()
+                .flat_map(|w| w.to_le_bytes().to_vec())
+                .collect::<Vec<_>>()
+        };
+        // mov r0, 0; exit
+        let instructions = [[0xb7, 0, 0, 0, 0, 0, 0, 0], [0x95, 0, 0, 0, 0, 0, 0, 0]].concat();
+
+        let mut elf = new_object();
+        add_section(
+            &mut elf,
+            "xdp/good",
+            SectionKind::Text,
+            &instructions,
+            "good",
+        );
+        add_section(
+            &mut elf,
+            "xdp/skipped",
+            SectionKind::Text,
+            &instructions,
+            "skipped",
+        );
+
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, 32,
+            // [2] struct foo { int a; }
+            5, kind(BTF_KIND_STRUCT, 1), 4, 9, 1, 0,
+        ];
+        let btf = elf.add_section(Vec::new(), b".BTF".to_vec(), SectionKind::Other);
+        elf.set_section_data(
+            btf,
+            btf_bytes(&types, b"\0int\0foo\0a\0xdp/good\0xdp/skipped\x000:0\0"),
+            4,
+        );
+        #[rustfmt::skip]
+        let core_relo = words(&[
+            // rec_size
+            16,
+            // xdp/good: one relocation referencing an instruction past the end of the program
+            11, 1,
+            // insn_off, type_id, access_str_off, kind (field byte offset)
+            40, 2, 32, 0,
+            // xdp/skipped: one valid relocation
+            20, 1,
+            0, 2, 32, 0,
+        ]);
+        // magic, version and flags followed by hdr_len and the info offsets and lengths
+        let mut btf_ext = [&0xeb9f_u16.to_le_bytes()[..], &[1, 0]].concat();
+        btf_ext.extend(words(&[32, 0, 0, 0, 0, 0, core_relo.len() as u32]));
+        btf_ext.extend(core_relo);
+        let section = elf.add_section(Vec::new(), b".BTF.ext".to_vec(), SectionKind::Other);
+        elf.set_section_data(section, btf_ext, 4);
+        let data = elf.write().unwrap();
+
+        // the target BTF doesn't define foo, so the valid relocation is poisoned
+        let target_btf =
+            Btf::parse(&btf_bytes(&types[..4], b"\0int\0"), Endianness::default()).unwrap();
+
+        let mut bpf = BpfLoader::new()
+            .btf(Some(&target_btf))
+            .autoload("skipped", false)
+            .load(&data)
+            .unwrap();
+        assert!(bpf.program("skipped").is_none());
+
+        // the CO-RE error is reported when loading the program that caused it
+        let prog: &mut Xdp = bpf.program_mut("good").unwrap().try_into().unwrap();
+        assert!(matches!(
+            prog.load(),
+            Err(ProgramError::RelocationError { function, .. }) if function == "good"
+        ));
+    }
+
+    thread_local! {
+        static BPF_CMDS: RefCell<Vec<bpf_cmd>> = const { RefCell::new(Vec::new()) };
+    }
+
+    // a map named `counters` with max_entries set to 1024, pinned by name
+    fn pinned_map_object() -> Vec<u8> {
+        let mut obj = new_object();
+        let def = bpf_map_def {
+            pinning: PinningType::ByName,
+            ..hash_map_def()
+        };
+        // Safety: bpf_map_def is POD
+        let data = unsafe { crate::util::bytes_of(&def) };
+        add_section(
+            &mut obj,
+            "maps/counters",
+            SectionKind::Data,
+            data,
+            "counters",
+        );
+        obj.write().unwrap()
+    }
+
+    // the map pinned by a previous version of the object only has 16 entries
+    fn pinned_map_syscall(call: Syscall) -> SysResult {
+        let cmd = match &call {
+            Syscall::Bpf { cmd, .. } => *cmd,
+            _ => return Err((-1, io::Error::from_raw_os_error(libc::EFAULT))),
+        };
+        BPF_CMDS.with(|cmds| cmds.borrow_mut().push(cmd));
+        match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET | bpf_cmd::BPF_MAP_CREATE,
+                ..
+            } => Ok(fake_fd().into_raw_fd() as i64),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_map_info) };
+                info.type_ = bpf_map_type::BPF_MAP_TYPE_HASH as u32;
+                info.key_size = 4;
+                info.value_size = 8;
+                info.max_entries = 16;
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_PIN,
+                ..
+            } => Ok(0),
+            _ => Err((-1, io::Error::from_raw_os_error(libc::EFAULT))),
+        }
+    }
+
+    // loads the pinned map object with `policy`. Returns the result, the bpf commands issued and
+    // whether the pinned map file still exists.
+    fn load_pinned_map(policy: PinnedMapPolicy) -> (Result<Bpf, BpfError>, Vec<bpf_cmd>, bool) {
+        // probe the features first so that their syscalls aren't recorded
+        features();
+        override_syscall(pinned_map_syscall);
+        BPF_CMDS.with(|cmds| cmds.borrow_mut().clear());
+
+        let dir = env::temp_dir().join(format!("aya-pinned-{:?}-{}", policy, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("counters"), b"").unwrap();
+        let result = BpfLoader::new()
+            .map_pin_path(&dir)
+            .pinned_map_policy(policy)
+            .load(&pinned_map_object());
+        let pin_exists = dir.join("counters").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        (result, BPF_CMDS.with(|cmds| cmds.take()), pin_exists)
+    }
+
+    #[test]
+    fn test_pinned_map_policy_error() {
+        let (result, cmds, pin_exists) = load_pinned_map(PinnedMapPolicy::Error);
+        assert!(matches!(
+            result,
+            Err(BpfError::MapError(MapError::IncompatibleMap { field, value: 16, expected: 1024, .. }))
+                if field == "max entries"
+        ));
+        assert!(!cmds.contains(&bpf_cmd::BPF_MAP_CREATE));
+        assert!(pin_exists);
+    }
+
+    #[test]
+    fn test_pinned_map_policy_accept() {
+        let (result, cmds, pin_exists) = load_pinned_map(PinnedMapPolicy::Accept);
+        let bpf = result.unwrap();
+        assert!(bpf.map("counters").unwrap().pinned);
+        // the mismatched map is used as is
+        assert!(!cmds.contains(&bpf_cmd::BPF_MAP_CREATE));
+        assert!(!cmds.contains(&bpf_cmd::BPF_OBJ_PIN));
+        assert!(pin_exists);
+    }
+
+    #[test]
+    fn test_pinned_map_policy_recreate() {
+        let (result, cmds, pin_exists) = load_pinned_map(PinnedMapPolicy::Recreate);
+        assert!(result.unwrap().map("counters").unwrap().pinned);
+        // the old pin is removed and a new map is created and pinned in its place
+        assert!(!pin_exists);
+        assert_eq!(
+            cmds,
+            vec![
+                bpf_cmd::BPF_OBJ_GET,
+                bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                bpf_cmd::BPF_MAP_CREATE,
+                bpf_cmd::BPF_OBJ_PIN,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kernel_btf_cached() {
+        use crate::obj::btf::btf_bytes;
+
+        // the search path is only used when the kernel doesn't expose its BTF in sysfs
+        let path = env::temp_dir().join(format!("aya-kernel-btf-{}", process::id()));
+        fs::write(&path, btf_bytes(&[], b"\0")).unwrap();
+        let data = new_object().write().unwrap();
+
+        let mut loader = BpfLoader::new();
+        loader.btf_search_path(&path);
+        let first = loader.load(&data).unwrap();
+        let (btf, _) = loader.kernel_btf.clone().unwrap();
+        // the BTF isn't parsed again when loading another object
+        let second = loader.load(&data).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(Arc::ptr_eq(&btf, &loader.kernel_btf.as_ref().unwrap().0));
+        assert!(first.kernel_btf_path().is_some());
+        assert_eq!(first.kernel_btf_path(), second.kernel_btf_path());
+
+        // adding a search path invalidates the cached BTF
+        loader.btf_search_path(&path);
+        assert!(loader.kernel_btf.is_none());
+    }
+}
@@ -0,0 +1,12 @@
+//! BTF (BPF Type Format) parsing and inspection.
+//!
+//! [`Btf`] gives read-only access to the types with [`Btf::iter_types`], [`Btf::find_type`] and
+//! [`Btf::get_type`], and can dump them as a C header with [`Btf::to_c_header`].
+
+pub use crate::obj::btf::{
+    ArrayInfo, Btf, BtfError, BtfKind, EnumValue, IntInfo, Member, Param, TypeRef,
+};
+
+// used by aya-gen to generate minimized kernel BTF, not a stable API
+#[doc(hidden)]
+pub use crate::obj::btf::{BtfBuilder, IntEncoding, Linkage};
@@ -0,0 +1,588 @@
+//! Probing of the eBPF features supported by the running kernel.
+//!
+//! Kernel versions are a poor indicator of which eBPF features are available: distributions
+//! routinely backport features to older kernels, and some features can be disabled at build time.
+//! The functions in this module detect support for a feature by trying to use it, loading tiny
+//! programs and creating small maps through the `bpf()` syscall.
+//!
+//! Probing requires the same privileges needed to load programs. When the required privileges
+//! are missing, every feature is reported as unsupported.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use aya::features::{self, MapType, ProgramType};
+//!
+//! if features::is_program_supported(ProgramType::Xdp) {
+//!     println!("XDP is supported");
+//! }
+//! if !features::is_map_supported(MapType::RingBuf) {
+//!     println!("ring buffers are not supported, falling back to perf buffers");
+//! }
+//! if features::features().bpf_link() {
+//!     println!("bpf_link is supported");
+//! }
+//! ```
+#[cfg(test)]
+use std::cell::RefCell;
+use std::{collections::HashMap, path::Path};
+
+use parking_lot::Mutex;
+
+use crate::{
+    generated::{bpf_attach_type, bpf_map_type, bpf_prog_type},
+    obj::btf::BtfFeatures,
+    sys,
+};
+
+macro_rules! kernel_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident => $kernel:ident {
+            $($(#[$vmeta:meta])* $variant:ident => $kvariant:ident),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+        pub enum $name {
+            $($(#[$vmeta])* $variant),+
+        }
+
+        impl $name {
+            /// Returns all the variants.
+            pub fn all() -> &'static [$name] {
+                &[$($name::$variant),+]
+            }
+        }
+
+        impl From<$name> for $kernel {
+            fn from(value: $name) -> $kernel {
+                match value {
+                    $($name::$variant => $kernel::$kvariant),+
+                }
+            }
+        }
+    };
+}
+
+kernel_enum! {
+    /// The type of an eBPF program.
+    pub enum ProgramType => bpf_prog_type {
+        /// A socket filter program.
+        SocketFilter => BPF_PROG_TYPE_SOCKET_FILTER,
+        /// A kprobe or uprobe program.
+        KProbe => BPF_PROG_TYPE_KPROBE,
+        /// A traffic control classifier program.
+        SchedClassifier => BPF_PROG_TYPE_SCHED_CLS,
+        /// A traffic control action program.
+        SchedAction => BPF_PROG_TYPE_SCHED_ACT,
+        /// A tracepoint program.
+        TracePoint => BPF_PROG_TYPE_TRACEPOINT,
+        /// An XDP program.
+        Xdp => BPF_PROG_TYPE_XDP,
+        /// A perf event program.
+        PerfEvent => BPF_PROG_TYPE_PERF_EVENT,
+        /// A cgroup skb program.
+        CgroupSkb => BPF_PROG_TYPE_CGROUP_SKB,
+        /// A cgroup socket program.
+        CgroupSock => BPF_PROG_TYPE_CGROUP_SOCK,
+        /// A lightweight tunnel input program.
+        LwtInput => BPF_PROG_TYPE_LWT_IN,
+        /// A lightweight tunnel output program.
+        LwtOutput => BPF_PROG_TYPE_LWT_OUT,
+        /// A lightweight tunnel xmit program.
+        LwtXmit => BPF_PROG_TYPE_LWT_XMIT,
+        /// A socket operations program.
+        SockOps => BPF_PROG_TYPE_SOCK_OPS,
+        /// A socket buffer program.
+        SkSkb => BPF_PROG_TYPE_SK_SKB,
+        /// A cgroup device program.
+        CgroupDevice => BPF_PROG_TYPE_CGROUP_DEVICE,
+        /// A socket message program.
+        SkMsg => BPF_PROG_TYPE_SK_MSG,
+        /// A raw tracepoint program.
+        RawTracePoint => BPF_PROG_TYPE_RAW_TRACEPOINT,
+        /// A cgroup socket address program.
+        CgroupSockAddr => BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+        /// A lightweight tunnel seg6local program.
+        LwtSeg6Local => BPF_PROG_TYPE_LWT_SEG6LOCAL,
+        /// An infrared remote control program.
+        LircMode2 => BPF_PROG_TYPE_LIRC_MODE2,
+        /// A socket reuseport program.
+        SkReuseport => BPF_PROG_TYPE_SK_REUSEPORT,
+        /// A flow dissector program.
+        FlowDissector => BPF_PROG_TYPE_FLOW_DISSECTOR,
+        /// A cgroup sysctl program.
+        CgroupSysctl => BPF_PROG_TYPE_CGROUP_SYSCTL,
+        /// A writable raw tracepoint program.
+        RawTracePointWritable => BPF_PROG_TYPE_RAW_TRACEPOINT_WRITABLE,
+        /// A cgroup socket option program.
+        CgroupSockopt => BPF_PROG_TYPE_CGROUP_SOCKOPT,
+        /// A BTF enabled tracing program (fentry, fexit, tp_btf, iterators).
+        Tracing => BPF_PROG_TYPE_TRACING,
+        /// A struct_ops program.
+        StructOps => BPF_PROG_TYPE_STRUCT_OPS,
+        /// An extension program.
+        Extension => BPF_PROG_TYPE_EXT,
+        /// A Linux Security Module program.
+        Lsm => BPF_PROG_TYPE_LSM,
+        /// A socket lookup program.
+        SkLookup => BPF_PROG_TYPE_SK_LOOKUP,
+        /// A syscall program.
+        Syscall => BPF_PROG_TYPE_SYSCALL,
+    }
+}
+
+kernel_enum! {
+    /// The type of an eBPF map.
+    pub enum MapType => bpf_map_type {
+        /// A hash map.
+        Hash => BPF_MAP_TYPE_HASH,
+        /// An array.
+        Array => BPF_MAP_TYPE_ARRAY,
+        /// An array of program file descriptors.
+        ProgramArray => BPF_MAP_TYPE_PROG_ARRAY,
+        /// An array of perf event file descriptors.
+        PerfEventArray => BPF_MAP_TYPE_PERF_EVENT_ARRAY,
+        /// A per-CPU hash map.
+        PerCpuHash => BPF_MAP_TYPE_PERCPU_HASH,
+        /// A per-CPU array.
+        PerCpuArray => BPF_MAP_TYPE_PERCPU_ARRAY,
+        /// A stack trace map.
+        StackTrace => BPF_MAP_TYPE_STACK_TRACE,
+        /// An array of cgroup file descriptors.
+        CgroupArray => BPF_MAP_TYPE_CGROUP_ARRAY,
+        /// A LRU hash map.
+        LruHash => BPF_MAP_TYPE_LRU_HASH,
+        /// A per-CPU LRU hash map.
+        LruPerCpuHash => BPF_MAP_TYPE_LRU_PERCPU_HASH,
+        /// A longest prefix match trie.
+        LpmTrie => BPF_MAP_TYPE_LPM_TRIE,
+        /// An array of maps.
+        ArrayOfMaps => BPF_MAP_TYPE_ARRAY_OF_MAPS,
+        /// A hash map of maps.
+        HashOfMaps => BPF_MAP_TYPE_HASH_OF_MAPS,
+        /// A network device map.
+        DevMap => BPF_MAP_TYPE_DEVMAP,
+        /// A socket map.
+        SockMap => BPF_MAP_TYPE_SOCKMAP,
+        /// A CPU map.
+        CpuMap => BPF_MAP_TYPE_CPUMAP,
+        /// An AF_XDP socket map.
+        XskMap => BPF_MAP_TYPE_XSKMAP,
+        /// A socket hash map.
+        SockHash => BPF_MAP_TYPE_SOCKHASH,
+        /// A cgroup storage map.
+        CgroupStorage => BPF_MAP_TYPE_CGROUP_STORAGE,
+        /// A reuseport socket array.
+        ReusePortSockArray => BPF_MAP_TYPE_REUSEPORT_SOCKARRAY,
+        /// A per-CPU cgroup storage map.
+        PerCpuCgroupStorage => BPF_MAP_TYPE_PERCPU_CGROUP_STORAGE,
+        /// A FIFO queue.
+        Queue => BPF_MAP_TYPE_QUEUE,
+        /// A LIFO stack.
+        Stack => BPF_MAP_TYPE_STACK,
+        /// A socket local storage map.
+        SkStorage => BPF_MAP_TYPE_SK_STORAGE,
+        /// A network device hash map.
+        DevMapHash => BPF_MAP_TYPE_DEVMAP_HASH,
+        /// A struct_ops map.
+        StructOps => BPF_MAP_TYPE_STRUCT_OPS,
+        /// A ring buffer.
+        RingBuf => BPF_MAP_TYPE_RINGBUF,
+        /// An inode local storage map.
+        InodeStorage => BPF_MAP_TYPE_INODE_STORAGE,
+        /// A task local storage map.
+        TaskStorage => BPF_MAP_TYPE_TASK_STORAGE,
+        /// A bloom filter.
+        BloomFilter => BPF_MAP_TYPE_BLOOM_FILTER,
+    }
+}
+
+kernel_enum! {
+    /// The attach type of an eBPF program.
+    pub enum AttachType => bpf_attach_type {
+        /// Cgroup ingress traffic.
+        CgroupInetIngress => BPF_CGROUP_INET_INGRESS,
+        /// Cgroup egress traffic.
+        CgroupInetEgress => BPF_CGROUP_INET_EGRESS,
+        /// Cgroup socket creation.
+        CgroupInetSockCreate => BPF_CGROUP_INET_SOCK_CREATE,
+        /// Cgroup socket operations.
+        CgroupSockOps => BPF_CGROUP_SOCK_OPS,
+        /// Socket buffer stream parser.
+        SkSkbStreamParser => BPF_SK_SKB_STREAM_PARSER,
+        /// Socket buffer stream verdict.
+        SkSkbStreamVerdict => BPF_SK_SKB_STREAM_VERDICT,
+        /// Cgroup device access.
+        CgroupDevice => BPF_CGROUP_DEVICE,
+        /// Socket message verdict.
+        SkMsgVerdict => BPF_SK_MSG_VERDICT,
+        /// Cgroup IPv4 bind.
+        CgroupInet4Bind => BPF_CGROUP_INET4_BIND,
+        /// Cgroup IPv6 bind.
+        CgroupInet6Bind => BPF_CGROUP_INET6_BIND,
+        /// Cgroup IPv4 connect.
+        CgroupInet4Connect => BPF_CGROUP_INET4_CONNECT,
+        /// Cgroup IPv6 connect.
+        CgroupInet6Connect => BPF_CGROUP_INET6_CONNECT,
+        /// Cgroup IPv4 post bind.
+        CgroupInet4PostBind => BPF_CGROUP_INET4_POST_BIND,
+        /// Cgroup IPv6 post bind.
+        CgroupInet6PostBind => BPF_CGROUP_INET6_POST_BIND,
+        /// Cgroup UDPv4 sendmsg.
+        CgroupUdp4Sendmsg => BPF_CGROUP_UDP4_SENDMSG,
+        /// Cgroup UDPv6 sendmsg.
+        CgroupUdp6Sendmsg => BPF_CGROUP_UDP6_SENDMSG,
+        /// Infrared remote control.
+        LircMode2 => BPF_LIRC_MODE2,
+        /// Flow dissector.
+        FlowDissector => BPF_FLOW_DISSECTOR,
+        /// Cgroup sysctl.
+        CgroupSysctl => BPF_CGROUP_SYSCTL,
+        /// Cgroup UDPv4 recvmsg.
+        CgroupUdp4Recvmsg => BPF_CGROUP_UDP4_RECVMSG,
+        /// Cgroup UDPv6 recvmsg.
+        CgroupUdp6Recvmsg => BPF_CGROUP_UDP6_RECVMSG,
+        /// Cgroup getsockopt.
+        CgroupGetsockopt => BPF_CGROUP_GETSOCKOPT,
+        /// Cgroup setsockopt.
+        CgroupSetsockopt => BPF_CGROUP_SETSOCKOPT,
+        /// BTF enabled raw tracepoint.
+        TraceRawTp => BPF_TRACE_RAW_TP,
+        /// Function entry.
+        TraceFentry => BPF_TRACE_FENTRY,
+        /// Function exit.
+        TraceFexit => BPF_TRACE_FEXIT,
+        /// Function return override.
+        ModifyReturn => BPF_MODIFY_RETURN,
+        /// LSM hook.
+        LsmMac => BPF_LSM_MAC,
+        /// Iterator.
+        TraceIter => BPF_TRACE_ITER,
+        /// Cgroup IPv4 getpeername.
+        CgroupInet4Getpeername => BPF_CGROUP_INET4_GETPEERNAME,
+        /// Cgroup IPv6 getpeername.
+        CgroupInet6Getpeername => BPF_CGROUP_INET6_GETPEERNAME,
+        /// Cgroup IPv4 getsockname.
+        CgroupInet4Getsockname => BPF_CGROUP_INET4_GETSOCKNAME,
+        /// Cgroup IPv6 getsockname.
+        CgroupInet6Getsockname => BPF_CGROUP_INET6_GETSOCKNAME,
+        /// XDP program in a device map.
+        XdpDevMap => BPF_XDP_DEVMAP,
+        /// Cgroup socket release.
+        CgroupInetSockRelease => BPF_CGROUP_INET_SOCK_RELEASE,
+        /// XDP program in a CPU map.
+        XdpCpuMap => BPF_XDP_CPUMAP,
+        /// Socket lookup.
+        SkLookup => BPF_SK_LOOKUP,
+        /// XDP.
+        Xdp => BPF_XDP,
+        /// Socket buffer verdict.
+        SkSkbVerdict => BPF_SK_SKB_VERDICT,
+        /// Socket reuseport select.
+        SkReuseportSelect => BPF_SK_REUSEPORT_SELECT,
+        /// Socket reuseport select or migrate.
+        SkReuseportSelectOrMigrate => BPF_SK_REUSEPORT_SELECT_OR_MIGRATE,
+        /// Perf event.
+        PerfEvent => BPF_PERF_EVENT,
+    }
+}
+
+/// The features supported by the running kernel.
+///
+/// Use [`features`] to get the cached result of probing the running kernel.
+#[derive(Debug, Clone)]
+pub struct Features {
+    bpf_name: bool,
+    bpf_link: bool,
+    bpf_link_xdp: bool,
+    bpf_perf_link: bool,
+    mmapable_array: bool,
+    kprobe_pmu: bool,
+    uprobe_pmu: bool,
+    pub(crate) btf: Option<BtfFeatures>,
+}
+
+impl Features {
+    fn probe() -> Features {
+        let btf = if sys::is_btf_supported() {
+            Some(BtfFeatures {
+                btf_func: sys::is_btf_func_supported(),
+                btf_func_global: sys::is_btf_func_global_supported(),
+                btf_datasec: sys::is_btf_datasec_supported(),
+                btf_float: sys::is_btf_float_supported(),
+                btf_decl_tag: sys::is_btf_decl_tag_supported(),
+                btf_type_tag: sys::is_btf_type_tag_supported(),
+            })
+        } else {
+            None
+        };
+
+        Features {
+            bpf_name: sys::is_prog_name_supported(),
+            bpf_link: sys::is_bpf_link_supported(
+                bpf_prog_type::BPF_PROG_TYPE_CGROUP_SKB,
+                bpf_attach_type::BPF_CGROUP_INET_INGRESS,
+            ),
+            bpf_link_xdp: sys::is_bpf_link_supported(
+                bpf_prog_type::BPF_PROG_TYPE_XDP,
+                bpf_attach_type::BPF_XDP,
+            ),
+            bpf_perf_link: sys::is_bpf_link_supported(
+                bpf_prog_type::BPF_PROG_TYPE_TRACEPOINT,
+                bpf_attach_type::BPF_PERF_EVENT,
+            ),
+            mmapable_array: sys::is_mmapable_array_supported(),
+            kprobe_pmu: Path::new("/sys/bus/event_source/devices/kprobe/type").exists(),
+            uprobe_pmu: Path::new("/sys/bus/event_source/devices/uprobe/type").exists(),
+            btf,
+        }
+    }
+
+    /// Returns whether programs and maps can be given a name.
+    pub fn bpf_name(&self) -> bool {
+        self.bpf_name
+    }
+
+    /// Returns whether cgroup programs can be attached using `bpf_link`.
+    pub fn bpf_link(&self) -> bool {
+        self.bpf_link
+    }
+
+    /// Returns whether XDP programs can be attached using `bpf_link`.
+    pub fn bpf_link_xdp(&self) -> bool {
+        self.bpf_link_xdp
+    }
+
+    /// Returns whether perf event based programs (kprobes, uprobes, tracepoints) can be attached
+    /// using `bpf_link`.
+    pub fn bpf_perf_link(&self) -> bool {
+        self.bpf_perf_link
+    }
+
+    /// Returns whether array maps can be created with `BPF_F_MMAPABLE` and mapped into memory.
+    pub fn mmapable_array(&self) -> bool {
+        self.mmapable_array
+    }
+
+    /// Returns whether kprobes can be created with `perf_event_open` using the kprobe PMU.
+    ///
+    /// When not supported, kprobes are created through debugfs.
+    pub fn kprobe_pmu(&self) -> bool {
+        self.kprobe_pmu
+    }
+
+    /// Returns whether uprobes can be created with `perf_event_open` using the uprobe PMU.
+    ///
+    /// When not supported, uprobes are created through debugfs.
+    pub fn uprobe_pmu(&self) -> bool {
+        self.uprobe_pmu
+    }
+
+    /// Returns whether BTF can be loaded.
+    pub fn btf(&self) -> bool {
+        self.btf.is_some()
+    }
+
+    /// Returns whether BTF supports the `BTF_KIND_FUNC` and `BTF_KIND_FUNC_PROTO` kinds.
+    pub fn btf_func(&self) -> bool {
+        self.btf.as_ref().map(|f| f.btf_func).unwrap_or(false)
+    }
+
+    /// Returns whether BTF supports global functions.
+    pub fn btf_func_global(&self) -> bool {
+        self.btf
+            .as_ref()
+            .map(|f| f.btf_func_global)
+            .unwrap_or(false)
+    }
+
+    /// Returns whether BTF supports the `BTF_KIND_VAR` and `BTF_KIND_DATASEC` kinds.
+    pub fn btf_datasec(&self) -> bool {
+        self.btf.as_ref().map(|f| f.btf_datasec).unwrap_or(false)
+    }
+
+    /// Returns whether BTF supports the `BTF_KIND_FLOAT` kind.
+    pub fn btf_float(&self) -> bool {
+        self.btf.as_ref().map(|f| f.btf_float).unwrap_or(false)
+    }
+
+    /// Returns whether BTF supports the `BTF_KIND_DECL_TAG` kind.
+    pub fn btf_decl_tag(&self) -> bool {
+        self.btf.as_ref().map(|f| f.btf_decl_tag).unwrap_or(false)
+    }
+
+    /// Returns whether BTF supports the `BTF_KIND_TYPE_TAG` kind.
+    pub fn btf_type_tag(&self) -> bool {
+        self.btf.as_ref().map(|f| f.btf_type_tag).unwrap_or(false)
+    }
+}
+
+lazy_static! {
+    static ref FEATURES: Features = Features::probe();
+    static ref PROGRAM_TYPES: Mutex<HashMap<ProgramType, bool>> = Mutex::new(HashMap::new());
+    static ref MAP_TYPES: Mutex<HashMap<MapType, bool>> = Mutex::new(HashMap::new());
+    static ref ATTACH_TYPES: Mutex<HashMap<(ProgramType, AttachType), bool>> =
+        Mutex::new(HashMap::new());
+    static ref HELPERS: Mutex<HashMap<(ProgramType, u32), bool>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the features supported by the running kernel.
+///
+/// The kernel is probed the first time this function is called, and the result is cached.
+pub fn features() -> &'static Features {
+    #[cfg(not(test))]
+    return &FEATURES;
+
+    // the bpf() syscall is mocked by each test thread, so the features are probed per thread
+    // against the mocked syscalls
+    #[cfg(test)]
+    TEST_FEATURES.with(|features| {
+        *features
+            .borrow_mut()
+            .get_or_insert_with(|| Box::leak(Box::new(Features::probe())))
+    })
+}
+
+#[cfg(test)]
+thread_local! {
+    static TEST_FEATURES: RefCell<Option<&'static Features>> = const { RefCell::new(None) };
+}
+
+/// Returns whether programs of type `program_type` can be loaded.
+///
+/// The result is cached.
+pub fn is_program_supported(program_type: ProgramType) -> bool {
+    *PROGRAM_TYPES
+        .lock()
+        .entry(program_type)
+        .or_insert_with(|| sys::is_prog_type_supported(program_type.into()))
+}
+
+/// Returns whether maps of type `map_type` can be created.
+///
+/// The result is cached.
+pub fn is_map_supported(map_type: MapType) -> bool {
+    *MAP_TYPES
+        .lock()
+        .entry(map_type)
+        .or_insert_with(|| sys::is_map_type_supported(map_type.into()))
+}
+
+/// Returns whether programs of type `program_type` can be loaded with the expected attach type
+/// `attach_type`.
+///
+/// The result is cached.
+pub fn is_attach_type_supported(program_type: ProgramType, attach_type: AttachType) -> bool {
+    *ATTACH_TYPES
+        .lock()
+        .entry((program_type, attach_type))
+        .or_insert_with(|| sys::is_attach_type_supported(program_type.into(), attach_type.into()))
+}
+
+/// Returns whether programs of type `program_type` can call the helper with id `helper_id`.
+///
+/// Helper ids are the `BPF_FUNC_*` values defined in `include/uapi/linux/bpf.h`. The result is
+/// cached.
+pub fn is_helper_supported(program_type: ProgramType, helper_id: u32) -> bool {
+    *HELPERS
+        .lock()
+        .entry((program_type, helper_id))
+        .or_insert_with(|| sys::is_helper_supported(program_type.into(), helper_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        os::unix::io::{IntoRawFd, RawFd},
+    };
+
+    use libc::{EBADF, EINVAL, ENODEV};
+
+    use super::*;
+    use crate::{
+        generated::bpf_cmd,
+        sys::{fake_fd, override_syscall, SysResult, Syscall},
+    };
+
+    fn link_syscall(call: Syscall, link_error: i32) -> SysResult {
+        match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_LOAD,
+                ..
+            } => Ok(fake_fd().into_raw_fd() as i64),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_LINK_CREATE,
+                ..
+            } => Err((-1, io::Error::from_raw_os_error(link_error))),
+            _ => Err((-1, io::Error::from_raw_os_error(libc::EFAULT))),
+        }
+    }
+
+    // BPF_LINK_CREATE on 5.7 and 5.8, which check the program fd before the attach type and
+    // don't support XDP links
+    fn link_syscall_5_8(call: Syscall) -> SysResult {
+        match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_LINK_CREATE,
+                attr,
+            } => {
+                let link_create = unsafe { attr.link_create };
+                let error = if link_create.prog_fd as RawFd == -1 {
+                    EBADF
+                } else if link_create.attach_type == bpf_attach_type::BPF_XDP as u32 {
+                    EINVAL
+                } else {
+                    EBADF
+                };
+                Err((-1, io::Error::from_raw_os_error(error)))
+            }
+            call => link_syscall(call, EINVAL),
+        }
+    }
+
+    fn is_xdp_link_supported() -> bool {
+        sys::is_bpf_link_supported(bpf_prog_type::BPF_PROG_TYPE_XDP, bpf_attach_type::BPF_XDP)
+    }
+
+    #[test]
+    fn test_bpf_link_probe() {
+        // the link is only refused because its target is invalid
+        override_syscall(|call| link_syscall(call, EBADF));
+        assert!(is_xdp_link_supported());
+        override_syscall(|call| link_syscall(call, ENODEV));
+        assert!(is_xdp_link_supported());
+
+        // the attach type isn't supported
+        override_syscall(|call| link_syscall(call, EINVAL));
+        assert!(!is_xdp_link_supported());
+        assert!(!Features::probe().bpf_link_xdp());
+
+        // the program can't be loaded
+        override_syscall(|_| Err((-1, io::Error::from_raw_os_error(libc::EPERM))));
+        assert!(!is_xdp_link_supported());
+    }
+
+    #[test]
+    fn test_bpf_link_probe_5_8() {
+        override_syscall(link_syscall_5_8);
+        let features = Features::probe();
+        assert!(features.bpf_link());
+        assert!(!features.bpf_link_xdp());
+    }
+
+    #[test]
+    fn test_kernel_enum_conversions() {
+        for (i, ty) in ProgramType::all().iter().enumerate() {
+            assert_eq!(bpf_prog_type::from(*ty) as usize, i + 1);
+        }
+        for (i, ty) in MapType::all().iter().enumerate() {
+            assert_eq!(bpf_map_type::from(*ty) as usize, i + 1);
+        }
+        for (i, ty) in AttachType::all().iter().enumerate() {
+            assert_eq!(bpf_attach_type::from(*ty) as usize, i);
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Access to the global variables of a loaded object.
+//!
+//! Global variables defined in the `.data`, `.bss` and `.rodata` sections of an object are stored
+//! in maps created by the loader. When the kernel supports it, the maps are created with
+//! `BPF_F_MMAPABLE` and mapped into memory, so reading and writing a global is as cheap as
+//! accessing a pointer. On older kernels globals are accessed with map lookups and updates.
+use std::{
+    marker::PhantomData,
+    mem,
+    os::unix::io::{AsRawFd, OwnedFd},
+    ptr::{self, NonNull},
+};
+
+use libc::{c_void, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
+use thiserror::Error;
+
+use crate::{
+    maps::MapError,
+    sys::{bpf_map_lookup_elem_ptr, bpf_map_update_elem_ptr},
+    util::page_size,
+    Pod,
+};
+
+/// Errors occuring from accessing global variables.
+#[derive(Error, Debug)]
+pub enum GlobalError {
+    /// The global variable does not exist, or the object has no BTF describing it.
+    #[error("global `{name}` not found")]
+    NotFound {
+        /// The name of the global.
+        name: String,
+    },
+
+    /// The size of the type used to access the global doesn't match the size of the global.
+    #[error("invalid size {size} for global `{name}`, expected {expected}")]
+    InvalidSize {
+        /// The name of the global.
+        name: String,
+        /// The size of the type used to access the global.
+        size: usize,
+        /// The size of the global.
+        expected: usize,
+    },
+
+    /// The global is defined in `.rodata` and can't be changed after the object is loaded.
+    #[error("global `{name}` is read-only")]
+    ReadOnly {
+        /// The name of the global.
+        name: String,
+    },
+
+    /// Reading or writing the map that holds the global failed.
+    #[error(transparent)]
+    MapError(#[from] MapError),
+}
+
+/// The location of a global variable within its data map.
+#[derive(Debug)]
+pub(crate) struct GlobalVar {
+    pub(crate) section: String,
+    pub(crate) offset: usize,
+    pub(crate) size: usize,
+}
+
+/// A `.data`, `.bss` or `.rodata` map, mapped into memory when possible.
+#[derive(Debug)]
+pub(crate) struct DataMap {
+    // a copy of the fd of the map, which can be taken out of the object with Bpf::take_map
+    fd: OwnedFd,
+    value_size: usize,
+    writable: bool,
+    mmap: Option<(NonNull<u8>, usize)>,
+}
+
+// Safety: the mapping is owned by the DataMap and unmapped when it's dropped, moving it to
+// another thread is fine. It's not Sync: accesses from multiple threads aren't synchronized.
+unsafe impl Send for DataMap {}
+
+impl DataMap {
+    pub(crate) fn new(fd: OwnedFd, value_size: usize, writable: bool) -> DataMap {
+        let page_size = page_size();
+        let len = (value_size + page_size - 1) & !(page_size - 1);
+        let prot = if writable {
+            PROT_READ | PROT_WRITE
+        } else {
+            PROT_READ
+        };
+        // fails if the map wasn't created with BPF_F_MMAPABLE
+        let ptr = unsafe { mmap(ptr::null_mut(), len, prot, MAP_SHARED, fd.as_raw_fd(), 0) };
+        let mmap = if ptr == MAP_FAILED {
+            None
+        } else {
+            NonNull::new(ptr as *mut u8).map(|ptr| (ptr, len))
+        };
+
+        DataMap {
+            fd,
+            value_size,
+            writable,
+            mmap,
+        }
+    }
+
+    fn read(&self, offset: usize, out: &mut [u8]) -> Result<(), MapError> {
+        match self.mmap {
+            // the memory is shared with the programs, which can change it at any time, so the
+            // compiler must not elide or reorder the accesses
+            Some((ptr, _)) => {
+                for (i, byte) in out.iter_mut().enumerate() {
+                    *byte = unsafe { ptr::read_volatile(ptr.as_ptr().add(offset + i)) };
+                }
+            }
+            None => {
+                let mut value = vec![0u8; self.value_size];
+                bpf_map_lookup_elem_ptr(self.fd.as_raw_fd(), &0u32, value.as_mut_ptr(), 0)
+                    .map_err(|(code, io_error)| MapError::SyscallError {
+                        call: "bpf_map_lookup_elem".to_owned(),
+                        code,
+                        io_error,
+                    })?;
+                out.copy_from_slice(&value[offset..offset + out.len()]);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, offset: usize, data: &[u8]) -> Result<(), MapError> {
+        match self.mmap {
+            Some((ptr, _)) => {
+                for (i, byte) in data.iter().enumerate() {
+                    unsafe { ptr::write_volatile(ptr.as_ptr().add(offset + i), *byte) };
+                }
+            }
+            None => {
+                // the map has a single value holding all the globals of the section. It isn't
+                // updated atomically, see Global::set
+                let mut value = vec![0u8; self.value_size];
+                self.read(0, &mut value)?;
+                value[offset..offset + data.len()].copy_from_slice(data);
+                bpf_map_update_elem_ptr(self.fd.as_raw_fd(), &0u32, value.as_mut_ptr(), 0)
+                    .map_err(|(code, io_error)| MapError::SyscallError {
+                        call: "bpf_map_update_elem".to_owned(),
+                        code,
+                        io_error,
+                    })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for DataMap {
+    fn drop(&mut self) {
+        if let Some((ptr, len)) = self.mmap {
+            unsafe { munmap(ptr.as_ptr() as *mut c_void, len) };
+        }
+    }
+}
+
+/// A global variable of a loaded object.
+///
+/// Returned by [`Bpf::global`](crate::Bpf::global).
+#[derive(Debug)]
+pub struct Global<'a, T> {
+    name: &'a str,
+    map: &'a DataMap,
+    offset: usize,
+    _t: PhantomData<T>,
+}
+
+impl<'a, T: Pod> Global<'a, T> {
+    pub(crate) fn new(
+        name: &'a str,
+        var: &GlobalVar,
+        map: &'a DataMap,
+    ) -> Result<Global<'a, T>, GlobalError> {
+        let size = mem::size_of::<T>();
+        if size != var.size {
+            return Err(GlobalError::InvalidSize {
+                name: name.to_owned(),
+                size,
+                expected: var.size,
+            });
+        }
+
+        Ok(Global {
+            name,
+            map,
+            offset: var.offset,
+            _t: PhantomData,
+        })
+    }
+
+    /// Returns the current value of the global.
+    pub fn get(&self) -> Result<T, GlobalError> {
+        let mut value = mem::MaybeUninit::<T>::zeroed();
+        // Safety: T is Pod
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, mem::size_of::<T>())
+        };
+        self.map.read(self.offset, bytes)?;
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Sets the value of the global.
+    ///
+    /// When the section of the global is mapped into memory, only the bytes of the global are
+    /// written. On kernels that don't support mmapable arrays (before 5.5), the section is
+    /// stored as a single map value: `set` looks the value up, changes the global in the copy
+    /// and writes the whole value back. Any change a program makes to another global of the same
+    /// section between the lookup and the update is lost, so on these kernels globals shouldn't
+    /// be set while programs that write to the section are running.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`GlobalError::ReadOnly`] if the global is defined in `.rodata`.
+    pub fn set(&self, value: T) -> Result<(), GlobalError> {
+        if !self.map.writable {
+            return Err(GlobalError::ReadOnly {
+                name: self.name.to_owned(),
+            });
+        }
+        // Safety: T is Pod
+        let bytes = unsafe {
+            std::slice::from_raw_parts(&value as *const T as *const u8, mem::size_of::<T>())
+        };
+        self.map.write(self.offset, bytes)?;
+        Ok(())
+    }
+}
+
+#[cfg_attr(test, allow(unused_variables))]
+unsafe fn mmap(
+    addr: *mut c_void,
+    len: usize,
+    prot: libc::c_int,
+    flags: libc::c_int,
+    fd: i32,
+    offset: libc::off_t,
+) -> *mut c_void {
+    #[cfg(not(test))]
+    return libc::mmap(addr, len, prot, flags, fd, offset);
+
+    #[cfg(test)]
+    use crate::sys::TEST_MMAP_RET;
+
+    #[cfg(test)]
+    TEST_MMAP_RET.with(|ret| *ret.borrow())
+}
+
+#[cfg_attr(test, allow(unused_variables))]
+unsafe fn munmap(addr: *mut c_void, len: usize) {
+    #[cfg(not(test))]
+    libc::munmap(addr, len);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        generated::bpf_cmd,
+        sys::{fake_fd, override_syscall, Syscall, TEST_MMAP_RET},
+    };
+
+    use super::*;
+
+    fn var() -> GlobalVar {
+        GlobalVar {
+            section: ".data".to_owned(),
+            offset: 4,
+            size: 4,
+        }
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        let map = DataMap::new(fake_fd(), 8, true);
+        assert!(matches!(
+            Global::<u64>::new("foo", &var(), &map),
+            Err(GlobalError::InvalidSize {
+                size: 8,
+                expected: 4,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_mmap() {
+        let mut buf = [0u32; 2];
+        TEST_MMAP_RET.with(|ret| *ret.borrow_mut() = buf.as_mut_ptr() as *mut c_void);
+        let map = DataMap::new(fake_fd(), 8, true);
+        let global = Global::<u32>::new("foo", &var(), &map).unwrap();
+
+        global.set(42).unwrap();
+        assert_eq!(global.get().unwrap(), 42);
+        drop(map);
+        assert_eq!(buf, [0, 42]);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let map = DataMap::new(fake_fd(), 8, false);
+        let global = Global::<u32>::new("foo", &var(), &map).unwrap();
+        assert!(matches!(global.set(1), Err(GlobalError::ReadOnly { .. })));
+    }
+
+    #[test]
+    fn test_syscall_fallback() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                attr,
+            } => {
+                let value = unsafe { attr.__bindgen_anon_2.__bindgen_anon_1.value } as *mut u32;
+                unsafe { ptr::write_unaligned(value as *mut [u32; 2], [7, 42]) };
+                Ok(0)
+            }
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                attr,
+            } => {
+                let value = unsafe { attr.__bindgen_anon_2.__bindgen_anon_1.value } as *const u32;
+                // the other global of the section is written back with the value looked up
+                assert_eq!(
+                    unsafe { ptr::read_unaligned(value as *const [u32; 2]) },
+                    [7, 24]
+                );
+                Ok(0)
+            }
+            _ => Err((-1, std::io::Error::from_raw_os_error(libc::EFAULT))),
+        });
+        let map = DataMap::new(fake_fd(), 8, true);
+        assert!(map.mmap.is_none());
+        let global = Global::<u32>::new("foo", &var(), &map).unwrap();
+
+        assert_eq!(global.get().unwrap(), 42);
+        global.set(24).unwrap();
+    }
+
+    #[test]
+    fn test_syscall_fallback_lookup_error() {
+        // the value isn't updated when it can't be looked up first
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                ..
+            } => Err((-1, std::io::Error::from_raw_os_error(libc::EPERM))),
+            _ => panic!("unexpected syscall"),
+        });
+        let map = DataMap::new(fake_fd(), 8, true);
+        let global = Global::<u32>::new("foo", &var(), &map).unwrap();
+
+        assert!(matches!(
+            global.set(24),
+            Err(GlobalError::MapError(MapError::SyscallError { call, .. }))
+                if call == "bpf_map_lookup_elem"
+        ));
+    }
+}
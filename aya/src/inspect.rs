@@ -0,0 +1,10 @@
+//! Inspection of eBPF object files.
+//!
+//! This module gives read-only access to the programs, maps and global variables defined in an
+//! object file, without loading it. It's mostly useful to tools like code generators. To load an
+//! object, use [`Bpf`](crate::Bpf) instead.
+
+pub use crate::obj::{
+    btf::{CoreRelocationKind, CoreRelocationTarget},
+    Global, Map, Object, ProgramSection,
+};
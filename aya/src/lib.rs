@@ -38,7 +38,12 @@ extern crate lazy_static;
 extern crate bitflags;
 
 mod bpf;
+pub mod btf;
+pub mod features;
 mod generated;
+mod global;
+pub mod inspect;
+pub mod linker;
 pub mod maps;
 mod obj;
 pub mod programs;
@@ -46,5 +51,9 @@ mod sys;
 pub mod util;
 
 pub use bpf::*;
-pub use obj::btf::{Btf, BtfError};
+pub use global::{Global, GlobalError};
+pub use obj::{
+    btf::{Btf, BtfError},
+    ExternError,
+};
 pub use object::Endianness;
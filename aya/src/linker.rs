@@ -0,0 +1,1358 @@
+//! Static linking of eBPF object files.
+//!
+//! Programs are often built from several compilation units, for example a library of helpers
+//! shared by multiple programs. [`Linker`] combines the object files produced by the compiler
+//! into a single object that can then be loaded with [`Bpf::load`](crate::Bpf::load) or
+//! [`BpfLoader`](crate::BpfLoader).
+//!
+//! Sections with the same name are concatenated, and global symbols declared as `extern` in
+//! one object are resolved to their definition in another object. Functions, maps defined in
+//! the `.maps` section and global variables can be shared this way. Legacy maps (defined in
+//! `maps/NAME` sections) can be defined in multiple objects as long as all their definitions
+//! are identical.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use aya::{linker::Linker, Bpf};
+//!
+//! let data = Linker::new()
+//!     .add_file("lib.o")?
+//!     .add_file("programs.o")?
+//!     .link()?;
+//! let bpf = Bpf::load(&data)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    convert::TryInto,
+    fs, io, mem,
+    path::{Path, PathBuf},
+};
+
+use object::{
+    elf,
+    read::{File as ElfFile, Object as ElfObject, ObjectSection, ObjectSymbol},
+    write::{
+        self, Object as ElfWriter, Relocation as WriteRelocation, SectionId, Symbol as WriteSymbol,
+        SymbolId, SymbolSection as WriteSymbolSection,
+    },
+    Architecture, BinaryFormat, Endianness, ObjectKind, RelocationEncoding, RelocationKind,
+    RelocationTarget, SectionFlags, SectionIndex, SectionKind, SymbolFlags, SymbolIndex,
+    SymbolKind, SymbolSection,
+};
+use thiserror::Error;
+
+use crate::{
+    generated::{
+        bpf_insn, btf_ext_header, btf_func_linkage, BTF_VAR_GLOBAL_ALLOCATED, BTF_VAR_GLOBAL_EXTERN,
+    },
+    obj::{
+        btf::{swap_btf_bytes, type_vlen, Btf, BtfError, BtfType},
+        KCONFIG_SECTION, KSYMS_SECTION,
+    },
+};
+
+const INS_SIZE: u64 = mem::size_of::<bpf_insn>() as u64;
+
+// relocation types used in data and debug sections
+const R_BPF_64_ABS64: u32 = 2;
+const R_BPF_64_ABS32: u32 = 3;
+const R_BPF_64_NODYLD32: u32 = 4;
+
+/// The error type returned by [`Linker`].
+#[derive(Debug, Error)]
+pub enum LinkerError {
+    /// Error reading an object file.
+    #[error("error reading {path}")]
+    FileError {
+        /// The path of the file.
+        path: PathBuf,
+        #[source]
+        /// The original [`io::Error`].
+        error: io::Error,
+    },
+
+    /// An input is not a valid ELF file.
+    #[error("error parsing ELF data of `{name}`")]
+    ElfError {
+        /// The name of the input.
+        name: String,
+        #[source]
+        /// The original error.
+        error: object::read::Error,
+    },
+
+    /// An input is not a relocatable eBPF object.
+    #[error("`{name}` is not a relocatable eBPF object file")]
+    NotBpfObject {
+        /// The name of the input.
+        name: String,
+    },
+
+    /// The inputs don't all have the same endianness.
+    #[error("the endianness of `{name}` doesn't match the endianness of the other objects")]
+    EndiannessMismatch {
+        /// The name of the input.
+        name: String,
+    },
+
+    /// A section can't be merged with the section with the same name of another object.
+    #[error("section `{section}` of `{name}` conflicts with another object")]
+    SectionConflict {
+        /// The name of the input.
+        name: String,
+        /// The name of the section.
+        section: String,
+    },
+
+    /// A global symbol is defined by more than one object.
+    #[error("symbol `{symbol}` of `{name}` is already defined by another object")]
+    DuplicateSymbol {
+        /// The name of the input.
+        name: String,
+        /// The name of the symbol.
+        symbol: String,
+    },
+
+    /// An extern variable isn't defined by any object.
+    #[error("unresolved extern `{symbol}`")]
+    UnresolvedSymbol {
+        /// The name of the symbol.
+        symbol: String,
+    },
+
+    /// An input contains a relocation that can't be linked.
+    #[error("unsupported relocation of type {kind:?} in `{name}`")]
+    UnsupportedRelocation {
+        /// The name of the input.
+        name: String,
+        /// The kind of relocation.
+        kind: RelocationKind,
+    },
+
+    /// The `.BTF.ext` section of an input is invalid.
+    #[error("invalid .BTF.ext section in `{name}`")]
+    InvalidBtfExt {
+        /// The name of the input.
+        name: String,
+    },
+
+    /// Error processing BTF.
+    #[error("BTF error")]
+    BtfError(#[from] BtfError),
+
+    /// Error writing the linked object.
+    #[error("error writing the linked object")]
+    WriteError(#[source] write::Error),
+}
+
+/// Links multiple eBPF object files into one.
+///
+/// See the [module level documentation](crate::linker) for more details.
+#[derive(Debug, Default)]
+pub struct Linker {
+    inputs: Vec<(String, Vec<u8>)>,
+}
+
+impl Linker {
+    /// Creates a new linker with no inputs.
+    pub fn new() -> Linker {
+        Linker::default()
+    }
+
+    /// Adds the object file at `path` to the objects to link.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Linker, LinkerError> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|error| LinkerError::FileError {
+            path: path.to_owned(),
+            error,
+        })?;
+        self.add_object(&path.to_string_lossy(), &data)
+    }
+
+    /// Adds an object file to the objects to link.
+    ///
+    /// `name` is only used to identify the object in errors.
+    pub fn add_object(&mut self, name: &str, data: &[u8]) -> Result<&mut Linker, LinkerError> {
+        let file = parse_input(name, data)?;
+        if let Some((first_name, first_data)) = self.inputs.first() {
+            if parse_input(first_name, first_data)?.endianness() != file.endianness() {
+                return Err(LinkerError::EndiannessMismatch {
+                    name: name.to_owned(),
+                });
+            }
+        }
+        self.inputs.push((name.to_owned(), data.to_vec()));
+        Ok(self)
+    }
+
+    /// Links the objects and returns the resulting ELF object file.
+    pub fn link(&self) -> Result<Vec<u8>, LinkerError> {
+        let files = self
+            .inputs
+            .iter()
+            .map(|(name, data)| Ok((name.as_str(), parse_input(name, data)?)))
+            .collect::<Result<Vec<_>, LinkerError>>()?;
+        let endianness = files
+            .first()
+            .map(|(_, file)| file.endianness())
+            .unwrap_or_default();
+
+        let mut state = LinkState {
+            out: ElfWriter::new(BinaryFormat::Elf, Architecture::Bpf, endianness),
+            endianness,
+            sections: HashMap::new(),
+            section_sizes: HashMap::new(),
+            input_sections: HashMap::new(),
+            symbols: HashMap::new(),
+            section_symbols: HashMap::new(),
+            globals: HashMap::new(),
+        };
+
+        for (file_index, (name, file)) in files.iter().enumerate() {
+            state.add_sections(file_index, name, file)?;
+        }
+        state.add_symbols(&files)?;
+        for (file_index, (name, file)) in files.iter().enumerate() {
+            state.add_relocations(file_index, name, file)?;
+        }
+        state.link_btf(&files)?;
+
+        state.out.write().map_err(LinkerError::WriteError)
+    }
+}
+
+fn parse_input<'a>(name: &str, data: &'a [u8]) -> Result<ElfFile<'a>, LinkerError> {
+    let file = ElfFile::parse(data).map_err(|error| LinkerError::ElfError {
+        name: name.to_owned(),
+        error,
+    })?;
+    if file.architecture() != Architecture::Bpf || file.kind() != ObjectKind::Relocatable {
+        return Err(LinkerError::NotBpfObject {
+            name: name.to_owned(),
+        });
+    }
+    Ok(file)
+}
+
+// how sections with the same name are combined
+#[derive(Debug, PartialEq)]
+enum SectionLinkage {
+    // appended to each other
+    Concat,
+    // all the definitions must be identical, only one is kept
+    Identical,
+    // must only be defined by one object
+    Unique,
+    // merged separately
+    Btf,
+    // not part of the output
+    Skip,
+}
+
+fn section_linkage(name: &str, kind: SectionKind) -> SectionLinkage {
+    match kind {
+        SectionKind::Metadata | SectionKind::Unknown | SectionKind::Note => {
+            return SectionLinkage::Skip
+        }
+        _ => {}
+    }
+    if name.is_empty() || name.starts_with(".debug_") || name.starts_with(".llvm") {
+        SectionLinkage::Skip
+    } else if name == ".BTF" || name == ".BTF.ext" {
+        SectionLinkage::Btf
+    } else if name.starts_with(".text")
+        || name.starts_with(".data")
+        || name.starts_with(".rodata")
+        || name.starts_with(".bss")
+        || name.starts_with(".maps")
+    {
+        SectionLinkage::Concat
+    } else if name == "license" || name == "version" || name == "maps" || name.starts_with("maps/")
+    {
+        SectionLinkage::Identical
+    } else {
+        SectionLinkage::Unique
+    }
+}
+
+struct GlobalSymbol {
+    // the file and symbol index of the definition
+    definition: Option<(usize, SymbolIndex)>,
+    weak_definition: bool,
+    // whether all the references to an undefined symbol are weak
+    weak_reference: bool,
+    id: Option<SymbolId>,
+}
+
+struct LinkState {
+    out: ElfWriter<'static>,
+    endianness: Endianness,
+    // output sections by name
+    sections: HashMap<String, (SectionId, SectionKind)>,
+    section_sizes: HashMap<SectionId, u64>,
+    // (file, section) => (output section, offset of the input section in the output section)
+    input_sections: HashMap<(usize, SectionIndex), (SectionId, u64)>,
+    // (file, symbol) => output symbol
+    symbols: HashMap<(usize, SymbolIndex), SymbolId>,
+    // (file, section symbol) => offset of the section in the output section
+    section_symbols: HashMap<(usize, SymbolIndex), u64>,
+    globals: HashMap<String, GlobalSymbol>,
+}
+
+impl LinkState {
+    fn add_sections(
+        &mut self,
+        file_index: usize,
+        name: &str,
+        file: &ElfFile,
+    ) -> Result<(), LinkerError> {
+        for section in file.sections() {
+            let section_name = section.name().unwrap_or_default();
+            let linkage = section_linkage(section_name, section.kind());
+            if let SectionLinkage::Skip | SectionLinkage::Btf = linkage {
+                continue;
+            }
+
+            let conflict = || LinkerError::SectionConflict {
+                name: name.to_owned(),
+                section: section_name.to_owned(),
+            };
+            let data = section.data().map_err(|error| LinkerError::ElfError {
+                name: name.to_owned(),
+                error,
+            })?;
+            let is_bss = section.kind() == SectionKind::UninitializedData;
+
+            let section_id = match self.sections.get(section_name) {
+                Some((section_id, kind)) => {
+                    if *kind != section.kind() {
+                        return Err(conflict());
+                    }
+                    match linkage {
+                        SectionLinkage::Concat => *section_id,
+                        SectionLinkage::Identical
+                            if self.out.section(*section_id).data() == data =>
+                        {
+                            self.input_sections
+                                .insert((file_index, section.index()), (*section_id, 0));
+                            continue;
+                        }
+                        _ => return Err(conflict()),
+                    }
+                }
+                None => {
+                    let section_id = self.out.add_section(
+                        Vec::new(),
+                        section_name.as_bytes().to_vec(),
+                        section.kind(),
+                    );
+                    if let SectionFlags::Elf { sh_flags } = section.flags() {
+                        self.out.section_mut(section_id).flags = SectionFlags::Elf { sh_flags };
+                    }
+                    self.sections
+                        .insert(section_name.to_owned(), (section_id, section.kind()));
+                    section_id
+                }
+            };
+
+            let align = section.align().max(1);
+            let offset = if is_bss {
+                self.out
+                    .append_section_bss(section_id, section.size(), align)
+            } else {
+                self.out.append_section_data(section_id, data, align)
+            };
+            self.section_sizes
+                .insert(section_id, offset + section.size());
+            self.input_sections
+                .insert((file_index, section.index()), (section_id, offset));
+        }
+
+        Ok(())
+    }
+
+    fn add_symbols(&mut self, files: &[(&str, ElfFile)]) -> Result<(), LinkerError> {
+        // resolve the global symbols first, so that all the objects use the same definition
+        for (file_index, (name, file)) in files.iter().enumerate() {
+            for symbol in file.symbols() {
+                if symbol.is_local() || symbol.kind() == SymbolKind::File {
+                    continue;
+                }
+                let symbol_name = match symbol.name() {
+                    Ok(symbol_name) if !symbol_name.is_empty() => symbol_name,
+                    _ => continue,
+                };
+                let global = self
+                    .globals
+                    .entry(symbol_name.to_owned())
+                    .or_insert(GlobalSymbol {
+                        definition: None,
+                        weak_definition: false,
+                        weak_reference: true,
+                        id: None,
+                    });
+
+                let section_index = match symbol.section() {
+                    SymbolSection::Section(index) => index,
+                    _ => {
+                        global.weak_reference &= symbol.is_weak();
+                        continue;
+                    }
+                };
+                let target = match self.input_sections.get(&(file_index, section_index)) {
+                    Some((section_id, offset)) => (*section_id, offset + symbol.address()),
+                    None => continue,
+                };
+
+                match global.definition {
+                    None => {}
+                    Some((def_file, def_index)) => {
+                        let def = files[def_file].1.symbol_by_index(def_index).unwrap();
+                        let def_target = match def.section() {
+                            SymbolSection::Section(index) => self
+                                .input_sections
+                                .get(&(def_file, index))
+                                .map(|(section_id, offset)| (*section_id, offset + def.address())),
+                            _ => None,
+                        };
+                        // the same symbol in a deduplicated section, eg a legacy map
+                        if def_target == Some(target) {
+                            continue;
+                        }
+                        match (global.weak_definition, symbol.is_weak()) {
+                            (false, false) => {
+                                return Err(LinkerError::DuplicateSymbol {
+                                    name: (*name).to_owned(),
+                                    symbol: symbol_name.to_owned(),
+                                })
+                            }
+                            // strong definitions win over weak ones, the first weak definition
+                            // wins over the others
+                            (true, false) => {}
+                            _ => continue,
+                        }
+                    }
+                }
+                global.definition = Some((file_index, symbol.index()));
+                global.weak_definition = symbol.is_weak();
+            }
+        }
+
+        for (file_index, (_, file)) in files.iter().enumerate() {
+            for symbol in file.symbols() {
+                let key = (file_index, symbol.index());
+                match symbol.kind() {
+                    SymbolKind::File => continue,
+                    SymbolKind::Section => {
+                        if let SymbolSection::Section(index) = symbol.section() {
+                            if let Some((section_id, offset)) =
+                                self.input_sections.get(&(file_index, index))
+                            {
+                                let id = self.out.section_symbol(*section_id);
+                                self.symbols.insert(key, id);
+                                self.section_symbols.insert(key, *offset);
+                            }
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+                let symbol_name = match symbol.name() {
+                    Ok(symbol_name) if !symbol_name.is_empty() => symbol_name,
+                    _ => continue,
+                };
+
+                if symbol.is_local() {
+                    if let Some(id) = self.add_symbol(file_index, &symbol) {
+                        self.symbols.insert(key, id);
+                    }
+                    continue;
+                }
+
+                let global = &self.globals[symbol_name];
+                let id = match global.id {
+                    Some(id) => id,
+                    None => {
+                        let id = match global.definition {
+                            Some((def_file, def_index)) => {
+                                let def = files[def_file].1.symbol_by_index(def_index).unwrap();
+                                self.add_symbol(def_file, &def).unwrap()
+                            }
+                            None => self.out.add_symbol(WriteSymbol {
+                                name: symbol_name.as_bytes().to_vec(),
+                                value: 0,
+                                size: 0,
+                                kind: symbol.kind(),
+                                scope: symbol.scope(),
+                                weak: global.weak_reference,
+                                section: WriteSymbolSection::Undefined,
+                                flags: SymbolFlags::None,
+                            }),
+                        };
+                        self.globals.get_mut(symbol_name).unwrap().id = Some(id);
+                        id
+                    }
+                };
+                self.symbols.insert(key, id);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_symbol(&mut self, file_index: usize, symbol: &object::read::Symbol) -> Option<SymbolId> {
+        let (section_id, offset) = match symbol.section() {
+            SymbolSection::Section(index) => *self.input_sections.get(&(file_index, index))?,
+            _ => return None,
+        };
+        Some(self.out.add_symbol(WriteSymbol {
+            name: symbol.name().ok()?.as_bytes().to_vec(),
+            value: offset + symbol.address(),
+            size: symbol.size(),
+            kind: symbol.kind(),
+            scope: symbol.scope(),
+            weak: symbol.is_weak(),
+            section: WriteSymbolSection::Section(section_id),
+            flags: SymbolFlags::None,
+        }))
+    }
+
+    fn add_relocations(
+        &mut self,
+        file_index: usize,
+        name: &str,
+        file: &ElfFile,
+    ) -> Result<(), LinkerError> {
+        for section in file.sections() {
+            let section_name = section.name().unwrap_or_default();
+            // the relocations of deduplicated sections have been added with the first copy
+            if section_linkage(section_name, section.kind()) != SectionLinkage::Concat
+                && section_linkage(section_name, section.kind()) != SectionLinkage::Unique
+            {
+                continue;
+            }
+            let (section_id, base) = match self.input_sections.get(&(file_index, section.index())) {
+                Some(s) => *s,
+                None => continue,
+            };
+
+            for (offset, relocation) in section.relocations() {
+                let symbol_index = match relocation.target() {
+                    RelocationTarget::Symbol(index) => index,
+                    _ => continue,
+                };
+                let symbol = match self.symbols.get(&(file_index, symbol_index)) {
+                    Some(symbol) => *symbol,
+                    // the target is in a section that isn't linked
+                    None => continue,
+                };
+                let r_type = match (relocation.kind(), relocation.size()) {
+                    (RelocationKind::Absolute, 64) => elf::R_BPF_64_64,
+                    (RelocationKind::Absolute, 32) => elf::R_BPF_64_32,
+                    (RelocationKind::Elf(r_type), _) => r_type,
+                    (kind, _) => {
+                        return Err(LinkerError::UnsupportedRelocation {
+                            name: name.to_owned(),
+                            kind,
+                        })
+                    }
+                };
+                let offset = base + offset;
+
+                // references to static data and functions are relative to the section symbol,
+                // with the offset encoded in the instruction. Now that the section has moved,
+                // the offset needs to be adjusted.
+                if let Some(section_offset) = self.section_symbols.get(&(file_index, symbol_index))
+                {
+                    let data = self.out.section_mut(section_id).data_mut();
+                    match r_type {
+                        elf::R_BPF_64_64 => {
+                            add_u32(data, offset + 4, *section_offset, self.endianness)
+                        }
+                        elf::R_BPF_64_32 => add_u32(
+                            data,
+                            offset + 4,
+                            *section_offset / INS_SIZE,
+                            self.endianness,
+                        ),
+                        R_BPF_64_ABS32 | R_BPF_64_NODYLD32 => {
+                            add_u32(data, offset, *section_offset, self.endianness)
+                        }
+                        R_BPF_64_ABS64 => add_u64(data, offset, *section_offset, self.endianness),
+                        _ => {}
+                    }
+                }
+
+                self.out
+                    .add_relocation(
+                        section_id,
+                        WriteRelocation {
+                            offset,
+                            size: relocation.size(),
+                            // pass the raw type through, so that the writer doesn't try to
+                            // rewrite the relocation
+                            kind: RelocationKind::Elf(r_type),
+                            encoding: RelocationEncoding::Generic,
+                            symbol,
+                            addend: 0,
+                        },
+                    )
+                    .map_err(LinkerError::WriteError)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn link_btf(&mut self, files: &[(&str, ElfFile)]) -> Result<(), LinkerError> {
+        let mut btf = Btf::new();
+        let mut strings = HashMap::new();
+        // DATASECs with the same name are merged
+        let mut datasecs: HashMap<String, (u32, Vec<_>)> = HashMap::new();
+        let mut ext = BtfExtBuilder::new(self.endianness);
+        let mut has_btf = false;
+
+        for (file_index, (name, file)) in files.iter().enumerate() {
+            let section = match file.section_by_name(".BTF") {
+                Some(section) => section,
+                None => continue,
+            };
+            has_btf = true;
+            let data = section.data().map_err(|error| LinkerError::ElfError {
+                name: (*name).to_owned(),
+                error,
+            })?;
+            let file_btf = Btf::parse(data, self.endianness)?;
+
+            // the offsets of the sections of this file in the output sections, by name
+            let section_offsets = file
+                .sections()
+                .filter_map(|section| {
+                    let offset = self.input_sections.get(&(file_index, section.index()))?.1;
+                    Some((section.name().ok()?.to_owned(), offset))
+                })
+                .collect::<HashMap<_, _>>();
+
+            // assign the ids first, types can reference types that come after them
+            let mut type_ids = vec![0];
+            let mut next_id = btf.types().count() as u32;
+            let mut new_datasecs = Vec::new();
+            for ty in file_btf.types().skip(1) {
+                if let BtfType::DataSec(_, _) = ty {
+                    let datasec_name = file_btf.type_name(ty)?.unwrap_or_default();
+                    match datasecs.entry(datasec_name.into_owned()) {
+                        Entry::Occupied(e) => {
+                            type_ids.push(e.get().0);
+                            continue;
+                        }
+                        Entry::Vacant(e) => {
+                            e.insert((next_id, Vec::new()));
+                        }
+                    }
+                    new_datasecs.push(next_id);
+                }
+                type_ids.push(next_id);
+                next_id += 1;
+            }
+
+            for (i, ty) in file_btf.types().enumerate().skip(1) {
+                let ty = ty.remap(
+                    |type_id| type_ids.get(type_id as usize).copied().unwrap_or(type_id),
+                    |name_off| intern_string(&mut btf, &mut strings, &file_btf, name_off),
+                )?;
+                if let BtfType::DataSec(datasec_ty, secinfos) = &ty {
+                    let datasec_name = btf.string_at(datasec_ty.name_off)?.into_owned();
+                    let offset = section_offsets.get(&datasec_name).copied().unwrap_or(0);
+                    datasecs
+                        .get_mut(&datasec_name)
+                        .unwrap()
+                        .1
+                        .extend(secinfos.iter().map(|secinfo| {
+                            let mut secinfo = *secinfo;
+                            secinfo.offset += offset as u32;
+                            secinfo
+                        }));
+                    if !new_datasecs.contains(&type_ids[i]) {
+                        continue;
+                    }
+                }
+                btf.add_type(ty);
+            }
+
+            if let Some(section) = file.section_by_name(".BTF.ext") {
+                let data = section.data().map_err(|error| LinkerError::ElfError {
+                    name: (*name).to_owned(),
+                    error,
+                })?;
+                ext.append(
+                    name,
+                    data,
+                    &file_btf,
+                    &type_ids,
+                    &section_offsets,
+                    |name_off| intern_string(&mut btf, &mut strings, &file_btf, name_off),
+                )?;
+            }
+        }
+
+        if !has_btf {
+            return Ok(());
+        }
+
+        // externs that are defined by another object are removed from their DATASEC, only the
+        // definition remains
+        let mut resolved_vars = Vec::new();
+        for (datasec_name, (datasec_id, secinfos)) in datasecs.iter_mut() {
+            if datasec_name == KCONFIG_SECTION || datasec_name == KSYMS_SECTION {
+                continue;
+            }
+            let mut kept = Vec::new();
+            for secinfo in secinfos.drain(..) {
+                if let ty @ BtfType::Var(_, var) = btf.type_by_id(secinfo.type_)? {
+                    if var.linkage == BTF_VAR_GLOBAL_EXTERN {
+                        let var_name = btf.type_name(ty)?.unwrap_or_default().into_owned();
+                        match self.globals.get(&var_name) {
+                            Some(GlobalSymbol {
+                                definition: Some(_),
+                                ..
+                            }) => resolved_vars.push(secinfo.type_),
+                            _ => return Err(LinkerError::UnresolvedSymbol { symbol: var_name }),
+                        }
+                        continue;
+                    }
+                }
+                kept.push(secinfo);
+            }
+            kept.sort_by_key(|secinfo| secinfo.offset);
+
+            let size = self
+                .sections
+                .get(datasec_name)
+                .and_then(|(section_id, _)| self.section_sizes.get(section_id))
+                .copied()
+                .unwrap_or(0);
+            if let BtfType::DataSec(ty, _) = btf.type_by_id(*datasec_id)? {
+                let name_off = ty.name_off;
+                *btf.type_by_id_mut(*datasec_id)? =
+                    BtfType::new_datasec(name_off, mem::take(&mut kept), size as u32);
+            }
+        }
+        for (datasec_name, (datasec_id, secinfos)) in datasecs.iter_mut() {
+            if datasec_name == KCONFIG_SECTION || datasec_name == KSYMS_SECTION {
+                if let BtfType::DataSec(ty, _) = btf.type_by_id(*datasec_id)? {
+                    let name_off = ty.name_off;
+                    *btf.type_by_id_mut(*datasec_id)? =
+                        BtfType::new_datasec(name_off, mem::take(secinfos), 0);
+                }
+            }
+        }
+        for var_id in resolved_vars {
+            if let BtfType::Var(_, var) = btf.type_by_id_mut(var_id)? {
+                var.linkage = BTF_VAR_GLOBAL_ALLOCATED;
+            }
+        }
+
+        // the kernel rejects extern functions, the ones that are now defined become global
+        let mut resolved_funcs = Vec::new();
+        for (i, ty) in btf.types().enumerate() {
+            if let BtfType::Func(func_ty) = ty {
+                if type_vlen(func_ty) == btf_func_linkage::BTF_FUNC_EXTERN as usize {
+                    let func_name = btf.type_name(ty)?.unwrap_or_default();
+                    if let Some(GlobalSymbol {
+                        definition: Some(_),
+                        ..
+                    }) = self.globals.get(&*func_name)
+                    {
+                        resolved_funcs.push(i as u32);
+                    }
+                }
+            }
+        }
+        for func_id in resolved_funcs {
+            if let BtfType::Func(func_ty) = btf.type_by_id_mut(func_id)? {
+                func_ty.info = (func_ty.info & !0xFFFF) | btf_func_linkage::BTF_FUNC_GLOBAL as u32;
+            }
+        }
+
+        let section_id = self
+            .out
+            .add_section(Vec::new(), b".BTF".to_vec(), SectionKind::Other);
+        // like .BTF.ext, the merged BTF is written in the byte order of the objects
+        let mut data = btf.to_bytes();
+        if self.endianness != Endianness::default() {
+            swap_btf_bytes(&mut data, false)?;
+        }
+        self.out.set_section_data(section_id, data, 4);
+        if !ext.is_empty() {
+            let section_id =
+                self.out
+                    .add_section(Vec::new(), b".BTF.ext".to_vec(), SectionKind::Other);
+            self.out.set_section_data(section_id, ext.to_bytes(), 4);
+        }
+
+        Ok(())
+    }
+}
+
+fn intern_string(
+    btf: &mut Btf,
+    strings: &mut HashMap<String, u32>,
+    file_btf: &Btf,
+    name_off: u32,
+) -> Result<u32, BtfError> {
+    if name_off == 0 {
+        return Ok(0);
+    }
+    let s = file_btf.string_at(name_off)?;
+    if let Some(offset) = strings.get(&*s) {
+        return Ok(*offset);
+    }
+    let offset = btf.add_string(s.to_string());
+    strings.insert(s.into_owned(), offset);
+    Ok(offset)
+}
+
+fn add_u32(data: &mut [u8], offset: u64, value: u64, endianness: Endianness) {
+    let offset = offset as usize;
+    let bytes = &mut data[offset..offset + 4];
+    let value = match endianness {
+        Endianness::Little => u32::from_le_bytes(bytes.try_into().unwrap()),
+        Endianness::Big => u32::from_be_bytes(bytes.try_into().unwrap()),
+    }
+    .wrapping_add(value as u32);
+    bytes.copy_from_slice(&match endianness {
+        Endianness::Little => value.to_le_bytes(),
+        Endianness::Big => value.to_be_bytes(),
+    });
+}
+
+fn add_u64(data: &mut [u8], offset: u64, value: u64, endianness: Endianness) {
+    let offset = offset as usize;
+    let bytes = &mut data[offset..offset + 8];
+    let value = match endianness {
+        Endianness::Little => u64::from_le_bytes(bytes.try_into().unwrap()),
+        Endianness::Big => u64::from_be_bytes(bytes.try_into().unwrap()),
+    }
+    .wrapping_add(value);
+    bytes.copy_from_slice(&match endianness {
+        Endianness::Little => value.to_le_bytes(),
+        Endianness::Big => value.to_be_bytes(),
+    });
+}
+
+// The func_info, line_info and CO-RE relocation records of the merged .BTF.ext section
+struct BtfExtBuilder {
+    endianness: Endianness,
+    infos: [BtfExtInfo; 3],
+}
+
+#[derive(Default)]
+struct BtfExtInfo {
+    rec_size: u32,
+    // records by section name offset, in the order the sections were first seen
+    sections: Vec<(u32, Vec<u8>)>,
+}
+
+// the fields of the records that are type ids or string offsets. The first field is always the
+// byte offset of the instruction the record applies to.
+const FUNC_INFO_FIELDS: (&[usize], &[usize]) = (&[1], &[]);
+const LINE_INFO_FIELDS: (&[usize], &[usize]) = (&[], &[1, 2]);
+const CORE_RELO_FIELDS: (&[usize], &[usize]) = (&[1], &[2]);
+
+impl BtfExtBuilder {
+    fn new(endianness: Endianness) -> BtfExtBuilder {
+        BtfExtBuilder {
+            endianness,
+            infos: Default::default(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.infos.iter().all(|info| info.sections.is_empty())
+    }
+
+    fn append(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        file_btf: &Btf,
+        type_ids: &[u32],
+        section_offsets: &HashMap<String, u64>,
+        mut intern_string: impl FnMut(u32) -> Result<u32, BtfError>,
+    ) -> Result<(), LinkerError> {
+        let endianness = self.endianness;
+        let invalid = || LinkerError::InvalidBtfExt {
+            name: name.to_owned(),
+        };
+        let read_u32 = |data: &[u8], offset: usize| -> Result<u32, LinkerError> {
+            let bytes = data
+                .get(offset..offset + 4)
+                .ok_or_else(invalid)?
+                .try_into()
+                .unwrap();
+            Ok(match endianness {
+                Endianness::Little => u32::from_le_bytes(bytes),
+                Endianness::Big => u32::from_be_bytes(bytes),
+            })
+        };
+
+        // the CO-RE relocation fields were added later, they're optional
+        let hdr_len = read_u32(data, 4)? as usize;
+        let mut header = [0u32; 6];
+        for (i, field) in header.iter_mut().enumerate() {
+            let offset = 8 + i * 4;
+            if offset + 4 <= hdr_len {
+                *field = read_u32(data, offset)?;
+            }
+        }
+
+        let fields = [FUNC_INFO_FIELDS, LINE_INFO_FIELDS, CORE_RELO_FIELDS];
+        for (i, (type_fields, string_fields)) in fields.iter().enumerate() {
+            let (off, len) = (header[i * 2] as usize, header[i * 2 + 1] as usize);
+            if len == 0 {
+                continue;
+            }
+            let start = hdr_len + off;
+            let data = data.get(start..start + len).ok_or_else(invalid)?;
+            let rec_size = read_u32(data, 0)?;
+            let info = &mut self.infos[i];
+            if info.rec_size != 0 && info.rec_size != rec_size || (rec_size as usize) < 8 {
+                return Err(invalid());
+            }
+            info.rec_size = rec_size;
+
+            let mut offset = 4;
+            while offset < data.len() {
+                let sec_name_off = read_u32(data, offset)?;
+                let num_info = read_u32(data, offset + 4)? as usize;
+                offset += 8;
+                let sec_name = file_btf.string_at(sec_name_off)?;
+                let section_offset = section_offsets.get(&*sec_name).copied().unwrap_or(0);
+                let out_name_off = intern_string(sec_name_off)?;
+
+                let records_len = num_info * rec_size as usize;
+                let mut records = data
+                    .get(offset..offset + records_len)
+                    .ok_or_else(invalid)?
+                    .to_vec();
+                offset += records_len;
+                for record in records.chunks_mut(rec_size as usize) {
+                    let insn_off = read_u32(record, 0)? as u64 + section_offset;
+                    write_u32(record, 0, insn_off as u32, endianness);
+                    for field in type_fields.iter() {
+                        let type_id = read_u32(record, field * 4)?;
+                        let type_id = type_ids.get(type_id as usize).copied().unwrap_or(type_id);
+                        write_u32(record, field * 4, type_id, endianness);
+                    }
+                    for field in string_fields.iter() {
+                        let name_off = intern_string(read_u32(record, field * 4)?)?;
+                        write_u32(record, field * 4, name_off, endianness);
+                    }
+                }
+
+                match info
+                    .sections
+                    .iter_mut()
+                    .find(|(name_off, _)| *name_off == out_name_off)
+                {
+                    Some((_, existing)) => existing.extend(records),
+                    None => info.sections.push((out_name_off, records)),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let endianness = self.endianness;
+        let u32_bytes = |value: u32| match endianness {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        };
+
+        let mut infos = Vec::<u8>::new();
+        let mut header = Vec::new();
+        for info in self.infos.iter() {
+            let start = infos.len();
+            if !info.sections.is_empty() {
+                infos.extend(&u32_bytes(info.rec_size));
+                for (name_off, records) in info.sections.iter() {
+                    infos.extend(&u32_bytes(*name_off));
+                    infos.extend(&u32_bytes(records.len() as u32 / info.rec_size));
+                    infos.extend(records);
+                }
+            }
+            header.push(start as u32);
+            header.push((infos.len() - start) as u32);
+        }
+
+        let magic: u16 = 0xeb9f;
+        let mut data = match endianness {
+            Endianness::Little => magic.to_le_bytes(),
+            Endianness::Big => magic.to_be_bytes(),
+        }
+        .to_vec();
+        // version and flags
+        data.extend(&[1, 0]);
+        data.extend(&u32_bytes(mem::size_of::<btf_ext_header>() as u32));
+        for field in header {
+            data.extend(&u32_bytes(field));
+        }
+        data.extend(infos);
+        data
+    }
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32, endianness: Endianness) {
+    data[offset..offset + 4].copy_from_slice(&match endianness {
+        Endianness::Little => value.to_le_bytes(),
+        Endianness::Big => value.to_be_bytes(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+
+    use super::*;
+    use crate::{
+        generated::{BTF_KIND_DATASEC, BTF_KIND_INT, BTF_KIND_VAR, BTF_VAR_STATIC},
+        obj::{
+            btf::{btf_bytes, BtfKind},
+            Object,
+        },
+    };
+
+    const MOV_R0_1: [u8; 8] = [0xb7, 0, 0, 0, 1, 0, 0, 0];
+    const EXIT: [u8; 8] = [0x95, 0, 0, 0, 0, 0, 0, 0];
+    const MAP_DEF: [u8; 20] = [2, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0];
+
+    fn kind(kind: u32, vlen: u32) -> u32 {
+        kind << 24 | vlen
+    }
+
+    fn ld_imm64(imm: i32) -> Vec<u8> {
+        let mut ins = vec![0x18, 0x01, 0, 0];
+        ins.extend(&imm.to_le_bytes());
+        ins.extend(&[0; 8]);
+        ins
+    }
+
+    fn call(imm: i32) -> Vec<u8> {
+        let mut ins = vec![0x85, 0x10, 0, 0];
+        ins.extend(&imm.to_le_bytes());
+        ins
+    }
+
+    fn add_section(obj: &mut ElfWriter, name: &str, kind: SectionKind, data: &[u8]) -> SectionId {
+        let id = obj.add_section(Vec::new(), name.as_bytes().to_vec(), kind);
+        obj.set_section_data(id, data.to_vec(), 8);
+        id
+    }
+
+    fn add_symbol(
+        obj: &mut ElfWriter,
+        name: &str,
+        kind: SymbolKind,
+        section: WriteSymbolSection,
+        size: u64,
+    ) -> SymbolId {
+        obj.add_symbol(WriteSymbol {
+            name: name.as_bytes().to_vec(),
+            value: 0,
+            size,
+            kind,
+            scope: object::SymbolScope::Dynamic,
+            weak: false,
+            section,
+            flags: SymbolFlags::None,
+        })
+    }
+
+    fn add_relocation(obj: &mut ElfWriter, section: SectionId, offset: u64, symbol: SymbolId) {
+        obj.add_relocation(
+            section,
+            WriteRelocation {
+                offset,
+                size: 64,
+                kind: RelocationKind::Elf(elf::R_BPF_64_64),
+                encoding: RelocationEncoding::Generic,
+                symbol,
+                addend: 0,
+            },
+        )
+        .unwrap();
+    }
+
+    fn new_object() -> ElfWriter<'static> {
+        let mut obj = ElfWriter::new(BinaryFormat::Elf, Architecture::Bpf, Endianness::Little);
+        add_section(&mut obj, "license", SectionKind::ReadOnlyData, b"GPL\0");
+        obj
+    }
+
+    // defines the helper function and the shared map
+    fn lib_object(map_def: &[u8]) -> Vec<u8> {
+        let mut obj = new_object();
+        let text = add_section(
+            &mut obj,
+            ".text",
+            SectionKind::Text,
+            &[MOV_R0_1, EXIT].concat(),
+        );
+        add_symbol(
+            &mut obj,
+            "helper",
+            SymbolKind::Text,
+            WriteSymbolSection::Section(text),
+            16,
+        );
+        let maps = add_section(&mut obj, "maps/shared", SectionKind::Data, map_def);
+        add_symbol(
+            &mut obj,
+            "shared",
+            SymbolKind::Data,
+            WriteSymbolSection::Section(maps),
+            MAP_DEF.len() as u64,
+        );
+        add_section(&mut obj, ".data", SectionKind::Data, &[1; 8]);
+        let rodata = add_section(&mut obj, ".rodata", SectionKind::ReadOnlyData, &[0; 4]);
+        add_symbol(
+            &mut obj,
+            "counter",
+            SymbolKind::Data,
+            WriteSymbolSection::Section(rodata),
+            4,
+        );
+
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, 32,
+            // [2] const int counter
+            5, kind(BTF_KIND_VAR, 0), 1, BTF_VAR_GLOBAL_ALLOCATED,
+            // [3] .rodata
+            13, kind(BTF_KIND_DATASEC, 1), 4, 2, 0, 4,
+        ];
+        add_section(
+            &mut obj,
+            ".BTF",
+            SectionKind::Other,
+            &btf_bytes(&types, b"\0int\0counter\0.rodata\0"),
+        );
+        obj.write().unwrap()
+    }
+
+    // defines a program that uses the helper, the shared map and a static variable
+    fn prog_object() -> Vec<u8> {
+        let mut obj = new_object();
+        let prog = add_section(
+            &mut obj,
+            "xdp/prog",
+            SectionKind::Text,
+            &[
+                ld_imm64(0),
+                ld_imm64(4),
+                call(-1),
+                MOV_R0_1.to_vec(),
+                EXIT.to_vec(),
+            ]
+            .concat(),
+        );
+        add_symbol(
+            &mut obj,
+            "prog",
+            SymbolKind::Text,
+            WriteSymbolSection::Section(prog),
+            56,
+        );
+        let maps = add_section(&mut obj, "maps/shared", SectionKind::Data, &MAP_DEF);
+        let shared = add_symbol(
+            &mut obj,
+            "shared",
+            SymbolKind::Data,
+            WriteSymbolSection::Section(maps),
+            MAP_DEF.len() as u64,
+        );
+        let data = add_section(&mut obj, ".data", SectionKind::Data, &[2; 8]);
+        let data_symbol = obj.section_symbol(data);
+        let helper = add_symbol(
+            &mut obj,
+            "helper",
+            SymbolKind::Unknown,
+            WriteSymbolSection::Undefined,
+            0,
+        );
+
+        add_symbol(
+            &mut obj,
+            "counter",
+            SymbolKind::Unknown,
+            WriteSymbolSection::Undefined,
+            0,
+        );
+
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, 32,
+            // [2] extern const int counter
+            5, kind(BTF_KIND_VAR, 0), 1, BTF_VAR_GLOBAL_EXTERN,
+            // [3] .rodata
+            13, kind(BTF_KIND_DATASEC, 1), 0, 2, 0, 4,
+            // [4] static int local
+            21, kind(BTF_KIND_VAR, 0), 1, BTF_VAR_STATIC,
+            // [5] .data
+            27, kind(BTF_KIND_DATASEC, 1), 8, 4, 4, 4,
+        ];
+        add_section(
+            &mut obj,
+            ".BTF",
+            SectionKind::Other,
+            &btf_bytes(&types, b"\0int\0counter\0.rodata\0local\0.data\0"),
+        );
+
+        add_relocation(&mut obj, prog, 0, shared);
+        add_relocation(&mut obj, prog, 16, data_symbol);
+        obj.add_relocation(
+            prog,
+            WriteRelocation {
+                offset: 32,
+                size: 32,
+                kind: RelocationKind::Elf(elf::R_BPF_64_32),
+                encoding: RelocationEncoding::Generic,
+                symbol: helper,
+                addend: 0,
+            },
+        )
+        .unwrap();
+        obj.write().unwrap()
+    }
+
+    #[test]
+    fn test_link() {
+        let data = Linker::new()
+            .add_object("lib.o", &lib_object(&MAP_DEF))
+            .unwrap()
+            .add_object("prog.o", &prog_object())
+            .unwrap()
+            .link()
+            .unwrap();
+        let obj = Object::parse(&data).unwrap();
+
+        assert_eq!(obj.license.to_str().unwrap(), "GPL");
+        assert_eq!(obj.maps.len(), 3);
+        assert_eq!(obj.maps["shared"].def.map_type, 2);
+        assert_eq!(obj.maps[".data"].data, [[1; 8], [2; 8]].concat());
+        assert_eq!(obj.functions.len(), 1);
+        assert_eq!(obj.functions[&0].name, "helper");
+
+        let prog = &obj.programs["prog"];
+        // the static variable moved along with the .data section of prog.o
+        assert_eq!(prog.function.instructions[2].imm, 12);
+        let relocations = &obj.relocations[&prog.function.section_index];
+        let helper = &obj.symbols_by_index[&relocations[&32].symbol_index];
+        assert_eq!(helper.name.as_deref(), Some("helper"));
+        assert!(helper.is_definition);
+        let shared = &obj.symbols_by_index[&relocations[&0].symbol_index];
+        assert_eq!(shared.name.as_deref(), Some("shared"));
+
+        let btf = obj.btf.as_ref().unwrap();
+        // the types of prog.o come after the ones of lib.o, except for .rodata which is merged
+        assert_eq!(btf.types().count(), 8);
+        let datasec = |name| {
+            let id = btf.id_by_type_name_kind(name, BtfKind::DataSec).unwrap();
+            match btf.type_by_id(id).unwrap() {
+                BtfType::DataSec(ty, secinfos) => (
+                    unsafe { ty.__bindgen_anon_1.size },
+                    secinfos
+                        .iter()
+                        .map(|s| (s.type_, s.offset))
+                        .collect::<Vec<_>>(),
+                ),
+                _ => panic!("unexpected BTF type"),
+            }
+        };
+        // the extern declaration of counter was resolved to the definition in lib.o
+        assert_eq!(datasec(".rodata"), (4, vec![(2, 0)]));
+        assert_matches!(
+            btf.type_by_id(5).unwrap(),
+            BtfType::Var(_, var) if var.linkage == BTF_VAR_GLOBAL_ALLOCATED
+        );
+        // local is in the second half of .data
+        assert_eq!(datasec(".data"), (16, vec![(6, 12)]));
+    }
+
+    #[test]
+    fn test_link_big_endian() {
+        let mut obj = ElfWriter::new(BinaryFormat::Elf, Architecture::Bpf, Endianness::Big);
+        add_section(&mut obj, "license", SectionKind::ReadOnlyData, b"GPL\0");
+        let prog = add_section(
+            &mut obj,
+            "xdp/prog",
+            SectionKind::Text,
+            &[MOV_R0_1, EXIT].concat(),
+        );
+        add_symbol(
+            &mut obj,
+            "prog",
+            SymbolKind::Text,
+            WriteSymbolSection::Section(prog),
+            16,
+        );
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, 32,
+        ];
+        let mut btf = btf_bytes(&types, b"\0int\0");
+        if Endianness::default() != Endianness::Big {
+            swap_btf_bytes(&mut btf, false).unwrap();
+        }
+        add_section(&mut obj, ".BTF", SectionKind::Other, &btf);
+
+        let data = Linker::new()
+            .add_object("prog.o", &obj.write().unwrap())
+            .unwrap()
+            .link()
+            .unwrap();
+        let file = ElfFile::parse(&*data).unwrap();
+        assert_eq!(file.endianness(), Endianness::Big);
+        let btf = file.section_by_name(".BTF").unwrap().data().unwrap();
+        assert_eq!(btf[..2], [0xeb, 0x9f]);
+        let btf = Btf::parse(btf, Endianness::Big).unwrap();
+        assert_eq!(btf.id_by_type_name_kind("int", BtfKind::Int).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_link_conflicts() {
+        assert_matches!(
+            Linker::new()
+                .add_object("lib.o", &lib_object(&MAP_DEF))
+                .unwrap()
+                .add_object("lib2.o", &lib_object(&MAP_DEF))
+                .unwrap()
+                .link(),
+            Err(LinkerError::DuplicateSymbol { symbol, .. }) if symbol == "helper"
+        );
+
+        let mut map_def = MAP_DEF;
+        map_def[12] = 2;
+        assert_matches!(
+            Linker::new()
+                .add_object("lib.o", &lib_object(&map_def))
+                .unwrap()
+                .add_object("prog.o", &prog_object())
+                .unwrap()
+                .link(),
+            Err(LinkerError::SectionConflict { section, .. }) if section == "maps/shared"
+        );
+
+        assert_matches!(
+            Linker::new().add_object("foo", b"foo"),
+            Err(LinkerError::ElfError { .. })
+        );
+    }
+}
@@ -153,7 +153,7 @@ mod tests {
             bpf_map_type::{BPF_MAP_TYPE_HASH, BPF_MAP_TYPE_PERF_EVENT_ARRAY},
         },
         obj,
-        sys::{override_syscall, SysResult, Syscall},
+        sys::{fake_fd, override_syscall, SysResult, Syscall},
     };
 
     use super::*;
@@ -168,8 +168,10 @@ mod tests {
                 ..Default::default()
             },
             section_index: 0,
+            symbol_index: None,
             data: Vec::new(),
             kind: obj::MapKind::Other,
+            btf_def: None,
         }
     }
 
@@ -221,8 +223,10 @@ mod tests {
                     ..Default::default()
                 },
                 section_index: 0,
+                symbol_index: None,
                 data: Vec::new(),
                 kind: obj::MapKind::Other,
+                btf_def: None,
             },
             fd: None,
             pinned: false,
@@ -252,7 +256,7 @@ mod tests {
     fn test_new_ok() {
         let mut map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
 
@@ -263,7 +267,7 @@ mod tests {
     fn test_try_from_ok() {
         let map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         assert!(HashMap::<_, u32, u32>::try_from(&map).is_ok())
@@ -281,10 +285,12 @@ mod tests {
                     ..Default::default()
                 },
                 section_index: 0,
+                symbol_index: None,
                 data: Vec::new(),
                 kind: obj::MapKind::Other,
+                btf_def: None,
             },
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
 
@@ -297,7 +303,7 @@ mod tests {
 
         let mut map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
@@ -320,7 +326,7 @@ mod tests {
 
         let mut map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
@@ -334,7 +340,7 @@ mod tests {
 
         let mut map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
@@ -357,7 +363,7 @@ mod tests {
 
         let mut map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let mut hm = HashMap::<_, u32, u32>::new(&mut map).unwrap();
@@ -370,7 +376,7 @@ mod tests {
         override_syscall(|_| sys_error(EFAULT));
         let map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
@@ -392,7 +398,7 @@ mod tests {
         });
         let map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
@@ -428,7 +434,7 @@ mod tests {
         });
         let map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
@@ -472,7 +478,7 @@ mod tests {
 
         let map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
@@ -500,7 +506,7 @@ mod tests {
         });
         let map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
@@ -530,7 +536,7 @@ mod tests {
         });
         let map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
@@ -563,7 +569,7 @@ mod tests {
         });
         let map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
@@ -597,7 +603,7 @@ mod tests {
         });
         let map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
@@ -637,7 +643,7 @@ mod tests {
         });
         let map = Map {
             obj: new_obj_map(),
-            fd: Some(42),
+            fd: Some(fake_fd()),
             pinned: false,
         };
         let hm = HashMap::<_, u32, u32>::new(&map).unwrap();
@@ -33,15 +33,25 @@
 //! versa. Because of that, all map values must be plain old data and therefore
 //! implement the [Pod] trait.
 use std::{
-    convert::TryFrom, ffi::CString, io, marker::PhantomData, mem, ops::Deref, os::unix::io::RawFd,
-    path::Path, ptr,
+    convert::TryFrom,
+    ffi::CString,
+    io,
+    marker::PhantomData,
+    mem,
+    ops::Deref,
+    os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    path::Path,
+    ptr,
 };
 use thiserror::Error;
 
 use crate::{
     generated::bpf_map_type,
     obj,
-    sys::{bpf_create_map, bpf_get_object, bpf_map_get_next_key, bpf_pin_object},
+    sys::{
+        bpf_create_map, bpf_get_object, bpf_map_get_info_by_fd, bpf_map_get_next_key,
+        bpf_pin_object,
+    },
     util::nr_cpus,
     Pod,
 };
@@ -104,6 +114,19 @@ pub enum MapError {
         io_error: io::Error,
     },
 
+    #[error(
+        "map `{name}` is incompatible with the object: {field} is {value}, expected {expected}"
+    )]
+    IncompatibleMap {
+        name: String,
+        field: String,
+        value: u32,
+        expected: u32,
+    },
+
+    #[error("invalid definition for map `{name}`: {reason}")]
+    InvalidDefinition { name: String, reason: String },
+
     #[error("invalid key size {size}, expected {expected}")]
     InvalidKeySize { size: usize, expected: usize },
 
@@ -143,7 +166,7 @@ pub enum MapError {
 #[derive(Debug)]
 pub struct Map {
     pub(crate) obj: obj::Map,
-    pub(crate) fd: Option<RawFd>,
+    pub(crate) fd: Option<OwnedFd>,
     pub pinned: bool,
 }
 
@@ -153,19 +176,9 @@ impl Map {
             return Err(MapError::AlreadyCreated { name: name.into() });
         }
 
-        let c_name = CString::new(name).map_err(|_| MapError::InvalidName { name: name.into() })?;
-
-        let fd = bpf_create_map(&c_name, &self.obj.def).map_err(|(code, io_error)| {
-            MapError::CreateError {
-                name: name.into(),
-                code,
-                io_error,
-            }
-        })? as RawFd;
-
-        self.fd = Some(fd);
+        let fd = create_map(name, &self.obj)?;
 
-        Ok(fd)
+        Ok(self.fd.insert(fd).as_raw_fd())
     }
 
     pub(crate) fn from_pinned<P: AsRef<Path>>(
@@ -190,10 +203,69 @@ impl Map {
             code,
             io_error,
         })? as RawFd;
+        // Safety: the fd was just returned by the kernel and nothing else owns it
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        Ok(self.fd.insert(fd).as_raw_fd())
+    }
 
-        self.fd = Some(fd);
+    /// Uses the existing map `fd` instead of creating a new map.
+    ///
+    /// The map must have the same type, key size and value size as the map defined in the object.
+    /// `fd` is duplicated, so the caller keeps ownership of it.
+    pub(crate) fn reuse(&mut self, name: &str, fd: RawFd) -> Result<RawFd, MapError> {
+        if self.fd.is_some() {
+            return Err(MapError::AlreadyCreated { name: name.into() });
+        }
+        self.check_compatible(name, fd, false)?;
+
+        // Safety: fd is valid for the duration of the call
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) }
+            .try_clone_to_owned()
+            .map_err(|io_error| MapError::SyscallError {
+                call: "dup".to_owned(),
+                code: -1,
+                io_error,
+            })?;
 
-        Ok(fd)
+        Ok(self.fd.insert(fd).as_raw_fd())
+    }
+
+    /// Checks that the existing map `fd` has the same type, key size and value size as the map
+    /// defined in the object. If `strict` is set, the maximum number of entries and the flags must
+    /// match too.
+    pub(crate) fn check_compatible(
+        &self,
+        name: &str,
+        fd: RawFd,
+        strict: bool,
+    ) -> Result<(), MapError> {
+        let info = bpf_map_get_info_by_fd(fd).map_err(|io_error| MapError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            code: -1,
+            io_error,
+        })?;
+        let def = &self.obj.def;
+        let mut fields = vec![
+            ("type", info.type_, def.map_type),
+            ("key size", info.key_size, def.key_size),
+            ("value size", info.value_size, def.value_size),
+        ];
+        if strict {
+            fields.push(("max entries", info.max_entries, def.max_entries));
+            fields.push(("flags", info.map_flags, def.map_flags));
+        }
+        for (field, value, expected) in fields {
+            if value != expected {
+                return Err(MapError::IncompatibleMap {
+                    name: name.into(),
+                    field: field.to_string(),
+                    value,
+                    expected,
+                });
+            }
+        }
+        Ok(())
     }
 
     pub fn map_type(&self) -> Result<bpf_map_type, MapError> {
@@ -201,7 +273,10 @@ impl Map {
     }
 
     pub(crate) fn fd_or_err(&self) -> Result<RawFd, MapError> {
-        self.fd.ok_or(MapError::NotCreated)
+        self.fd
+            .as_ref()
+            .map(|fd| fd.as_raw_fd())
+            .ok_or(MapError::NotCreated)
     }
 
     pub(crate) fn pin<P: AsRef<Path>>(&mut self, name: &str, path: P) -> Result<(), MapError> {
@@ -225,13 +300,29 @@ impl Map {
     }
 }
 
-impl Drop for Map {
-    fn drop(&mut self) {
-        // TODO: Replace this with an OwnedFd once that is stabilized.
-        if let Some(fd) = self.fd.take() {
-            unsafe { libc::close(fd) };
-        }
-    }
+fn create_map(name: &str, obj: &obj::Map) -> Result<OwnedFd, MapError> {
+    let c_name = CString::new(name).map_err(|_| MapError::InvalidName { name: name.into() })?;
+
+    // map-in-maps need a template of the inner map at creation time. The template is only used
+    // by the kernel to validate the maps that get inserted, so it can be closed right after.
+    let inner_map_fd = match obj.btf_def.as_ref().and_then(|def| def.inner.as_ref()) {
+        Some(inner) => Some(create_map(&format!("{}.inner", name), inner)?),
+        None => None,
+    };
+
+    let ret = bpf_create_map(
+        &c_name,
+        &obj.def,
+        inner_map_fd.as_ref().map(|fd| fd.as_raw_fd()),
+    );
+
+    // Safety: the fd was just returned by the kernel and nothing else owns it
+    ret.map(|fd| unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+        .map_err(|(code, io_error)| MapError::CreateError {
+            name: name.into(),
+            code,
+            io_error,
+        })
 }
 
 pub trait IterableMap<K: Pod, V> {
@@ -473,12 +564,13 @@ impl<T: Pod> Deref for PerCpuValues<T> {
 #[cfg(test)]
 mod tests {
     use libc::EFAULT;
+    use std::{fs::File, os::unix::io::IntoRawFd};
 
     use crate::{
         bpf_map_def,
-        generated::{bpf_cmd, bpf_map_type::BPF_MAP_TYPE_HASH},
+        generated::{bpf_cmd, bpf_map_info, bpf_map_type::BPF_MAP_TYPE_HASH},
         obj::MapKind,
-        sys::{override_syscall, Syscall},
+        sys::{fake_fd, override_syscall, SysResult, Syscall},
     };
 
     use super::*;
@@ -493,8 +585,10 @@ mod tests {
                 ..Default::default()
             },
             section_index: 0,
+            symbol_index: None,
             data: Vec::new(),
             kind: MapKind::Other,
+            btf_def: None,
         }
     }
 
@@ -512,13 +606,13 @@ mod tests {
             Syscall::Bpf {
                 cmd: bpf_cmd::BPF_MAP_CREATE,
                 ..
-            } => Ok(42),
+            } => Ok(fake_fd().into_raw_fd() as i64),
             _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
         });
 
         let mut map = new_map();
-        assert!(matches!(map.create("foo"), Ok(42)));
-        assert_eq!(map.fd, Some(42));
+        let fd = map.create("foo").unwrap();
+        assert_eq!(map.fd_or_err().unwrap(), fd);
         assert!(matches!(
             map.create("foo"),
             Err(MapError::AlreadyCreated { .. })
@@ -542,6 +636,75 @@ mod tests {
             assert_eq!(code, -42);
             assert_eq!(io_error.raw_os_error(), Some(EFAULT));
         }
-        assert_eq!(map.fd, None);
+        assert!(map.fd.is_none());
+    }
+
+    fn map_info_syscall(call: Syscall, value_size: u32) -> SysResult {
+        match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_map_info) };
+                info.type_ = BPF_MAP_TYPE_HASH as u32;
+                info.key_size = 4;
+                info.value_size = value_size;
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(EFAULT))),
+        }
+    }
+
+    #[test]
+    fn test_reuse() {
+        override_syscall(|call| map_info_syscall(call, 4));
+        let file = File::open("/dev/null").unwrap();
+
+        let mut map = new_map();
+        let fd = map.reuse("foo", file.as_raw_fd()).unwrap();
+        assert_eq!(map.fd_or_err().unwrap(), fd);
+        assert_ne!(fd, file.as_raw_fd());
+        assert!(matches!(
+            map.reuse("foo", file.as_raw_fd()),
+            Err(MapError::AlreadyCreated { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reuse_incompatible() {
+        override_syscall(|call| map_info_syscall(call, 8));
+        let file = File::open("/dev/null").unwrap();
+
+        let mut map = new_map();
+        match map.reuse("foo", file.as_raw_fd()) {
+            Err(MapError::IncompatibleMap {
+                name,
+                field,
+                value,
+                expected,
+            }) => {
+                assert_eq!(name, "foo");
+                assert_eq!(field, "value size");
+                assert_eq!(value, 8);
+                assert_eq!(expected, 4);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(map.fd.is_none());
+    }
+
+    #[test]
+    fn test_check_compatible_strict() {
+        // the map info returned by the fake syscall has max_entries set to 0
+        override_syscall(|call| map_info_syscall(call, 4));
+        let file = File::open("/dev/null").unwrap();
+
+        let map = new_map();
+        map.check_compatible("foo", file.as_raw_fd(), false)
+            .unwrap();
+        assert!(matches!(
+            map.check_compatible("foo", file.as_raw_fd(), true),
+            Err(MapError::IncompatibleMap { field, value: 0, expected: 1024, .. }) if field == "max entries"
+        ));
     }
 }
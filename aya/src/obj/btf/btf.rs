@@ -1,21 +1,30 @@
 use std::{
     borrow::Cow,
+    collections::HashMap,
     convert::TryInto,
     ffi::{c_void, CStr},
     fs, io, mem,
     path::{Path, PathBuf},
     ptr,
+    sync::Arc,
 };
 
-use object::Endianness;
+use log::warn;
+use object::{Endianness, Object, ObjectSection};
 use thiserror::Error;
 
 use crate::{
-    generated::{btf_ext_header, btf_header},
-    obj::btf::{relocation::Relocation, BtfKind, BtfType},
+    generated::{
+        bpf_func_info, bpf_insn, bpf_line_info, btf_enum, btf_ext_header, btf_func_linkage,
+        btf_header, btf_member, btf_param, BTF_VAR_STATIC,
+    },
+    obj::btf::{relocation::Relocation, type_vlen, BtfKind, BtfType},
+    sys::kernel_release,
+    util::bytes_of,
 };
 
 pub(crate) const MAX_RESOLVE_DEPTH: u8 = 32;
+pub(crate) const BTF_MAGIC: u16 = 0xeb9f;
 pub(crate) const MAX_SPEC_LEN: usize = 64;
 
 /// The error type returned when `BTF` operations fail.
@@ -31,6 +40,15 @@ pub enum BtfError {
     #[error("error parsing BTF header")]
     InvalidHeader,
 
+    #[error("{path} is not an ELF file with a .BTF section")]
+    NoBtfSection { path: PathBuf },
+
+    #[error("the BTF of the running kernel couldn't be found")]
+    KernelBtfNotFound,
+
+    #[error("split BTF can't be used as base BTF")]
+    InvalidBaseBtf,
+
     #[error("invalid BTF type info segment")]
     InvalidTypeInfo,
 
@@ -71,6 +89,33 @@ pub enum BtfError {
 
     #[error("maximum depth reached resolving BTF type")]
     MaximumTypeDepthReached { type_id: u32 },
+
+    #[error("invalid bitfield of {bitfield_size} bits at bit offset {bit_offset}")]
+    InvalidBitfield { bit_offset: u32, bitfield_size: u32 },
+
+    /// Loading the btf failed
+    #[error("the BPF_BTF_LOAD syscall failed. Verifier output: {verifier_log}")]
+    LoadError {
+        /// The [`io::Error`] returned by the `BPF_BTF_LOAD` syscall.
+        #[source]
+        io_error: io::Error,
+        /// The error log produced by the kernel verifier.
+        verifier_log: String,
+    },
+}
+
+/// The BTF kinds and features supported by the kernel's BTF loader.
+///
+/// Object BTF using unsupported kinds is sanitized before being loaded, see
+/// [`Btf::fixup_and_sanitize`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BtfFeatures {
+    pub(crate) btf_func: bool,
+    pub(crate) btf_func_global: bool,
+    pub(crate) btf_datasec: bool,
+    pub(crate) btf_float: bool,
+    pub(crate) btf_decl_tag: bool,
+    pub(crate) btf_type_tag: bool,
 }
 
 /// Bpf Type Format metadata.
@@ -81,20 +126,157 @@ pub enum BtfError {
 /// Aya automatically loads BTF metadata if you use [`Bpf::load_file`](crate::Bpf::load_file). You
 /// only need to explicitly use this type if you want to load BTF from a non-standard
 /// location or if you are using [`Bpf::load`](crate::Bpf::load).
+///
+/// Kernel modules ship split BTF, which only contains the types defined by the module and
+/// references the types of the kernel's BTF. See [`Btf::parse_split_file`].
 #[derive(Clone, Debug)]
 pub struct Btf {
     header: btf_header,
     strings: Vec<u8>,
     types: Vec<BtfType>,
     _endianness: Endianness,
+    // for split BTF, the types and strings of the base BTF come first
+    base: Option<Arc<Btf>>,
 }
 
 impl Btf {
+    pub(crate) fn new() -> Btf {
+        Btf {
+            header: btf_header {
+                magic: BTF_MAGIC,
+                version: 0x01,
+                flags: 0x00,
+                hdr_len: mem::size_of::<btf_header>() as u32,
+                type_off: 0,
+                type_len: 0,
+                str_off: 0,
+                str_len: 0x01,
+            },
+            strings: vec![0],
+            types: vec![BtfType::Unknown],
+            _endianness: Endianness::default(),
+            base: None,
+        }
+    }
+
     /// Loads BTF metadata from `/sys/kernel/btf/vmlinux`.
     pub fn from_sys_fs() -> Result<Btf, BtfError> {
         Btf::parse_file("/sys/kernel/btf/vmlinux", Endianness::default())
     }
 
+    /// Finds and loads the BTF of the running kernel.
+    ///
+    /// `/sys/kernel/btf/vmlinux` is tried first. Kernels that don't expose it often ship a vmlinux
+    /// image with debug info, so `search_paths` and then the usual locations of vmlinux images
+    /// are searched next:
+    ///
+    /// * `/boot/vmlinux-$(uname -r)`
+    /// * `/lib/modules/$(uname -r)/vmlinux-$(uname -r)`
+    /// * `/lib/modules/$(uname -r)/build/vmlinux`
+    /// * `/usr/lib/modules/$(uname -r)/kernel/vmlinux`
+    /// * `/usr/lib/debug/boot/vmlinux-$(uname -r)`
+    /// * `/usr/lib/debug/boot/vmlinux-$(uname -r).debug`
+    /// * `/usr/lib/debug/lib/modules/$(uname -r)/vmlinux`
+    ///
+    /// Each entry of `search_paths` can be a file, or a directory containing
+    /// `vmlinux-$(uname -r)` or `$(uname -r).btf`. Files can contain raw BTF, or be vmlinux ELF
+    /// images from which the `.BTF` section is extracted. Files that don't exist or can't be
+    /// parsed are skipped.
+    ///
+    /// Returns the BTF along with the path it was loaded from.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use aya::Btf;
+    ///
+    /// let (btf, path) = Btf::from_kernel(&["/opt/btfhub"])?;
+    /// println!("loaded kernel BTF from {}", path.display());
+    /// # Ok::<(), aya::BtfError>(())
+    /// ```
+    pub fn from_kernel<P: AsRef<Path>>(search_paths: &[P]) -> Result<(Btf, PathBuf), BtfError> {
+        let release = kernel_release().ok();
+        for path in kernel_btf_paths(release.as_deref(), search_paths) {
+            if let Ok(btf) = Btf::parse_raw_or_elf_file(&path) {
+                return Ok((btf, path));
+            }
+        }
+
+        Err(BtfError::KernelBtfNotFound)
+    }
+
+    /// Loads BTF metadata from the `.BTF` section of the ELF file at `path`, for example a
+    /// vmlinux image built with `CONFIG_DEBUG_INFO_BTF`.
+    pub fn parse_elf_file<P: AsRef<Path>>(path: P) -> Result<Btf, BtfError> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|error| BtfError::FileError {
+            path: path.to_owned(),
+            error,
+        })?;
+        Btf::parse_elf(&data, path)
+    }
+
+    fn parse_elf(data: &[u8], path: &Path) -> Result<Btf, BtfError> {
+        let no_btf = || BtfError::NoBtfSection {
+            path: path.to_owned(),
+        };
+        let file = object::read::File::parse(data).map_err(|_| no_btf())?;
+        let section = file.section_by_name(".BTF").ok_or_else(no_btf)?;
+        let data = section.data().map_err(|_| no_btf())?;
+        Btf::parse(data, file.endianness())
+    }
+
+    fn parse_raw_or_elf_file(path: &Path) -> Result<Btf, BtfError> {
+        let data = fs::read(path).map_err(|error| BtfError::FileError {
+            path: path.to_owned(),
+            error,
+        })?;
+        if data.starts_with(b"\x7fELF") {
+            Btf::parse_elf(&data, path)
+        } else {
+            Btf::parse(&data, Endianness::default())
+        }
+    }
+
+    /// Loads the split BTF of all the loaded kernel modules from `/sys/kernel/btf`, using
+    /// `vmlinux` as base BTF.
+    ///
+    /// Returns an empty list if the kernel doesn't expose module BTF. Modules whose BTF can't be
+    /// parsed are skipped.
+    pub(crate) fn modules_from_sys_fs(vmlinux: Arc<Btf>) -> Result<Vec<ModuleBtf>, BtfError> {
+        let dir = Path::new("/sys/kernel/btf");
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => {
+                return Err(BtfError::FileError {
+                    path: dir.to_owned(),
+                    error,
+                })
+            }
+        };
+
+        let mut modules = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|error| BtfError::FileError {
+                path: dir.to_owned(),
+                error,
+            })?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name == "vmlinux" {
+                continue;
+            }
+            match Btf::parse_split_file(entry.path(), Arc::clone(&vmlinux), Endianness::default()) {
+                Ok(btf) => modules.push(ModuleBtf { name, btf }),
+                Err(e) => warn!("skipping the BTF of module {}: {}", name, e),
+            }
+        }
+        // make lookups deterministic
+        modules.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(modules)
+    }
+
     /// Loads BTF metadata from the given `path`.
     pub fn parse_file<P: AsRef<Path>>(path: P, endianness: Endianness) -> Result<Btf, BtfError> {
         let path = path.as_ref();
@@ -107,11 +289,77 @@ impl Btf {
         )
     }
 
+    /// Loads split BTF metadata from the given `path`, using `base` as base BTF.
+    ///
+    /// Split BTF, like the BTF of kernel modules found in `/sys/kernel/btf/<module>`, only
+    /// contains the types not already defined in its base BTF, which for kernel modules is
+    /// `/sys/kernel/btf/vmlinux`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use aya::{Btf, Endianness};
+    /// use std::sync::Arc;
+    ///
+    /// let vmlinux = Arc::new(Btf::from_sys_fs()?);
+    /// let nf_conntrack =
+    ///     Btf::parse_split_file("/sys/kernel/btf/nf_conntrack", vmlinux, Endianness::default())?;
+    /// # Ok::<(), aya::BtfError>(())
+    /// ```
+    pub fn parse_split_file<P: AsRef<Path>>(
+        path: P,
+        base: Arc<Btf>,
+        endianness: Endianness,
+    ) -> Result<Btf, BtfError> {
+        let path = path.as_ref();
+        Btf::parse_split(
+            &fs::read(path).map_err(|error| BtfError::FileError {
+                path: path.to_owned(),
+                error,
+            })?,
+            base,
+            endianness,
+        )
+    }
+
     pub(crate) fn parse(data: &[u8], endianness: Endianness) -> Result<Btf, BtfError> {
+        Btf::parse_inner(data, None, endianness)
+    }
+
+    pub(crate) fn parse_split(
+        data: &[u8],
+        base: Arc<Btf>,
+        endianness: Endianness,
+    ) -> Result<Btf, BtfError> {
+        if base.base.is_some() {
+            return Err(BtfError::InvalidBaseBtf);
+        }
+        Btf::parse_inner(data, Some(base), endianness)
+    }
+
+    fn parse_inner(
+        data: &[u8],
+        base: Option<Arc<Btf>>,
+        endianness: Endianness,
+    ) -> Result<Btf, BtfError> {
         if data.len() < mem::size_of::<btf_header>() {
             return Err(BtfError::InvalidHeader);
         }
 
+        // BTF in the opposite byte order of the host is converted first, so that the types can be
+        // read in place
+        let swapped;
+        let (data, endianness) = match unsafe { read_btf_header(data) }.magic {
+            BTF_MAGIC => (data, endianness),
+            magic if magic == BTF_MAGIC.swap_bytes() => {
+                let mut data = data.to_vec();
+                swap_btf_bytes(&mut data, true)?;
+                swapped = data;
+                (&swapped[..], Endianness::default())
+            }
+            _ => return Err(BtfError::InvalidHeader),
+        };
+
         // safety: btf_header is POD so read_unaligned is safe
         let header = unsafe { read_btf_header(data) };
 
@@ -122,19 +370,21 @@ impl Btf {
         }
 
         let strings = data[str_off..str_off + str_len].to_vec();
-        let types = Btf::read_type_info(&header, data, endianness)?;
+        let types = Btf::read_type_info(&header, data, base.is_some(), endianness)?;
 
         Ok(Btf {
             header,
             strings,
             types,
             _endianness: endianness,
+            base,
         })
     }
 
     fn read_type_info(
         header: &btf_header,
         data: &[u8],
+        split: bool,
         endianness: Endianness,
     ) -> Result<Vec<BtfType>, BtfError> {
         let hdr_len = header.hdr_len as usize;
@@ -146,7 +396,12 @@ impl Btf {
         }
 
         let mut data = &data[base..base + type_len];
-        let mut types = vec![BtfType::Unknown];
+        // the void type is only defined once, in the base BTF
+        let mut types = if split {
+            Vec::new()
+        } else {
+            vec![BtfType::Unknown]
+        };
         while !data.is_empty() {
             // Safety:
             // read() reads POD values from ELF, which is sound, but the values can still contain
@@ -159,6 +414,12 @@ impl Btf {
     }
 
     pub(crate) fn string_at(&self, offset: u32) -> Result<Cow<'_, str>, BtfError> {
+        let start_offset = match &self.base {
+            Some(base) if (offset as usize) < base.strings.len() => return base.string_at(offset),
+            Some(base) => base.strings.len(),
+            None => 0,
+        };
+
         let btf_header {
             hdr_len,
             mut str_off,
@@ -166,13 +427,11 @@ impl Btf {
             ..
         } = self.header;
         str_off += hdr_len;
-        if offset >= str_off + str_len {
-            return Err(BtfError::InvalidStringOffset {
-                offset: offset as usize,
-            });
+        let offset = offset as usize - start_offset;
+        if offset as u32 >= str_off + str_len {
+            return Err(BtfError::InvalidStringOffset { offset });
         }
 
-        let offset = offset as usize;
         let nul = self.strings[offset..]
             .iter()
             .position(|c| *c == 0u8)
@@ -185,15 +444,247 @@ impl Btf {
     }
 
     pub(crate) fn type_by_id(&self, type_id: u32) -> Result<&BtfType, BtfError> {
+        let first_type_id = self.first_type_id();
+        if type_id < first_type_id {
+            // first_type_id is only non-zero for split BTF
+            return self.base.as_ref().unwrap().type_by_id(type_id);
+        }
         self.types
-            .get(type_id as usize)
+            .get((type_id - first_type_id) as usize)
             .ok_or(BtfError::UnknownBtfType { type_id })
     }
 
+    pub(crate) fn type_by_id_mut(&mut self, type_id: u32) -> Result<&mut BtfType, BtfError> {
+        let index = type_id.checked_sub(self.first_type_id());
+        let types = &mut self.types;
+        index
+            .and_then(move |index| types.get_mut(index as usize))
+            .ok_or(BtfError::UnknownBtfType { type_id })
+    }
+
+    /// Returns the id of the first type defined by this BTF.
+    ///
+    /// This is zero (the void type), unless this is split BTF.
+    pub(crate) fn first_type_id(&self) -> u32 {
+        self.base
+            .as_ref()
+            .map(|b| b.types.len() as u32)
+            .unwrap_or(0)
+    }
+
+    /// Returns the types defined by this BTF, starting at [`Btf::first_type_id`].
+    ///
+    /// For split BTF, the types of the base BTF are not included.
     pub(crate) fn types(&self) -> impl Iterator<Item = &BtfType> {
         self.types.iter()
     }
 
+    pub(crate) fn add_type(&mut self, ty: BtfType) -> u32 {
+        let type_id = self.first_type_id() as usize + self.types.len();
+        self.types.push(ty);
+        type_id as u32
+    }
+
+    pub(crate) fn add_string(&mut self, name: String) -> u32 {
+        let str = name.into_bytes();
+        let name_off =
+            self.base.as_ref().map(|b| b.strings.len()).unwrap_or(0) + self.strings.len();
+        self.strings.extend(str);
+        self.strings.push(0);
+        self.header.str_len = self.strings.len() as u32;
+        name_off as u32
+    }
+
+    /// Encodes the BTF in the format expected by `BPF_BTF_LOAD`.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        // the first type is the implicit void type, it's not part of the encoded type section
+        let types = self
+            .types
+            .iter()
+            .skip(if self.base.is_none() { 1 } else { 0 })
+            .flat_map(|ty| ty.to_bytes())
+            .collect::<Vec<_>>();
+
+        let header = btf_header {
+            hdr_len: mem::size_of::<btf_header>() as u32,
+            type_off: 0,
+            type_len: types.len() as u32,
+            str_off: types.len() as u32,
+            str_len: self.strings.len() as u32,
+            ..self.header
+        };
+
+        // Safety: btf_header is POD
+        let mut buf = unsafe { bytes_of::<btf_header>(&header).to_vec() };
+        buf.extend(types);
+        buf.extend(&self.strings);
+        buf
+    }
+
+    /// Fixes up and sanitizes the BTF emitted by the compiler so that it can be loaded by the
+    /// kernel.
+    ///
+    /// LLVM leaves the size of DATASEC types and the offsets of the variables they contain to
+    /// be filled in by the loader, since they're only known after linking. `section_sizes` and
+    /// `symbol_offsets` map ELF section and symbol names to their size and offset.
+    ///
+    /// Kinds that the running kernel doesn't support, according to `features`, are replaced with
+    /// compatible equivalents so that type ids are preserved.
+    pub(crate) fn fixup_and_sanitize(
+        &mut self,
+        section_sizes: &HashMap<String, u64>,
+        symbol_offsets: &HashMap<String, u64>,
+        features: &BtfFeatures,
+    ) -> Result<(), BtfError> {
+        let types = mem::take(&mut self.types);
+        let fixed_types = types
+            .iter()
+            .map(|ty| {
+                self.fixup_and_sanitize_type(&types, ty, section_sizes, symbol_offsets, features)
+            })
+            .collect::<Result<Vec<_>, _>>();
+        match fixed_types {
+            Ok(fixed_types) => self.types = fixed_types,
+            Err(e) => {
+                self.types = types;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fixup_and_sanitize_type(
+        &mut self,
+        types: &[BtfType],
+        ty: &BtfType,
+        section_sizes: &HashMap<String, u64>,
+        symbol_offsets: &HashMap<String, u64>,
+        features: &BtfFeatures,
+    ) -> Result<BtfType, BtfError> {
+        use BtfType::*;
+        Ok(match ty {
+            // the kernel rejects named pointers
+            Ptr(ty) => {
+                // Safety: union
+                BtfType::new_ptr(0, unsafe { ty.__bindgen_anon_1.type_ })
+            }
+            // VARs are only allowed inside DATASECs, if those aren't supported the VARs
+            // become a byte sized int
+            Var(ty, _) if !features.btf_datasec => BtfType::new_int(ty.name_off, 1, 0, 0),
+            DataSec(ty, secinfos) => {
+                let name = self.string_at(ty.name_off)?.into_owned();
+                // Safety: union
+                let mut size = unsafe { ty.__bindgen_anon_1.size };
+                if let Some(section_size) = section_sizes.get(&name) {
+                    size = *section_size as u32;
+                }
+
+                let mut secinfos = secinfos.clone();
+                for secinfo in secinfos.iter_mut() {
+                    if let Some(Var(var_ty, var)) = types.get(secinfo.type_ as usize) {
+                        // static variables don't have a global symbol, llvm already
+                        // emits their offset
+                        if var.linkage == BTF_VAR_STATIC {
+                            continue;
+                        }
+                        let var_name = self.string_at(var_ty.name_off)?;
+                        if let Some(offset) = symbol_offsets.get(&*var_name) {
+                            secinfo.offset = *offset as u32;
+                        }
+                    }
+                }
+                // the kernel requires the variables to be sorted by offset
+                secinfos.sort_by_key(|secinfo| secinfo.offset);
+
+                if features.btf_datasec {
+                    BtfType::new_datasec(ty.name_off, secinfos, size)
+                } else {
+                    // replace the DATASEC with a struct that has a member for each variable
+                    let members = secinfos
+                        .iter()
+                        .map(|secinfo| {
+                            let (name_off, type_) = match types.get(secinfo.type_ as usize) {
+                                // Safety: union
+                                Some(Var(var_ty, _)) => {
+                                    (var_ty.name_off, unsafe { var_ty.__bindgen_anon_1.type_ })
+                                }
+                                _ => (0, secinfo.type_),
+                            };
+                            btf_member {
+                                name_off,
+                                type_,
+                                offset: secinfo.offset * 8,
+                            }
+                        })
+                        .collect();
+                    // section names like .data aren't valid struct names
+                    let name_off = self.add_string(name.replace('.', "_"));
+                    BtfType::new_struct(name_off, members, size)
+                }
+            }
+            FuncProto(ty, params) => {
+                // the kernel requires the parameters of functions to be named
+                let params = params
+                    .iter()
+                    .enumerate()
+                    .map(|(i, param)| {
+                        if param.name_off == 0 && param.type_ != 0 {
+                            btf_param {
+                                name_off: self.add_string(format!("param{}", i)),
+                                ..*param
+                            }
+                        } else {
+                            *param
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                if features.btf_func {
+                    // Safety: union
+                    BtfType::new_func_proto(params, unsafe { ty.__bindgen_anon_1.type_ })
+                } else {
+                    // replace the FUNC_PROTO with an enum that has a value for each param
+                    BtfType::new_enum(
+                        ty.name_off,
+                        params
+                            .iter()
+                            .map(|param| btf_enum {
+                                name_off: param.name_off,
+                                val: param.type_ as i32,
+                            })
+                            .collect(),
+                    )
+                }
+            }
+            Func(ty) if !features.btf_func => {
+                // Safety: union
+                BtfType::new_typedef(ty.name_off, unsafe { ty.__bindgen_anon_1.type_ })
+            }
+            Func(ty)
+                if !features.btf_func_global
+                    && type_vlen(ty) == btf_func_linkage::BTF_FUNC_GLOBAL as usize =>
+            {
+                BtfType::new_func(
+                    ty.name_off,
+                    // Safety: union
+                    unsafe { ty.__bindgen_anon_1.type_ },
+                    btf_func_linkage::BTF_FUNC_STATIC,
+                )
+            }
+            Float(ty) if !features.btf_float => {
+                // Safety: union
+                BtfType::new_struct(0, Vec::new(), unsafe { ty.__bindgen_anon_1.size })
+            }
+            DeclTag(_, _) if !features.btf_decl_tag => BtfType::new_int(0, 1, 0, 0),
+            TypeTag(ty) if !features.btf_type_tag => {
+                // Safety: union
+                BtfType::new_const(0, unsafe { ty.__bindgen_anon_1.type_ })
+            }
+            ty => ty.clone(),
+        })
+    }
+
     pub(crate) fn resolve_type(&self, root_type_id: u32) -> Result<u32, BtfError> {
         let mut type_id = root_type_id;
         for _ in 0..MAX_RESOLVE_DEPTH {
@@ -201,7 +692,7 @@ impl Btf {
 
             use BtfType::*;
             match ty {
-                Volatile(ty) | Const(ty) | Restrict(ty) | Typedef(ty) => {
+                Volatile(ty) | Const(ty) | Restrict(ty) | Typedef(ty) | TypeTag(ty) => {
                     // Safety: union
                     type_id = unsafe { ty.__bindgen_anon_1.type_ };
                     continue;
@@ -225,7 +716,27 @@ impl Btf {
     }
 
     pub(crate) fn id_by_type_name_kind(&self, name: &str, kind: BtfKind) -> Result<u32, BtfError> {
-        for (type_id, ty) in self.types().enumerate() {
+        if let Some(base) = &self.base {
+            if let Some(type_id) = base.split_id_by_type_name_kind(name, kind)? {
+                return Ok(type_id);
+            }
+        }
+
+        self.split_id_by_type_name_kind(name, kind)?
+            .ok_or_else(|| BtfError::UnknownBtfTypeName {
+                type_name: name.to_string(),
+            })
+    }
+
+    /// Like [`Btf::id_by_type_name_kind`], but for split BTF only looks at the types that are not
+    /// part of the base BTF.
+    pub(crate) fn split_id_by_type_name_kind(
+        &self,
+        name: &str,
+        kind: BtfKind,
+    ) -> Result<Option<u32>, BtfError> {
+        let first_type_id = self.first_type_id();
+        for (i, ty) in self.types().enumerate() {
             match ty.kind()? {
                 Some(k) => {
                     if k != kind {
@@ -238,7 +749,7 @@ impl Btf {
             match self.type_name(ty)? {
                 Some(ty_name) => {
                     if ty_name == name {
-                        return Ok(type_id as u32);
+                        return Ok(Some(first_type_id + i as u32));
                     }
                     continue;
                 }
@@ -246,9 +757,7 @@ impl Btf {
             }
         }
 
-        Err(BtfError::UnknownBtfTypeName {
-            type_name: name.to_string(),
-        })
+        Ok(None)
     }
 
     pub(crate) fn type_size(&self, root_type_id: u32) -> Result<usize, BtfError> {
@@ -269,7 +778,12 @@ impl Btf {
                     unsafe { ty.__bindgen_anon_1.size as usize }
                 }
                 Ptr(_) => mem::size_of::<*const c_void>(), // FIXME
-                Typedef(ty) | Volatile(ty) | Const(ty) | Restrict(ty) | Var(ty, _) => {
+                Typedef(ty)
+                | Volatile(ty)
+                | Const(ty)
+                | Restrict(ty)
+                | Var(ty, _)
+                | TypeTag(ty) => {
                     // Safety: union
                     type_id = unsafe { ty.__bindgen_anon_1.type_ };
                     continue;
@@ -279,7 +793,7 @@ impl Btf {
                     type_id = array.type_;
                     continue;
                 }
-                Unknown | Fwd(_) | Func(_) | FuncProto(_, _) => {
+                Unknown | Fwd(_) | Func(_) | FuncProto(_, _) | DeclTag(_, _) => {
                     return Err(BtfError::UnexpectedBtfType { type_id })
                 }
             };
@@ -291,6 +805,72 @@ impl Btf {
             type_id: root_type_id,
         })
     }
+
+    /// Returns a C declaration of a variable called `name` with the type `type_id`, for example
+    /// `struct event name[4]`.
+    ///
+    /// Type qualifiers are dropped. Pointers are declared as `unsigned long long`, since they
+    /// point to kernel memory, and anonymous types as arrays of bytes of the same size.
+    #[doc(hidden)]
+    pub fn c_declaration(&self, type_id: u32, name: &str) -> Result<String, BtfError> {
+        let mut suffix = String::new();
+        let mut current_id = type_id;
+        for _ in 0..MAX_RESOLVE_DEPTH {
+            let ty = self.type_by_id(current_id)?;
+            let type_name = self.type_name(ty)?.filter(|name| !name.is_empty());
+
+            use BtfType::*;
+            let base = match (ty, type_name) {
+                (Volatile(ty), _) | (Const(ty), _) | (Restrict(ty), _) | (TypeTag(ty), _) => {
+                    // Safety: union
+                    current_id = unsafe { ty.__bindgen_anon_1.type_ };
+                    continue;
+                }
+                (Array(_, array), _) => {
+                    suffix.push_str(&format!("[{}]", array.nelems));
+                    current_id = array.type_;
+                    continue;
+                }
+                (Ptr(_), _) => "unsigned long long".to_owned(),
+                (Int(_, _), Some(name)) | (Float(_), Some(name)) | (Typedef(_), Some(name)) => {
+                    name.into_owned()
+                }
+                (Struct(_, _), Some(name)) => format!("struct {}", name),
+                (Union(_, _), Some(name)) => format!("union {}", name),
+                (Enum(_, _), Some(name)) => format!("enum {}", name),
+                (Fwd(ty), Some(name)) => {
+                    // the kind flag is set for forward declarations of unions
+                    let kind = if ty.info >> 31 == 1 {
+                        "union"
+                    } else {
+                        "struct"
+                    };
+                    format!("{} {}", kind, name)
+                }
+                (Struct(_, _), None) | (Union(_, _), None) | (Enum(_, _), None) => {
+                    suffix.push_str(&format!("[{}]", self.type_size(current_id)?));
+                    "unsigned char".to_owned()
+                }
+                _ => {
+                    return Err(BtfError::UnexpectedBtfType {
+                        type_id: current_id,
+                    })
+                }
+            };
+
+            return Ok(format!("{} {}{}", base, name, suffix));
+        }
+
+        Err(BtfError::MaximumTypeDepthReached { type_id })
+    }
+}
+
+/// The split BTF of a kernel module.
+#[derive(Debug)]
+pub(crate) struct ModuleBtf {
+    /// The name of the module.
+    pub(crate) name: String,
+    pub(crate) btf: Btf,
 }
 
 unsafe fn read_btf_header(data: &[u8]) -> btf_header {
@@ -298,19 +878,59 @@ unsafe fn read_btf_header(data: &[u8]) -> btf_header {
     ptr::read_unaligned(data.as_ptr() as *const btf_header)
 }
 
+/// Swaps the byte order of encoded BTF, converting it from the host byte order to the opposite
+/// one, or back if `to_native` is true.
+///
+/// All the fields of the type section are 32 bit words. The string section is left unchanged.
+pub(crate) fn swap_btf_bytes(data: &mut [u8], to_native: bool) -> Result<(), BtfError> {
+    if data.len() < mem::size_of::<btf_header>() {
+        return Err(BtfError::InvalidHeader);
+    }
+
+    // the header must be read in the host byte order
+    let header = if to_native {
+        None
+    } else {
+        Some(unsafe { read_btf_header(data) })
+    };
+    // magic, followed by the version and flags bytes and by the 32 bit fields
+    data.swap(0, 1);
+    for word in data[4..mem::size_of::<btf_header>()].chunks_exact_mut(4) {
+        word.reverse();
+    }
+    let header = header.unwrap_or_else(|| unsafe { read_btf_header(data) });
+
+    let start = header.hdr_len as usize + header.type_off as usize;
+    let end = start + header.type_len as usize;
+    if end > data.len() || header.type_len & 3 != 0 {
+        return Err(BtfError::InvalidTypeInfo);
+    }
+    for word in data[start..end].chunks_exact_mut(4) {
+        word.reverse();
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub struct BtfExt {
     data: Vec<u8>,
     _endianness: Endianness,
     relocations: Vec<(u32, Vec<Relocation>)>,
+    func_info: HashMap<String, Vec<bpf_func_info>>,
+    line_info: HashMap<String, Vec<bpf_line_info>>,
     header: btf_ext_header,
-    _func_info_rec_size: usize,
-    _line_info_rec_size: usize,
+    func_info_rec_size: usize,
+    line_info_rec_size: usize,
     core_relo_rec_size: usize,
 }
 
 impl BtfExt {
-    pub(crate) fn parse(data: &[u8], endianness: Endianness) -> Result<BtfExt, BtfError> {
+    pub(crate) fn parse(
+        data: &[u8],
+        endianness: Endianness,
+        btf: &Btf,
+    ) -> Result<BtfExt, BtfError> {
         // Safety: btf_ext_header is POD so read_unaligned is safe
         let header = unsafe {
             ptr::read_unaligned::<btf_ext_header>(data.as_ptr() as *const btf_ext_header)
@@ -352,8 +972,10 @@ impl BtfExt {
         let mut ext = BtfExt {
             header,
             relocations: Vec::new(),
-            _func_info_rec_size: rec_size(func_info_off, func_info_len)?,
-            _line_info_rec_size: rec_size(line_info_off, line_info_len)?,
+            func_info: HashMap::new(),
+            line_info: HashMap::new(),
+            func_info_rec_size: rec_size(func_info_off, func_info_len)?,
+            line_info_rec_size: rec_size(line_info_off, line_info_len)?,
             core_relo_rec_size: rec_size(core_relo_off, core_relo_len)?,
             data: data.to_vec(),
             _endianness: endianness,
@@ -374,6 +996,28 @@ impl BtfExt {
                 .collect::<Result<Vec<_>, _>>()?,
         );
 
+        ext.func_info.extend(
+            SecInfoIter::new(ext.func_info_data(), ext.func_info_rec_size, endianness)
+                .map(|sec| {
+                    Ok((
+                        btf.string_at(sec.sec_name_off)?.into_owned(),
+                        sec.records(ext.func_info_rec_size)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, BtfError>>()?,
+        );
+
+        ext.line_info.extend(
+            SecInfoIter::new(ext.line_info_data(), ext.line_info_rec_size, endianness)
+                .map(|sec| {
+                    Ok((
+                        btf.string_at(sec.sec_name_off)?.into_owned(),
+                        sec.records(ext.line_info_rec_size)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, BtfError>>()?,
+        );
+
         Ok(ext)
     }
 
@@ -392,9 +1036,83 @@ impl BtfExt {
         self.info_data(self.header.core_relo_off, self.header.core_relo_len)
     }
 
+    fn func_info_data(&self) -> &[u8] {
+        self.info_data(self.header.func_info_off, self.header.func_info_len)
+    }
+
+    fn line_info_data(&self) -> &[u8] {
+        self.info_data(self.header.line_info_off, self.header.line_info_len)
+    }
+
     pub(crate) fn relocations(&self) -> impl Iterator<Item = &(u32, Vec<Relocation>)> {
         self.relocations.iter()
     }
+
+    /// Returns the func_info records of the function at `offset..offset + len` (in bytes) in
+    /// the section `section_name`, with `insn_off` relative to the start of the function.
+    pub(crate) fn func_info(
+        &self,
+        section_name: &str,
+        offset: usize,
+        len: usize,
+    ) -> Vec<bpf_func_info> {
+        function_records(&self.func_info, section_name, offset, len)
+            .map(|info| bpf_func_info {
+                insn_off: (info.insn_off as usize - offset) as u32 / INS_SIZE,
+                ..*info
+            })
+            .collect()
+    }
+
+    /// Returns the line_info records of the function at `offset..offset + len` (in bytes) in
+    /// the section `section_name`, with `insn_off` relative to the start of the function.
+    pub(crate) fn line_info(
+        &self,
+        section_name: &str,
+        offset: usize,
+        len: usize,
+    ) -> Vec<bpf_line_info> {
+        function_records(&self.line_info, section_name, offset, len)
+            .map(|info| bpf_line_info {
+                insn_off: (info.insn_off as usize - offset) as u32 / INS_SIZE,
+                ..*info
+            })
+            .collect()
+    }
+}
+
+const INS_SIZE: u32 = mem::size_of::<bpf_insn>() as u32;
+
+trait InsnOffset {
+    fn insn_off(&self) -> u32;
+}
+
+impl InsnOffset for bpf_func_info {
+    fn insn_off(&self) -> u32 {
+        self.insn_off
+    }
+}
+
+impl InsnOffset for bpf_line_info {
+    fn insn_off(&self) -> u32 {
+        self.insn_off
+    }
+}
+
+fn function_records<'a, T: InsnOffset>(
+    records: &'a HashMap<String, Vec<T>>,
+    section_name: &str,
+    offset: usize,
+    len: usize,
+) -> impl Iterator<Item = &'a T> {
+    records
+        .get(section_name)
+        .into_iter()
+        .flatten()
+        .filter(move |rec| {
+            let insn_off = rec.insn_off() as usize;
+            insn_off >= offset && insn_off < offset + len
+        })
 }
 
 pub(crate) struct SecInfoIter<'a> {
@@ -452,9 +1170,334 @@ pub(crate) struct SecInfo<'a> {
     data: &'a [u8],
 }
 
+impl<'a> SecInfo<'a> {
+    fn records<T>(&self, rec_size: usize) -> Result<Vec<T>, BtfError> {
+        if rec_size < mem::size_of::<T>() {
+            return Err(BtfError::InvalidInfo {
+                offset: 0,
+                len: rec_size,
+                section_len: self.data.len(),
+            });
+        }
+
+        Ok(self
+            .data
+            .chunks_exact(rec_size)
+            // Safety: func_info and line_info records are POD, and we checked that the records
+            // are big enough
+            .map(|rec| unsafe { ptr::read_unaligned::<T>(rec.as_ptr() as *const T) })
+            .collect())
+    }
+}
+
+/// Builds a `.BTF` blob out of type info expressed as u32 words and a string table.
+#[cfg(test)]
+pub(crate) fn btf_bytes(types: &[u32], strings: &[u8]) -> Vec<u8> {
+    let type_len = mem::size_of_val(types) as u32;
+    let header = [
+        0xeb9f_u16.to_ne_bytes()[0],
+        0xeb9f_u16.to_ne_bytes()[1],
+        1,
+        0,
+    ];
+    let mut data = header.to_vec();
+    for word in [24, 0, type_len, type_len, strings.len() as u32] {
+        data.extend_from_slice(&word.to_ne_bytes());
+    }
+    for word in types {
+        data.extend_from_slice(&word.to_ne_bytes());
+    }
+    data.extend_from_slice(strings);
+    data
+}
+
+// The locations searched for the BTF of the running kernel, in order. libbpf searches the same
+// locations.
+fn kernel_btf_paths<P: AsRef<Path>>(release: Option<&str>, search_paths: &[P]) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("/sys/kernel/btf/vmlinux")];
+    for path in search_paths {
+        let path = path.as_ref();
+        paths.push(path.to_owned());
+        if let Some(release) = release {
+            paths.push(path.join(format!("vmlinux-{}", release)));
+            paths.push(path.join(format!("{}.btf", release)));
+        }
+    }
+    if let Some(release) = release {
+        paths.extend(
+            [
+                "/boot/vmlinux-{}",
+                "/lib/modules/{}/vmlinux-{}",
+                "/lib/modules/{}/build/vmlinux",
+                "/usr/lib/modules/{}/kernel/vmlinux",
+                "/usr/lib/debug/boot/vmlinux-{}",
+                "/usr/lib/debug/boot/vmlinux-{}.debug",
+                "/usr/lib/debug/lib/modules/{}/vmlinux",
+            ]
+            .iter()
+            .map(|path| PathBuf::from(path.replace("{}", release))),
+        );
+    }
+
+    paths
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::generated::{
+        BTF_KIND_ARRAY, BTF_KIND_CONST, BTF_KIND_DATASEC, BTF_KIND_DECL_TAG, BTF_KIND_FLOAT,
+        BTF_KIND_FUNC, BTF_KIND_FUNC_PROTO, BTF_KIND_INT, BTF_KIND_PTR, BTF_KIND_STRUCT,
+        BTF_KIND_TYPE_TAG, BTF_KIND_VAR, BTF_VAR_GLOBAL_ALLOCATED,
+    };
+
+    fn test_btf() -> Btf {
+        let strings = b"\0int\0p\0foo\0.data\0xdp\0float\0tag\0";
+        let kind = |kind: u32, vlen: u32| kind << 24 | vlen;
+        #[rustfmt::skip]
+        let types = [
+            // [1] int, 4 bytes
+            1, kind(BTF_KIND_INT, 0), 4, 32,
+            // [2] named pointer to [1]
+            5, kind(BTF_KIND_PTR, 0), 1,
+            // [3] global var foo of type [1]
+            7, kind(BTF_KIND_VAR, 0), 1, BTF_VAR_GLOBAL_ALLOCATED,
+            // [4] .data, size and offsets left to the loader
+            11, kind(BTF_KIND_DATASEC, 1), 0, 3, 0, 4,
+            // [5] int (*)(int) with an unnamed parameter
+            0, kind(BTF_KIND_FUNC_PROTO, 1), 1, 0, 1,
+            // [6] global function foo of type [5]
+            7, kind(BTF_KIND_FUNC, 1), 5,
+            // [7] float, 4 bytes
+            21, kind(BTF_KIND_FLOAT, 0), 4,
+            // [8] decl tag on [3]
+            27, kind(BTF_KIND_DECL_TAG, 0), 3, -1i32 as u32,
+            // [9] type tag on [1]
+            27, kind(BTF_KIND_TYPE_TAG, 0), 1,
+        ];
+        Btf::parse(&btf_bytes(&types, strings), Endianness::default()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_split() {
+        let base = Arc::new(test_btf());
+        let kind = |kind: u32, vlen: u32| kind << 24 | vlen;
+        #[rustfmt::skip]
+        let types = [
+            // [10] pointer to [1] in the base BTF
+            0, kind(BTF_KIND_PTR, 0), 1,
+            // [11] function bar of type [5] in the base BTF
+            31, kind(BTF_KIND_FUNC, 0), 5,
+        ];
+        let mut btf =
+            Btf::parse_split(&btf_bytes(&types, b"bar\0"), base, Endianness::default()).unwrap();
+
+        assert_eq!(btf.first_type_id(), 10);
+        assert_eq!(btf.types().count(), 2);
+        assert!(matches!(btf.type_by_id(1).unwrap(), BtfType::Int(_, _)));
+        assert!(matches!(btf.type_by_id(10).unwrap(), BtfType::Ptr(_)));
+        assert!(matches!(btf.type_by_id(11).unwrap(), BtfType::Func(_)));
+        assert!(btf.type_by_id(12).is_err());
+
+        assert_eq!(btf.string_at(7).unwrap(), "foo");
+        assert_eq!(btf.string_at(31).unwrap(), "bar");
+
+        assert_eq!(btf.id_by_type_name_kind("bar", BtfKind::Func).unwrap(), 11);
+        assert_eq!(btf.id_by_type_name_kind("foo", BtfKind::Func).unwrap(), 6);
+        assert_eq!(
+            btf.split_id_by_type_name_kind("foo", BtfKind::Func)
+                .unwrap(),
+            None
+        );
+
+        assert_eq!(btf.add_string("baz".to_string()), 35);
+        assert_eq!(btf.add_type(BtfType::new_ptr(0, 1)), 12);
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let btf = test_btf();
+        let data = btf.to_bytes();
+        let parsed = Btf::parse(&data, Endianness::default()).unwrap();
+        assert_eq!(parsed.to_bytes(), data);
+        assert_eq!(parsed.types().count(), 10);
+        assert_eq!(
+            parsed.type_name(parsed.type_by_id(3).unwrap()).unwrap(),
+            Some("foo".into())
+        );
+    }
+
+    #[test]
+    fn test_c_declaration() {
+        let kind = |kind: u32, vlen: u32| kind << 24 | vlen;
+        #[rustfmt::skip]
+        let types = [
+            // [1] int, 4 bytes
+            1, kind(BTF_KIND_INT, 0), 4, 32,
+            // [2] struct ev, 8 bytes
+            5, kind(BTF_KIND_STRUCT, 0), 8,
+            // [3] struct ev[4]
+            0, kind(BTF_KIND_ARRAY, 0), 0, 2, 1, 4,
+            // [4] const [3]
+            0, kind(BTF_KIND_CONST, 0), 3,
+            // [5] int *
+            0, kind(BTF_KIND_PTR, 0), 1,
+            // [6] anonymous struct, 12 bytes
+            0, kind(BTF_KIND_STRUCT, 0), 12,
+            // [7] int[2]
+            0, kind(BTF_KIND_ARRAY, 0), 0, 1, 1, 2,
+            // [8] int[3][2]
+            0, kind(BTF_KIND_ARRAY, 0), 0, 7, 1, 3,
+        ];
+        let btf = Btf::parse(&btf_bytes(&types, b"\0int\0ev\0"), Endianness::default()).unwrap();
+
+        assert_eq!(btf.c_declaration(1, "i").unwrap(), "int i");
+        assert_eq!(btf.c_declaration(4, "x").unwrap(), "struct ev x[4]");
+        assert_eq!(btf.c_declaration(5, "p").unwrap(), "unsigned long long p");
+        assert_eq!(btf.c_declaration(6, "a").unwrap(), "unsigned char a[12]");
+        assert_eq!(btf.c_declaration(8, "m").unwrap(), "int m[3][2]");
+        assert!(btf.c_declaration(0, "v").is_err());
+    }
+
+    fn fixup_and_sanitize(features: BtfFeatures) -> Btf {
+        let mut btf = test_btf();
+        let section_sizes = vec![(".data".to_string(), 8)].into_iter().collect();
+        let symbol_offsets = vec![("foo".to_string(), 4)].into_iter().collect();
+        btf.fixup_and_sanitize(&section_sizes, &symbol_offsets, &features)
+            .unwrap();
+        Btf::parse(&btf.to_bytes(), Endianness::default()).unwrap()
+    }
+
+    #[test]
+    fn test_fixup() {
+        let btf = fixup_and_sanitize(BtfFeatures {
+            btf_func: true,
+            btf_func_global: true,
+            btf_datasec: true,
+            btf_float: true,
+            btf_decl_tag: true,
+            btf_type_tag: true,
+        });
+        match btf.type_by_id(2).unwrap() {
+            BtfType::Ptr(ty) => assert_eq!(ty.name_off, 0),
+            ty => panic!("unexpected type {:?}", ty),
+        }
+        match btf.type_by_id(4).unwrap() {
+            BtfType::DataSec(ty, secinfos) => {
+                assert_eq!(unsafe { ty.__bindgen_anon_1.size }, 8);
+                assert_eq!(secinfos[0].offset, 4);
+            }
+            ty => panic!("unexpected type {:?}", ty),
+        }
+        match btf.type_by_id(5).unwrap() {
+            BtfType::FuncProto(_, params) => {
+                assert_eq!(btf.string_at(params[0].name_off).unwrap(), "param0")
+            }
+            ty => panic!("unexpected type {:?}", ty),
+        }
+        assert_eq!(
+            btf.type_by_id(6).unwrap().kind().unwrap(),
+            Some(BtfKind::Func)
+        );
+        assert_eq!(
+            btf.type_by_id(7).unwrap().kind().unwrap(),
+            Some(BtfKind::Float)
+        );
+        assert_eq!(
+            btf.type_by_id(8).unwrap().kind().unwrap(),
+            Some(BtfKind::DeclTag)
+        );
+        assert_eq!(
+            btf.type_by_id(9).unwrap().kind().unwrap(),
+            Some(BtfKind::TypeTag)
+        );
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let btf = fixup_and_sanitize(BtfFeatures::default());
+
+        let kinds = btf
+            .types()
+            .skip(1)
+            .map(|ty| ty.kind().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                BtfKind::Int,
+                BtfKind::Ptr,
+                BtfKind::Int,
+                BtfKind::Struct,
+                BtfKind::Enum,
+                BtfKind::Typedef,
+                BtfKind::Struct,
+                BtfKind::Int,
+                BtfKind::Const,
+            ]
+        );
+
+        // the DATASEC is replaced by a struct with a member for each VAR
+        match btf.type_by_id(4).unwrap() {
+            BtfType::Struct(ty, members) => {
+                assert_eq!(btf.string_at(ty.name_off).unwrap(), "_data");
+                assert_eq!(unsafe { ty.__bindgen_anon_1.size }, 8);
+                assert_eq!(btf.string_at(members[0].name_off).unwrap(), "foo");
+                assert_eq!(members[0].type_, 1);
+                assert_eq!(members[0].offset, 32);
+            }
+            ty => panic!("unexpected type {:?}", ty),
+        }
+        // the float is replaced by a struct of the same size
+        assert_eq!(btf.type_size(7).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_sanitize_func_global() {
+        let btf = fixup_and_sanitize(BtfFeatures {
+            btf_func: true,
+            ..Default::default()
+        });
+        match btf.type_by_id(6).unwrap() {
+            BtfType::Func(ty) => {
+                assert_eq!(type_vlen(ty), btf_func_linkage::BTF_FUNC_STATIC as usize)
+            }
+            ty => panic!("unexpected type {:?}", ty),
+        }
+    }
+
+    #[test]
+    fn test_parse_func_info() {
+        let btf = test_btf();
+        let words = |words: &[u32]| {
+            words
+                .iter()
+                .flat_map(|w| w.to_ne_bytes().to_vec())
+                .collect::<Vec<_>>()
+        };
+        #[rustfmt::skip]
+        let func_info = words(&[
+            // rec_size
+            8,
+            // xdp section, 2 records
+            17, 2,
+            // insn_off (in bytes), type_id
+            0, 5,
+            16, 5,
+        ]);
+        // magic, version and flags followed by hdr_len and the info offsets and lengths
+        let mut data = [&0xeb9f_u16.to_ne_bytes()[..], &[1, 0]].concat();
+        data.extend(words(&[32, 0, func_info.len() as u32, 0, 0, 0, 0]));
+        data.extend(func_info);
+
+        let ext = BtfExt::parse(&data, Endianness::default(), &btf).unwrap();
+        let info = ext.func_info("xdp", 16, 16);
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].insn_off, 0);
+        assert_eq!(info[0].type_id, 5);
+        assert_eq!(ext.func_info("xdp", 0, 32).len(), 2);
+        assert!(ext.func_info(".text", 0, 32).is_empty());
+    }
 
     #[test]
     fn test_parse_header() {
@@ -472,4 +1515,54 @@ mod tests {
         assert_eq!(header.str_off, 0x2a5464);
         assert_eq!(header.str_len, 0x1c6410);
     }
+
+    #[test]
+    fn test_kernel_btf_paths() {
+        let paths = kernel_btf_paths(Some("5.4.0-1"), &["/opt/btf"]);
+        assert_eq!(
+            paths,
+            [
+                "/sys/kernel/btf/vmlinux",
+                "/opt/btf",
+                "/opt/btf/vmlinux-5.4.0-1",
+                "/opt/btf/5.4.0-1.btf",
+                "/boot/vmlinux-5.4.0-1",
+                "/lib/modules/5.4.0-1/vmlinux-5.4.0-1",
+                "/lib/modules/5.4.0-1/build/vmlinux",
+                "/usr/lib/modules/5.4.0-1/kernel/vmlinux",
+                "/usr/lib/debug/boot/vmlinux-5.4.0-1",
+                "/usr/lib/debug/boot/vmlinux-5.4.0-1.debug",
+                "/usr/lib/debug/lib/modules/5.4.0-1/vmlinux",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        );
+
+        let paths = kernel_btf_paths::<&str>(None, &[]);
+        assert_eq!(paths, vec![PathBuf::from("/sys/kernel/btf/vmlinux")]);
+    }
+
+    #[test]
+    fn test_parse_elf() {
+        use object::{write, Architecture, BinaryFormat, SectionKind};
+
+        let btf = test_btf();
+        let mut elf = write::Object::new(
+            BinaryFormat::Elf,
+            Architecture::X86_64,
+            Endianness::default(),
+        );
+        let section = elf.add_section(Vec::new(), b".BTF".to_vec(), SectionKind::Metadata);
+        elf.append_section_data(section, &btf.to_bytes(), 4);
+        let data = elf.write().unwrap();
+
+        let parsed = Btf::parse_elf(&data, Path::new("vmlinux")).unwrap();
+        assert_eq!(parsed.to_bytes(), btf.to_bytes());
+
+        assert!(matches!(
+            Btf::parse_elf(&btf.to_bytes(), Path::new("vmlinux")),
+            Err(BtfError::NoBtfSection { .. })
+        ));
+    }
 }
@@ -0,0 +1,885 @@
+//! Construction, serialization and deduplication of BTF.
+
+use std::{collections::HashMap, mem};
+
+use object::Endianness;
+
+use crate::{
+    generated::{
+        btf_enum, btf_func_linkage, btf_member, btf_param, btf_var_secinfo, BTF_INT_BOOL,
+        BTF_INT_CHAR, BTF_INT_SIGNED,
+    },
+    obj::btf::{swap_btf_bytes, Btf, BtfError, BtfType},
+};
+
+/// The encoding of an integer type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntEncoding {
+    /// An unsigned integer.
+    Unsigned,
+    /// A signed integer.
+    Signed,
+    /// A `char`.
+    Char,
+    /// A `bool`.
+    Bool,
+}
+
+/// The linkage of a function or variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Linkage {
+    /// A static function or variable, only visible in its compilation unit.
+    Static,
+    /// A global function, or a global variable allocated in its compilation unit.
+    Global,
+    /// A function or variable defined elsewhere.
+    Extern,
+}
+
+/// Builds BTF type information.
+///
+/// Types are added one at a time, and each `add_*` method returns the id of the new type, to be
+/// used to reference it from other types. The members of structs and unions, the values of
+/// enums, the parameters of function prototypes and the variables of data sections are added to
+/// the last type of the corresponding kind, so for example a pointer to a struct can be added
+/// before the members of the struct that use it. Strings are deduplicated.
+///
+/// # Examples
+///
+/// ```
+/// use aya::btf::{BtfBuilder, BtfKind, IntEncoding};
+/// use aya::Endianness;
+///
+/// let mut builder = BtfBuilder::new();
+/// let int = builder.add_int("int", 4, IntEncoding::Signed);
+/// let pair = builder.add_struct("pair", 8);
+/// builder.add_member("first", int, 0, 0)?;
+/// builder.add_member("second", int, 32, 0)?;
+///
+/// // the contents of a .BTF section
+/// let data = builder.to_bytes(Endianness::default())?;
+/// assert_eq!(&data[..2], &0xeb9f_u16.to_ne_bytes());
+///
+/// let btf = builder.build()?;
+/// assert_eq!(btf.find_type("pair", BtfKind::Struct)?.id(), pair);
+/// # Ok::<(), aya::BtfError>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct BtfBuilder {
+    btf: Btf,
+    strings: HashMap<String, u32>,
+    next_type_id: u32,
+    // the types that members, values, parameters and variables are added to
+    last_composite_id: u32,
+    last_enum_id: u32,
+    last_func_proto_id: u32,
+    last_datasec_id: u32,
+}
+
+impl BtfBuilder {
+    /// Creates a builder with no types, other than the implicit `void` type with id `0`.
+    pub fn new() -> BtfBuilder {
+        BtfBuilder {
+            btf: Btf::new(),
+            strings: HashMap::new(),
+            next_type_id: 1,
+            last_composite_id: 0,
+            last_enum_id: 0,
+            last_func_proto_id: 0,
+            last_datasec_id: 0,
+        }
+    }
+
+    /// Adds a string to the string section and returns its offset.
+    ///
+    /// Adding the same string again returns the same offset. The empty string is at offset `0`.
+    pub fn add_string(&mut self, s: &str) -> u32 {
+        if s.is_empty() {
+            return 0;
+        }
+        if let Some(offset) = self.strings.get(s) {
+            return *offset;
+        }
+        let offset = self.btf.add_string(s.to_owned());
+        self.strings.insert(s.to_owned(), offset);
+        offset
+    }
+
+    fn add_type(&mut self, ty: BtfType) -> u32 {
+        let last_id = match ty {
+            BtfType::Struct(_, _) | BtfType::Union(_, _) => Some(&mut self.last_composite_id),
+            BtfType::Enum(_, _) => Some(&mut self.last_enum_id),
+            BtfType::FuncProto(_, _) => Some(&mut self.last_func_proto_id),
+            BtfType::DataSec(_, _) => Some(&mut self.last_datasec_id),
+            _ => None,
+        };
+        let type_id = self.next_type_id;
+        if let Some(last_id) = last_id {
+            *last_id = type_id;
+        }
+        self.next_type_id = self.btf.add_type(ty) + 1;
+        type_id
+    }
+
+    /// Adds an integer type of `size` bytes.
+    pub fn add_int(&mut self, name: &str, size: u32, encoding: IntEncoding) -> u32 {
+        let name_off = self.add_string(name);
+        let encoding = match encoding {
+            IntEncoding::Unsigned => 0,
+            IntEncoding::Signed => BTF_INT_SIGNED,
+            IntEncoding::Char => BTF_INT_CHAR,
+            IntEncoding::Bool => BTF_INT_BOOL,
+        };
+        self.add_type(BtfType::new_int(name_off, size, encoding, 0))
+    }
+
+    /// Adds a floating point type of `size` bytes.
+    pub fn add_float(&mut self, name: &str, size: u32) -> u32 {
+        let name_off = self.add_string(name);
+        self.add_type(BtfType::new_float(name_off, size))
+    }
+
+    /// Adds a pointer to `type_id`.
+    pub fn add_ptr(&mut self, type_id: u32) -> u32 {
+        self.add_type(BtfType::new_ptr(0, type_id))
+    }
+
+    /// Adds a `const` qualified `type_id`.
+    pub fn add_const(&mut self, type_id: u32) -> u32 {
+        self.add_type(BtfType::new_const(0, type_id))
+    }
+
+    /// Adds a `volatile` qualified `type_id`.
+    pub fn add_volatile(&mut self, type_id: u32) -> u32 {
+        self.add_type(BtfType::new_volatile(0, type_id))
+    }
+
+    /// Adds a `restrict` qualified `type_id`.
+    pub fn add_restrict(&mut self, type_id: u32) -> u32 {
+        self.add_type(BtfType::new_restrict(0, type_id))
+    }
+
+    /// Adds a typedef called `name` for `type_id`.
+    pub fn add_typedef(&mut self, name: &str, type_id: u32) -> u32 {
+        let name_off = self.add_string(name);
+        self.add_type(BtfType::new_typedef(name_off, type_id))
+    }
+
+    /// Adds `type_id` tagged with `tag`.
+    pub fn add_type_tag(&mut self, tag: &str, type_id: u32) -> u32 {
+        let name_off = self.add_string(tag);
+        self.add_type(BtfType::new_type_tag(name_off, type_id))
+    }
+
+    /// Adds a forward declaration of a struct, or of a union if `is_union` is true.
+    pub fn add_fwd(&mut self, name: &str, is_union: bool) -> u32 {
+        let name_off = self.add_string(name);
+        self.add_type(BtfType::new_fwd(name_off, is_union))
+    }
+
+    /// Adds an array of `len` elements of `element_type_id`, indexed by `index_type_id`.
+    pub fn add_array(&mut self, element_type_id: u32, index_type_id: u32, len: u32) -> u32 {
+        self.add_type(BtfType::new_array(0, element_type_id, index_type_id, len))
+    }
+
+    /// Adds a struct of `size` bytes.
+    ///
+    /// Use [`BtfBuilder::add_member`] to add the members. Pass an empty name for anonymous
+    /// structs.
+    pub fn add_struct(&mut self, name: &str, size: u32) -> u32 {
+        let name_off = self.add_string(name);
+        self.add_type(BtfType::new_struct(name_off, Vec::new(), size))
+    }
+
+    /// Adds a union of `size` bytes.
+    ///
+    /// Use [`BtfBuilder::add_member`] to add the members. Pass an empty name for anonymous
+    /// unions.
+    pub fn add_union(&mut self, name: &str, size: u32) -> u32 {
+        let name_off = self.add_string(name);
+        self.add_type(BtfType::new_union(name_off, Vec::new(), size))
+    }
+
+    /// Adds a member to the struct or union that was added last.
+    ///
+    /// `bitfield_size` is the size in bits of bitfield members, or `0` for other members.
+    pub fn add_member(
+        &mut self,
+        name: &str,
+        type_id: u32,
+        bit_offset: u32,
+        bitfield_size: u32,
+    ) -> Result<(), BtfError> {
+        if bitfield_size > 0xFF {
+            return Err(BtfError::InvalidBitfield {
+                bit_offset,
+                bitfield_size,
+            });
+        }
+        let name_off = self.add_string(name);
+        let last_id = self.last_composite_id;
+        match self.btf.type_by_id_mut(last_id)? {
+            BtfType::Struct(ty, members) | BtfType::Union(ty, members) => {
+                // bitfields need the kind flag, with which the offsets are limited to 24 bits
+                let had_k_flag = ty.info >> 31 == 1;
+                let k_flag = had_k_flag || bitfield_size > 0;
+                if k_flag
+                    && (bit_offset > 0xFFFFFF
+                        || !had_k_flag && members.iter().any(|m| m.offset > 0xFFFFFF))
+                {
+                    return Err(BtfError::InvalidBitfield {
+                        bit_offset,
+                        bitfield_size,
+                    });
+                }
+                let offset = if k_flag {
+                    ty.info |= 1 << 31;
+                    bitfield_size << 24 | bit_offset
+                } else {
+                    bit_offset
+                };
+                members.push(btf_member {
+                    name_off,
+                    type_: type_id,
+                    offset,
+                });
+                ty.info = (ty.info & !0xFFFF) | members.len() as u32;
+                Ok(())
+            }
+            _ => Err(BtfError::UnexpectedBtfType { type_id: last_id }),
+        }
+    }
+
+    /// Adds an enum of `size` bytes.
+    ///
+    /// Use [`BtfBuilder::add_enum_value`] to add the values.
+    pub fn add_enum(&mut self, name: &str, size: u32) -> u32 {
+        let name_off = self.add_string(name);
+        let mut ty = BtfType::new_enum(name_off, Vec::new());
+        if let BtfType::Enum(ty, _) = &mut ty {
+            ty.__bindgen_anon_1.size = size;
+        }
+        self.add_type(ty)
+    }
+
+    /// Adds a value to the enum that was added last.
+    pub fn add_enum_value(&mut self, name: &str, value: i32) -> Result<(), BtfError> {
+        let name_off = self.add_string(name);
+        let last_id = self.last_enum_id;
+        match self.btf.type_by_id_mut(last_id)? {
+            BtfType::Enum(ty, values) => {
+                values.push(btf_enum {
+                    name_off,
+                    val: value,
+                });
+                ty.info = (ty.info & !0xFFFF) | values.len() as u32;
+                Ok(())
+            }
+            _ => Err(BtfError::UnexpectedBtfType { type_id: last_id }),
+        }
+    }
+
+    /// Adds a function prototype returning `return_type_id`.
+    ///
+    /// Use [`BtfBuilder::add_param`] to add the parameters.
+    pub fn add_func_proto(&mut self, return_type_id: u32) -> u32 {
+        self.add_type(BtfType::new_func_proto(Vec::new(), return_type_id))
+    }
+
+    /// Adds a parameter to the function prototype that was added last.
+    ///
+    /// A last parameter with an empty name and type id `0` makes the function variadic.
+    pub fn add_param(&mut self, name: &str, type_id: u32) -> Result<(), BtfError> {
+        let name_off = self.add_string(name);
+        let last_id = self.last_func_proto_id;
+        match self.btf.type_by_id_mut(last_id)? {
+            BtfType::FuncProto(ty, params) => {
+                params.push(btf_param {
+                    name_off,
+                    type_: type_id,
+                });
+                ty.info = (ty.info & !0xFFFF) | params.len() as u32;
+                Ok(())
+            }
+            _ => Err(BtfError::UnexpectedBtfType { type_id: last_id }),
+        }
+    }
+
+    /// Adds a function with the prototype `proto_type_id`.
+    pub fn add_func(&mut self, name: &str, proto_type_id: u32, linkage: Linkage) -> u32 {
+        let name_off = self.add_string(name);
+        let linkage = match linkage {
+            Linkage::Static => btf_func_linkage::BTF_FUNC_STATIC,
+            Linkage::Global => btf_func_linkage::BTF_FUNC_GLOBAL,
+            Linkage::Extern => btf_func_linkage::BTF_FUNC_EXTERN,
+        };
+        self.add_type(BtfType::new_func(name_off, proto_type_id, linkage))
+    }
+
+    /// Adds a global variable of type `type_id`.
+    pub fn add_var(&mut self, name: &str, type_id: u32, linkage: Linkage) -> u32 {
+        let name_off = self.add_string(name);
+        // the variable linkages have the same values as the function ones
+        let linkage = match linkage {
+            Linkage::Static => btf_func_linkage::BTF_FUNC_STATIC,
+            Linkage::Global => btf_func_linkage::BTF_FUNC_GLOBAL,
+            Linkage::Extern => btf_func_linkage::BTF_FUNC_EXTERN,
+        };
+        self.add_type(BtfType::new_var(name_off, type_id, linkage as u32))
+    }
+
+    /// Adds a data section of `size` bytes.
+    ///
+    /// Use [`BtfBuilder::add_datasec_var`] to add the variables it contains.
+    pub fn add_datasec(&mut self, name: &str, size: u32) -> u32 {
+        let name_off = self.add_string(name);
+        self.add_type(BtfType::new_datasec(name_off, Vec::new(), size))
+    }
+
+    /// Adds the variable `var_type_id` to the data section that was added last.
+    pub fn add_datasec_var(
+        &mut self,
+        var_type_id: u32,
+        offset: u32,
+        size: u32,
+    ) -> Result<(), BtfError> {
+        let last_id = self.last_datasec_id;
+        match self.btf.type_by_id_mut(last_id)? {
+            BtfType::DataSec(ty, secinfos) => {
+                secinfos.push(btf_var_secinfo {
+                    type_: var_type_id,
+                    offset,
+                    size,
+                });
+                ty.info = (ty.info & !0xFFFF) | secinfos.len() as u32;
+                Ok(())
+            }
+            _ => Err(BtfError::UnexpectedBtfType { type_id: last_id }),
+        }
+    }
+
+    /// Adds the declaration tag `tag` to `type_id`.
+    ///
+    /// `component` is the index of the member or parameter the tag applies to, or `None` if it
+    /// applies to the type itself.
+    pub fn add_decl_tag(&mut self, tag: &str, type_id: u32, component: Option<u32>) -> u32 {
+        let name_off = self.add_string(tag);
+        let component_idx = component.map(|c| c as i32).unwrap_or(-1);
+        self.add_type(BtfType::new_decl_tag(name_off, type_id, component_idx))
+    }
+
+    /// Adds all the types of `btf` and returns the new id of its first type.
+    ///
+    /// The types keep their relative order, so a type with id `n` in `btf` gets the id
+    /// `first_id + n - 1`. For split BTF, the types of the base BTF are added as well. Use
+    /// [`BtfBuilder::dedup`] to merge the types that were already added.
+    pub fn add_btf(&mut self, btf: &Btf) -> Result<u32, BtfError> {
+        let first_id = self.next_type_id;
+        let type_count = btf.first_type_id() + btf.types().count() as u32;
+        for type_id in 1..type_count {
+            let ty = btf.type_by_id(type_id)?.remap(
+                |type_id| {
+                    if type_id == 0 {
+                        0
+                    } else {
+                        first_id + type_id - 1
+                    }
+                },
+                |name_off| self.copy_string(btf, name_off),
+            )?;
+            self.add_type(ty);
+        }
+
+        Ok(first_id)
+    }
+
+    /// Adds the type `type_id` of `btf` along with all the types it references, and returns its
+    /// new id.
+    ///
+    /// This is useful to extract a subset of a large BTF, like the one of the kernel.
+    pub fn add_type_from(&mut self, btf: &Btf, type_id: u32) -> Result<u32, BtfError> {
+        let type_ids = self.copy_types(btf, &[type_id], |_, _| true)?;
+        Ok(type_ids[&type_id])
+    }
+
+    /// Adds the types `type_ids` of `btf` along with the types they reference, keeping only the
+    /// members of structs and unions for which `keep_member(type_id, index)` returns true.
+    /// Returns the new ids of `type_ids`.
+    ///
+    /// The kept members have the same offsets as in `btf`, and structs and unions keep their
+    /// size. Types only referenced by members that are dropped aren't added. This can be used
+    /// with [`Object::core_relocation_targets`] to extract from the BTF of a kernel the
+    /// minimum needed to apply the CO-RE relocations of an object.
+    ///
+    /// [`Object::core_relocation_targets`]: crate::inspect::Object::core_relocation_targets
+    pub fn add_pruned_types_from<F>(
+        &mut self,
+        btf: &Btf,
+        type_ids: &[u32],
+        keep_member: F,
+    ) -> Result<Vec<u32>, BtfError>
+    where
+        F: Fn(u32, usize) -> bool,
+    {
+        let new_ids = self.copy_types(btf, type_ids, keep_member)?;
+        Ok(type_ids.iter().map(|type_id| new_ids[type_id]).collect())
+    }
+
+    fn copy_types<F>(
+        &mut self,
+        btf: &Btf,
+        roots: &[u32],
+        keep_member: F,
+    ) -> Result<HashMap<u32, u32>, BtfError>
+    where
+        F: Fn(u32, usize) -> bool,
+    {
+        // find all the types to copy first, since types can reference each other
+        let mut type_ids = HashMap::new();
+        type_ids.insert(0, 0);
+        let mut order = Vec::new();
+        let mut stack = roots.iter().rev().copied().collect::<Vec<_>>();
+        while let Some(type_id) = stack.pop() {
+            if type_ids.contains_key(&type_id) {
+                continue;
+            }
+            type_ids.insert(type_id, self.next_type_id + order.len() as u32);
+            let ty = prune_members(btf.type_by_id(type_id)?, type_id, &keep_member);
+            stack.extend(ty.referenced_types().into_iter().rev());
+            order.push(ty);
+        }
+
+        for ty in order {
+            let ty = ty.remap(
+                |type_id| type_ids.get(&type_id).copied().unwrap_or(type_id),
+                |name_off| self.copy_string(btf, name_off),
+            )?;
+            self.add_type(ty);
+        }
+
+        Ok(type_ids)
+    }
+
+    fn copy_string(&mut self, btf: &Btf, name_off: u32) -> Result<u32, BtfError> {
+        if name_off == 0 {
+            return Ok(0);
+        }
+        let s = btf.string_at(name_off)?;
+        Ok(self.add_string(&s))
+    }
+
+    /// Merges the types that are identical, and returns the new id of each type, indexed by the
+    /// old id.
+    ///
+    /// Two types are identical when they have the same kind, name and layout, and reference
+    /// identical types. This also applies to types that reference themselves, for example
+    /// through pointers. Forward declarations are replaced by the struct or union they declare,
+    /// if there is a single one with the same name. Variables and data sections are never merged.
+    ///
+    /// Unused strings are dropped as well. The types that are kept stay in the same order.
+    pub fn dedup(&mut self) -> Result<Vec<u32>, BtfError> {
+        let type_count = self.next_type_id as usize;
+        let mut signatures = Vec::with_capacity(type_count);
+        let mut refs = Vec::with_capacity(type_count);
+        for type_id in 0..type_count as u32 {
+            let ty = self.btf.type_by_id(type_id)?;
+            // the layout of the type, without the types it references
+            let mut signature = ty.remap::<BtfError>(|_| 0, Ok)?.to_bytes();
+            if let BtfType::Var(_, _) | BtfType::DataSec(_, _) = ty {
+                signature.extend_from_slice(&type_id.to_ne_bytes());
+            }
+            signatures.push(signature);
+            refs.push(ty.referenced_types());
+        }
+
+        let mut redirects = (0..type_count as u32).collect::<Vec<_>>();
+        let classes = equivalence_classes(&signatures, &refs, &redirects);
+
+        // forward declarations are resolved when a single struct or union has the same name
+        let mut candidates = HashMap::new();
+        for type_id in 0..type_count as u32 {
+            let (name_off, is_union) = match self.btf.type_by_id(type_id)? {
+                BtfType::Struct(ty, _) => (ty.name_off, false),
+                BtfType::Union(ty, _) => (ty.name_off, true),
+                _ => continue,
+            };
+            if name_off == 0 {
+                continue;
+            }
+            candidates
+                .entry((name_off, is_union))
+                .and_modify(|candidate: &mut Option<u32>| {
+                    if let Some(candidate_id) = candidate {
+                        if classes[*candidate_id as usize] != classes[type_id as usize] {
+                            *candidate = None;
+                        }
+                    }
+                })
+                .or_insert(Some(type_id));
+        }
+        let mut has_redirects = false;
+        for (type_id, redirect) in redirects.iter_mut().enumerate() {
+            if let BtfType::Fwd(ty) = self.btf.type_by_id(type_id as u32)? {
+                let is_union = ty.info >> 31 == 1;
+                if let Some(Some(target_id)) = candidates.get(&(ty.name_off, is_union)) {
+                    *redirect = *target_id;
+                    has_redirects = true;
+                }
+            }
+        }
+        let classes = if has_redirects {
+            equivalence_classes(&signatures, &refs, &redirects)
+        } else {
+            classes
+        };
+
+        // the first type of each class is kept
+        let mut class_ids = HashMap::new();
+        let mut kept = Vec::new();
+        for type_id in 0..type_count {
+            if redirects[type_id] as usize != type_id {
+                continue;
+            }
+            class_ids.entry(classes[type_id]).or_insert_with(|| {
+                kept.push(type_id as u32);
+                kept.len() as u32 - 1
+            });
+        }
+        let type_ids = redirects
+            .iter()
+            .map(|redirect| class_ids[&classes[*redirect as usize]])
+            .collect::<Vec<_>>();
+
+        let old = mem::take(self);
+        for type_id in kept.into_iter().skip(1) {
+            let ty = old.btf.type_by_id(type_id)?.remap(
+                |type_id| type_ids.get(type_id as usize).copied().unwrap_or(type_id),
+                |name_off| self.copy_string(&old.btf, name_off),
+            )?;
+            self.add_type(ty);
+        }
+
+        Ok(type_ids)
+    }
+
+    /// Serializes the types to the `.BTF` format, with the given byte order.
+    pub fn to_bytes(&self, endianness: Endianness) -> Result<Vec<u8>, BtfError> {
+        let mut data = self.btf.to_bytes();
+        if endianness != Endianness::default() {
+            swap_btf_bytes(&mut data, false)?;
+        }
+        Ok(data)
+    }
+
+    /// Returns a [`Btf`] object with the types added so far.
+    pub fn build(&self) -> Result<Btf, BtfError> {
+        let endianness = Endianness::default();
+        Btf::parse(&self.to_bytes(endianness)?, endianness)
+    }
+}
+
+impl Default for BtfBuilder {
+    fn default() -> Self {
+        BtfBuilder::new()
+    }
+}
+
+fn prune_members<F>(ty: &BtfType, type_id: u32, keep_member: &F) -> BtfType
+where
+    F: Fn(u32, usize) -> bool,
+{
+    let mut pruned = ty.clone();
+    if let BtfType::Struct(ty, members) | BtfType::Union(ty, members) = &mut pruned {
+        let mut index = 0;
+        members.retain(|_| {
+            index += 1;
+            keep_member(type_id, index - 1)
+        });
+        ty.info = (ty.info & !0xFFFF) | members.len() as u32;
+    }
+    pruned
+}
+
+/// Partitions the types in classes of identical types, and returns the class of each type.
+///
+/// Types start in the same class when they have the same signature, then the classes are split
+/// until all the types in a class reference types of the same classes. References are followed
+/// through `redirects` first.
+fn equivalence_classes(signatures: &[Vec<u8>], refs: &[Vec<u32>], redirects: &[u32]) -> Vec<usize> {
+    let mut ids = HashMap::new();
+    let mut classes = signatures
+        .iter()
+        .map(|signature| {
+            let next_class = ids.len();
+            *ids.entry(signature).or_insert(next_class)
+        })
+        .collect::<Vec<_>>();
+    let mut class_count = ids.len();
+
+    loop {
+        let mut ids = HashMap::new();
+        let new_classes = classes
+            .iter()
+            .zip(refs)
+            .map(|(class, refs)| {
+                let ref_classes = refs
+                    .iter()
+                    .map(|type_id| {
+                        redirects
+                            .get(*type_id as usize)
+                            .and_then(|type_id| classes.get(*type_id as usize))
+                            .copied()
+                            .unwrap_or(usize::MAX)
+                    })
+                    .collect::<Vec<_>>();
+                let next_class = ids.len();
+                *ids.entry((*class, ref_classes)).or_insert(next_class)
+            })
+            .collect::<Vec<_>>();
+
+        // each pass can only split classes, it's done when no class was split
+        if ids.len() == class_count {
+            return new_classes;
+        }
+        class_count = ids.len();
+        classes = new_classes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj::btf::BtfKind;
+
+    fn list_types(builder: &mut BtfBuilder) -> u32 {
+        let int = builder.add_int("int", 4, IntEncoding::Signed);
+        let list_head = builder.add_struct("list_head", 16);
+        let list_head_ptr = builder.add_ptr(list_head);
+        builder.add_member("next", list_head_ptr, 0, 0).unwrap();
+        builder.add_member("prev", list_head_ptr, 64, 0).unwrap();
+        let node = builder.add_struct("node", 24);
+        builder.add_member("list", list_head, 0, 0).unwrap();
+        builder.add_member("value", int, 128, 0).unwrap();
+        builder.add_member("flags", int, 160, 3).unwrap();
+        node
+    }
+
+    fn all_types(builder: &mut BtfBuilder) {
+        let node = list_types(builder);
+        let char_ = builder.add_int("char", 1, IntEncoding::Char);
+        let array = builder.add_array(char_, 1, 16);
+        builder.add_typedef("name_t", array);
+        let state = builder.add_enum("state", 4);
+        builder.add_enum_value("RUNNING", 0).unwrap();
+        builder.add_enum_value("STOPPED", -1).unwrap();
+        let const_node = builder.add_const(node);
+        let node_ptr = builder.add_ptr(const_node);
+        let proto = builder.add_func_proto(1);
+        builder.add_param("node", node_ptr).unwrap();
+        builder.add_param("state", state).unwrap();
+        let func = builder.add_func("handle", proto, Linkage::Global);
+        builder.add_decl_tag("hook", func, Some(0));
+        builder.add_fwd("sock", false);
+        builder.add_float("double", 8);
+        let var = builder.add_var("counter", 1, Linkage::Global);
+        builder.add_datasec(".data", 4);
+        builder.add_datasec_var(var, 0, 4).unwrap();
+    }
+
+    fn foreign_endianness() -> Endianness {
+        match Endianness::default() {
+            Endianness::Little => Endianness::Big,
+            Endianness::Big => Endianness::Little,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut builder = BtfBuilder::new();
+        all_types(&mut builder);
+        let data = builder.to_bytes(Endianness::default()).unwrap();
+
+        let btf = Btf::parse(&data, Endianness::default()).unwrap();
+        assert_eq!(btf.to_bytes(), data);
+        assert_eq!(btf.iter_types().count(), 17);
+
+        let node = btf.find_type("node", BtfKind::Struct).unwrap();
+        let flags = node.member("flags").unwrap();
+        assert_eq!(flags.bit_offset, 160);
+        assert_eq!(flags.bitfield_size, Some(3));
+        assert_eq!(node.member("value").unwrap().bit_offset, 128);
+        let handle = btf.find_type("handle", BtfKind::Func).unwrap();
+        let params = handle.params();
+        assert_eq!(params[1].ty().unwrap().name().unwrap(), "state");
+        let state = btf.find_type("state", BtfKind::Enum).unwrap();
+        assert_eq!(state.enum_values()[1].value, -1);
+    }
+
+    #[test]
+    fn test_round_trip_foreign_endianness() {
+        let mut builder = BtfBuilder::new();
+        all_types(&mut builder);
+        let native = builder.to_bytes(Endianness::default()).unwrap();
+        let foreign = builder.to_bytes(foreign_endianness()).unwrap();
+        assert_ne!(native, foreign);
+        assert_eq!(&foreign[..2], &0xeb9f_u16.swap_bytes().to_ne_bytes());
+
+        let btf = Btf::parse(&foreign, foreign_endianness()).unwrap();
+        assert_eq!(btf.to_bytes(), native);
+    }
+
+    #[test]
+    fn test_strings_are_deduplicated() {
+        let mut builder = BtfBuilder::new();
+        let a = builder.add_string("foo");
+        let b = builder.add_string("bar");
+        assert_ne!(a, b);
+        assert_eq!(builder.add_string("foo"), a);
+        assert_eq!(builder.add_string(""), 0);
+    }
+
+    #[test]
+    fn test_add_to_wrong_type() {
+        let mut builder = BtfBuilder::new();
+        let int = builder.add_int("int", 4, IntEncoding::Signed);
+        // no struct was added yet
+        assert!(matches!(
+            builder.add_member("a", int, 0, 0),
+            Err(BtfError::UnexpectedBtfType { type_id: 0 })
+        ));
+        assert!(matches!(
+            builder.add_enum_value("A", 0),
+            Err(BtfError::UnexpectedBtfType { .. })
+        ));
+        builder.add_struct("s", 4);
+        assert!(matches!(
+            builder.add_member("a", int, 0, 256),
+            Err(BtfError::InvalidBitfield { .. })
+        ));
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut input = BtfBuilder::new();
+        list_types(&mut input);
+        let input = input.build().unwrap();
+
+        let mut builder = BtfBuilder::new();
+        assert_eq!(builder.add_btf(&input).unwrap(), 1);
+        let second = builder.add_btf(&input).unwrap();
+        assert_eq!(second, 5);
+        // a struct referencing node through a forward declaration, and an identical struct
+        // referencing the definition
+        let fwd = builder.add_fwd("node", false);
+        let fwd_ptr = builder.add_ptr(fwd);
+        builder.add_struct("holder", 8);
+        builder.add_member("node", fwd_ptr, 0, 0).unwrap();
+        let node_ptr = builder.add_ptr(4);
+        builder.add_struct("holder", 8);
+        builder.add_member("node", node_ptr, 0, 0).unwrap();
+        // variables are never merged
+        builder.add_var("counter", 1, Linkage::Global);
+        builder.add_var("counter", 5, Linkage::Global);
+
+        let type_ids = builder.dedup().unwrap();
+        assert_eq!(
+            type_ids,
+            vec![0, 1, 2, 3, 4, 1, 2, 3, 4, 4, 5, 6, 5, 6, 7, 8]
+        );
+
+        let btf = builder.build().unwrap();
+        let kinds = btf.iter_types().map(|ty| ty.kind()).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                BtfKind::Int,
+                BtfKind::Struct,
+                BtfKind::Ptr,
+                BtfKind::Struct,
+                BtfKind::Ptr,
+                BtfKind::Struct,
+                BtfKind::Var,
+                BtfKind::Var,
+            ]
+        );
+        let holder = btf.find_type("holder", BtfKind::Struct).unwrap();
+        let node = holder.members()[0].ty().unwrap().referenced_type().unwrap();
+        assert_eq!(node.name().unwrap(), "node");
+        assert_eq!(node.kind(), BtfKind::Struct);
+    }
+
+    #[test]
+    fn test_dedup_keeps_different_types() {
+        let mut builder = BtfBuilder::new();
+        let int = builder.add_int("int", 4, IntEncoding::Signed);
+        let uint = builder.add_int("unsigned int", 4, IntEncoding::Unsigned);
+        builder.add_struct("a", 4);
+        builder.add_member("x", int, 0, 0).unwrap();
+        builder.add_struct("a", 4);
+        builder.add_member("x", uint, 0, 0).unwrap();
+        // two forward declarations can't be resolved when the name is ambiguous
+        builder.add_fwd("a", false);
+
+        let type_ids = builder.dedup().unwrap();
+        assert_eq!(type_ids, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_add_type_from() {
+        let mut input = BtfBuilder::new();
+        all_types(&mut input);
+        let input = input.build().unwrap();
+        let node_id = input.find_type("node", BtfKind::Struct).unwrap().id();
+
+        let mut builder = BtfBuilder::new();
+        let new_id = builder.add_type_from(&input, node_id).unwrap();
+        assert_eq!(new_id, 1);
+        let btf = builder.build().unwrap();
+        let names = btf
+            .iter_types()
+            .map(|ty| ty.name().map(|name| name.into_owned()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                Some("node".to_owned()),
+                Some("list_head".to_owned()),
+                None,
+                Some("int".to_owned()),
+            ]
+        );
+        let node = btf.get_type(new_id).unwrap();
+        assert_eq!(node.member("value").unwrap().ty().unwrap().id(), 4);
+    }
+
+    #[test]
+    fn test_add_pruned_types_from() {
+        let mut input = BtfBuilder::new();
+        all_types(&mut input);
+        let input = input.build().unwrap();
+        let node_id = input.find_type("node", BtfKind::Struct).unwrap().id();
+        let state_id = input.find_type("state", BtfKind::Enum).unwrap().id();
+
+        let mut builder = BtfBuilder::new();
+        let new_ids = builder
+            .add_pruned_types_from(&input, &[node_id, state_id], |type_id, index| {
+                type_id == node_id && index == 1
+            })
+            .unwrap();
+        assert_eq!(new_ids, vec![1, 3]);
+        let btf = builder.build().unwrap();
+        let names = btf
+            .iter_types()
+            .map(|ty| ty.name().unwrap().into_owned())
+            .collect::<Vec<_>>();
+        // list_head is only referenced by the dropped member
+        assert_eq!(names, vec!["node", "int", "state"]);
+        let node = btf.get_type(1).unwrap();
+        assert_eq!(node.size().unwrap(), 24);
+        let members = node.members();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].name.as_deref(), Some("value"));
+        assert_eq!(members[0].bit_offset, 128);
+        assert_eq!(btf.get_type(3).unwrap().enum_values().len(), 2);
+    }
+}
@@ -0,0 +1,885 @@
+//! Dumping of BTF types as C declarations.
+//!
+//! This follows the algorithm of libbpf's `btf_dump`, so that the output matches the one of
+//! `bpftool btf dump format c`. Types are first put in an order where each type comes after the
+//! types it embeds, then emitted, with forward declarations breaking the cycles that go through
+//! pointers.
+
+use std::{collections::HashMap, mem};
+
+use crate::obj::btf::{
+    graph::ref_type, member_bit_field_size, member_bit_offset, Btf, BtfError, BtfType,
+    MAX_RESOLVE_DEPTH,
+};
+
+impl Btf {
+    /// Returns the types of this BTF as a C header, like `bpftool btf dump format c`.
+    ///
+    /// All the struct, union, enum and typedef definitions are emitted, in an order that makes the
+    /// header compile, with forward declarations where needed. Types that would have the same
+    /// name in C are disambiguated with a `___N` suffix. For split BTF, the types of the base BTF
+    /// are included as well.
+    ///
+    /// Like `bpftool`, the definitions are wrapped in a `preserve_access_index` attribute so that
+    /// programs compiled against the header get CO-RE relocations.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::Btf;
+    ///
+    /// let btf = Btf::from_sys_fs()?;
+    /// std::fs::write("vmlinux.h", btf.to_c_header()?)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn to_c_header(&self) -> Result<String, BtfError> {
+        let mut dump = CDump::new(self)?;
+        dump.out.push_str(
+            "#ifndef __VMLINUX_H__\n\
+             #define __VMLINUX_H__\n\
+             \n\
+             #ifndef BPF_NO_PRESERVE_ACCESS_INDEX\n\
+             #pragma clang attribute push (__attribute__((preserve_access_index)), apply_to = record)\n\
+             #endif\n\
+             \n",
+        );
+        for type_id in 1..dump.type_count {
+            dump.order_type(type_id, false)?;
+            for type_id in mem::take(&mut dump.queue) {
+                dump.emit_type(type_id, 0)?;
+            }
+        }
+        dump.out.push_str(
+            "#ifndef BPF_NO_PRESERVE_ACCESS_INDEX\n\
+             #pragma clang attribute pop\n\
+             #endif\n\
+             \n\
+             #endif /* __VMLINUX_H__ */\n",
+        );
+
+        Ok(dump.out)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    NotDone,
+    InProgress,
+    Done,
+}
+
+struct CDump<'a> {
+    btf: &'a Btf,
+    type_count: u32,
+    ptr_size: usize,
+    out: String,
+    // whether each type is referenced by another type
+    referenced: Vec<bool>,
+    order_state: Vec<State>,
+    emit_state: Vec<State>,
+    fwd_emitted: Vec<bool>,
+    // the top level types to emit, in order
+    queue: Vec<u32>,
+    // the C names of the types, with the ___N suffix for duplicates
+    names: HashMap<u32, String>,
+    // the number of times each name was seen, for tags and for identifiers
+    type_names: HashMap<String, usize>,
+    ident_names: HashMap<String, usize>,
+}
+
+impl<'a> CDump<'a> {
+    fn new(btf: &'a Btf) -> Result<CDump<'a>, BtfError> {
+        let type_count = btf.first_type_id() + btf.types().count() as u32;
+        let len = type_count as usize;
+        let mut dump = CDump {
+            btf,
+            type_count,
+            ptr_size: mem::size_of::<usize>(),
+            out: String::new(),
+            referenced: vec![false; len],
+            order_state: vec![State::NotDone; len],
+            emit_state: vec![State::NotDone; len],
+            fwd_emitted: vec![false; len],
+            queue: Vec::new(),
+            names: HashMap::new(),
+            type_names: HashMap::new(),
+            ident_names: HashMap::new(),
+        };
+        // void is always available
+        dump.order_state[0] = State::Done;
+        dump.emit_state[0] = State::Done;
+
+        for type_id in 1..type_count {
+            let ty = btf.type_by_id(type_id)?;
+            if let BtfType::Int(_, _) = ty {
+                if let Some(name) = btf.type_name(ty)? {
+                    if ["long", "long int", "unsigned long", "long unsigned int"]
+                        .contains(&name.as_ref())
+                    {
+                        dump.ptr_size = btf.type_size(type_id)?;
+                    }
+                }
+            }
+            for ref_id in ty.referenced_types() {
+                if let Some(referenced) = dump.referenced.get_mut(ref_id as usize) {
+                    *referenced = true;
+                }
+            }
+        }
+
+        Ok(dump)
+    }
+
+    /// Puts the type in the emit queue after all the types it depends on.
+    ///
+    /// Returns `true` if the type is a "strong" dependency, that must be fully defined before the
+    /// types that use it, and `false` if a forward declaration is enough.
+    fn order_type(&mut self, type_id: u32, through_ptr: bool) -> Result<bool, BtfError> {
+        if self.order_state[type_id as usize] == State::Done {
+            return Ok(true);
+        }
+
+        let btf = self.btf;
+        let ty = btf.type_by_id(type_id)?;
+        use BtfType::*;
+        match ty {
+            Int(_, _) | Float(_) => {
+                self.order_state[type_id as usize] = State::Done;
+                Ok(false)
+            }
+            Ptr(t) => self.order_type(ref_type(t), true),
+            Array(_, array) => self.order_type(array.type_, false),
+            Struct(t, members) | Union(t, members) => {
+                // a pointer to a named struct only needs a forward declaration
+                if through_ptr && t.name_off != 0 {
+                    return Ok(false);
+                }
+                if self.order_state[type_id as usize] == State::InProgress {
+                    if t.name_off != 0 {
+                        return Ok(false);
+                    }
+                    return Err(BtfError::UnexpectedBtfType { type_id });
+                }
+
+                self.order_state[type_id as usize] = State::InProgress;
+                for member in members {
+                    self.order_type(member.type_, false)?;
+                }
+                if t.name_off != 0 {
+                    self.queue.push(type_id);
+                }
+                self.order_state[type_id as usize] = State::Done;
+                Ok(true)
+            }
+            Enum(t, _) | Fwd(t) => {
+                // anonymous enums are defined inline where they're used, unless they're not used
+                // at all
+                if t.name_off != 0 || !self.referenced[type_id as usize] {
+                    self.queue.push(type_id);
+                }
+                self.order_state[type_id as usize] = State::Done;
+                Ok(true)
+            }
+            Typedef(t) => {
+                let is_strong = self.order_type(ref_type(t), through_ptr)?;
+                if through_ptr && !is_strong {
+                    return Ok(false);
+                }
+                self.queue.push(type_id);
+                self.order_state[type_id as usize] = State::Done;
+                Ok(true)
+            }
+            Volatile(t) | Const(t) | Restrict(t) | TypeTag(t) => {
+                self.order_type(ref_type(t), through_ptr)
+            }
+            FuncProto(t, params) => {
+                let mut is_strong = self.order_type(ref_type(t), through_ptr)?;
+                for param in params {
+                    is_strong |= self.order_type(param.type_, through_ptr)?;
+                }
+                Ok(is_strong)
+            }
+            Func(_) | Var(_, _) | DataSec(_, _) | DeclTag(_, _) => {
+                self.order_state[type_id as usize] = State::Done;
+                Ok(false)
+            }
+            Unknown => Err(BtfError::UnexpectedBtfType { type_id }),
+        }
+    }
+
+    /// Emits the type, and the forward declarations and definitions it needs.
+    ///
+    /// `cont_id` is the id of the type being defined that contains this type, or `0` for top level
+    /// definitions.
+    fn emit_type(&mut self, type_id: u32, cont_id: u32) -> Result<(), BtfError> {
+        let index = type_id as usize;
+        if self.emit_state[index] == State::Done {
+            return Ok(());
+        }
+
+        let btf = self.btf;
+        let ty = btf.type_by_id(type_id)?;
+        let top_level_def = cont_id == 0;
+        use BtfType::*;
+
+        if self.emit_state[index] == State::InProgress {
+            // we're in a cycle, a forward declaration is needed
+            if self.fwd_emitted[index] {
+                return Ok(());
+            }
+            match ty {
+                Struct(t, _) | Union(t, _) => {
+                    // a struct referencing itself doesn't need a forward declaration
+                    if type_id == cont_id || t.name_off == 0 {
+                        return Ok(());
+                    }
+                    self.emit_fwd(type_id)?;
+                    self.out.push_str(";\n\n");
+                    self.fwd_emitted[index] = true;
+                }
+                Typedef(_) => {
+                    if !self.is_ignored(ty)? {
+                        self.emit_typedef_def(type_id, 0)?;
+                        self.out.push_str(";\n\n");
+                    }
+                    self.fwd_emitted[index] = true;
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        match ty {
+            Int(_, _) | Float(_) => self.emit_state[index] = State::Done,
+            Enum(_, _) => {
+                if top_level_def {
+                    self.emit_enum_def(type_id, 0)?;
+                    self.out.push_str(";\n\n");
+                }
+                self.emit_state[index] = State::Done;
+            }
+            Ptr(t) | Volatile(t) | Const(t) | Restrict(t) | TypeTag(t) => {
+                self.emit_type(ref_type(t), cont_id)?
+            }
+            Array(_, array) => self.emit_type(array.type_, cont_id)?,
+            Fwd(_) => {
+                self.emit_fwd(type_id)?;
+                self.out.push_str(";\n\n");
+                self.emit_state[index] = State::Done;
+            }
+            Typedef(t) => {
+                self.emit_state[index] = State::InProgress;
+                self.emit_type(ref_type(t), type_id)?;
+                // the typedef may have been emitted as a forward declaration already
+                if !self.fwd_emitted[index] && !self.is_ignored(ty)? {
+                    self.emit_typedef_def(type_id, 0)?;
+                    self.out.push_str(";\n\n");
+                }
+                self.emit_state[index] = State::Done;
+            }
+            Struct(t, members) | Union(t, members) => {
+                self.emit_state[index] = State::InProgress;
+                // the members of top level and anonymous structs are emitted along with the
+                // struct, so the types they use must be declared first
+                if top_level_def || t.name_off == 0 {
+                    let new_cont_id = if t.name_off == 0 { cont_id } else { type_id };
+                    for member in members {
+                        self.emit_type(member.type_, new_cont_id)?;
+                    }
+                } else if !self.fwd_emitted[index] && type_id != cont_id {
+                    self.emit_fwd(type_id)?;
+                    self.out.push_str(";\n\n");
+                    self.fwd_emitted[index] = true;
+                }
+
+                if top_level_def {
+                    self.emit_struct_def(type_id, 0)?;
+                    self.out.push_str(";\n\n");
+                    self.emit_state[index] = State::Done;
+                } else {
+                    self.emit_state[index] = State::NotDone;
+                }
+            }
+            FuncProto(t, params) => {
+                self.emit_type(ref_type(t), cont_id)?;
+                for param in params {
+                    self.emit_type(param.type_, cont_id)?;
+                }
+            }
+            Unknown | Func(_) | Var(_, _) | DataSec(_, _) | DeclTag(_, _) => {}
+        }
+
+        Ok(())
+    }
+
+    fn emit_fwd(&mut self, type_id: u32) -> Result<(), BtfError> {
+        let keyword = match self.btf.type_by_id(type_id)? {
+            BtfType::Union(_, _) => "union",
+            // the kind flag is set for forward declarations of unions
+            BtfType::Fwd(t) if t.info >> 31 == 1 => "union",
+            _ => "struct",
+        };
+        let name = self.type_name(type_id)?;
+        self.out.push_str(keyword);
+        if !name.is_empty() {
+            self.out.push(' ');
+            self.out.push_str(&name);
+        }
+        Ok(())
+    }
+
+    fn emit_typedef_def(&mut self, type_id: u32, lvl: usize) -> Result<(), BtfError> {
+        let target_id = match self.btf.type_by_id(type_id)? {
+            BtfType::Typedef(t) => ref_type(t),
+            _ => return Err(BtfError::UnexpectedBtfType { type_id }),
+        };
+        let name = self.ident_name(type_id)?;
+        // old versions of GCC emit __gnuc_va_list as a typedef of void
+        if target_id == 0 && name == "__gnuc_va_list" {
+            self.out
+                .push_str("typedef __builtin_va_list __gnuc_va_list");
+            return Ok(());
+        }
+        self.out.push_str("typedef ");
+        self.emit_type_decl(target_id, &name, lvl)
+    }
+
+    fn emit_struct_def(&mut self, type_id: u32, lvl: usize) -> Result<(), BtfError> {
+        let btf = self.btf;
+        let (t, members, is_struct) = match btf.type_by_id(type_id)? {
+            BtfType::Struct(t, members) => (t, members, true),
+            BtfType::Union(t, members) => (t, members, false),
+            _ => return Err(BtfError::UnexpectedBtfType { type_id }),
+        };
+        let packed = is_struct && self.is_packed(type_id)?;
+
+        self.emit_fwd(type_id)?;
+        self.out.push_str(" {");
+        let mut offset = 0;
+        for member in members {
+            let name = btf.string_at(member.name_off)?;
+            let member_offset = member_bit_offset(t.info, member);
+            let bitfield_size = member_bit_field_size(t, member);
+            let align = if packed {
+                1
+            } else {
+                self.align_of(member.type_)?
+            };
+            self.emit_bit_padding(offset, member_offset, bitfield_size, align, lvl + 1);
+
+            self.out.push('\n');
+            self.out.push_str(&indent(lvl + 1));
+            self.emit_type_decl(member.type_, &name, lvl + 1)?;
+            if bitfield_size > 0 {
+                self.out.push_str(&format!(": {}", bitfield_size));
+                offset = member_offset + bitfield_size;
+            } else {
+                let size = btf.type_size(member.type_).unwrap_or(0);
+                offset = member_offset + size * 8;
+            }
+            self.out.push(';');
+        }
+
+        if is_struct {
+            let align = if packed { 1 } else { self.align_of(type_id)? };
+            // Safety: union
+            let size = unsafe { t.__bindgen_anon_1.size } as usize;
+            self.emit_bit_padding(offset, size * 8, 0, align, lvl + 1);
+        }
+        if !members.is_empty() {
+            self.out.push('\n');
+        }
+        self.out.push_str(&indent(lvl));
+        self.out.push('}');
+        if packed {
+            self.out.push_str(" __attribute__((packed))");
+        }
+
+        Ok(())
+    }
+
+    fn emit_bit_padding(
+        &mut self,
+        offset: usize,
+        member_offset: usize,
+        bitfield_size: usize,
+        align: usize,
+        lvl: usize,
+    ) {
+        if member_offset <= offset {
+            return;
+        }
+        let mut gap = member_offset - offset;
+        // the compiler adds the natural padding in front of aligned members
+        if bitfield_size == 0 && gap < align * 8 {
+            return;
+        }
+
+        let ptr_bits = self.ptr_size * 8;
+        while gap > 0 {
+            let (pad_type, pad_bits) = if ptr_bits > 32 && gap > 32 {
+                ("long", chip_away_bits(gap, ptr_bits))
+            } else if gap > 16 {
+                ("int", chip_away_bits(gap, 32))
+            } else if gap > 8 {
+                ("short", chip_away_bits(gap, 16))
+            } else {
+                ("char", chip_away_bits(gap, 8))
+            };
+            self.out
+                .push_str(&format!("\n{}{}: {};", indent(lvl), pad_type, pad_bits));
+            gap -= pad_bits;
+        }
+    }
+
+    fn emit_enum_def(&mut self, type_id: u32, lvl: usize) -> Result<(), BtfError> {
+        let btf = self.btf;
+        let (t, values) = match btf.type_by_id(type_id)? {
+            BtfType::Enum(t, values) => (t, values),
+            _ => return Err(BtfError::UnexpectedBtfType { type_id }),
+        };
+        let name = self.type_name(type_id)?;
+        self.out.push_str("enum");
+        if !name.is_empty() {
+            self.out.push(' ');
+            self.out.push_str(&name);
+        }
+        if values.is_empty() {
+            return Ok(());
+        }
+
+        // the kind flag is set for signed enums
+        let signed = t.info >> 31 == 1;
+        self.out.push_str(" {");
+        for value in values {
+            let mut name = btf.string_at(value.name_off)?.into_owned();
+            // enum values share the namespace of typedefs
+            let count = bump(&mut self.ident_names, &name);
+            if count > 1 {
+                name = format!("{}___{}", name, count);
+            }
+            let value = if signed {
+                value.val.to_string()
+            } else {
+                (value.val as u32).to_string()
+            };
+            self.out
+                .push_str(&format!("\n{}{} = {},", indent(lvl + 1), name, value));
+        }
+        self.out.push('\n');
+        self.out.push_str(&indent(lvl));
+        self.out.push('}');
+
+        Ok(())
+    }
+
+    /// Emits the declaration of `name` with the given type, for example `int (*name)[4]`.
+    fn emit_type_decl(&mut self, type_id: u32, name: &str, lvl: usize) -> Result<(), BtfError> {
+        let mut decls = Vec::new();
+        let mut current_id = type_id;
+        loop {
+            if decls.len() > MAX_RESOLVE_DEPTH as usize {
+                return Err(BtfError::MaximumTypeDepthReached { type_id });
+            }
+            decls.push(current_id);
+
+            use BtfType::*;
+            match self.btf.type_by_id(current_id)? {
+                Ptr(t) | Volatile(t) | Const(t) | Restrict(t) | TypeTag(t) | FuncProto(t, _) => {
+                    current_id = ref_type(t)
+                }
+                Array(_, array) => current_id = array.type_,
+                Unknown
+                | Int(_, _)
+                | Float(_)
+                | Enum(_, _)
+                | Fwd(_)
+                | Struct(_, _)
+                | Union(_, _)
+                | Typedef(_) => break,
+                Func(_) | Var(_, _) | DataSec(_, _) | DeclTag(_, _) => {
+                    return Err(BtfError::UnexpectedBtfType {
+                        type_id: current_id,
+                    })
+                }
+            }
+        }
+
+        self.emit_type_chain(&mut decls, name, lvl)
+    }
+
+    /// Emits a declarator, given the stack of types from the outermost to the innermost one.
+    fn emit_type_chain(
+        &mut self,
+        decls: &mut Vec<u32>,
+        name: &str,
+        lvl: usize,
+    ) -> Result<(), BtfError> {
+        let btf = self.btf;
+        let mut last_was_ptr = true;
+        while let Some(type_id) = decls.pop() {
+            let ty = btf.type_by_id(type_id)?;
+            use BtfType::*;
+            match ty {
+                Unknown => {
+                    self.emit_mods(decls)?;
+                    self.out.push_str("void");
+                }
+                Int(_, _) | Float(_) => {
+                    self.emit_mods(decls)?;
+                    let name = btf.type_name(ty)?.unwrap_or_default();
+                    self.out.push_str(&name);
+                }
+                Struct(t, _) | Union(t, _) => {
+                    self.emit_mods(decls)?;
+                    // anonymous types are defined inline
+                    if t.name_off == 0 {
+                        self.emit_struct_def(type_id, lvl)?;
+                    } else {
+                        self.emit_fwd(type_id)?;
+                    }
+                }
+                Enum(t, _) => {
+                    self.emit_mods(decls)?;
+                    if t.name_off == 0 {
+                        self.emit_enum_def(type_id, lvl)?;
+                    } else {
+                        let name = self.type_name(type_id)?;
+                        self.out.push_str("enum ");
+                        self.out.push_str(&name);
+                    }
+                }
+                Fwd(_) => {
+                    self.emit_mods(decls)?;
+                    self.emit_fwd(type_id)?;
+                }
+                Typedef(_) => {
+                    self.emit_mods(decls)?;
+                    let name = self.ident_name(type_id)?;
+                    self.out.push_str(&name);
+                }
+                Ptr(_) => self.out.push_str(if last_was_ptr { "*" } else { " *" }),
+                Volatile(_) => self.out.push_str(" volatile"),
+                Const(_) => self.out.push_str(" const"),
+                Restrict(_) => self.out.push_str(" restrict"),
+                TypeTag(_) => {
+                    let tag = btf.type_name(ty)?.unwrap_or_default();
+                    self.out
+                        .push_str(&format!(" __attribute__((btf_type_tag(\"{}\")))", tag));
+                }
+                Array(_, array) => {
+                    // qualifiers of arrays are meaningless, they apply to the elements
+                    self.drop_mods(decls)?;
+                    let next_id = match decls.last() {
+                        Some(next_id) => *next_id,
+                        None => {
+                            self.emit_name(name, last_was_ptr);
+                            self.out.push_str(&format!("[{}]", array.nelems));
+                            return Ok(());
+                        }
+                    };
+
+                    // no parentheses for multi-dimensional arrays
+                    let multidim = matches!(btf.type_by_id(next_id)?, Array(_, _));
+                    if !name.is_empty() && !last_was_ptr {
+                        self.out.push(' ');
+                    }
+                    if !multidim {
+                        self.out.push('(');
+                    }
+                    self.emit_type_chain(decls, name, lvl)?;
+                    if !multidim {
+                        self.out.push(')');
+                    }
+                    self.out.push_str(&format!("[{}]", array.nelems));
+                    return Ok(());
+                }
+                FuncProto(_, params) => {
+                    self.drop_mods(decls)?;
+                    if decls.is_empty() {
+                        self.emit_name(name, last_was_ptr);
+                    } else {
+                        self.out.push_str(" (");
+                        self.emit_type_chain(decls, name, lvl)?;
+                        self.out.push(')');
+                    }
+
+                    self.out.push('(');
+                    // prototypes without arguments have a single void argument
+                    if params.is_empty() || (params.len() == 1 && params[0].type_ == 0) {
+                        self.out.push_str("void)");
+                        return Ok(());
+                    }
+                    for (i, param) in params.iter().enumerate() {
+                        if i > 0 {
+                            self.out.push_str(", ");
+                        }
+                        // a last argument of type void means that the function is variadic
+                        if i == params.len() - 1 && param.type_ == 0 {
+                            self.out.push_str("...");
+                            break;
+                        }
+                        let name = btf.string_at(param.name_off)?;
+                        self.emit_type_decl(param.type_, &name, lvl)?;
+                    }
+                    self.out.push(')');
+                    return Ok(());
+                }
+                Func(_) | Var(_, _) | DataSec(_, _) | DeclTag(_, _) => {
+                    return Err(BtfError::UnexpectedBtfType { type_id })
+                }
+            }
+            last_was_ptr = matches!(ty, Ptr(_));
+        }
+        self.emit_name(name, last_was_ptr);
+
+        Ok(())
+    }
+
+    fn emit_name(&mut self, name: &str, last_was_ptr: bool) {
+        if name.is_empty() {
+            return;
+        }
+        if !last_was_ptr {
+            self.out.push(' ');
+        }
+        self.out.push_str(name);
+    }
+
+    /// Emits the qualifiers at the top of the stack, in front of the type they apply to.
+    fn emit_mods(&mut self, decls: &mut Vec<u32>) -> Result<(), BtfError> {
+        while let Some(type_id) = decls.last() {
+            let qualifier = match self.btf.type_by_id(*type_id)? {
+                BtfType::Volatile(_) => "volatile ",
+                BtfType::Const(_) => "const ",
+                BtfType::Restrict(_) => "restrict ",
+                _ => return Ok(()),
+            };
+            self.out.push_str(qualifier);
+            decls.pop();
+        }
+        Ok(())
+    }
+
+    fn drop_mods(&mut self, decls: &mut Vec<u32>) -> Result<(), BtfError> {
+        while let Some(type_id) = decls.last() {
+            match self.btf.type_by_id(*type_id)? {
+                BtfType::Volatile(_) | BtfType::Const(_) | BtfType::Restrict(_) => decls.pop(),
+                _ => return Ok(()),
+            };
+        }
+        Ok(())
+    }
+
+    fn align_of(&self, type_id: u32) -> Result<usize, BtfError> {
+        let btf = self.btf;
+        use BtfType::*;
+        match btf.type_by_id(type_id)? {
+            Int(t, _) | Enum(t, _) | Float(t) => {
+                // Safety: union
+                let size = unsafe { t.__bindgen_anon_1.size } as usize;
+                Ok(size.min(self.ptr_size))
+            }
+            Ptr(_) => Ok(self.ptr_size),
+            Typedef(t) | Volatile(t) | Const(t) | Restrict(t) | TypeTag(t) => {
+                self.align_of(ref_type(t))
+            }
+            Array(_, array) => self.align_of(array.type_),
+            Struct(t, members) | Union(t, members) => {
+                let mut max_align = 1;
+                for member in members {
+                    let align = self.align_of(member.type_)?;
+                    max_align = max_align.max(align);
+                    // a member that isn't naturally aligned means that the struct is packed
+                    if member_bit_field_size(t, member) == 0
+                        && !is_aligned(member_bit_offset(t.info, member), 8 * align)
+                    {
+                        return Ok(1);
+                    }
+                }
+                // Safety: union
+                let size = unsafe { t.__bindgen_anon_1.size } as usize;
+                if !is_aligned(size, max_align) {
+                    return Ok(1);
+                }
+                Ok(max_align)
+            }
+            _ => Err(BtfError::UnexpectedBtfType { type_id }),
+        }
+    }
+
+    fn is_packed(&self, type_id: u32) -> Result<bool, BtfError> {
+        let (t, members) = match self.btf.type_by_id(type_id)? {
+            BtfType::Struct(t, members) => (t, members),
+            _ => return Ok(false),
+        };
+        let align = self.align_of(type_id)?;
+        // Safety: union
+        let size = unsafe { t.__bindgen_anon_1.size } as usize;
+        if !is_aligned(size, align) {
+            return Ok(true);
+        }
+        for member in members {
+            let align = self.align_of(member.type_)?;
+            if member_bit_field_size(t, member) == 0
+                && !is_aligned(member_bit_offset(t.info, member), 8 * align)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn is_ignored(&self, ty: &BtfType) -> Result<bool, BtfError> {
+        // provided by the compiler
+        Ok(self.btf.type_name(ty)?.as_deref() == Some("__builtin_va_list"))
+    }
+
+    /// Returns the name of a struct, union or enum.
+    fn type_name(&mut self, type_id: u32) -> Result<String, BtfError> {
+        self.resolve_name(type_id, false)
+    }
+
+    /// Returns the name of a typedef.
+    fn ident_name(&mut self, type_id: u32) -> Result<String, BtfError> {
+        self.resolve_name(type_id, true)
+    }
+
+    fn resolve_name(&mut self, type_id: u32, ident: bool) -> Result<String, BtfError> {
+        if let Some(name) = self.names.get(&type_id) {
+            return Ok(name.clone());
+        }
+        let name = match self.btf.type_name(self.btf.type_by_id(type_id)?)? {
+            Some(name) if !name.is_empty() => name.into_owned(),
+            _ => return Ok(String::new()),
+        };
+        let names = if ident {
+            &mut self.ident_names
+        } else {
+            &mut self.type_names
+        };
+        let count = bump(names, &name);
+        let name = if count > 1 {
+            format!("{}___{}", name, count)
+        } else {
+            name
+        };
+        self.names.insert(type_id, name.clone());
+        Ok(name)
+    }
+}
+
+fn bump(names: &mut HashMap<String, usize>, name: &str) -> usize {
+    let count = names.entry(name.to_owned()).or_insert(0);
+    *count += 1;
+    *count
+}
+
+fn chip_away_bits(total: usize, at_most: usize) -> usize {
+    match total % at_most {
+        0 => at_most,
+        bits => bits,
+    }
+}
+
+fn is_aligned(value: usize, align: usize) -> bool {
+    match value.checked_rem(align) {
+        Some(rem) => rem == 0,
+        None => true,
+    }
+}
+
+fn indent(lvl: usize) -> String {
+    "\t".repeat(lvl)
+}
+
+#[cfg(test)]
+mod tests {
+    use object::Endianness;
+
+    use super::*;
+    use crate::{
+        generated::{
+            BTF_INT_CHAR, BTF_INT_SIGNED, BTF_KIND_ARRAY, BTF_KIND_ENUM, BTF_KIND_FUNC_PROTO,
+            BTF_KIND_INT, BTF_KIND_PTR, BTF_KIND_STRUCT, BTF_KIND_TYPEDEF, BTF_KIND_UNION,
+        },
+        obj::btf::btf_bytes,
+    };
+
+    fn kind(kind: u32, vlen: u32) -> u32 {
+        kind << 24 | vlen
+    }
+
+    #[test]
+    fn test_to_c_header() {
+        let strings = b"\0int\0char\0node\0next\0cb\0cb_t\0ctx\0a\0b\0flags\0buf\0ZERO\0";
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, (BTF_INT_SIGNED << 24) | 32,
+            // [2] char
+            5, kind(BTF_KIND_INT, 0), 1, (BTF_INT_CHAR << 24) | 8,
+            // [3] struct node
+            10, 1 << 31 | kind(BTF_KIND_STRUCT, 5), 32,
+            15, 4, 0,
+            20, 6, 64,
+            0, 7, 128,
+            36, 1, 3 << 24 | 160,
+            42, 8, 168,
+            // [4] struct node *
+            0, kind(BTF_KIND_PTR, 0), 3,
+            // [5] int (struct node *ctx)
+            0, kind(BTF_KIND_FUNC_PROTO, 1), 1,
+            28, 4,
+            // [6] typedef int (*cb_t)(struct node *ctx)
+            23, kind(BTF_KIND_TYPEDEF, 0), 9,
+            // [7] union { int a; int b; }
+            0, kind(BTF_KIND_UNION, 2), 4,
+            32, 1, 0,
+            34, 1, 0,
+            // [8] char[8]
+            0, kind(BTF_KIND_ARRAY, 0), 0, 2, 1, 8,
+            // [9] pointer to [5]
+            0, kind(BTF_KIND_PTR, 0), 5,
+            // [10] enum { ZERO }
+            0, kind(BTF_KIND_ENUM, 1), 4,
+            46, 0,
+        ];
+        let btf = Btf::parse(&btf_bytes(&types, strings), Endianness::default()).unwrap();
+
+        let expected = "\
+#ifndef __VMLINUX_H__
+#define __VMLINUX_H__
+
+#ifndef BPF_NO_PRESERVE_ACCESS_INDEX
+#pragma clang attribute push (__attribute__((preserve_access_index)), apply_to = record)
+#endif
+
+struct node;
+
+typedef int (*cb_t)(struct node *ctx);
+
+struct node {
+	struct node *next;
+	cb_t cb;
+	union {
+		int a;
+		int b;
+	};
+	int flags: 3;
+	char buf[8];
+};
+
+enum {
+	ZERO = 0,
+};
+
+#ifndef BPF_NO_PRESERVE_ACCESS_INDEX
+#pragma clang attribute pop
+#endif
+
+#endif /* __VMLINUX_H__ */
+";
+        assert_eq!(btf.to_c_header().unwrap(), expected);
+    }
+}
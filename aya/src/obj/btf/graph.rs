@@ -0,0 +1,529 @@
+//! Read-only access to the types of a [`Btf`] object.
+
+use std::{borrow::Cow, fmt};
+
+use crate::{
+    generated::{btf_type, BTF_INT_BOOL, BTF_INT_CHAR, BTF_INT_SIGNED},
+    obj::btf::{
+        member_bit_field_size, member_bit_offset, Btf, BtfError, BtfKind, BtfType,
+        MAX_RESOLVE_DEPTH,
+    },
+};
+
+impl Btf {
+    /// Returns the type with the given id.
+    ///
+    /// The id `0` is the `void` type, of kind [`BtfKind::Unknown`]. For split BTF, the ids of the
+    /// base BTF can be used as well.
+    pub fn get_type(&self, type_id: u32) -> Result<TypeRef<'_>, BtfError> {
+        Ok(TypeRef {
+            btf: self,
+            id: type_id,
+            ty: self.type_by_id(type_id)?,
+        })
+    }
+
+    /// Returns an iterator over the types defined by this BTF, in id order.
+    ///
+    /// The `void` type is not included. For split BTF, the types of the base BTF are not included
+    /// either.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::btf::{Btf, BtfKind};
+    ///
+    /// let btf = Btf::from_sys_fs()?;
+    /// for ty in btf.iter_types().filter(|ty| ty.kind() == BtfKind::Struct) {
+    ///     if let Some(name) = ty.name() {
+    ///         if name.starts_with("trace_event_raw_") {
+    ///             println!("{}", name);
+    ///         }
+    ///     }
+    /// }
+    /// # Ok::<(), aya::BtfError>(())
+    /// ```
+    pub fn iter_types(&self) -> impl Iterator<Item = TypeRef<'_>> {
+        let first_type_id = self.first_type_id();
+        self.types()
+            .enumerate()
+            .map(move |(i, ty)| TypeRef {
+                btf: self,
+                id: first_type_id + i as u32,
+                ty,
+            })
+            .filter(|ty| ty.id != 0)
+    }
+
+    /// Returns the first type called `name` of the given kind.
+    ///
+    /// For split BTF, the base BTF is searched first.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use aya::btf::{Btf, BtfKind};
+    ///
+    /// let btf = Btf::from_sys_fs()?;
+    /// let task = btf.find_type("task_struct", BtfKind::Struct)?;
+    /// if let Some(pid) = task.member("pid") {
+    ///     println!("task_struct::pid is at offset {}", pid.byte_offset());
+    /// }
+    /// # Ok::<(), aya::BtfError>(())
+    /// ```
+    pub fn find_type(&self, name: &str, kind: BtfKind) -> Result<TypeRef<'_>, BtfError> {
+        self.get_type(self.id_by_type_name_kind(name, kind)?)
+    }
+}
+
+/// A type of a [`Btf`] object.
+///
+/// Obtained with [`Btf::get_type`], [`Btf::iter_types`] or [`Btf::find_type`].
+#[derive(Clone, Copy)]
+pub struct TypeRef<'a> {
+    btf: &'a Btf,
+    id: u32,
+    ty: &'a BtfType,
+}
+
+impl<'a> TypeRef<'a> {
+    /// Returns the id of the type.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the kind of the type.
+    pub fn kind(&self) -> BtfKind {
+        self.ty.kind().ok().flatten().unwrap_or(BtfKind::Unknown)
+    }
+
+    /// Returns the name of the type, or `None` if the type is anonymous.
+    pub fn name(&self) -> Option<Cow<'a, str>> {
+        self.btf
+            .type_name(self.ty)
+            .ok()
+            .flatten()
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Returns the size of the type in bytes.
+    ///
+    /// Modifiers and typedefs are resolved, and the size of arrays is the size of all their
+    /// elements. Fails for types that don't have a size, like functions.
+    pub fn size(&self) -> Result<usize, BtfError> {
+        self.btf.type_size(self.id)
+    }
+
+    /// Returns the type referenced by this type.
+    ///
+    /// This is the pointee of pointers, the target of typedefs and modifiers, the prototype of
+    /// functions, the type of variables and the tagged type of declaration tags. Returns `None`
+    /// for all the other kinds.
+    pub fn referenced_type(&self) -> Option<TypeRef<'a>> {
+        use BtfType::*;
+        match self.ty {
+            Ptr(ty)
+            | Typedef(ty)
+            | Volatile(ty)
+            | Const(ty)
+            | Restrict(ty)
+            | TypeTag(ty)
+            | Func(ty)
+            | Var(ty, _)
+            | DeclTag(ty, _) => self.btf.get_type(ref_type(ty)).ok(),
+            _ => None,
+        }
+    }
+
+    /// Skips the modifiers (`const`, `volatile`, `restrict`, type tags) and typedefs in front of
+    /// this type and returns the underlying type.
+    pub fn resolve(&self) -> Result<TypeRef<'a>, BtfError> {
+        self.btf.get_type(self.btf.resolve_type(self.id)?)
+    }
+
+    /// Returns the members of a struct or union, or an empty list for the other kinds.
+    pub fn members(&self) -> Vec<Member<'a>> {
+        match self.ty {
+            BtfType::Struct(ty, members) | BtfType::Union(ty, members) => members
+                .iter()
+                .map(|m| {
+                    let bitfield_size = member_bit_field_size(ty, m) as u32;
+                    Member {
+                        btf: self.btf,
+                        name: self
+                            .btf
+                            .string_at(m.name_off)
+                            .ok()
+                            .filter(|n| !n.is_empty()),
+                        type_id: m.type_,
+                        bit_offset: member_bit_offset(ty.info, m) as u32,
+                        bitfield_size: if bitfield_size > 0 {
+                            Some(bitfield_size)
+                        } else {
+                            None
+                        },
+                    }
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the member called `name` of a struct or union.
+    ///
+    /// Like in C, the members of anonymous structs and unions nested in this type are found too.
+    /// Their offset is then relative to this type.
+    pub fn member(&self, name: &str) -> Option<Member<'a>> {
+        self.find_member(name, 0)
+    }
+
+    fn find_member(&self, name: &str, depth: u8) -> Option<Member<'a>> {
+        if depth >= MAX_RESOLVE_DEPTH {
+            return None;
+        }
+
+        for member in self.members() {
+            match &member.name {
+                Some(member_name) if member_name == name => return Some(member),
+                Some(_) => continue,
+                None => {
+                    let nested = member
+                        .ty()
+                        .and_then(|ty| ty.resolve())
+                        .ok()
+                        .and_then(|ty| ty.find_member(name, depth + 1));
+                    if let Some(mut nested) = nested {
+                        nested.bit_offset += member.bit_offset;
+                        return Some(nested);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the parameters of a function or function prototype, or an empty list for the other
+    /// kinds.
+    ///
+    /// The last parameter of a variadic function has type id `0`.
+    pub fn params(&self) -> Vec<Param<'a>> {
+        match self.func_proto() {
+            Some(BtfType::FuncProto(_, params)) => params
+                .iter()
+                .map(|p| Param {
+                    btf: self.btf,
+                    name: self
+                        .btf
+                        .string_at(p.name_off)
+                        .ok()
+                        .filter(|n| !n.is_empty()),
+                    type_id: p.type_,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the return type of a function or function prototype.
+    ///
+    /// Returns `None` for the other kinds.
+    pub fn return_type(&self) -> Option<TypeRef<'a>> {
+        match self.func_proto() {
+            Some(BtfType::FuncProto(ty, _)) => self.btf.get_type(ref_type(ty)).ok(),
+            _ => None,
+        }
+    }
+
+    fn func_proto(&self) -> Option<&'a BtfType> {
+        match self.ty {
+            BtfType::FuncProto(_, _) => Some(self.ty),
+            BtfType::Func(ty) => self.btf.type_by_id(ref_type(ty)).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the values of an enum, or an empty list for the other kinds.
+    pub fn enum_values(&self) -> Vec<EnumValue<'a>> {
+        match self.ty {
+            BtfType::Enum(_, values) => values
+                .iter()
+                .map(|v| EnumValue {
+                    name: self.btf.string_at(v.name_off).unwrap_or_default(),
+                    value: v.val,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the element type and length of an array.
+    pub fn array(&self) -> Option<ArrayInfo> {
+        match self.ty {
+            BtfType::Array(_, array) => Some(ArrayInfo {
+                element_type_id: array.type_,
+                index_type_id: array.index_type,
+                len: array.nelems,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the encoding of an integer.
+    pub fn int(&self) -> Option<IntInfo> {
+        match self.ty {
+            BtfType::Int(_, data) => {
+                let encoding = data >> 24;
+                Some(IntInfo {
+                    bits: data & 0xff,
+                    offset: (data >> 16) & 0xff,
+                    is_signed: encoding & BTF_INT_SIGNED != 0,
+                    is_char: encoding & BTF_INT_CHAR != 0,
+                    is_bool: encoding & BTF_INT_BOOL != 0,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for TypeRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypeRef")
+            .field("id", &self.id)
+            .field("kind", &self.kind())
+            .field("name", &self.name())
+            .finish()
+    }
+}
+
+/// A member of a struct or union.
+#[derive(Clone, Debug)]
+pub struct Member<'a> {
+    btf: &'a Btf,
+    /// The name of the member, or `None` for anonymous members.
+    pub name: Option<Cow<'a, str>>,
+    /// The id of the type of the member.
+    pub type_id: u32,
+    /// The offset of the member from the start of the struct or union, in bits.
+    pub bit_offset: u32,
+    /// The size in bits of bitfield members.
+    pub bitfield_size: Option<u32>,
+}
+
+impl<'a> Member<'a> {
+    /// Returns the type of the member.
+    pub fn ty(&self) -> Result<TypeRef<'a>, BtfError> {
+        self.btf.get_type(self.type_id)
+    }
+
+    /// Returns the offset of the member from the start of the struct or union, in bytes.
+    ///
+    /// For bitfields, this is the offset of the byte containing the first bit of the member.
+    pub fn byte_offset(&self) -> u32 {
+        self.bit_offset / 8
+    }
+}
+
+/// A parameter of a function prototype.
+#[derive(Clone, Debug)]
+pub struct Param<'a> {
+    btf: &'a Btf,
+    /// The name of the parameter, or `None` if it's not known.
+    pub name: Option<Cow<'a, str>>,
+    /// The id of the type of the parameter.
+    pub type_id: u32,
+}
+
+impl<'a> Param<'a> {
+    /// Returns the type of the parameter.
+    pub fn ty(&self) -> Result<TypeRef<'a>, BtfError> {
+        self.btf.get_type(self.type_id)
+    }
+}
+
+/// A value of an enum.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnumValue<'a> {
+    /// The name of the value.
+    pub name: Cow<'a, str>,
+    /// The value.
+    pub value: i32,
+}
+
+/// The layout of an array type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArrayInfo {
+    /// The id of the type of the elements.
+    pub element_type_id: u32,
+    /// The id of the type of the index.
+    pub index_type_id: u32,
+    /// The number of elements.
+    pub len: u32,
+}
+
+/// The encoding of an integer type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntInfo {
+    /// The number of bits used to store the value.
+    pub bits: u32,
+    /// The offset of the value in bits, for bitfields.
+    pub offset: u32,
+    /// Whether the integer is signed.
+    pub is_signed: bool,
+    /// Whether the integer is a `char`.
+    pub is_char: bool,
+    /// Whether the integer is a `bool`.
+    pub is_bool: bool,
+}
+
+pub(crate) fn ref_type(ty: &btf_type) -> u32 {
+    // Safety: union
+    unsafe { ty.__bindgen_anon_1.type_ }
+}
+
+#[cfg(test)]
+mod tests {
+    use object::Endianness;
+
+    use super::*;
+    use crate::{
+        generated::{
+            BTF_KIND_ARRAY, BTF_KIND_CONST, BTF_KIND_ENUM, BTF_KIND_FUNC, BTF_KIND_FUNC_PROTO,
+            BTF_KIND_INT, BTF_KIND_PTR, BTF_KIND_STRUCT, BTF_KIND_TYPEDEF, BTF_KIND_UNION,
+        },
+        obj::btf::btf_bytes,
+    };
+
+    fn kind(kind: u32, vlen: u32) -> u32 {
+        kind << 24 | vlen
+    }
+
+    fn test_btf() -> Btf {
+        let strings = b"\0int\0event\0pid\0comm\0a\0b\0flags\0char\0state\0RUNNING\0STOPPED\0\
+                        pid_t\0handle\0ctx\0len\0";
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, (BTF_INT_SIGNED << 24) | 32,
+            // [2] char
+            30, kind(BTF_KIND_INT, 0), 1, (BTF_INT_CHAR << 24) | 8,
+            // [3] char[16]
+            0, kind(BTF_KIND_ARRAY, 0), 0, 2, 1, 16,
+            // [4] union { int a; int b; }
+            0, kind(BTF_KIND_UNION, 2), 4,
+            20, 1, 0,
+            22, 1, 0,
+            // [5] struct event { pid_t pid; char comm[16]; union { .. }; int flags:3; }
+            5, 1 << 31 | kind(BTF_KIND_STRUCT, 4), 28,
+            11, 8, 0,
+            15, 3, 32,
+            0, 4, 160,
+            24, 1, 3 << 24 | 192,
+            // [6] enum state { RUNNING, STOPPED = -1 }
+            35, kind(BTF_KIND_ENUM, 2), 4,
+            41, 0,
+            49, -1i32 as u32,
+            // [7] const struct event
+            0, kind(BTF_KIND_CONST, 0), 5,
+            // [8] typedef int pid_t
+            57, kind(BTF_KIND_TYPEDEF, 0), 1,
+            // [9] const struct event *
+            0, kind(BTF_KIND_PTR, 0), 7,
+            // [10] int (const struct event *ctx, int len)
+            0, kind(BTF_KIND_FUNC_PROTO, 2), 1,
+            70, 9,
+            74, 1,
+            // [11] int handle(const struct event *ctx, int len)
+            63, kind(BTF_KIND_FUNC, 1), 10,
+        ];
+        Btf::parse(&btf_bytes(&types, strings), Endianness::default()).unwrap()
+    }
+
+    #[test]
+    fn test_iter_and_find() {
+        let btf = test_btf();
+        let ids = btf.iter_types().map(|ty| ty.id()).collect::<Vec<_>>();
+        assert_eq!(ids, (1..=11).collect::<Vec<_>>());
+
+        let event = btf.find_type("event", BtfKind::Struct).unwrap();
+        assert_eq!(event.id(), 5);
+        assert_eq!(event.name().unwrap(), "event");
+        assert_eq!(event.size().unwrap(), 28);
+        assert!(matches!(
+            btf.find_type("event", BtfKind::Union),
+            Err(BtfError::UnknownBtfTypeName { .. })
+        ));
+
+        let void = btf.get_type(0).unwrap();
+        assert_eq!(void.kind(), BtfKind::Unknown);
+        assert!(void.name().is_none());
+    }
+
+    #[test]
+    fn test_members() {
+        let btf = test_btf();
+        let event = btf.get_type(5).unwrap();
+        let members = event.members();
+        assert_eq!(members.len(), 4);
+        assert_eq!(members[0].name.as_deref(), Some("pid"));
+        assert_eq!(members[0].ty().unwrap().name().unwrap(), "pid_t");
+        assert_eq!(members[0].ty().unwrap().resolve().unwrap().id(), 1);
+        assert_eq!(members[1].byte_offset(), 4);
+        assert_eq!(
+            members[1].ty().unwrap().array(),
+            Some(ArrayInfo {
+                element_type_id: 2,
+                index_type_id: 1,
+                len: 16
+            })
+        );
+        assert!(members[2].name.is_none());
+        assert_eq!(members[3].bitfield_size, Some(3));
+        assert_eq!(members[3].bit_offset, 192);
+
+        // members of anonymous unions are found with their offset in the outer struct
+        let b = event.member("b").unwrap();
+        assert_eq!(b.byte_offset(), 20);
+        assert!(event.member("missing").is_none());
+        assert!(btf.get_type(1).unwrap().members().is_empty());
+    }
+
+    #[test]
+    fn test_functions_and_enums() {
+        let btf = test_btf();
+        let handle = btf.find_type("handle", BtfKind::Func).unwrap();
+        assert_eq!(handle.referenced_type().unwrap().kind(), BtfKind::FuncProto);
+        assert_eq!(handle.return_type().unwrap().id(), 1);
+        let params = handle.params();
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[0].name.as_deref(), Some("ctx"));
+        let ctx = params[0].ty().unwrap();
+        assert_eq!(ctx.kind(), BtfKind::Ptr);
+        let pointee = ctx.referenced_type().unwrap();
+        assert_eq!(pointee.kind(), BtfKind::Const);
+        assert_eq!(pointee.resolve().unwrap().id(), 5);
+        assert_eq!(params[1].name.as_deref(), Some("len"));
+
+        let state = btf.find_type("state", BtfKind::Enum).unwrap();
+        assert_eq!(
+            state.enum_values(),
+            vec![
+                EnumValue {
+                    name: "RUNNING".into(),
+                    value: 0
+                },
+                EnumValue {
+                    name: "STOPPED".into(),
+                    value: -1
+                },
+            ]
+        );
+
+        let int = btf.get_type(1).unwrap().int().unwrap();
+        assert_eq!(int.bits, 32);
+        assert!(int.is_signed);
+        assert!(btf.get_type(2).unwrap().int().unwrap().is_char);
+        assert!(state.int().is_none());
+    }
+}
@@ -1,8 +1,17 @@
 #[allow(clippy::module_inception)]
 mod btf;
+mod builder;
+mod c_header;
+mod graph;
 mod relocation;
 mod types;
 
 pub use btf::*;
-pub use relocation::RelocationError;
+pub use builder::{BtfBuilder, IntEncoding, Linkage};
+pub use graph::{ArrayInfo, EnumValue, IntInfo, Member, Param, TypeRef};
+pub use relocation::{
+    CoreRelocation, CoreRelocationKind, CoreRelocationResult, CoreRelocationTarget,
+    RelocationError, CORE_POISON_IMM,
+};
+pub use types::BtfKind;
 pub(crate) use types::*;
@@ -3,21 +3,23 @@ use std::{
     convert::{TryFrom, TryInto},
     io, mem, ptr,
     str::FromStr,
+    sync::Arc,
 };
 
 use thiserror::Error;
 
 use crate::{
     generated::{
-        bpf_core_relo, bpf_core_relo_kind::*, bpf_insn, BPF_ALU, BPF_ALU64, BPF_B, BPF_DW, BPF_H,
-        BPF_K, BPF_LD, BPF_LDX, BPF_ST, BPF_STX, BPF_W, BTF_INT_SIGNED,
+        bpf_core_relo, bpf_core_relo_kind::*, bpf_insn, BPF_ALU, BPF_ALU64, BPF_B, BPF_CALL,
+        BPF_DW, BPF_H, BPF_JMP, BPF_K, BPF_LD, BPF_LDX, BPF_ST, BPF_STX, BPF_W, BTF_INT_SIGNED,
     },
     obj::{
         btf::{
             fields_are_compatible, member_bit_field_size, member_bit_offset, types_are_compatible,
-            BtfType, MAX_SPEC_LEN,
+            BtfType, ModuleBtf, MAX_SPEC_LEN,
         },
-        Btf, BtfError, Object, Program, ProgramSection,
+        Btf, BtfError, Object, Program, ProgramSection, RelocationError as ObjRelocationError,
+        RelocationErrors,
     },
     BpfError,
 };
@@ -82,28 +84,108 @@ fn err_type_name(name: &Option<String>) -> String {
     name.clone().unwrap_or_else(|| "[unknown name]".to_string())
 }
 
-#[derive(Copy, Clone, Debug)]
+/// The immediate of the call instruction that replaces the instructions of CO-RE relocations
+/// that can't be resolved. The verifier rejects the program with `invalid func unknown#195896080`
+/// if the instruction is reachable.
+pub const CORE_POISON_IMM: i32 = 0xbad2310;
+
+/// The kind of a CO-RE relocation.
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(u32)]
-enum RelocationKind {
+pub enum CoreRelocationKind {
+    /// The byte offset of a field.
     FieldByteOffset = BPF_CORE_FIELD_BYTE_OFFSET,
+    /// The byte size of a field.
     FieldByteSize = BPF_CORE_FIELD_BYTE_SIZE,
+    /// Whether a field exists.
     FieldExists = BPF_CORE_FIELD_EXISTS,
+    /// Whether a field is signed.
     FieldSigned = BPF_CORE_FIELD_SIGNED,
+    /// The left shift needed to extract a bitfield.
     FieldLShift64 = BPF_CORE_FIELD_LSHIFT_U64,
+    /// The right shift needed to extract a bitfield.
     FieldRShift64 = BPF_CORE_FIELD_RSHIFT_U64,
+    /// The id of a type in the BTF of the object.
     TypeIdLocal = BPF_CORE_TYPE_ID_LOCAL,
+    /// The id of a type in the BTF of the kernel.
     TypeIdTarget = BPF_CORE_TYPE_ID_TARGET,
+    /// Whether a type exists.
     TypeExists = BPF_CORE_TYPE_EXISTS,
+    /// The size of a type.
     TypeSize = BPF_CORE_TYPE_SIZE,
+    /// Whether an enum variant exists.
     EnumVariantExists = BPF_CORE_ENUMVAL_EXISTS,
+    /// The value of an enum variant.
     EnumVariantValue = BPF_CORE_ENUMVAL_VALUE,
 }
 
-impl TryFrom<u32> for RelocationKind {
+/// A CO-RE relocation applied to a program.
+///
+/// Returned by [`Program::core_relocations`](crate::programs::Program::core_relocations).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoreRelocation {
+    /// The number of the relocation in the `.BTF.ext` section.
+    pub number: usize,
+    /// The index of the relocated instruction.
+    pub insn: usize,
+    /// The kind of the relocation.
+    pub kind: CoreRelocationKind,
+    /// The name of the relocated type.
+    pub type_name: String,
+    /// The access string of the relocation, for example `0:1:2`.
+    pub access_str: String,
+    /// The outcome of the relocation.
+    pub result: CoreRelocationResult,
+}
+
+/// The outcome of a CO-RE relocation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoreRelocationResult {
+    /// The relocation was resolved and the instruction patched.
+    Resolved {
+        /// The value computed against the BTF of the object.
+        local: u32,
+        /// The value computed against the BTF of the kernel and written to the instruction.
+        target: u32,
+    },
+    /// No matching type was found in the kernel, the instruction was replaced by a call to the
+    /// invalid helper [`CORE_POISON_IMM`].
+    Poisoned,
+}
+
+/// A type of a target BTF matched by a CO-RE relocation.
+///
+/// Returned by [`Object::core_relocation_targets`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoreRelocationTarget {
+    /// The kind of the relocation.
+    pub kind: CoreRelocationKind,
+    /// The name of the relocated type.
+    pub type_name: String,
+    /// The id of the matching type in the target BTF.
+    pub type_id: u32,
+    /// The struct and union members accessed by field relocations, as `(type_id, index)` pairs
+    /// of the target BTF. The anonymous members leading to nested members are included.
+    pub members: Vec<(u32, usize)>,
+}
+
+impl CoreRelocationKind {
+    // Whether the relocation can only be applied if a matching target type is found. Existence
+    // checks evaluate to 0 when there's no match.
+    fn needs_target(&self) -> bool {
+        use CoreRelocationKind::*;
+        !matches!(
+            self,
+            TypeIdLocal | TypeExists | FieldExists | EnumVariantExists
+        )
+    }
+}
+
+impl TryFrom<u32> for CoreRelocationKind {
     type Error = BtfError;
 
     fn try_from(v: u32) -> Result<Self, Self::Error> {
-        use RelocationKind::*;
+        use CoreRelocationKind::*;
 
         Ok(match v {
             BPF_CORE_FIELD_BYTE_OFFSET => FieldByteOffset,
@@ -125,7 +207,7 @@ impl TryFrom<u32> for RelocationKind {
 
 #[derive(Debug, Copy, Clone)]
 pub struct Relocation {
-    kind: RelocationKind,
+    kind: CoreRelocationKind,
     ins_offset: usize,
     type_id: u32,
     access_str_offset: u32,
@@ -152,10 +234,25 @@ impl Relocation {
 }
 
 impl Object {
-    pub fn relocate_btf(&mut self, target_btf: &Btf) -> Result<(), BpfError> {
+    /// Applies the CO-RE relocations of the object.
+    ///
+    /// Candidate target types are searched in `target_btf` and in the split BTF of the kernel
+    /// modules in `module_btfs`. Relocations for which no candidate matches are poisoned, so that
+    /// the programs can still be loaded as long as the relocated instructions are unreachable.
+    ///
+    /// Returns the relocations applied to each program, by program name. Like
+    /// [relocate_programs](Object::relocate_programs), a program that fails to relocate doesn't
+    /// prevent the others from being relocated: the errors are returned by program name.
+    pub(crate) fn relocate_btf(
+        &mut self,
+        target_btf: &Btf,
+        module_btfs: &[ModuleBtf],
+    ) -> Result<(HashMap<String, Vec<CoreRelocation>>, RelocationErrors), BpfError> {
+        let mut report = HashMap::new();
+        let mut errors = HashMap::new();
         let (local_btf, btf_ext) = match (&self.btf, &self.btf_ext) {
             (Some(btf), Some(btf_ext)) => (btf, btf_ext),
-            _ => return Ok(()),
+            _ => return Ok((report, errors)),
         };
 
         let mut candidates_cache = HashMap::<u32, Vec<Candidate>>::new();
@@ -175,21 +272,113 @@ impl Object {
                     function: section_name.to_owned(),
                     error: Box::new(RelocationError::ProgramNotFound),
                 })?;
-            match relocate_btf_program(program, relos, local_btf, target_btf, &mut candidates_cache)
-            {
-                Ok(_) => {}
+            match relocate_btf_program(
+                program,
+                relos,
+                local_btf,
+                target_btf,
+                module_btfs,
+                &mut candidates_cache,
+            ) {
+                Ok(relocations) => {
+                    report
+                        .entry(section_name.to_owned())
+                        .or_insert_with(Vec::new)
+                        .extend(relocations);
+                }
+                Err(ErrorWrapper::BtfError(e)) => return Err(e.into()),
+                Err(ErrorWrapper::RelocationError(error)) => {
+                    errors.insert(
+                        section_name.to_owned(),
+                        (
+                            section_name.to_owned(),
+                            ObjRelocationError::CoreRelocation(Arc::new(error)),
+                        ),
+                    );
+                }
+            }
+        }
+
+        Ok((report, errors))
+    }
+
+    /// Resolves the CO-RE relocations of the object against `target_btf`, without applying them.
+    ///
+    /// Returns the types of `target_btf` that each relocation matches. Relocations that match
+    /// several candidate types are reported once per candidate, and relocations that don't match
+    /// any type are not reported. This can be used to extract from the BTF of a kernel the
+    /// types needed to load the object, see [`BtfBuilder::add_pruned_types_from`].
+    ///
+    /// [`BtfBuilder::add_pruned_types_from`]: crate::btf::BtfBuilder::add_pruned_types_from
+    pub fn core_relocation_targets(
+        &self,
+        target_btf: &Btf,
+    ) -> Result<Vec<CoreRelocationTarget>, BpfError> {
+        let mut targets = Vec::new();
+        let (local_btf, btf_ext) = match (&self.btf, &self.btf_ext) {
+            (Some(btf), Some(btf_ext)) => (btf, btf_ext),
+            _ => return Ok(targets),
+        };
+
+        let mut candidates_cache = HashMap::<u32, Vec<Candidate>>::new();
+        for (sec_name_off, relos) in btf_ext.relocations() {
+            match relocation_targets(relos, local_btf, target_btf, &mut candidates_cache) {
+                Ok(section_targets) => targets.extend(section_targets),
                 Err(ErrorWrapper::BtfError(e)) => return Err(e.into()),
                 Err(ErrorWrapper::RelocationError(error)) => {
                     return Err(BpfError::RelocationError {
-                        function: section_name.to_owned(),
+                        function: local_btf.string_at(*sec_name_off)?.into_owned(),
                         error: Box::new(error),
                     })
                 }
             }
         }
 
-        Ok(())
+        Ok(targets)
+    }
+}
+
+fn relocation_targets<'target>(
+    relos: &[Relocation],
+    local_btf: &Btf,
+    target_btf: &'target Btf,
+    candidates_cache: &mut HashMap<u32, Vec<Candidate<'target>>>,
+) -> Result<Vec<CoreRelocationTarget>, ErrorWrapper> {
+    let mut targets = Vec::new();
+    for rel in relos {
+        if rel.kind == CoreRelocationKind::TypeIdLocal {
+            continue;
+        }
+
+        let local_ty = local_btf.type_by_id(rel.type_id)?;
+        let local_name = &*local_btf.type_name(local_ty)?.unwrap();
+        let access_str = &*local_btf.string_at(rel.access_str_offset)?;
+        let local_spec = AccessSpec::new(local_btf, rel.type_id, access_str, *rel)?;
+
+        let candidates = match candidates_cache.get(&rel.type_id) {
+            Some(cands) => cands,
+            None => {
+                candidates_cache.insert(
+                    rel.type_id,
+                    find_candidates(local_ty, local_name, target_btf, &[])?,
+                );
+                candidates_cache.get(&rel.type_id).unwrap()
+            }
+        };
+
+        for candidate in candidates {
+            if let Some(target_spec) = match_candidate(&local_spec, candidate)? {
+                targets.push(CoreRelocationTarget {
+                    kind: rel.kind,
+                    type_name: local_name.to_owned(),
+                    type_id: target_spec.root_type_id,
+                    members: target_spec.members,
+                });
+            }
+        }
     }
+
+    Ok(targets)
 }
 
 fn relocate_btf_program<'target>(
@@ -197,8 +386,10 @@ fn relocate_btf_program<'target>(
     relos: &[Relocation],
     local_btf: &Btf,
     target_btf: &'target Btf,
+    module_btfs: &'target [ModuleBtf],
     candidates_cache: &mut HashMap<u32, Vec<Candidate<'target>>>,
-) -> Result<(), ErrorWrapper> {
+) -> Result<Vec<CoreRelocation>, ErrorWrapper> {
+    let mut report = Vec::new();
     for rel in relos {
         let instructions = &mut program.function.instructions;
         let ins_index = rel.ins_offset as usize / std::mem::size_of::<bpf_insn>();
@@ -217,14 +408,14 @@ fn relocate_btf_program<'target>(
         let local_spec = AccessSpec::new(local_btf, rel.type_id, access_str, *rel)?;
 
         let mut matches = match rel.kind {
-            RelocationKind::TypeIdLocal => Vec::new(), // we don't need to look at target types to relocate this value
+            CoreRelocationKind::TypeIdLocal => Vec::new(), // we don't need to look at target types to relocate this value
             _ => {
                 let candidates = match candidates_cache.get(&rel.type_id) {
                     Some(cands) => cands,
                     None => {
                         candidates_cache.insert(
                             rel.type_id,
-                            find_candidates(local_ty, local_name, target_btf)?,
+                            find_candidates(local_ty, local_name, target_btf, module_btfs)?,
                         );
                         candidates_cache.get(&rel.type_id).unwrap()
                     }
@@ -243,7 +434,7 @@ fn relocate_btf_program<'target>(
             }
         };
 
-        let comp_rel = if !matches.is_empty() {
+        let (comp_rel, target_btf) = if !matches.is_empty() {
             let mut matches = matches.drain(..);
             let (_, target_spec, target_comp_rel) = matches.next().unwrap();
 
@@ -267,15 +458,68 @@ fn relocate_btf_program<'target>(
                 }
                 .into());
             }
-            target_comp_rel
+            // the candidate might come from the BTF of a kernel module
+            (target_comp_rel, target_spec.btf)
+        } else if rel.kind.needs_target() {
+            // there are no candidate matches, the instruction can't be patched. It's poisoned
+            // instead, so that loading fails only if the verifier finds it reachable
+            poison(program, rel, ins_index)?;
+            report.push(CoreRelocation {
+                number: rel.number,
+                insn: ins_index,
+                kind: rel.kind,
+                type_name: local_name.to_owned(),
+                access_str: access_str.to_owned(),
+                result: CoreRelocationResult::Poisoned,
+            });
+            continue;
         } else {
-            // there are no candidate matches and therefore no target_spec. This might mean
-            // that matching failed, or that the relocation can be applied looking at local
-            // types only
-            ComputedRelocation::new(rel, &local_spec, None)?
+            // there are no candidate matches and therefore no target_spec, the relocation can be
+            // applied looking at local types only
+            (ComputedRelocation::new(rel, &local_spec, None)?, target_btf)
         };
 
         comp_rel.apply(program, rel, local_btf, target_btf)?;
+        report.push(CoreRelocation {
+            number: rel.number,
+            insn: ins_index,
+            kind: rel.kind,
+            type_name: local_name.to_owned(),
+            access_str: access_str.to_owned(),
+            result: CoreRelocationResult::Resolved {
+                local: comp_rel.local.value,
+                target: comp_rel.target.value,
+            },
+        });
+    }
+
+    Ok(report)
+}
+
+// Replaces the instruction at `ins_index` with a call to an invalid helper. Both halves of
+// ld_imm64 instructions are replaced, so that the verifier doesn't complain about the second
+// half being an unknown opcode.
+fn poison(program: &mut Program, rel: &Relocation, ins_index: usize) -> Result<(), ErrorWrapper> {
+    let instructions = &mut program.function.instructions;
+    let num_instructions = instructions.len();
+    let len = if (instructions[ins_index].code & 0x07) as u32 == BPF_LD {
+        2
+    } else {
+        1
+    };
+    let poisoned = instructions.get_mut(ins_index..ins_index + len).ok_or(
+        RelocationError::InvalidInstructionIndex {
+            index: ins_index + 1,
+            num_instructions,
+            relocation_number: rel.number,
+        },
+    )?;
+    for ins in poisoned {
+        ins.code = (BPF_JMP | BPF_CALL) as u8;
+        ins.set_dst_reg(0);
+        ins.set_src_reg(0);
+        ins.off = 0;
+        ins.imm = CORE_POISON_IMM;
     }
 
     Ok(())
@@ -289,24 +533,31 @@ fn find_candidates<'target>(
     local_ty: &BtfType,
     local_name: &str,
     target_btf: &'target Btf,
+    module_btfs: &'target [ModuleBtf],
 ) -> Result<Vec<Candidate<'target>>, BtfError> {
     let mut candidates = Vec::new();
     let local_name = flavorless_name(local_name);
-    for (type_id, ty) in target_btf.types().enumerate() {
-        if local_ty.kind()? != ty.kind()? {
-            continue;
-        }
-        let name = &*target_btf.type_name(ty)?.unwrap();
-        if local_name != flavorless_name(name) {
-            continue;
-        }
+    // module BTF is split BTF with target_btf as base, so only the types defined by the module
+    // need to be searched
+    let btfs = std::iter::once(target_btf).chain(module_btfs.iter().map(|m| &m.btf));
+    for btf in btfs {
+        let first_type_id = btf.first_type_id();
+        for (i, ty) in btf.types().enumerate() {
+            if local_ty.kind()? != ty.kind()? {
+                continue;
+            }
+            let name = &*btf.type_name(ty)?.unwrap();
+            if local_name != flavorless_name(name) {
+                continue;
+            }
 
-        candidates.push(Candidate {
-            name: name.to_owned(),
-            btf: target_btf,
-            _ty: ty,
-            type_id: type_id as u32,
-        });
+            candidates.push(Candidate {
+                name: name.to_owned(),
+                btf,
+                _ty: ty,
+                type_id: first_type_id + i as u32,
+            });
+        }
     }
 
     Ok(candidates)
@@ -322,14 +573,15 @@ fn match_candidate<'target>(
         relocation: local_spec.relocation,
         parts: Vec::new(),
         accessors: Vec::new(),
+        members: Vec::new(),
         bit_offset: 0,
     };
 
     match local_spec.relocation.kind {
-        RelocationKind::TypeIdLocal
-        | RelocationKind::TypeIdTarget
-        | RelocationKind::TypeExists
-        | RelocationKind::TypeSize => {
+        CoreRelocationKind::TypeIdLocal
+        | CoreRelocationKind::TypeIdTarget
+        | CoreRelocationKind::TypeExists
+        | CoreRelocationKind::TypeSize => {
             if types_are_compatible(
                 local_spec.btf,
                 local_spec.root_type_id,
@@ -341,7 +593,7 @@ fn match_candidate<'target>(
                 return Ok(None);
             }
         }
-        RelocationKind::EnumVariantExists | RelocationKind::EnumVariantValue => {
+        CoreRelocationKind::EnumVariantExists | CoreRelocationKind::EnumVariantValue => {
             let target_id = candidate.btf.resolve_type(candidate.type_id)?;
             let target_ty = candidate.btf.type_by_id(target_id)?;
             // the first accessor is guaranteed to have a name by construction
@@ -366,12 +618,12 @@ fn match_candidate<'target>(
                 _ => return Ok(None),
             }
         }
-        RelocationKind::FieldByteOffset
-        | RelocationKind::FieldByteSize
-        | RelocationKind::FieldExists
-        | RelocationKind::FieldSigned
-        | RelocationKind::FieldLShift64
-        | RelocationKind::FieldRShift64 => {
+        CoreRelocationKind::FieldByteOffset
+        | CoreRelocationKind::FieldByteSize
+        | CoreRelocationKind::FieldExists
+        | CoreRelocationKind::FieldSigned
+        | CoreRelocationKind::FieldLShift64
+        | CoreRelocationKind::FieldRShift64 => {
             let mut target_id = candidate.type_id;
             for (i, accessor) in local_spec.accessors.iter().enumerate() {
                 target_id = candidate.btf.resolve_type(target_id)?;
@@ -488,6 +740,7 @@ fn match_member<'local, 'target>(
             if ret.is_some() {
                 target_spec.bit_offset += bit_offset;
                 target_spec.parts.push(index);
+                target_spec.members.push((target_id, index));
                 return Ok(ret);
             }
         } else if local_name == target_name {
@@ -499,6 +752,7 @@ fn match_member<'local, 'target>(
             )? {
                 target_spec.bit_offset += bit_offset;
                 target_spec.parts.push(index);
+                target_spec.members.push((target_id, index));
                 target_spec.accessors.push(Accessor {
                     type_id: target_id,
                     index,
@@ -520,6 +774,8 @@ struct AccessSpec<'a> {
     root_type_id: u32,
     parts: Vec<usize>,
     accessors: Vec<Accessor>,
+    // the struct and union members accessed in target specs, including anonymous ones
+    members: Vec<(u32, usize)>,
     relocation: Relocation,
     bit_offset: usize,
 }
@@ -543,10 +799,10 @@ impl<'a> AccessSpec<'a> {
         let ty = btf.type_by_id(type_id)?;
 
         let spec = match relocation.kind {
-            RelocationKind::TypeIdLocal
-            | RelocationKind::TypeIdTarget
-            | RelocationKind::TypeExists
-            | RelocationKind::TypeSize => {
+            CoreRelocationKind::TypeIdLocal
+            | CoreRelocationKind::TypeIdTarget
+            | CoreRelocationKind::TypeExists
+            | CoreRelocationKind::TypeSize => {
                 if parts != [0] {
                     return Err(RelocationError::InvalidAccessString {
                         access_str: spec.to_string(),
@@ -559,60 +815,64 @@ impl<'a> AccessSpec<'a> {
                     relocation,
                     parts,
                     accessors: Vec::new(),
+                    members: Vec::new(),
                     bit_offset: 0,
                 }
             }
-            RelocationKind::EnumVariantExists | RelocationKind::EnumVariantValue => match ty {
-                BtfType::Enum(_, members) => {
-                    if parts.len() != 1 {
-                        return Err(RelocationError::InvalidAccessString {
-                            access_str: spec.to_string(),
+            CoreRelocationKind::EnumVariantExists | CoreRelocationKind::EnumVariantValue => {
+                match ty {
+                    BtfType::Enum(_, members) => {
+                        if parts.len() != 1 {
+                            return Err(RelocationError::InvalidAccessString {
+                                access_str: spec.to_string(),
+                            }
+                            .into());
                         }
-                        .into());
-                    }
-                    let index = parts[0];
-                    if index >= members.len() {
-                        return Err(RelocationError::InvalidAccessIndex {
-                            type_name: btf.err_type_name(ty),
-                            spec: spec.to_string(),
+                        let index = parts[0];
+                        if index >= members.len() {
+                            return Err(RelocationError::InvalidAccessIndex {
+                                type_name: btf.err_type_name(ty),
+                                spec: spec.to_string(),
+                                index,
+                                max_index: members.len(),
+                                error: "tried to access nonexistant enum variant".to_string(),
+                            }
+                            .into());
+                        }
+                        let accessors = vec![Accessor {
+                            type_id,
                             index,
-                            max_index: members.len(),
-                            error: "tried to access nonexistant enum variant".to_string(),
+                            name: Some(btf.string_at(members[index].name_off)?.to_string()),
+                        }];
+
+                        AccessSpec {
+                            btf,
+                            root_type_id,
+                            relocation,
+                            parts,
+                            accessors,
+                            members: Vec::new(),
+                            bit_offset: 0,
                         }
-                        .into());
-                    }
-                    let accessors = vec![Accessor {
-                        type_id,
-                        index,
-                        name: Some(btf.string_at(members[index].name_off)?.to_string()),
-                    }];
-
-                    AccessSpec {
-                        btf,
-                        root_type_id,
-                        relocation,
-                        parts,
-                        accessors,
-                        bit_offset: 0,
                     }
-                }
-                _ => {
-                    return Err(RelocationError::InvalidRelocationKindForType {
-                        relocation_number: relocation.number,
-                        relocation_kind: format!("{:?}", relocation.kind),
-                        type_kind: format!("{:?}", ty.kind()?.unwrap()),
-                        error: "enum relocation on non-enum type".to_string(),
+                    _ => {
+                        return Err(RelocationError::InvalidRelocationKindForType {
+                            relocation_number: relocation.number,
+                            relocation_kind: format!("{:?}", relocation.kind),
+                            type_kind: format!("{:?}", ty.kind()?.unwrap()),
+                            error: "enum relocation on non-enum type".to_string(),
+                        }
+                        .into())
                     }
-                    .into())
                 }
-            },
+            }
 
-            RelocationKind::FieldByteOffset
-            | RelocationKind::FieldByteSize
-            | RelocationKind::FieldExists
-            | RelocationKind::FieldSigned
-            | RelocationKind::FieldLShift64
-            | RelocationKind::FieldRShift64 => {
+            CoreRelocationKind::FieldByteOffset
+            | CoreRelocationKind::FieldByteSize
+            | CoreRelocationKind::FieldExists
+            | CoreRelocationKind::FieldSigned
+            | CoreRelocationKind::FieldLShift64
+            | CoreRelocationKind::FieldRShift64 => {
                 let mut accessors = vec![Accessor {
                     type_id,
                     index: parts[0],
@@ -699,6 +959,7 @@ impl<'a> AccessSpec<'a> {
                     root_type_id,
                     parts,
                     accessors,
+                    members: Vec::new(),
                     relocation,
                     bit_offset,
                 }
@@ -743,7 +1004,7 @@ impl ComputedRelocation {
         local_spec: &AccessSpec,
         target_spec: Option<&AccessSpec>,
     ) -> Result<ComputedRelocation, ErrorWrapper> {
-        use RelocationKind::*;
+        use CoreRelocationKind::*;
         let ret = match rel.kind {
             FieldByteOffset | FieldByteSize | FieldExists | FieldSigned | FieldLShift64
             | FieldRShift64 => ComputedRelocation {
@@ -883,7 +1144,7 @@ impl ComputedRelocation {
         rel: &Relocation,
         spec: Option<&AccessSpec>,
     ) -> Result<ComputedRelocationValue, ErrorWrapper> {
-        use RelocationKind::*;
+        use CoreRelocationKind::*;
         let value = match rel.kind {
             EnumVariantExists => spec.is_some() as u32,
             EnumVariantValue => {
@@ -909,7 +1170,7 @@ impl ComputedRelocation {
         rel: &Relocation,
         spec: Option<&AccessSpec>,
     ) -> Result<ComputedRelocationValue, ErrorWrapper> {
-        use RelocationKind::*;
+        use CoreRelocationKind::*;
 
         if let FieldExists = rel.kind {
             // this is the bpf_preserve_field_info(member_access, FIELD_EXISTENCE) case. If we
@@ -1038,7 +1299,7 @@ impl ComputedRelocation {
         local_spec: &AccessSpec,
         target_spec: Option<&AccessSpec>,
     ) -> Result<ComputedRelocationValue, ErrorWrapper> {
-        use RelocationKind::*;
+        use CoreRelocationKind::*;
         let value = match rel.kind {
             TypeIdLocal => local_spec.root_type_id,
             _ => match target_spec {
@@ -1048,8 +1309,7 @@ impl ComputedRelocation {
                     TypeSize => target_spec.btf.type_size(target_spec.root_type_id)? as u32,
                     _ => panic!("bug! this should not be reached"),
                 },
-                // FIXME in the case of TypeIdTarget and TypeSize this should probably fail the
-                // relocation...
+                // TypeIdTarget and TypeSize relocations without a target are poisoned
                 None => 0,
             },
         };
@@ -1072,3 +1332,221 @@ enum ErrorWrapper {
     #[error(transparent)]
     RelocationError(#[from] RelocationError),
 }
+
+#[cfg(test)]
+mod tests {
+    use object::Endianness;
+
+    use super::*;
+    use crate::{
+        generated::{BTF_KIND_INT, BTF_KIND_STRUCT, BTF_KIND_UNION},
+        obj::{btf::btf_bytes, fake_function, fake_program},
+    };
+
+    fn kind(kind: u32, vlen: u32) -> u32 {
+        kind << 24 | vlen
+    }
+
+    fn ins(code: u32, off: i16, imm: i32) -> bpf_insn {
+        bpf_insn {
+            code: code as u8,
+            _bitfield_align_1: [],
+            _bitfield_1: bpf_insn::new_bitfield_1(0, 0),
+            off,
+            imm,
+        }
+    }
+
+    fn relocation(
+        number: usize,
+        kind: CoreRelocationKind,
+        type_id: u32,
+        access: u32,
+    ) -> Relocation {
+        Relocation {
+            kind,
+            ins_offset: number * mem::size_of::<bpf_insn>(),
+            type_id,
+            access_str_offset: access,
+            number,
+        }
+    }
+
+    // The local BTF of the tests, followed by the access strings "0:1" at offset 17, "0:0" at 21
+    // and "0" at 25.
+    fn local_btf() -> Btf {
+        let strings = b"\0int\0foo\0a\0b\0bar\x000:1\x000:0\x000\0";
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, BTF_INT_SIGNED << 24 | 32,
+            // [2] struct foo { int a; int b; }
+            5, kind(BTF_KIND_STRUCT, 2), 8, 9, 1, 0, 11, 1, 32,
+            // [3] struct bar { int a; }
+            13, kind(BTF_KIND_STRUCT, 1), 4, 9, 1, 0,
+        ];
+        Btf::parse(&btf_bytes(&types, strings), Endianness::default()).unwrap()
+    }
+
+    #[test]
+    fn test_poison_unresolved_relocations() {
+        let local_btf = local_btf();
+
+        // the kernel's foo doesn't have b, and there's no bar
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, BTF_INT_SIGNED << 24 | 32,
+            // [2] struct foo { int a; }
+            5, kind(BTF_KIND_STRUCT, 1), 4, 9, 1, 0,
+        ];
+        let target_btf = Btf::parse(
+            &btf_bytes(&types, b"\0int\0foo\0a\0"),
+            Endianness::default(),
+        )
+        .unwrap();
+
+        let mut program = fake_program(
+            "prog",
+            fake_function(
+                0,
+                1,
+                vec![
+                    // r0 = foo->a
+                    ins(BPF_LDX | BPF_W | 0x60, 0, 0),
+                    // r0 = foo->b
+                    ins(BPF_LDX | BPF_W | 0x60, 4, 0),
+                    // r0 = bpf_core_field_exists(foo->b)
+                    ins(BPF_ALU64 | 0xb0, 0, 1),
+                    // r0 = bpf_core_type_size(struct bar)
+                    ins(BPF_LD | BPF_DW, 0, 4),
+                    ins(0, 0, 0),
+                ],
+            ),
+        );
+        let relos = [
+            relocation(0, CoreRelocationKind::FieldByteOffset, 2, 21),
+            relocation(1, CoreRelocationKind::FieldByteOffset, 2, 17),
+            relocation(2, CoreRelocationKind::FieldExists, 2, 17),
+            relocation(3, CoreRelocationKind::TypeSize, 3, 25),
+        ];
+
+        let report = relocate_btf_program(
+            &mut program,
+            &relos,
+            &local_btf,
+            &target_btf,
+            &[],
+            &mut HashMap::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            report
+                .iter()
+                .map(|rel| (
+                    rel.insn,
+                    rel.kind,
+                    rel.type_name.as_str(),
+                    rel.access_str.as_str(),
+                    rel.result
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    0,
+                    CoreRelocationKind::FieldByteOffset,
+                    "foo",
+                    "0:0",
+                    CoreRelocationResult::Resolved {
+                        local: 0,
+                        target: 0
+                    }
+                ),
+                (
+                    1,
+                    CoreRelocationKind::FieldByteOffset,
+                    "foo",
+                    "0:1",
+                    CoreRelocationResult::Poisoned
+                ),
+                (
+                    2,
+                    CoreRelocationKind::FieldExists,
+                    "foo",
+                    "0:1",
+                    CoreRelocationResult::Resolved {
+                        local: 1,
+                        target: 0
+                    }
+                ),
+                (
+                    3,
+                    CoreRelocationKind::TypeSize,
+                    "bar",
+                    "0",
+                    CoreRelocationResult::Poisoned
+                ),
+            ]
+        );
+
+        let poison = |ins: &bpf_insn| {
+            ins.code as u32 == BPF_JMP | BPF_CALL
+                && ins.src_reg() == 0
+                && ins.dst_reg() == 0
+                && ins.imm == CORE_POISON_IMM
+        };
+        let instructions = &program.function.instructions;
+        assert!(!poison(&instructions[0]));
+        assert!(poison(&instructions[1]));
+        assert_eq!(instructions[2].imm, 0);
+        assert!(poison(&instructions[3]));
+        assert!(poison(&instructions[4]));
+    }
+
+    #[test]
+    fn test_relocation_targets() {
+        let local_btf = local_btf();
+
+        // the kernel's foo has b in an anonymous union, and there's no bar
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, BTF_INT_SIGNED << 24 | 32,
+            // [2] struct foo { int a; union { int b; }; }
+            5, kind(BTF_KIND_STRUCT, 2), 8, 9, 1, 0, 0, 3, 32,
+            // [3] union { int b; }
+            0, kind(BTF_KIND_UNION, 1), 4, 11, 1, 0,
+        ];
+        let target_btf = Btf::parse(
+            &btf_bytes(&types, b"\0int\0foo\0a\0b\0"),
+            Endianness::default(),
+        )
+        .unwrap();
+
+        let relos = [
+            relocation(0, CoreRelocationKind::FieldByteOffset, 2, 17),
+            relocation(1, CoreRelocationKind::FieldExists, 2, 21),
+            relocation(2, CoreRelocationKind::TypeSize, 2, 25),
+            relocation(3, CoreRelocationKind::TypeSize, 3, 25),
+            relocation(4, CoreRelocationKind::TypeIdLocal, 2, 25),
+        ];
+
+        let targets =
+            relocation_targets(&relos, &local_btf, &target_btf, &mut HashMap::new()).unwrap();
+        let target = |kind, members| CoreRelocationTarget {
+            kind,
+            type_name: "foo".to_owned(),
+            type_id: 2,
+            members,
+        };
+        assert_eq!(
+            targets,
+            vec![
+                target(CoreRelocationKind::FieldByteOffset, vec![(3, 0), (2, 1)]),
+                target(CoreRelocationKind::FieldExists, vec![(2, 0)]),
+                target(CoreRelocationKind::TypeSize, vec![]),
+            ]
+        );
+    }
+}
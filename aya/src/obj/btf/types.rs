@@ -7,11 +7,12 @@ use object::Endianness;
 
 use crate::{
     generated::{
-        btf_array, btf_enum, btf_member, btf_param, btf_type, btf_type__bindgen_ty_1, btf_var,
-        btf_var_secinfo, BTF_KIND_ARRAY, BTF_KIND_CONST, BTF_KIND_DATASEC, BTF_KIND_ENUM,
-        BTF_KIND_FLOAT, BTF_KIND_FUNC, BTF_KIND_FUNC_PROTO, BTF_KIND_FWD, BTF_KIND_INT,
-        BTF_KIND_PTR, BTF_KIND_RESTRICT, BTF_KIND_STRUCT, BTF_KIND_TYPEDEF, BTF_KIND_UNION,
-        BTF_KIND_UNKN, BTF_KIND_VAR, BTF_KIND_VOLATILE,
+        btf_array, btf_decl_tag, btf_enum, btf_func_linkage, btf_member, btf_param, btf_type,
+        btf_type__bindgen_ty_1, btf_var, btf_var_secinfo, BTF_KIND_ARRAY, BTF_KIND_CONST,
+        BTF_KIND_DATASEC, BTF_KIND_DECL_TAG, BTF_KIND_ENUM, BTF_KIND_FLOAT, BTF_KIND_FUNC,
+        BTF_KIND_FUNC_PROTO, BTF_KIND_FWD, BTF_KIND_INT, BTF_KIND_PTR, BTF_KIND_RESTRICT,
+        BTF_KIND_STRUCT, BTF_KIND_TYPEDEF, BTF_KIND_TYPE_TAG, BTF_KIND_UNION, BTF_KIND_UNKN,
+        BTF_KIND_VAR, BTF_KIND_VOLATILE,
     },
     obj::btf::{Btf, BtfError, MAX_RESOLVE_DEPTH},
 };
@@ -35,28 +36,52 @@ pub(crate) enum BtfType {
     FuncProto(btf_type, Vec<btf_param>),
     Var(btf_type, btf_var),
     DataSec(btf_type, Vec<btf_var_secinfo>),
+    DeclTag(btf_type, btf_decl_tag),
+    TypeTag(btf_type),
 }
 
+/// The kind of a BTF type.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(u32)]
-pub(crate) enum BtfKind {
+pub enum BtfKind {
+    /// The `void` type.
     Unknown = BTF_KIND_UNKN,
+    /// An integer, including `char` and `bool`.
     Int = BTF_KIND_INT,
+    /// A floating point number.
     Float = BTF_KIND_FLOAT,
+    /// A pointer.
     Ptr = BTF_KIND_PTR,
+    /// An array.
     Array = BTF_KIND_ARRAY,
+    /// A struct.
     Struct = BTF_KIND_STRUCT,
+    /// A union.
     Union = BTF_KIND_UNION,
+    /// An enum.
     Enum = BTF_KIND_ENUM,
+    /// A forward declaration of a struct or union.
     Fwd = BTF_KIND_FWD,
+    /// A typedef.
     Typedef = BTF_KIND_TYPEDEF,
+    /// The `volatile` qualifier.
     Volatile = BTF_KIND_VOLATILE,
+    /// The `const` qualifier.
     Const = BTF_KIND_CONST,
+    /// The `restrict` qualifier.
     Restrict = BTF_KIND_RESTRICT,
+    /// A function, with its name and linkage.
     Func = BTF_KIND_FUNC,
+    /// A function prototype.
     FuncProto = BTF_KIND_FUNC_PROTO,
+    /// A global variable.
     Var = BTF_KIND_VAR,
+    /// A data section, listing the variables it contains.
     DataSec = BTF_KIND_DATASEC,
+    /// A tag attached to a declaration.
+    DeclTag = BTF_KIND_DECL_TAG,
+    /// A tag attached to a type.
+    TypeTag = BTF_KIND_TYPE_TAG,
 }
 
 impl TryFrom<u32> for BtfKind {
@@ -82,6 +107,8 @@ impl TryFrom<u32> for BtfKind {
             BTF_KIND_FUNC_PROTO => FuncProto,
             BTF_KIND_VAR => Var,
             BTF_KIND_DATASEC => DataSec,
+            BTF_KIND_DECL_TAG => DeclTag,
+            BTF_KIND_TYPE_TAG => TypeTag,
             kind => return Err(BtfError::InvalidTypeKind { kind }),
         })
     }
@@ -146,6 +173,8 @@ impl BtfType {
             BtfKind::FuncProto => FuncProto(ty, unsafe { read_array(data, vlen)? }),
             BtfKind::Var => Var(ty, unsafe { read(data)? }),
             BtfKind::DataSec => DataSec(ty, unsafe { read_array(data, vlen)? }),
+            BtfKind::DeclTag => DeclTag(ty, unsafe { read(data)? }),
+            BtfKind::TypeTag => TypeTag(ty),
         })
     }
 
@@ -156,7 +185,7 @@ impl BtfType {
         match self {
             Unknown => ty_size,
             Fwd(_) | Const(_) | Volatile(_) | Restrict(_) | Ptr(_) | Typedef(_) | Func(_)
-            | Float(_) => ty_size,
+            | Float(_) | TypeTag(_) => ty_size,
             Int(_, _) => ty_size + mem::size_of::<u32>(),
             Enum(ty, _) => ty_size + type_vlen(ty) * mem::size_of::<btf_enum>(),
             Array(_, _) => ty_size + mem::size_of::<btf_array>(),
@@ -165,9 +194,171 @@ impl BtfType {
             FuncProto(ty, _) => ty_size + type_vlen(ty) * mem::size_of::<btf_param>(),
             Var(_, _) => ty_size + mem::size_of::<btf_var>(),
             DataSec(ty, _) => ty_size + type_vlen(ty) * mem::size_of::<btf_var_secinfo>(),
+            DeclTag(_, _) => ty_size + mem::size_of::<btf_decl_tag>(),
         }
     }
 
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        fn bytes_of<T>(val: &T) -> &[u8] {
+            // Safety: all btf types are POD
+            unsafe { crate::util::bytes_of(val) }
+        }
+        fn bytes_of_slice<T>(vals: &[T]) -> Vec<u8> {
+            vals.iter().flat_map(|v| bytes_of(v).to_vec()).collect()
+        }
+
+        use BtfType::*;
+        let mut buf = Vec::with_capacity(self.type_info_size());
+        match self {
+            // BTF_KIND_UNKN carries no payload, write it out as a zeroed type so that the ids
+            // of the following types are preserved
+            Unknown => buf.extend(bytes_of(&new_sized_type(0, 0, 0))),
+            Fwd(ty) | Const(ty) | Volatile(ty) | Restrict(ty) | Ptr(ty) | Typedef(ty)
+            | Func(ty) | Float(ty) | TypeTag(ty) => buf.extend(bytes_of(ty)),
+            Int(ty, info) => {
+                buf.extend(bytes_of(ty));
+                buf.extend(&info.to_ne_bytes());
+            }
+            Enum(ty, values) => {
+                buf.extend(bytes_of(ty));
+                buf.extend(bytes_of_slice(values));
+            }
+            Array(ty, array) => {
+                buf.extend(bytes_of(ty));
+                buf.extend(bytes_of(array));
+            }
+            Struct(ty, members) | Union(ty, members) => {
+                buf.extend(bytes_of(ty));
+                buf.extend(bytes_of_slice(members));
+            }
+            FuncProto(ty, params) => {
+                buf.extend(bytes_of(ty));
+                buf.extend(bytes_of_slice(params));
+            }
+            Var(ty, var) => {
+                buf.extend(bytes_of(ty));
+                buf.extend(bytes_of(var));
+            }
+            DataSec(ty, secinfo) => {
+                buf.extend(bytes_of(ty));
+                buf.extend(bytes_of_slice(secinfo));
+            }
+            DeclTag(ty, decl_tag) => {
+                buf.extend(bytes_of(ty));
+                buf.extend(bytes_of(decl_tag));
+            }
+        }
+        buf
+    }
+
+    /// Returns a copy of the type with all the type ids it references and all its string offsets
+    /// translated with `type_id` and `name_off`.
+    pub(crate) fn remap<E>(
+        &self,
+        mut type_id: impl FnMut(u32) -> u32,
+        mut name_off: impl FnMut(u32) -> Result<u32, E>,
+    ) -> Result<BtfType, E> {
+        let mut ty = self.clone();
+
+        use BtfType::*;
+        match &mut ty {
+            Unknown => return Ok(ty),
+            Const(t)
+            | Volatile(t)
+            | Restrict(t)
+            | Ptr(t)
+            | Typedef(t)
+            | Func(t)
+            | TypeTag(t)
+            | Var(t, _)
+            | DeclTag(t, _)
+            | FuncProto(t, _) => {
+                // Safety: union
+                unsafe { t.__bindgen_anon_1.type_ = type_id(t.__bindgen_anon_1.type_) };
+            }
+            Fwd(_)
+            | Int(_, _)
+            | Float(_)
+            | Enum(_, _)
+            | Array(_, _)
+            | Struct(_, _)
+            | Union(_, _)
+            | DataSec(_, _) => {}
+        }
+        match &mut ty {
+            Enum(_, values) => {
+                for value in values {
+                    value.name_off = name_off(value.name_off)?;
+                }
+            }
+            Array(_, array) => {
+                array.type_ = type_id(array.type_);
+                array.index_type = type_id(array.index_type);
+            }
+            Struct(_, members) | Union(_, members) => {
+                for member in members {
+                    member.name_off = name_off(member.name_off)?;
+                    member.type_ = type_id(member.type_);
+                }
+            }
+            FuncProto(_, params) => {
+                for param in params {
+                    param.name_off = name_off(param.name_off)?;
+                    param.type_ = type_id(param.type_);
+                }
+            }
+            DataSec(_, secinfos) => {
+                for secinfo in secinfos {
+                    secinfo.type_ = type_id(secinfo.type_);
+                }
+            }
+            _ => {}
+        }
+        if let Some(t) = ty.btf_type_mut() {
+            t.name_off = name_off(t.name_off)?;
+        }
+
+        Ok(ty)
+    }
+
+    /// Returns the ids of the types referenced by this type.
+    pub(crate) fn referenced_types(&self) -> Vec<u32> {
+        let mut ids = Vec::new();
+        let _ = self.remap::<()>(
+            |type_id| {
+                ids.push(type_id);
+                type_id
+            },
+            Ok,
+        );
+        ids
+    }
+
+    pub(crate) fn btf_type_mut(&mut self) -> Option<&mut btf_type> {
+        use BtfType::*;
+        Some(match self {
+            Unknown => return None,
+            Fwd(ty)
+            | Const(ty)
+            | Volatile(ty)
+            | Restrict(ty)
+            | Ptr(ty)
+            | Typedef(ty)
+            | Func(ty)
+            | Int(ty, _)
+            | Float(ty)
+            | Enum(ty, _)
+            | Array(ty, _)
+            | Struct(ty, _)
+            | Union(ty, _)
+            | FuncProto(ty, _)
+            | Var(ty, _)
+            | DataSec(ty, _)
+            | DeclTag(ty, _)
+            | TypeTag(ty) => ty,
+        })
+    }
+
     pub(crate) fn btf_type(&self) -> Option<&btf_type> {
         use BtfType::*;
         Some(match self {
@@ -188,6 +379,8 @@ impl BtfType {
             FuncProto(ty, _) => ty,
             Var(ty, _) => ty,
             DataSec(ty, _) => ty,
+            DeclTag(ty, _) => ty,
+            TypeTag(ty) => ty,
         })
     }
 
@@ -206,13 +399,151 @@ impl BtfType {
     pub(crate) fn is_composite(&self) -> bool {
         matches!(self, BtfType::Struct(_, _) | BtfType::Union(_, _))
     }
+
+    pub(crate) fn new_int(name_off: u32, size: u32, encoding: u32, offset: u32) -> BtfType {
+        let info = type_info(BtfKind::Int, 0);
+        let data = encoding << 24 | offset << 16 | (size * 8);
+        BtfType::Int(new_sized_type(name_off, info, size), data)
+    }
+
+    pub(crate) fn new_float(name_off: u32, size: u32) -> BtfType {
+        BtfType::Float(new_sized_type(name_off, type_info(BtfKind::Float, 0), size))
+    }
+
+    pub(crate) fn new_ptr(name_off: u32, type_: u32) -> BtfType {
+        BtfType::Ptr(new_ref_type(name_off, type_info(BtfKind::Ptr, 0), type_))
+    }
+
+    pub(crate) fn new_const(name_off: u32, type_: u32) -> BtfType {
+        BtfType::Const(new_ref_type(name_off, type_info(BtfKind::Const, 0), type_))
+    }
+
+    pub(crate) fn new_volatile(name_off: u32, type_: u32) -> BtfType {
+        BtfType::Volatile(new_ref_type(
+            name_off,
+            type_info(BtfKind::Volatile, 0),
+            type_,
+        ))
+    }
+
+    pub(crate) fn new_restrict(name_off: u32, type_: u32) -> BtfType {
+        BtfType::Restrict(new_ref_type(
+            name_off,
+            type_info(BtfKind::Restrict, 0),
+            type_,
+        ))
+    }
+
+    pub(crate) fn new_fwd(name_off: u32, is_union: bool) -> BtfType {
+        // the kind flag is set for forward declarations of unions
+        let info = type_info(BtfKind::Fwd, 0) | (is_union as u32) << 31;
+        BtfType::Fwd(new_ref_type(name_off, info, 0))
+    }
+
+    pub(crate) fn new_array(name_off: u32, type_: u32, index_type: u32, nelems: u32) -> BtfType {
+        BtfType::Array(
+            new_sized_type(name_off, type_info(BtfKind::Array, 0), 0),
+            btf_array {
+                type_,
+                index_type,
+                nelems,
+            },
+        )
+    }
+
+    pub(crate) fn new_typedef(name_off: u32, type_: u32) -> BtfType {
+        BtfType::Typedef(new_ref_type(
+            name_off,
+            type_info(BtfKind::Typedef, 0),
+            type_,
+        ))
+    }
+
+    pub(crate) fn new_struct(name_off: u32, members: Vec<btf_member>, size: u32) -> BtfType {
+        let info = type_info(BtfKind::Struct, members.len());
+        BtfType::Struct(new_sized_type(name_off, info, size), members)
+    }
+
+    pub(crate) fn new_union(name_off: u32, members: Vec<btf_member>, size: u32) -> BtfType {
+        let info = type_info(BtfKind::Union, members.len());
+        BtfType::Union(new_sized_type(name_off, info, size), members)
+    }
+
+    pub(crate) fn new_enum(name_off: u32, values: Vec<btf_enum>) -> BtfType {
+        let info = type_info(BtfKind::Enum, values.len());
+        BtfType::Enum(
+            new_sized_type(name_off, info, mem::size_of::<u32>() as u32),
+            values,
+        )
+    }
+
+    pub(crate) fn new_func(name_off: u32, proto: u32, linkage: btf_func_linkage) -> BtfType {
+        // the linkage of a function is encoded in vlen
+        let info = type_info(BtfKind::Func, linkage as usize);
+        BtfType::Func(new_ref_type(name_off, info, proto))
+    }
+
+    pub(crate) fn new_func_proto(params: Vec<btf_param>, return_type: u32) -> BtfType {
+        let info = type_info(BtfKind::FuncProto, params.len());
+        BtfType::FuncProto(new_ref_type(0, info, return_type), params)
+    }
+
+    pub(crate) fn new_var(name_off: u32, type_: u32, linkage: u32) -> BtfType {
+        let info = type_info(BtfKind::Var, 0);
+        BtfType::Var(new_ref_type(name_off, info, type_), btf_var { linkage })
+    }
+
+    pub(crate) fn new_datasec(
+        name_off: u32,
+        variables: Vec<btf_var_secinfo>,
+        size: u32,
+    ) -> BtfType {
+        let info = type_info(BtfKind::DataSec, variables.len());
+        BtfType::DataSec(new_sized_type(name_off, info, size), variables)
+    }
+
+    pub(crate) fn new_decl_tag(name_off: u32, type_: u32, component_idx: i32) -> BtfType {
+        let info = type_info(BtfKind::DeclTag, 0);
+        BtfType::DeclTag(
+            new_ref_type(name_off, info, type_),
+            btf_decl_tag { component_idx },
+        )
+    }
+
+    pub(crate) fn new_type_tag(name_off: u32, type_: u32) -> BtfType {
+        BtfType::TypeTag(new_ref_type(
+            name_off,
+            type_info(BtfKind::TypeTag, 0),
+            type_,
+        ))
+    }
+}
+
+fn type_info(kind: BtfKind, vlen: usize) -> u32 {
+    (kind as u32) << 24 | (vlen as u32 & 0xFFFF)
+}
+
+fn new_sized_type(name_off: u32, info: u32, size: u32) -> btf_type {
+    btf_type {
+        name_off,
+        info,
+        __bindgen_anon_1: btf_type__bindgen_ty_1 { size },
+    }
+}
+
+fn new_ref_type(name_off: u32, info: u32, type_: u32) -> btf_type {
+    btf_type {
+        name_off,
+        info,
+        __bindgen_anon_1: btf_type__bindgen_ty_1 { type_ },
+    }
 }
 
 fn type_kind(ty: &btf_type) -> Result<BtfKind, BtfError> {
     ((ty.info >> 24) & 0x1F).try_into()
 }
 
-fn type_vlen(ty: &btf_type) -> usize {
+pub(crate) fn type_vlen(ty: &btf_type) -> usize {
     (ty.info & 0xFFFF) as usize
 }
 
@@ -265,8 +596,12 @@ pub(crate) fn types_are_compatible(
                 return Ok(true)
             }
             Int(_, local_off) => {
+                // the int data packs the encoding, the bit offset and the size of the int, only
+                // the bit offset matters
+                let local_off = (local_off >> 16) & 0xFF;
                 if let Int(_, target_off) = target_ty {
-                    return Ok(*local_off == 0 && *target_off == 0);
+                    let target_off = (target_off >> 16) & 0xFF;
+                    return Ok(local_off == 0 && target_off == 0);
                 }
             }
             Ptr(l_ty) => {
@@ -619,4 +954,26 @@ mod tests {
             Err(_) => panic!("unexpected error"),
         }
     }
+
+    #[test]
+    fn test_types_are_compatible_int() {
+        use crate::{generated::BTF_INT_SIGNED, obj::btf::btf_bytes};
+
+        let kind = |kind: u32, vlen: u32| kind << 24 | vlen;
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, BTF_INT_SIGNED << 24 | 32,
+            // [2] int with a bit offset of 8
+            1, kind(BTF_KIND_INT, 0), 4, 8 << 16 | 24,
+            // [3] typedef int s32
+            5, kind(BTF_KIND_TYPEDEF, 0), 1,
+        ];
+        let btf = Btf::parse(&btf_bytes(&types, b"\0int\0s32\0"), Endianness::default()).unwrap();
+
+        assert!(types_are_compatible(&btf, 1, &btf, 1).unwrap());
+        assert!(types_are_compatible(&btf, 3, &btf, 3).unwrap());
+        assert!(!types_are_compatible(&btf, 1, &btf, 2).unwrap());
+        assert!(!types_are_compatible(&btf, 2, &btf, 1).unwrap());
+    }
 }
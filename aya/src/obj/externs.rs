@@ -0,0 +1,1089 @@
+//! Extern variables resolved at load time, eg:
+//!
+//! ```c
+//! extern unsigned int LINUX_KERNEL_VERSION __kconfig;
+//! extern bool CONFIG_BPF_JIT __kconfig __weak;
+//! extern const struct rq runqueues __ksym;
+//! ```
+//!
+//! and kernel functions (kfuncs) called by the programs, eg:
+//!
+//! ```c
+//! extern struct task_struct *bpf_task_acquire(struct task_struct *p) __ksym;
+//! ```
+use std::{collections::HashMap, io, mem};
+
+use object::Endianness;
+use thiserror::Error;
+
+use crate::{
+    bpf_map_def,
+    generated::{
+        bpf_insn, bpf_map_type::BPF_MAP_TYPE_ARRAY, btf_func_linkage, BPF_F_RDONLY_PROG,
+        BTF_INT_BOOL, BTF_INT_SIGNED, BTF_VAR_GLOBAL_ALLOCATED, BTF_VAR_GLOBAL_EXTERN,
+    },
+    obj::{
+        btf::{type_vlen, types_are_compatible, Btf, BtfError, BtfKind, BtfType, ModuleBtf},
+        relocation::is_call,
+        Map, MapKind, Object, ParseError,
+    },
+    BpfError,
+};
+
+pub(crate) const KCONFIG_SECTION: &str = ".kconfig";
+pub(crate) const KSYMS_SECTION: &str = ".ksyms";
+
+const LINUX_KERNEL_VERSION: &str = "LINUX_KERNEL_VERSION";
+
+/// The error type returned when extern variables can't be resolved.
+#[derive(Debug, Error)]
+pub enum ExternError {
+    #[error("unsupported type for kconfig extern `{name}`")]
+    UnsupportedKconfigType { name: String },
+
+    #[error("extern `{name}` must have global extern linkage")]
+    InvalidLinkage { name: String },
+
+    #[error("kconfig value for `{name}` not found")]
+    KconfigNotFound { name: String },
+
+    #[error("invalid kconfig value `{value}` for `{name}`")]
+    InvalidKconfigValue { name: String, value: String },
+
+    #[error("kernel symbol `{name}` not found")]
+    KsymNotFound { name: String },
+
+    #[error("the type of kernel symbol `{name}` doesn't match the kernel's")]
+    IncompatibleKsymType { name: String },
+
+    #[error("failed to open the BTF of kernel module `{module}`")]
+    ModuleBtfError {
+        module: String,
+        #[source]
+        io_error: io::Error,
+    },
+
+    #[error("BTF error")]
+    BtfError(#[from] BtfError),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Extern {
+    pub(crate) name: String,
+    pub(crate) is_weak: bool,
+    pub(crate) kind: ExternKind,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ExternKind {
+    Kconfig {
+        ty: KconfigType,
+        // offset of the value inside the .kconfig map
+        offset: usize,
+    },
+    Ksym {
+        // the type of the variable as declared by the program, None for typeless (`void`) ksyms
+        type_id: Option<u32>,
+        value: Option<KsymValue>,
+    },
+    Kfunc {
+        // the FUNC declared by the program, None for functions declared without BTF
+        type_id: Option<u32>,
+        value: Option<KfuncValue>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum KconfigType {
+    Bool,
+    Char,
+    Tristate,
+    Int { size: usize, signed: bool },
+    CharArray { len: usize },
+}
+
+impl KconfigType {
+    fn size(&self) -> usize {
+        match self {
+            KconfigType::Bool | KconfigType::Char => 1,
+            KconfigType::Tristate => mem::size_of::<u32>(),
+            KconfigType::Int { size, .. } => *size,
+            KconfigType::CharArray { len } => *len,
+        }
+    }
+
+    fn align(&self) -> usize {
+        match self {
+            KconfigType::CharArray { .. } => 1,
+            _ => self.size(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum KsymValue {
+    // the address of a typeless ksym, from /proc/kallsyms
+    Address(u64),
+    // the id of the VAR in the kernel BTF
+    BtfId(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct KfuncValue {
+    // the id of the FUNC in the kernel or module BTF
+    pub(crate) btf_id: u32,
+    // the index of the fd of the module BTF in the fd_array passed to BPF_PROG_LOAD, 0 for vmlinux
+    pub(crate) fd_index: i16,
+}
+
+impl Object {
+    /// Collects the extern variables declared in the `.kconfig` and `.ksyms` sections and the
+    /// kfuncs called by the programs.
+    pub(crate) fn collect_externs(&mut self) -> Result<(), BpfError> {
+        self.collect_btf_externs()?;
+        self.collect_kfunc_calls();
+
+        Ok(())
+    }
+
+    /// Collects the externs described by the `.kconfig` and `.ksyms` DATASECs.
+    ///
+    /// The kernel doesn't accept extern VARs and FUNCs, so the BTF is rewritten to describe the
+    /// data the loader provides for them instead. Kconfig externs are stored in a read-only
+    /// `.kconfig` map.
+    fn collect_btf_externs(&mut self) -> Result<(), BpfError> {
+        let btf = match &mut self.btf {
+            Some(btf) => btf,
+            None => return Ok(()),
+        };
+
+        let mut datasecs = Vec::new();
+        for ty in btf.types() {
+            if let BtfType::DataSec(_, secinfos) = ty {
+                let name = btf.type_name(ty)?;
+                if let Some(name @ KCONFIG_SECTION) | Some(name @ KSYMS_SECTION) = name.as_deref() {
+                    let var_ids = secinfos.iter().map(|s| s.type_).collect::<Vec<_>>();
+                    datasecs.push((name.to_owned(), var_ids));
+                }
+            }
+        }
+
+        let mut kconfig = Vec::new();
+        let mut ksyms = Vec::new();
+        for (section, var_ids) in datasecs {
+            for var_id in var_ids {
+                let (name, type_id, is_func) = match btf.type_by_id(var_id)? {
+                    ty @ BtfType::Var(var_ty, var) => {
+                        let name = btf.type_name(ty)?.unwrap_or_default().into_owned();
+                        if var.linkage != BTF_VAR_GLOBAL_EXTERN {
+                            return Err(ExternError::InvalidLinkage { name }.into());
+                        }
+                        // Safety: union
+                        (name, unsafe { var_ty.__bindgen_anon_1.type_ }, false)
+                    }
+                    ty @ BtfType::Func(func_ty) if section == KSYMS_SECTION => {
+                        let name = btf.type_name(ty)?.unwrap_or_default().into_owned();
+                        if type_vlen(func_ty) != btf_func_linkage::BTF_FUNC_EXTERN as usize {
+                            return Err(ExternError::InvalidLinkage { name }.into());
+                        }
+                        (name, var_id, true)
+                    }
+                    _ => return Err(BtfError::UnexpectedBtfType { type_id: var_id }.into()),
+                };
+
+                let symbol = self
+                    .symbols_by_index
+                    .values()
+                    .find(|s| s.section_index.is_none() && s.name.as_deref() == Some(&name))
+                    .ok_or_else(|| ParseError::SymbolNotFound { name: name.clone() })?;
+
+                if section == KCONFIG_SECTION {
+                    let ty = kconfig_type(btf, type_id)?.ok_or_else(|| {
+                        ExternError::UnsupportedKconfigType { name: name.clone() }
+                    })?;
+                    kconfig.push((symbol.index, symbol.is_weak, name, var_id, ty));
+                } else if is_func {
+                    ksyms.push((symbol.index, symbol.is_weak, name, var_id, type_id, None));
+                } else {
+                    let void = btf.resolve_type(type_id)? == 0;
+                    ksyms.push((
+                        symbol.index,
+                        symbol.is_weak,
+                        name,
+                        var_id,
+                        type_id,
+                        Some(void),
+                    ));
+                }
+            }
+        }
+
+        // lay out the kconfig values from the most to the least aligned so there's no padding
+        // between them
+        kconfig.sort_by(|(_, _, a_name, _, a), (_, _, b_name, _, b)| {
+            b.align().cmp(&a.align()).then_with(|| a_name.cmp(b_name))
+        });
+        let mut kconfig_offsets = HashMap::new();
+        let mut kconfig_size = 0;
+        for (symbol_index, is_weak, name, var_id, ty) in kconfig {
+            let offset = (kconfig_size + ty.align() - 1) & !(ty.align() - 1);
+            kconfig_size = offset + ty.size();
+            kconfig_offsets.insert(var_id, (offset, ty.size()));
+            self.externs.insert(
+                symbol_index,
+                Extern {
+                    name,
+                    is_weak,
+                    kind: ExternKind::Kconfig { ty, offset },
+                },
+            );
+        }
+
+        // typed ksyms are resolved to kernel BTF ids and typeless ones to addresses, neither
+        // takes space in a map so the variables are all described as plain ints. DATASECs can
+        // only contain VARs, so the FUNCs of kfuncs are replaced by a dummy int VAR.
+        let mut ksym_offsets = HashMap::new();
+        let mut kfunc_ids = Vec::new();
+        let mut dummy_var_id = 0;
+        if !ksyms.is_empty() {
+            let int_id = match btf.id_by_type_name_kind("int", BtfKind::Int) {
+                Ok(id) => id,
+                Err(_) => {
+                    let name_off = btf.add_string("int".to_string());
+                    btf.add_type(BtfType::new_int(name_off, 4, BTF_INT_SIGNED, 0))
+                }
+            };
+            for (i, (symbol_index, is_weak, name, var_id, type_id, void)) in
+                ksyms.into_iter().enumerate()
+            {
+                let kind = match void {
+                    Some(void) => {
+                        if let BtfType::Var(var_ty, _) = btf.type_by_id_mut(var_id)? {
+                            var_ty.__bindgen_anon_1.type_ = int_id;
+                        }
+                        ExternKind::Ksym {
+                            type_id: if void { None } else { Some(type_id) },
+                            value: None,
+                        }
+                    }
+                    None => {
+                        if dummy_var_id == 0 {
+                            let name_off = btf.add_string("dummy_ksym".to_string());
+                            dummy_var_id = btf.add_type(BtfType::new_var(
+                                name_off,
+                                int_id,
+                                BTF_VAR_GLOBAL_ALLOCATED,
+                            ));
+                        }
+                        // the kernel doesn't accept extern FUNCs either
+                        let func = btf.type_by_id_mut(var_id)?;
+                        if let BtfType::Func(func_ty) = func {
+                            *func = BtfType::new_func(
+                                func_ty.name_off,
+                                // Safety: union
+                                unsafe { func_ty.__bindgen_anon_1.type_ },
+                                btf_func_linkage::BTF_FUNC_GLOBAL,
+                            );
+                        }
+                        kfunc_ids.push(var_id);
+                        ExternKind::Kfunc {
+                            type_id: Some(type_id),
+                            value: None,
+                        }
+                    }
+                };
+                ksym_offsets.insert(var_id, (i * mem::size_of::<u32>(), mem::size_of::<u32>()));
+                self.externs.insert(
+                    symbol_index,
+                    Extern {
+                        name,
+                        is_weak,
+                        kind,
+                    },
+                );
+            }
+        }
+
+        // rewrite the VARs and DATASECs so that the kernel accepts them
+        let var_ids = kconfig_offsets
+            .keys()
+            .chain(ksym_offsets.keys())
+            .copied()
+            .collect::<Vec<_>>();
+        for var_id in var_ids {
+            if let BtfType::Var(_, var) = btf.type_by_id_mut(var_id)? {
+                var.linkage = BTF_VAR_GLOBAL_ALLOCATED;
+            }
+        }
+        let mut datasec_ids = Vec::new();
+        for (i, ty) in btf.types().enumerate() {
+            if let BtfType::DataSec(_, _) = ty {
+                match btf.type_name(ty)?.as_deref() {
+                    Some(KCONFIG_SECTION) => {
+                        datasec_ids.push((btf.first_type_id() + i as u32, &kconfig_offsets))
+                    }
+                    Some(KSYMS_SECTION) => {
+                        datasec_ids.push((btf.first_type_id() + i as u32, &ksym_offsets))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (datasec_id, offsets) in datasec_ids {
+            if let BtfType::DataSec(ty, secinfos) = btf.type_by_id_mut(datasec_id)? {
+                let mut size = 0;
+                for secinfo in secinfos.iter_mut() {
+                    if let Some((offset, var_size)) = offsets.get(&secinfo.type_) {
+                        secinfo.offset = *offset as u32;
+                        secinfo.size = *var_size as u32;
+                        size = size.max(offset + var_size);
+                    }
+                    if kfunc_ids.contains(&secinfo.type_) {
+                        secinfo.type_ = dummy_var_id;
+                    }
+                }
+                ty.__bindgen_anon_1.size = size as u32;
+            }
+        }
+
+        if kconfig_size > 0 {
+            self.maps.insert(
+                KCONFIG_SECTION.to_string(),
+                Map {
+                    def: bpf_map_def {
+                        map_type: BPF_MAP_TYPE_ARRAY as u32,
+                        key_size: mem::size_of::<u32>() as u32,
+                        value_size: kconfig_size as u32,
+                        max_entries: 1,
+                        map_flags: BPF_F_RDONLY_PROG,
+                        ..Default::default()
+                    },
+                    section_index: 0,
+                    symbol_index: None,
+                    data: vec![0; kconfig_size],
+                    kind: MapKind::Rodata,
+                    btf_def: None,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Collects the kfuncs called by the programs that aren't described by BTF.
+    ///
+    /// Objects compiled from Rust don't have BTF, calls to undefined functions are assumed to be
+    /// calls to kfuncs.
+    fn collect_kfunc_calls(&mut self) {
+        let functions = self
+            .programs
+            .values()
+            .map(|program| &program.function)
+            .chain(self.functions.values());
+        for fun in functions {
+            let relocations = match self.relocations.get(&fun.section_index) {
+                Some(relocations) => relocations,
+                None => continue,
+            };
+            for rel in relocations.values() {
+                let ins_index = match (rel.offset as usize).checked_sub(fun.section_offset) {
+                    Some(offset) => offset / mem::size_of::<bpf_insn>(),
+                    None => continue,
+                };
+                match fun.instructions.get(ins_index) {
+                    Some(ins) if is_call(ins) => {}
+                    _ => continue,
+                }
+                if self.externs.contains_key(&rel.symbol_index) {
+                    continue;
+                }
+                let symbol = match self.symbols_by_index.get(&rel.symbol_index) {
+                    Some(symbol) if symbol.section_index.is_none() => symbol,
+                    _ => continue,
+                };
+                if let Some(name) = &symbol.name {
+                    self.externs.insert(
+                        symbol.index,
+                        Extern {
+                            name: name.clone(),
+                            is_weak: symbol.is_weak,
+                            kind: ExternKind::Kfunc {
+                                type_id: None,
+                                value: None,
+                            },
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    pub(crate) fn has_kconfig_externs(&self) -> bool {
+        self.externs
+            .values()
+            .any(|ext| matches!(ext.kind, ExternKind::Kconfig { .. }))
+    }
+
+    pub(crate) fn has_ksym_externs(&self) -> bool {
+        self.externs
+            .values()
+            .any(|ext| matches!(ext.kind, ExternKind::Ksym { .. } | ExternKind::Kfunc { .. }))
+    }
+
+    pub(crate) fn has_kfunc_externs(&self) -> bool {
+        self.externs
+            .values()
+            .any(|ext| matches!(ext.kind, ExternKind::Kfunc { .. }))
+    }
+
+    pub(crate) fn has_typeless_ksym_externs(&self) -> bool {
+        self.externs
+            .values()
+            .any(|ext| matches!(ext.kind, ExternKind::Ksym { type_id: None, .. }))
+    }
+
+    /// Writes the values of the kconfig externs into the `.kconfig` map.
+    ///
+    /// `config` maps option names like `CONFIG_BPF_JIT` to their values as they appear in the
+    /// kernel config file. `kernel_version` is the value of `LINUX_KERNEL_VERSION`.
+    pub(crate) fn resolve_kconfig(
+        &mut self,
+        config: &HashMap<String, String>,
+        kernel_version: u32,
+    ) -> Result<(), ExternError> {
+        let map = match self.maps.get_mut(KCONFIG_SECTION) {
+            Some(map) => map,
+            None => return Ok(()),
+        };
+
+        for ext in self.externs.values() {
+            let (ty, offset) = match &ext.kind {
+                ExternKind::Kconfig { ty, offset } => (*ty, *offset),
+                _ => continue,
+            };
+            let data = &mut map.data[offset..offset + ty.size()];
+
+            if ext.name == LINUX_KERNEL_VERSION {
+                if !matches!(ty, KconfigType::Int { size: 4, .. }) {
+                    return Err(ExternError::UnsupportedKconfigType {
+                        name: ext.name.clone(),
+                    });
+                }
+                write_int(data, kernel_version as u64, self.endianness);
+                continue;
+            }
+
+            let value = match config.get(&ext.name) {
+                Some(value) => value,
+                None if ext.is_weak => continue,
+                None => {
+                    return Err(ExternError::KconfigNotFound {
+                        name: ext.name.clone(),
+                    })
+                }
+            };
+            let invalid_value = || ExternError::InvalidKconfigValue {
+                name: ext.name.clone(),
+                value: value.clone(),
+            };
+
+            match (ty, value.as_str()) {
+                (KconfigType::Bool, "y") | (KconfigType::Tristate, "y") => {
+                    write_int(data, 1, self.endianness)
+                }
+                (KconfigType::Bool, "n") | (KconfigType::Tristate, "n") => {
+                    write_int(data, 0, self.endianness)
+                }
+                (KconfigType::Tristate, "m") => write_int(data, 2, self.endianness),
+                (KconfigType::Char, "y") | (KconfigType::Char, "n") | (KconfigType::Char, "m") => {
+                    data[0] = value.as_bytes()[0]
+                }
+                (KconfigType::Tristate, _) => return Err(invalid_value()),
+                (KconfigType::CharArray { len }, value) => {
+                    let s = value
+                        .strip_prefix('"')
+                        .and_then(|s| s.strip_suffix('"'))
+                        .ok_or_else(invalid_value)?;
+                    // the value is truncated if it doesn't fit, the last byte is always NUL
+                    let n = s.len().min(len - 1);
+                    data[..n].copy_from_slice(&s.as_bytes()[..n]);
+                    data[n] = 0;
+                }
+                (ty, value) => {
+                    let n = parse_kconfig_number(value).ok_or_else(invalid_value)?;
+                    let (min, max) = match ty {
+                        KconfigType::Bool => (0, 1),
+                        KconfigType::Int { size, signed: true } => {
+                            (-(1i128 << (size * 8 - 1)), (1i128 << (size * 8 - 1)) - 1)
+                        }
+                        _ => (0, (1i128 << (ty.size() * 8)) - 1),
+                    };
+                    if n < min || n > max {
+                        return Err(invalid_value());
+                    }
+                    write_int(data, n as u64, self.endianness);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the ksym and kfunc externs.
+    ///
+    /// Typed ksyms are looked up by name among the VARs of `kernel_btf`, typeless ksyms in
+    /// `kernel_symbols`. Kfuncs are looked up among the FUNCs of `kernel_btf` and then of
+    /// `module_btfs`. Weak ksyms that can't be found are left unresolved.
+    ///
+    /// Returns the names of the modules that define kfuncs, in the order in which their BTF fds
+    /// must be passed in the fd_array, after the unused fd of vmlinux.
+    pub(crate) fn resolve_ksyms(
+        &mut self,
+        kernel_btf: Option<&Btf>,
+        module_btfs: &[ModuleBtf],
+        kernel_symbols: &HashMap<String, u64>,
+    ) -> Result<Vec<String>, ExternError> {
+        let mut modules = Vec::new();
+        for ext in self.externs.values_mut() {
+            let (type_id, value) = match &mut ext.kind {
+                ExternKind::Ksym { type_id, value } => (*type_id, value),
+                ExternKind::Kfunc { type_id, value } => {
+                    *value = match kernel_btf {
+                        Some(kernel_btf) => resolve_kfunc(
+                            &ext.name,
+                            self.btf.as_ref().zip(*type_id),
+                            kernel_btf,
+                            module_btfs,
+                            &mut modules,
+                        )?,
+                        None => None,
+                    };
+                    if value.is_none() && !ext.is_weak {
+                        return Err(ExternError::KsymNotFound {
+                            name: ext.name.clone(),
+                        });
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+
+            *value = match (type_id, kernel_btf, &self.btf) {
+                (None, _, _) => kernel_symbols
+                    .get(&ext.name)
+                    .copied()
+                    .map(KsymValue::Address),
+                (Some(local_type_id), Some(kernel_btf), Some(local_btf)) => {
+                    match kernel_btf.id_by_type_name_kind(&ext.name, BtfKind::Var) {
+                        Ok(var_id) => {
+                            let target_type_id = match kernel_btf.type_by_id(var_id)? {
+                                // Safety: union
+                                BtfType::Var(ty, _) => unsafe { ty.__bindgen_anon_1.type_ },
+                                _ => {
+                                    return Err(
+                                        BtfError::UnexpectedBtfType { type_id: var_id }.into()
+                                    )
+                                }
+                            };
+                            if !types_are_compatible(
+                                local_btf,
+                                local_type_id,
+                                kernel_btf,
+                                target_type_id,
+                            )? {
+                                return Err(ExternError::IncompatibleKsymType {
+                                    name: ext.name.clone(),
+                                });
+                            }
+                            Some(KsymValue::BtfId(var_id))
+                        }
+                        Err(BtfError::UnknownBtfTypeName { .. }) => None,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                // typed ksyms can't be resolved without BTF
+                (Some(_), _, _) => None,
+            };
+
+            if value.is_none() && !ext.is_weak {
+                return Err(ExternError::KsymNotFound {
+                    name: ext.name.clone(),
+                });
+            }
+        }
+
+        Ok(modules)
+    }
+}
+
+// Finds the kfunc `name` in vmlinux or in the modules. `local` is the FUNC declared by the
+// program, if any, its prototype must match the kernel's. The modules that define kfuncs are
+// added to `modules`.
+fn resolve_kfunc(
+    name: &str,
+    local: Option<(&Btf, u32)>,
+    kernel_btf: &Btf,
+    module_btfs: &[ModuleBtf],
+    modules: &mut Vec<String>,
+) -> Result<Option<KfuncValue>, ExternError> {
+    let (btf, btf_id, module) = match kernel_btf.id_by_type_name_kind(name, BtfKind::Func) {
+        Ok(btf_id) => (kernel_btf, btf_id, None),
+        Err(BtfError::UnknownBtfTypeName { .. }) => {
+            let mut found = None;
+            for module in module_btfs {
+                if let Some(btf_id) = module.btf.split_id_by_type_name_kind(name, BtfKind::Func)? {
+                    found = Some((&module.btf, btf_id, Some(&module.name)));
+                    break;
+                }
+            }
+            match found {
+                Some(found) => found,
+                None => return Ok(None),
+            }
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some((local_btf, local_id)) = local {
+        if !types_are_compatible(
+            local_btf,
+            func_proto(local_btf, local_id)?,
+            btf,
+            func_proto(btf, btf_id)?,
+        )? {
+            return Err(ExternError::IncompatibleKsymType {
+                name: name.to_owned(),
+            });
+        }
+    }
+
+    let fd_index = match module {
+        Some(module) => match modules.iter().position(|m| m == module) {
+            Some(i) => i + 1,
+            None => {
+                modules.push(module.clone());
+                modules.len()
+            }
+        },
+        None => 0,
+    };
+
+    Ok(Some(KfuncValue {
+        btf_id,
+        fd_index: fd_index as i16,
+    }))
+}
+
+fn func_proto(btf: &Btf, func_id: u32) -> Result<u32, BtfError> {
+    match btf.type_by_id(func_id)? {
+        // Safety: union
+        BtfType::Func(ty) => Ok(unsafe { ty.__bindgen_anon_1.type_ }),
+        _ => Err(BtfError::UnexpectedBtfType { type_id: func_id }),
+    }
+}
+
+fn kconfig_type(btf: &Btf, type_id: u32) -> Result<Option<KconfigType>, BtfError> {
+    let ty = btf.type_by_id(btf.resolve_type(type_id)?)?;
+    Ok(match ty {
+        BtfType::Int(int_ty, data) => {
+            // Safety: union
+            let size = unsafe { int_ty.__bindgen_anon_1.size } as usize;
+            let encoding = data >> 24;
+            if size == 1 && encoding & BTF_INT_BOOL != 0 {
+                Some(KconfigType::Bool)
+            } else if size == 1 {
+                Some(KconfigType::Char)
+            } else if size.is_power_of_two() && size <= mem::size_of::<u64>() {
+                Some(KconfigType::Int {
+                    size,
+                    signed: encoding & BTF_INT_SIGNED != 0,
+                })
+            } else {
+                None
+            }
+        }
+        BtfType::Enum(enum_ty, _)
+            if btf.type_name(ty)?.as_deref() == Some("libbpf_tristate")
+                // Safety: union
+                && unsafe { enum_ty.__bindgen_anon_1.size } == 4 =>
+        {
+            Some(KconfigType::Tristate)
+        }
+        BtfType::Array(_, array) if array.nelems > 0 => match kconfig_type(btf, array.type_)? {
+            Some(KconfigType::Char) => Some(KconfigType::CharArray {
+                len: array.nelems as usize,
+            }),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+// Parses a decimal or hexadecimal kconfig value
+fn parse_kconfig_number(value: &str) -> Option<i128> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    let n = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<u64>().ok()?,
+    } as i128;
+
+    Some(if negative { -n } else { n })
+}
+
+fn write_int(data: &mut [u8], value: u64, endianness: Endianness) {
+    let len = data.len();
+    match endianness {
+        Endianness::Little => data.copy_from_slice(&value.to_le_bytes()[..len]),
+        Endianness::Big => data.copy_from_slice(&value.to_be_bytes()[8 - len..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matches::assert_matches;
+
+    use super::*;
+    use crate::{
+        generated::{
+            BTF_INT_CHAR, BTF_KIND_ARRAY, BTF_KIND_DATASEC, BTF_KIND_FUNC, BTF_KIND_FUNC_PROTO,
+            BTF_KIND_INT, BTF_KIND_VAR, BTF_VAR_GLOBAL_EXTERN,
+        },
+        obj::{self, btf::btf_bytes, Symbol},
+    };
+
+    fn kind(kind: u32, vlen: u32) -> u32 {
+        kind << 24 | vlen
+    }
+
+    fn fake_obj(types: &[u32], strings: &[u8], externs: &[(&str, bool)]) -> Object {
+        let mut obj = obj::fake_obj();
+        obj.btf = Some(Btf::parse(&btf_bytes(types, strings), Endianness::Little).unwrap());
+        for (i, (name, is_weak)) in externs.iter().enumerate() {
+            obj.symbols_by_index.insert(
+                i + 1,
+                Symbol {
+                    index: i + 1,
+                    section_index: None,
+                    name: Some(name.to_string()),
+                    address: 0,
+                    size: 0,
+                    is_definition: false,
+                    is_text: false,
+                    is_weak: *is_weak,
+                },
+            );
+        }
+        obj
+    }
+
+    fn kconfig_obj() -> Object {
+        let strings = b"\0unsigned int\0bool\0char\0int\0.kconfig\0LINUX_KERNEL_VERSION\0\
+                        CONFIG_BPF\0CONFIG_HZ\0CONFIG_LOCALVERSION\0CONFIG_MISSING\0";
+        #[rustfmt::skip]
+        let types = [
+            // [1] unsigned int
+            1, kind(BTF_KIND_INT, 0), 4, 32,
+            // [2] bool
+            14, kind(BTF_KIND_INT, 0), 1, BTF_INT_BOOL << 24 | 8,
+            // [3] char
+            19, kind(BTF_KIND_INT, 0), 1, BTF_INT_CHAR << 24 | 8,
+            // [4] int
+            24, kind(BTF_KIND_INT, 0), 4, BTF_INT_SIGNED << 24 | 32,
+            // [5] char[8]
+            0, kind(BTF_KIND_ARRAY, 0), 0, 3, 4, 8,
+            // [6] extern unsigned int LINUX_KERNEL_VERSION
+            37, kind(BTF_KIND_VAR, 0), 1, BTF_VAR_GLOBAL_EXTERN,
+            // [7] extern bool CONFIG_BPF
+            58, kind(BTF_KIND_VAR, 0), 2, BTF_VAR_GLOBAL_EXTERN,
+            // [8] extern int CONFIG_HZ
+            69, kind(BTF_KIND_VAR, 0), 4, BTF_VAR_GLOBAL_EXTERN,
+            // [9] extern char CONFIG_LOCALVERSION[8]
+            79, kind(BTF_KIND_VAR, 0), 5, BTF_VAR_GLOBAL_EXTERN,
+            // [10] extern bool CONFIG_MISSING __weak
+            99, kind(BTF_KIND_VAR, 0), 2, BTF_VAR_GLOBAL_EXTERN,
+            // [11] .kconfig
+            28, kind(BTF_KIND_DATASEC, 5), 0,
+                6, 0, 4, 7, 0, 1, 8, 0, 4, 9, 0, 8, 10, 0, 1,
+        ];
+        let mut obj = fake_obj(
+            &types,
+            strings,
+            &[
+                ("LINUX_KERNEL_VERSION", false),
+                ("CONFIG_BPF", false),
+                ("CONFIG_HZ", false),
+                ("CONFIG_LOCALVERSION", false),
+                ("CONFIG_MISSING", true),
+            ],
+        );
+        obj.collect_externs().unwrap();
+        obj
+    }
+
+    fn kconfig(values: &[(&str, &str)]) -> HashMap<String, String> {
+        values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_collect_kconfig_externs() {
+        let obj = kconfig_obj();
+
+        let offsets = (1..=5)
+            .map(|i| match &obj.externs[&i].kind {
+                ExternKind::Kconfig { offset, .. } => *offset,
+                _ => panic!("unexpected extern kind"),
+            })
+            .collect::<Vec<_>>();
+        // the 4 byte values come first, then the rest sorted by name
+        assert_eq!(offsets, vec![4, 8, 0, 9, 17]);
+
+        let map = &obj.maps[KCONFIG_SECTION];
+        assert_eq!(map.def.value_size, 18);
+        assert_eq!(map.def.map_flags, BPF_F_RDONLY_PROG);
+        assert_eq!(map.kind, MapKind::Rodata);
+        assert_eq!(map.data, vec![0; 18]);
+
+        let btf = obj.btf.as_ref().unwrap();
+        assert_matches!(
+            btf.type_by_id(7).unwrap(),
+            BtfType::Var(_, var) if var.linkage == BTF_VAR_GLOBAL_ALLOCATED
+        );
+        match btf.type_by_id(11).unwrap() {
+            BtfType::DataSec(ty, secinfos) => {
+                assert_eq!(unsafe { ty.__bindgen_anon_1.size }, 18);
+                assert_eq!(
+                    secinfos
+                        .iter()
+                        .map(|s| (s.offset, s.size))
+                        .collect::<Vec<_>>(),
+                    vec![(4, 4), (8, 1), (0, 4), (9, 8), (17, 1)]
+                );
+            }
+            _ => panic!("unexpected BTF type"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_kconfig() {
+        let mut obj = kconfig_obj();
+        let config = kconfig(&[
+            ("CONFIG_BPF", "y"),
+            ("CONFIG_HZ", "-250"),
+            ("CONFIG_LOCALVERSION", "\"-foo-bar\""),
+        ]);
+        obj.resolve_kconfig(&config, 0x050a00).unwrap();
+        assert_eq!(
+            obj.maps[KCONFIG_SECTION].data,
+            [
+                &(-250i32).to_le_bytes()[..],
+                &[0x00, 0x0a, 0x05, 0x00],
+                &[1],
+                b"-foo-ba\0",
+                &[0]
+            ]
+            .concat()
+        );
+
+        let mut obj = kconfig_obj();
+        assert_matches!(
+            obj.resolve_kconfig(
+                &kconfig(&[("CONFIG_BPF", "y"), ("CONFIG_LOCALVERSION", "\"\"")]),
+                0
+            ),
+            Err(ExternError::KconfigNotFound { name }) if name == "CONFIG_HZ"
+        );
+
+        for (name, value) in [
+            ("CONFIG_BPF", "m"),
+            ("CONFIG_BPF", "2"),
+            ("CONFIG_HZ", "0x80000000"),
+            ("CONFIG_HZ", "foo"),
+            ("CONFIG_LOCALVERSION", "y"),
+        ] {
+            let mut config = kconfig(&[
+                ("CONFIG_BPF", "y"),
+                ("CONFIG_HZ", "250"),
+                ("CONFIG_LOCALVERSION", "\"\""),
+            ]);
+            config.insert(name.to_string(), value.to_string());
+            let mut obj = kconfig_obj();
+            assert_matches!(
+                obj.resolve_kconfig(&config, 0),
+                Err(ExternError::InvalidKconfigValue { .. })
+            );
+        }
+    }
+
+    #[test]
+    fn test_ksym_externs() {
+        let strings = b"\0int\0.ksyms\0foo\0bar\0";
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, BTF_INT_SIGNED << 24 | 32,
+            // [2] extern const void foo
+            12, kind(BTF_KIND_VAR, 0), 0, BTF_VAR_GLOBAL_EXTERN,
+            // [3] extern int bar __weak
+            16, kind(BTF_KIND_VAR, 0), 1, BTF_VAR_GLOBAL_EXTERN,
+            // [4] .ksyms
+            5, kind(BTF_KIND_DATASEC, 2), 0, 2, 0, 0, 3, 0, 0,
+        ];
+        let mut obj = fake_obj(&types, strings, &[("foo", false), ("bar", true)]);
+        obj.collect_externs().unwrap();
+
+        assert!(obj.maps.is_empty());
+        let btf = obj.btf.as_ref().unwrap();
+        assert_matches!(
+            btf.type_by_id(2).unwrap(),
+            BtfType::Var(ty, var) if unsafe { ty.__bindgen_anon_1.type_ } == 1
+                && var.linkage == BTF_VAR_GLOBAL_ALLOCATED
+        );
+        assert_matches!(
+            btf.type_by_id(4).unwrap(),
+            BtfType::DataSec(ty, _) if unsafe { ty.__bindgen_anon_1.size } == 8
+        );
+
+        let symbols = [("foo".to_string(), 0xffff_ffff_8100_0000)]
+            .iter()
+            .cloned()
+            .collect();
+        obj.resolve_ksyms(None, &[], &symbols).unwrap();
+        assert_matches!(
+            obj.externs[&1].kind,
+            ExternKind::Ksym {
+                type_id: None,
+                value: Some(KsymValue::Address(0xffff_ffff_8100_0000))
+            }
+        );
+        // bar is typed but there's no kernel BTF, it's weak so that's not an error
+        assert_matches!(
+            obj.externs[&2].kind,
+            ExternKind::Ksym {
+                type_id: Some(1),
+                value: None
+            }
+        );
+
+        assert_matches!(
+            obj.resolve_ksyms(None, &[], &HashMap::new()),
+            Err(ExternError::KsymNotFound { name }) if name == "foo"
+        );
+    }
+
+    #[test]
+    fn test_kfunc_externs() {
+        const EXTERN: u32 = btf_func_linkage::BTF_FUNC_EXTERN as u32;
+        const GLOBAL: u32 = btf_func_linkage::BTF_FUNC_GLOBAL as u32;
+
+        let strings = b"\0int\0.ksyms\0bpf_foo\0bpf_mod\0bpf_bar\0";
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, BTF_INT_SIGNED << 24 | 32,
+            // [2] int (int)
+            0, kind(BTF_KIND_FUNC_PROTO, 1), 1, 0, 1,
+            // [3] extern int bpf_foo(int)
+            12, kind(BTF_KIND_FUNC, EXTERN), 2,
+            // [4] extern int bpf_mod(int)
+            20, kind(BTF_KIND_FUNC, EXTERN), 2,
+            // [5] extern int bpf_bar(int) __weak
+            28, kind(BTF_KIND_FUNC, EXTERN), 2,
+            // [6] .ksyms
+            5, kind(BTF_KIND_DATASEC, 3), 0, 3, 0, 0, 4, 0, 0, 5, 0, 0,
+        ];
+        let mut obj = fake_obj(
+            &types,
+            strings,
+            &[("bpf_foo", false), ("bpf_mod", false), ("bpf_bar", true)],
+        );
+        obj.collect_externs().unwrap();
+
+        // the FUNCs are replaced by a dummy VAR in the DATASEC and made global
+        let btf = obj.btf.as_ref().unwrap();
+        match btf.type_by_id(6).unwrap() {
+            BtfType::DataSec(ty, secinfos) => {
+                assert_eq!(unsafe { ty.__bindgen_anon_1.size }, 12);
+                assert_eq!(
+                    secinfos
+                        .iter()
+                        .map(|s| (s.type_, s.offset, s.size))
+                        .collect::<Vec<_>>(),
+                    vec![(7, 0, 4), (7, 4, 4), (7, 8, 4)]
+                );
+            }
+            _ => panic!("unexpected BTF type"),
+        }
+        assert_matches!(
+            btf.type_by_id(7).unwrap(),
+            BtfType::Var(ty, var) if unsafe { ty.__bindgen_anon_1.type_ } == 1
+                && var.linkage == BTF_VAR_GLOBAL_ALLOCATED
+        );
+        assert_matches!(
+            btf.type_by_id(3).unwrap(),
+            BtfType::Func(ty) if type_vlen(ty) == GLOBAL as usize
+        );
+
+        // bpf_foo is defined by vmlinux, bpf_mod by a module
+        let strings = b"\0int\0bpf_foo\0";
+        #[rustfmt::skip]
+        let types = [
+            // [1] int
+            1, kind(BTF_KIND_INT, 0), 4, BTF_INT_SIGNED << 24 | 32,
+            // [2] int (int)
+            0, kind(BTF_KIND_FUNC_PROTO, 1), 1, 0, 1,
+            // [3] int bpf_foo(int)
+            5, kind(BTF_KIND_FUNC, GLOBAL), 2,
+        ];
+        let vmlinux = std::sync::Arc::new(
+            Btf::parse(&btf_bytes(&types, strings), Endianness::Little).unwrap(),
+        );
+        let module = ModuleBtf {
+            name: "mod".to_string(),
+            btf: Btf::parse_split(
+                &btf_bytes(&[13, kind(BTF_KIND_FUNC, GLOBAL), 2], b"bpf_mod\0"),
+                vmlinux.clone(),
+                Endianness::Little,
+            )
+            .unwrap(),
+        };
+
+        let modules = obj
+            .resolve_ksyms(Some(&vmlinux), &[module], &HashMap::new())
+            .unwrap();
+        assert_eq!(modules, vec!["mod".to_string()]);
+        let values = (1..=3)
+            .map(|i| match &obj.externs[&i].kind {
+                ExternKind::Kfunc { value, .. } => *value,
+                _ => panic!("unexpected extern kind"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                Some(KfuncValue {
+                    btf_id: 3,
+                    fd_index: 0
+                }),
+                Some(KfuncValue {
+                    btf_id: 4,
+                    fd_index: 1
+                }),
+                None
+            ]
+        );
+
+        assert_matches!(
+            obj.resolve_ksyms(Some(&vmlinux), &[], &HashMap::new()),
+            Err(ExternError::KsymNotFound { name }) if name == "bpf_mod"
+        );
+    }
+}
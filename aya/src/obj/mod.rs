@@ -1,4 +1,5 @@
 pub(crate) mod btf;
+mod externs;
 mod relocation;
 
 use object::{
@@ -15,13 +16,19 @@ use std::{
 };
 use thiserror::Error;
 
-use relocation::*;
+pub use externs::ExternError;
+pub(crate) use externs::*;
+pub(crate) use relocation::*;
 
 use crate::{
     bpf_map_def,
-    generated::{bpf_insn, bpf_map_type::BPF_MAP_TYPE_ARRAY, BPF_F_RDONLY_PROG},
-    obj::btf::{Btf, BtfError, BtfExt},
-    BpfError,
+    generated::{
+        bpf_func_info, bpf_insn, bpf_line_info,
+        bpf_map_type::{BPF_MAP_TYPE_ARRAY, BPF_MAP_TYPE_ARRAY_OF_MAPS, BPF_MAP_TYPE_HASH_OF_MAPS},
+        BPF_F_RDONLY_PROG,
+    },
+    obj::btf::{Btf, BtfError, BtfExt, BtfFeatures, BtfType},
+    BpfError, PinningType,
 };
 use std::slice::from_raw_parts_mut;
 
@@ -29,18 +36,22 @@ const KERNEL_VERSION_ANY: u32 = 0xFFFF_FFFE;
 /// The first five __u32 of `bpf_map_def` must be defined.
 const MINIMUM_MAP_SIZE: usize = mem::size_of::<u32>() * 5;
 
+/// A parsed eBPF object file.
 #[derive(Clone)]
 pub struct Object {
     pub(crate) endianness: Endianness,
-    pub license: CString,
-    pub kernel_version: KernelVersion,
-    pub btf: Option<Btf>,
-    pub btf_ext: Option<BtfExt>,
+    pub(crate) license: CString,
+    pub(crate) kernel_version: KernelVersion,
+    pub(crate) btf: Option<Btf>,
+    pub(crate) btf_ext: Option<BtfExt>,
     pub(crate) maps: HashMap<String, Map>,
     pub(crate) programs: HashMap<String, Program>,
     pub(crate) functions: HashMap<u64, Function>,
     pub(crate) relocations: HashMap<SectionIndex, HashMap<u64, Relocation>>,
     pub(crate) symbols_by_index: HashMap<usize, Symbol>,
+    pub(crate) section_sizes: HashMap<String, u64>,
+    // extern variables, indexed by symbol
+    pub(crate) externs: HashMap<usize, Extern>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -65,12 +76,78 @@ impl From<&str> for MapKind {
     }
 }
 
+/// A map defined in an object.
 #[derive(Debug, Clone)]
 pub struct Map {
     pub(crate) def: bpf_map_def,
     pub(crate) section_index: usize,
+    // maps defined in the BTF `.maps` section all share the same ELF section, so they're
+    // identified by their symbol instead
+    pub(crate) symbol_index: Option<usize>,
     pub(crate) data: Vec<u8>,
     pub(crate) kind: MapKind,
+    pub(crate) btf_def: Option<BtfMapDef>,
+}
+
+impl Map {
+    /// Returns the type of the map, one of the `BPF_MAP_TYPE_*` constants.
+    pub fn map_type(&self) -> u32 {
+        self.def.map_type
+    }
+
+    /// Returns the size of the keys of the map.
+    pub fn key_size(&self) -> u32 {
+        self.def.key_size
+    }
+
+    /// Returns the size of the values of the map.
+    pub fn value_size(&self) -> u32 {
+        self.def.value_size
+    }
+
+    /// Returns the maximum number of entries of the map.
+    pub fn max_entries(&self) -> u32 {
+        self.def.max_entries
+    }
+
+    /// Returns the BTF type id of the keys, if the map is defined with BTF.
+    pub fn key_type_id(&self) -> Option<u32> {
+        self.btf_def.as_ref().and_then(|def| def.key_type_id)
+    }
+
+    /// Returns the BTF type id of the values, if the map is defined with BTF.
+    pub fn value_type_id(&self) -> Option<u32> {
+        self.btf_def.as_ref().and_then(|def| def.value_type_id)
+    }
+
+    /// Returns true if the map holds global data, like the `.data`, `.rodata` and `.bss` maps.
+    pub fn is_global_data(&self) -> bool {
+        self.kind != MapKind::Other
+    }
+}
+
+/// A global variable defined in a data section of an object.
+#[derive(Debug, Clone)]
+pub struct Global {
+    /// The name of the variable.
+    pub name: String,
+    /// The name of the section the variable is defined in, for example `.rodata`.
+    pub section: String,
+    /// The offset of the variable within the section.
+    pub offset: u32,
+    /// The size of the variable.
+    pub size: u32,
+    /// The BTF type id of the variable.
+    pub type_id: u32,
+}
+
+/// The parts of a BTF map definition that don't fit in `bpf_map_def`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BtfMapDef {
+    pub(crate) key_type_id: Option<u32>,
+    pub(crate) value_type_id: Option<u32>,
+    // the template used to create the inner map of a map-in-map
+    pub(crate) inner: Option<Box<Map>>,
 }
 
 #[derive(Debug, Clone)]
@@ -88,8 +165,12 @@ pub(crate) struct Function {
     pub(crate) section_index: SectionIndex,
     pub(crate) section_offset: usize,
     pub(crate) instructions: Vec<bpf_insn>,
+    // insn_off is relative to the first instruction of the function
+    pub(crate) func_info: Vec<bpf_func_info>,
+    pub(crate) line_info: Vec<bpf_line_info>,
 }
 
+/// The section a program is defined in, which determines its type.
 #[derive(Debug, Clone)]
 pub enum ProgramSection {
     KRetProbe { name: String },
@@ -204,7 +285,8 @@ impl FromStr for ProgramSection {
 }
 
 impl Object {
-    pub(crate) fn parse(data: &[u8]) -> Result<Object, BpfError> {
+    /// Parses the eBPF object code contained in `data`.
+    pub fn parse(data: &[u8]) -> Result<Object, BpfError> {
         let obj = object::read::File::parse(data).map_err(ParseError::ElfError)?;
         let endianness = obj.endianness();
 
@@ -232,6 +314,7 @@ impl Object {
                     size: symbol.size(),
                     is_definition: symbol.is_definition(),
                     is_text: symbol.kind() == SymbolKind::Text,
+                    is_weak: symbol.is_weak(),
                 };
                 bpf_obj
                     .symbols_by_index
@@ -239,10 +322,28 @@ impl Object {
             }
         }
 
-        for s in obj.sections() {
+        // BTF maps and the func and line info of programs are decoded from the .BTF and .BTF.ext
+        // sections, so make sure they're parsed before any other section
+        if let Some(s) = obj.section_by_name(".BTF") {
             bpf_obj.parse_section(Section::try_from(&s)?)?;
+            if let Some(s) = obj.section_by_name(".BTF.ext") {
+                bpf_obj.parse_section(Section::try_from(&s)?)?;
+            }
         }
 
+        for s in obj.sections() {
+            let section = Section::try_from(&s)?;
+            bpf_obj
+                .section_sizes
+                .insert(section.name.to_owned(), section.size);
+            if let BpfSectionKind::Btf | BpfSectionKind::BtfExt = section.kind {
+                continue;
+            }
+            bpf_obj.parse_section(section)?;
+        }
+
+        bpf_obj.collect_externs()?;
+
         Ok(bpf_obj)
     }
 
@@ -258,10 +359,15 @@ impl Object {
             functions: HashMap::new(),
             relocations: HashMap::new(),
             symbols_by_index: HashMap::new(),
+            section_sizes: HashMap::new(),
+            externs: HashMap::new(),
         }
     }
 
-    pub fn patch_map_data(&mut self, globals: HashMap<&str, &[u8]>) -> Result<(), ParseError> {
+    pub(crate) fn patch_map_data(
+        &mut self,
+        globals: HashMap<&str, &[u8]>,
+    ) -> Result<(), ParseError> {
         let symbols: HashMap<String, &Symbol> = self
             .symbols_by_index
             .iter()
@@ -306,20 +412,168 @@ impl Object {
         Ok(())
     }
 
+    /// Returns the BTF of the object, if it has any.
+    pub fn btf(&self) -> Option<&Btf> {
+        self.btf.as_ref()
+    }
+
+    /// Returns the programs defined in the object, along with the section they're defined in.
+    pub fn programs(&self) -> impl Iterator<Item = (&str, &ProgramSection)> {
+        self.programs
+            .iter()
+            .map(|(name, program)| (name.as_str(), &program.section))
+    }
+
+    /// Returns the maps defined in the object, including the maps created for global data.
+    pub fn maps(&self) -> impl Iterator<Item = (&str, &Map)> {
+        self.maps.iter().map(|(name, map)| (name.as_str(), map))
+    }
+
+    /// Returns the global variables described by the `DATASEC` types of the object BTF.
+    ///
+    /// Objects without BTF have no globals.
+    pub fn globals(&self) -> Result<Vec<Global>, BtfError> {
+        let btf = match &self.btf {
+            Some(btf) => btf,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut globals = Vec::new();
+        for ty in btf.types() {
+            let (section, secinfos) = match ty {
+                BtfType::DataSec(ty, secinfos) => (btf.string_at(ty.name_off)?, secinfos),
+                _ => continue,
+            };
+            let section_index = match self.maps.get(&*section) {
+                Some(map) => SectionIndex(map.section_index),
+                None => continue,
+            };
+            for secinfo in secinfos {
+                let (name, type_id) = match btf.type_by_id(secinfo.type_)? {
+                    // Safety: union
+                    BtfType::Var(ty, _) => (btf.string_at(ty.name_off)?, unsafe {
+                        ty.__bindgen_anon_1.type_
+                    }),
+                    _ => continue,
+                };
+                // the offsets of global variables are only known from their symbol, llvm leaves
+                // them to be filled in by the loader. Static variables of different sections can
+                // have the same name, so the symbol must be defined in the section of the map.
+                let offset = self
+                    .symbols_by_index
+                    .values()
+                    .find(|sym| {
+                        sym.name.as_deref() == Some(&*name)
+                            && sym.is_definition
+                            && sym.section_index == Some(section_index)
+                    })
+                    .map(|sym| sym.address as u32)
+                    .unwrap_or(secinfo.offset);
+                globals.push(Global {
+                    name: name.into_owned(),
+                    section: section.to_string(),
+                    offset,
+                    size: btf.type_size(type_id)? as u32,
+                    type_id,
+                });
+            }
+        }
+
+        Ok(globals)
+    }
+
     fn parse_btf(&mut self, section: &Section) -> Result<(), BtfError> {
         self.btf = Some(Btf::parse(section.data, self.endianness)?);
 
         Ok(())
     }
 
-    fn parse_btf_ext(&mut self, section: &Section) -> Result<(), BtfError> {
-        self.btf_ext = Some(BtfExt::parse(section.data, self.endianness)?);
+    fn parse_btf_ext(&mut self, section: &Section) -> Result<(), BpfError> {
+        let btf = self.btf.as_ref().ok_or(ParseError::NoBTF)?;
+        self.btf_ext = Some(BtfExt::parse(section.data, self.endianness, btf)?);
+        Ok(())
+    }
+
+    pub(crate) fn has_btf_relocations(&self) -> bool {
+        self.btf_ext
+            .as_ref()
+            .map(|ext| ext.relocations().any(|(_, relos)| !relos.is_empty()))
+            .unwrap_or(false)
+    }
+
+    /// Fixes up and sanitizes the object's BTF so that it can be loaded into the kernel.
+    pub(crate) fn fixup_and_sanitize_btf(
+        &mut self,
+        features: &BtfFeatures,
+    ) -> Result<(), BtfError> {
+        // the offsets of extern variables are assigned by the loader, not by their symbols
+        let symbol_offsets = self
+            .symbols_by_index
+            .values()
+            .filter(|s| s.section_index.is_some())
+            .filter_map(|s| s.name.as_ref().map(|name| (name.clone(), s.address)))
+            .collect::<HashMap<_, _>>();
+        if let Some(btf) = &mut self.btf {
+            btf.fixup_and_sanitize(&self.section_sizes, &symbol_offsets, features)?;
+        }
+        Ok(())
+    }
+
+    fn function_btf_info(
+        &self,
+        section: &Section,
+        offset: usize,
+        len: usize,
+    ) -> (Vec<bpf_func_info>, Vec<bpf_line_info>) {
+        match &self.btf_ext {
+            Some(ext) => (
+                ext.func_info(section.name, offset, len),
+                ext.line_info(section.name, offset, len),
+            ),
+            None => (Vec::new(), Vec::new()),
+        }
+    }
+
+    fn parse_btf_maps(&mut self, section: &Section) -> Result<(), BpfError> {
+        let btf = self.btf.as_ref().ok_or(ParseError::NoBTF)?;
+        let symbols: HashMap<&str, &Symbol> = self
+            .symbols_by_index
+            .values()
+            .filter(|s| s.section_index == Some(section.index))
+            .filter_map(|s| s.name.as_deref().map(|name| (name, s)))
+            .collect();
+
+        let mut maps = Vec::new();
+        for ty in btf.types() {
+            let vars = match ty {
+                BtfType::DataSec(_, vars) => vars,
+                _ => continue,
+            };
+            if btf.type_name(ty)?.as_deref() != Some(section.name) {
+                continue;
+            }
+
+            // each variable in the DATASEC is a map definition
+            for var in vars {
+                let (name, mut map) = parse_btf_map(btf, var.type_)?;
+                let symbol = symbols
+                    .get(name.as_str())
+                    .ok_or_else(|| ParseError::SymbolNotFound { name: name.clone() })?;
+                map.section_index = section.index.0;
+                map.symbol_index = Some(symbol.index);
+                maps.push((name, map));
+            }
+        }
+
+        self.maps.extend(maps);
+
         Ok(())
     }
 
     fn parse_program(&self, section: &Section) -> Result<Program, ParseError> {
         let prog_sec = ProgramSection::from_str(section.name)?;
         let name = prog_sec.name().to_owned();
+        let (func_info, line_info) = self.function_btf_info(section, 0, section.data.len());
         Ok(Program {
             license: self.license.clone(),
             kernel_version: self.kernel_version,
@@ -330,6 +584,8 @@ impl Object {
                 section_index: section.index,
                 section_offset: 0,
                 instructions: copy_instructions(section.data)?,
+                func_info,
+                line_info,
             },
         })
     }
@@ -365,6 +621,8 @@ impl Object {
                 });
             }
 
+            let (func_info, line_info) =
+                self.function_btf_info(&section, offset, sym.size as usize);
             self.functions.insert(
                 sym.address,
                 Function {
@@ -375,6 +633,8 @@ impl Object {
                     instructions: copy_instructions(
                         &section.data[offset..offset + sym.size as usize],
                     )?,
+                    func_info,
+                    line_info,
                 },
             );
 
@@ -421,6 +681,7 @@ impl Object {
                 self.maps
                     .insert(name.to_string(), parse_map(&section, name)?);
             }
+            BpfSectionKind::BtfMaps => self.parse_btf_maps(&section)?,
             BpfSectionKind::Program => {
                 let program = self.parse_program(&section)?;
                 self.programs
@@ -498,6 +759,12 @@ pub enum ParseError {
 
     #[error("map for section with index {index} not found")]
     MapNotFound { index: usize },
+
+    #[error("the object contains BTF maps but no BTF info")]
+    NoBTF,
+
+    #[error("invalid pinning type `{pinning}` for map `{name}`")]
+    InvalidPinningType { name: String, pinning: u32 },
 }
 
 #[derive(Debug)]
@@ -674,12 +941,146 @@ fn parse_map(section: &Section, name: &str) -> Result<Map, ParseError> {
     };
     Ok(Map {
         section_index: section.index.0,
+        symbol_index: None,
         def,
         data,
         kind,
+        btf_def: None,
     })
 }
 
+// Parses a map declared in the `.maps` section, eg:
+//
+// struct {
+//     __uint(type, BPF_MAP_TYPE_HASH);
+//     __type(key, u32);
+//     __type(value, u64);
+//     __uint(max_entries, 1024);
+// } counters SEC(".maps");
+fn parse_btf_map(btf: &Btf, var_type_id: u32) -> Result<(String, Map), BpfError> {
+    let var = btf.type_by_id(var_type_id)?;
+    let def_type_id = match var {
+        // Safety: union
+        BtfType::Var(ty, _) => unsafe { ty.__bindgen_anon_1.type_ },
+        _ => {
+            return Err(BtfError::UnexpectedBtfType {
+                type_id: var_type_id,
+            }
+            .into())
+        }
+    };
+    let name = btf
+        .type_name(var)?
+        .map(String::from)
+        .ok_or(BtfError::UnexpectedBtfType {
+            type_id: var_type_id,
+        })?;
+    let map = parse_btf_map_def(btf, &name, def_type_id)?;
+
+    Ok((name, map))
+}
+
+fn parse_btf_map_def(btf: &Btf, name: &str, type_id: u32) -> Result<Map, BpfError> {
+    let type_id = btf.resolve_type(type_id)?;
+    let members = match btf.type_by_id(type_id)? {
+        BtfType::Struct(_, members) => members,
+        _ => return Err(BtfError::UnexpectedBtfType { type_id }.into()),
+    };
+
+    let mut def = bpf_map_def::default();
+    let mut btf_def = BtfMapDef::default();
+    let mut inner_type_id = None;
+    for member in members {
+        match &*btf.string_at(member.name_off)? {
+            "type" => def.map_type = btf_map_field_value(btf, member.type_)?,
+            "key" => {
+                let key_type_id = btf_map_field_type(btf, member.type_)?;
+                def.key_size = btf.type_size(key_type_id)? as u32;
+                btf_def.key_type_id = Some(key_type_id);
+            }
+            "key_size" => def.key_size = btf_map_field_value(btf, member.type_)?,
+            "value" => {
+                let value_type_id = btf_map_field_type(btf, member.type_)?;
+                def.value_size = btf.type_size(value_type_id)? as u32;
+                btf_def.value_type_id = Some(value_type_id);
+            }
+            "value_size" => def.value_size = btf_map_field_value(btf, member.type_)?,
+            "max_entries" => def.max_entries = btf_map_field_value(btf, member.type_)?,
+            "map_flags" => def.map_flags = btf_map_field_value(btf, member.type_)?,
+            "pinning" => {
+                def.pinning = match btf_map_field_value(btf, member.type_)? {
+                    0 => PinningType::None,
+                    1 => PinningType::ByName,
+                    pinning => {
+                        return Err(ParseError::InvalidPinningType {
+                            name: name.to_owned(),
+                            pinning,
+                        }
+                        .into())
+                    }
+                }
+            }
+            // `__array(values, ...)` is an array of pointers to the inner map definition
+            // (for map-in-maps) or to a function prototype (for program arrays)
+            "values" => match btf.type_by_id(member.type_)? {
+                BtfType::Array(_, array) => {
+                    inner_type_id = Some(btf_map_field_type(btf, array.type_)?)
+                }
+                _ => {
+                    return Err(BtfError::UnexpectedBtfType {
+                        type_id: member.type_,
+                    }
+                    .into())
+                }
+            },
+            // ignore fields we don't know about, eg numa_node
+            _ => {}
+        }
+    }
+
+    if let Some(inner_type_id) = inner_type_id {
+        if def.map_type == BPF_MAP_TYPE_ARRAY_OF_MAPS as u32
+            || def.map_type == BPF_MAP_TYPE_HASH_OF_MAPS as u32
+        {
+            let inner = parse_btf_map_def(btf, &format!("{}.inner", name), inner_type_id)?;
+            btf_def.inner = Some(Box::new(inner));
+        }
+        // the values of map-in-maps and program arrays are fds
+        if def.value_size == 0 {
+            def.value_size = mem::size_of::<u32>() as u32;
+        }
+    }
+
+    Ok(Map {
+        def,
+        section_index: 0,
+        symbol_index: None,
+        data: Vec::new(),
+        kind: MapKind::Other,
+        btf_def: Some(btf_def),
+    })
+}
+
+// `__type(name, T)` is encoded as `T *name`
+fn btf_map_field_type(btf: &Btf, type_id: u32) -> Result<u32, BtfError> {
+    match btf.type_by_id(type_id)? {
+        // Safety: union
+        BtfType::Ptr(ty) => Ok(unsafe { ty.__bindgen_anon_1.type_ }),
+        _ => Err(BtfError::UnexpectedBtfType { type_id }),
+    }
+}
+
+// `__uint(name, N)` is encoded as `int (*name)[N]`
+fn btf_map_field_value(btf: &Btf, type_id: u32) -> Result<u32, BtfError> {
+    let array_type_id = btf_map_field_type(btf, type_id)?;
+    match btf.type_by_id(array_type_id)? {
+        BtfType::Array(_, array) => Ok(array.nelems),
+        _ => Err(BtfError::UnexpectedBtfType {
+            type_id: array_type_id,
+        }),
+    }
+}
+
 fn parse_map_def(name: &str, data: &[u8]) -> Result<bpf_map_def, ParseError> {
     if data.len() < MINIMUM_MAP_SIZE {
         return Err(ParseError::InvalidMapDefinition {
@@ -716,13 +1117,61 @@ fn copy_instructions(data: &[u8]) -> Result<Vec<bpf_insn>, ParseError> {
     Ok(instructions)
 }
 
+/// Returns an empty GPL licensed object.
+#[cfg(test)]
+pub(crate) fn fake_obj() -> Object {
+    Object::new(
+        Endianness::Little,
+        CString::new("GPL").unwrap(),
+        KernelVersion::Any,
+    )
+}
+
+/// Returns a function named after its address.
+#[cfg(test)]
+pub(crate) fn fake_function(
+    address: u64,
+    section_index: usize,
+    instructions: Vec<bpf_insn>,
+) -> Function {
+    Function {
+        address,
+        name: format!("fun_{}", address),
+        section_index: SectionIndex(section_index),
+        section_offset: 0,
+        instructions,
+        func_info: Vec::new(),
+        line_info: Vec::new(),
+    }
+}
+
+/// Returns a GPL licensed XDP program running `function`.
+#[cfg(test)]
+pub(crate) fn fake_program(name: &str, function: Function) -> Program {
+    Program {
+        license: CString::new("GPL").unwrap(),
+        kernel_version: KernelVersion::Any,
+        section: ProgramSection::Xdp {
+            name: name.to_string(),
+        },
+        function,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use matches::assert_matches;
     use object::Endianness;
 
     use super::*;
-    use crate::PinningType;
+    use crate::{
+        generated::{
+            BTF_KIND_ARRAY, BTF_KIND_DATASEC, BTF_KIND_INT, BTF_KIND_PTR, BTF_KIND_STRUCT,
+            BTF_KIND_VAR,
+        },
+        obj::btf::btf_bytes,
+        PinningType,
+    };
 
     fn fake_section<'a>(kind: BpfSectionKind, name: &'a str, data: &'a [u8]) -> Section<'a> {
         Section {
@@ -936,19 +1385,12 @@ mod tests {
                     pinning: PinningType::None,
                 },
                 data,
-                kind
+                kind,
+                ..
             }) if data == map_data && value_size == map_data.len() as u32 && kind == MapKind::Bss
         ))
     }
 
-    fn fake_obj() -> Object {
-        Object::new(
-            Endianness::Little,
-            CString::new("GPL").unwrap(),
-            KernelVersion::Any,
-        )
-    }
-
     #[test]
     fn test_parse_program_error() {
         let obj = fake_obj();
@@ -978,7 +1420,8 @@ mod tests {
                     address: 0,
                     section_index: SectionIndex(0),
                     section_offset: 0,
-                    instructions
+                    instructions,
+                    ..
                 }
             }) if license.to_string_lossy() == "GPL" && name == "foo" && instructions.len() == 1
         );
@@ -1344,8 +1787,10 @@ mod tests {
                     pinning: PinningType::None,
                 },
                 section_index: 1,
+                symbol_index: None,
                 data: vec![0, 0, 0],
                 kind: MapKind::Rodata,
+                btf_def: None,
             },
         );
         obj.symbols_by_index.insert(
@@ -1358,6 +1803,7 @@ mod tests {
                 size: 3,
                 is_definition: true,
                 is_text: false,
+                is_weak: false,
             },
         );
 
@@ -1368,4 +1814,164 @@ mod tests {
         let map = obj.maps.get(".rodata").unwrap();
         assert_eq!(test_data, map.data);
     }
+
+    #[test]
+    fn test_globals() {
+        let strings = b"\0int\0my_config\0.rodata\0";
+        let kind = |kind: u32, vlen: u32| kind << 24 | vlen;
+        #[rustfmt::skip]
+        let types = [
+            // [1] int, 4 bytes
+            1, kind(BTF_KIND_INT, 0), 4, 32,
+            // [2] my_config
+            5, kind(BTF_KIND_VAR, 0), 1, 1,
+            // [3] .rodata, the offset is left to the loader
+            15, kind(BTF_KIND_DATASEC, 1), 0,
+            2, 0, 4,
+        ];
+
+        let mut obj = fake_obj();
+        assert!(obj.globals().unwrap().is_empty());
+
+        obj.btf = Some(Btf::parse(&btf_bytes(&types, strings), Endianness::default()).unwrap());
+        obj.maps.insert(
+            ".rodata".to_string(),
+            Map {
+                def: bpf_map_def {
+                    map_type: BPF_MAP_TYPE_ARRAY as u32,
+                    key_size: mem::size_of::<u32>() as u32,
+                    value_size: 8,
+                    max_entries: 1,
+                    ..Default::default()
+                },
+                section_index: 1,
+                symbol_index: None,
+                data: vec![0; 8],
+                kind: MapKind::Rodata,
+                btf_def: None,
+            },
+        );
+        // a variable with the same name defined in another section
+        obj.symbols_by_index.insert(
+            0,
+            Symbol {
+                index: 0,
+                section_index: Some(SectionIndex(2)),
+                name: Some("my_config".to_string()),
+                address: 12,
+                size: 4,
+                is_definition: true,
+                is_text: false,
+                is_weak: false,
+            },
+        );
+        obj.symbols_by_index.insert(
+            1,
+            Symbol {
+                index: 1,
+                section_index: Some(SectionIndex(1)),
+                name: Some("my_config".to_string()),
+                address: 4,
+                size: 4,
+                is_definition: true,
+                is_text: false,
+                is_weak: false,
+            },
+        );
+
+        let globals = obj.globals().unwrap();
+        assert_eq!(globals.len(), 1);
+        let global = &globals[0];
+        assert_eq!(global.name, "my_config");
+        assert_eq!(global.section, ".rodata");
+        assert_eq!((global.offset, global.size, global.type_id), (4, 4, 1));
+
+        // the symbol of the other section is never used
+        obj.symbols_by_index.remove(&1);
+        assert_eq!(obj.globals().unwrap()[0].offset, 0);
+    }
+
+    #[test]
+    fn test_parse_btf_maps() {
+        let strings = b"\0int\0type\0key\0value\0max_entries\0counters\0.maps\0";
+        let kind = |kind: u32, vlen: u32| kind << 24 | vlen;
+        #[rustfmt::skip]
+        let types = [
+            // [1] int, 4 bytes
+            1, kind(BTF_KIND_INT, 0), 4, 32,
+            // [2] int[1], BPF_MAP_TYPE_HASH
+            0, kind(BTF_KIND_ARRAY, 0), 0, 1, 1, 1,
+            // [3] int (*)[1]
+            0, kind(BTF_KIND_PTR, 0), 2,
+            // [4] int[1024]
+            0, kind(BTF_KIND_ARRAY, 0), 0, 1, 1, 1024,
+            // [5] int (*)[1024]
+            0, kind(BTF_KIND_PTR, 0), 4,
+            // [6] int *
+            0, kind(BTF_KIND_PTR, 0), 1,
+            // [7] struct { type; key; value; max_entries; }
+            0, kind(BTF_KIND_STRUCT, 4), 32,
+            5, 3, 0,
+            10, 6, 64,
+            14, 6, 128,
+            20, 5, 192,
+            // [8] counters
+            32, kind(BTF_KIND_VAR, 0), 7, 1,
+            // [9] .maps
+            41, kind(BTF_KIND_DATASEC, 1), 32,
+            8, 0, 32,
+        ];
+
+        let mut obj = fake_obj();
+        obj.btf = Some(Btf::parse(&btf_bytes(&types, strings), Endianness::default()).unwrap());
+        obj.symbols_by_index.insert(
+            1,
+            Symbol {
+                index: 1,
+                section_index: Some(SectionIndex(0)),
+                name: Some("counters".to_string()),
+                address: 0,
+                size: 32,
+                is_definition: true,
+                is_text: false,
+                is_weak: false,
+            },
+        );
+
+        assert_matches!(
+            obj.parse_section(fake_section(BpfSectionKind::BtfMaps, ".maps", &[0u8; 32])),
+            Ok(())
+        );
+        assert_matches!(
+            obj.maps.get("counters"),
+            Some(Map {
+                def: bpf_map_def {
+                    map_type: 1,
+                    key_size: 4,
+                    value_size: 4,
+                    max_entries: 1024,
+                    map_flags: 0,
+                    pinning: PinningType::None,
+                    ..
+                },
+                section_index: 0,
+                symbol_index: Some(1),
+                btf_def: Some(BtfMapDef {
+                    key_type_id: Some(1),
+                    value_type_id: Some(1),
+                    inner: None,
+                }),
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_btf_maps_no_btf() {
+        let mut obj = fake_obj();
+        assert_matches!(
+            obj.parse_section(fake_section(BpfSectionKind::BtfMaps, ".maps", &[])),
+            Err(BpfError::ParseError(ParseError::NoBTF))
+        );
+    }
 }
@@ -1,22 +1,25 @@
-use std::{collections::HashMap, mem};
+use std::{collections::HashMap, mem, sync::Arc};
 
 use object::SectionIndex;
 use thiserror::Error;
 
 use crate::{
     generated::{
-        bpf_insn, BPF_CALL, BPF_JMP, BPF_K, BPF_PSEUDO_CALL, BPF_PSEUDO_MAP_FD,
-        BPF_PSEUDO_MAP_VALUE,
+        bpf_func_info, bpf_insn, bpf_line_info, BPF_CALL, BPF_DW, BPF_JMP, BPF_K, BPF_LD,
+        BPF_PSEUDO_BTF_ID, BPF_PSEUDO_CALL, BPF_PSEUDO_FUNC, BPF_PSEUDO_KFUNC_CALL,
+        BPF_PSEUDO_MAP_FD, BPF_PSEUDO_MAP_VALUE,
     },
     maps::Map,
-    obj::{Function, Object, Program},
-    BpfError,
+    obj::{
+        btf::RelocationError as CoreRelocationError, Extern, ExternKind, Function, KfuncValue,
+        KsymValue, Object, Program, KCONFIG_SECTION,
+    },
 };
 
 const INS_SIZE: usize = mem::size_of::<bpf_insn>();
 
-#[derive(Debug, Error)]
-enum RelocationError {
+#[derive(Debug, Clone, Error)]
+pub(crate) enum RelocationError {
     #[error("unknown symbol, index `{index}`")]
     UnknownSymbol { index: usize },
 
@@ -34,13 +37,22 @@ enum RelocationError {
     #[error("the map `{name}` at section `{section_index}` has not been created")]
     MapNotCreated { section_index: usize, name: String },
 
+    #[error("extern `{name}` has not been resolved")]
+    UnresolvedExtern { name: String },
+
     #[error("invalid offset `{offset}` applying relocation #{relocation_number}")]
     InvalidRelocationOffset {
         offset: u64,
         relocation_number: usize,
     },
+
+    #[error(transparent)]
+    CoreRelocation(Arc<CoreRelocationError>),
 }
 
+/// Relocation errors by program name, along with the name of the function that failed.
+pub(crate) type RelocationErrors = HashMap<String, (String, RelocationError)>;
+
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct Relocation {
     // byte offset of the instruction to be relocated
@@ -58,65 +70,95 @@ pub(crate) struct Symbol {
     pub(crate) size: u64,
     pub(crate) is_definition: bool,
     pub(crate) is_text: bool,
+    pub(crate) is_weak: bool,
 }
 
 impl Object {
-    pub fn relocate_maps<'a>(
-        &'a mut self,
+    /// Relocates the maps referenced by the programs and links in the functions they call.
+    ///
+    /// A program that fails to relocate doesn't prevent the others from being relocated: the
+    /// errors are returned by program name, along with the name of the function that failed.
+    pub(crate) fn relocate_programs<'a>(
+        &mut self,
         maps: impl Iterator<Item = (&'a str, &'a Map)>,
-    ) -> Result<(), BpfError> {
-        let maps_by_section = maps
-            .map(|(name, map)| (map.obj.section_index, (name, map)))
-            .collect::<HashMap<_, _>>();
-
-        let functions = self
-            .programs
-            .values_mut()
-            .map(|p| &mut p.function)
-            .chain(self.functions.values_mut());
-
-        for function in functions {
-            if let Some(relocations) = self.relocations.get(&function.section_index) {
-                relocate_maps(
-                    function,
-                    relocations.values(),
-                    &maps_by_section,
-                    &self.symbols_by_index,
-                )
-                .map_err(|error| BpfError::RelocationError {
-                    function: function.name.clone(),
-                    error: Box::new(error),
-                })?;
+    ) -> RelocationErrors {
+        let mut maps_by_section = HashMap::new();
+        let mut maps_by_symbol = HashMap::new();
+        let mut kconfig_map = None;
+        for (name, map) in maps {
+            // the .kconfig map isn't defined by any section, it's referenced through the kconfig
+            // externs
+            if name == KCONFIG_SECTION {
+                kconfig_map = Some(map);
+                continue;
             }
+            match map.obj.symbol_index {
+                Some(index) => maps_by_symbol.insert(index, (name, map)),
+                None => maps_by_section.insert(map.obj.section_index, (name, map)),
+            };
         }
 
-        Ok(())
-    }
-
-    pub fn relocate_calls(&mut self) -> Result<(), BpfError> {
+        let all_functions = &self.functions;
+        let mut errors = HashMap::new();
         for (name, program) in self.programs.iter_mut() {
-            let linker =
-                FunctionLinker::new(&self.functions, &self.relocations, &self.symbols_by_index);
-            linker
-                .link(program)
-                .map_err(|error| BpfError::RelocationError {
-                    function: name.clone(),
-                    error: Box::new(error),
-                })?;
+            let linker = FunctionLinker::new(
+                all_functions,
+                &self.relocations,
+                &self.symbols_by_index,
+                &self.externs,
+            );
+            let linked_functions = match linker.link(program) {
+                Ok(linked_functions) => linked_functions,
+                Err(error) => {
+                    errors.insert(name.clone(), (name.clone(), error));
+                    continue;
+                }
+            };
+
+            // relocate the program's own instructions, then the instructions of each function
+            // that was linked in
+            let main_function = (0, program.function.instructions.len(), &program.function);
+            let functions = linked_functions.iter().map(|(address, start_ins)| {
+                let fun = &all_functions[address];
+                (*start_ins, fun.instructions.len(), fun)
+            });
+            let mut instructions = program.function.instructions.clone();
+            for (start_ins, len, fun) in std::iter::once(main_function).chain(functions) {
+                if let Some(relocations) = self.relocations.get(&fun.section_index) {
+                    if let Err(error) = relocate_maps(
+                        fun,
+                        &mut instructions[start_ins..start_ins + len],
+                        relocations.values(),
+                        &maps_by_section,
+                        &maps_by_symbol,
+                        &self.symbols_by_index,
+                        &self.externs,
+                        kconfig_map,
+                    ) {
+                        errors.insert(name.clone(), (fun.name.clone(), error));
+                        break;
+                    }
+                }
+            }
+            program.function.instructions = instructions;
         }
 
-        Ok(())
+        errors
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn relocate_maps<'a, I: Iterator<Item = &'a Relocation>>(
-    fun: &mut Function,
+    fun: &Function,
+    instructions: &mut [bpf_insn],
     relocations: I,
     maps_by_section: &HashMap<usize, (&str, &Map)>,
+    maps_by_symbol: &HashMap<usize, (&str, &Map)>,
     symbol_table: &HashMap<usize, Symbol>,
+    externs: &HashMap<usize, Extern>,
+    kconfig_map: Option<&Map>,
 ) -> Result<(), RelocationError> {
     let section_offset = fun.section_offset;
-    let instructions = &mut fun.instructions;
     let function_size = instructions.len() * INS_SIZE;
 
     for (rel_n, rel) in relocations.enumerate() {
@@ -136,8 +178,10 @@ fn relocate_maps<'a, I: Iterator<Item = &'a Relocation>>(
         }
         let ins_index = ins_offset / INS_SIZE;
 
-        // calls are relocated in a separate step
-        if is_call(&instructions[ins_index]) {
+        // calls and callback references are relocated in a separate step, kfunc calls have
+        // already been patched
+        let ins = &instructions[ins_index];
+        if is_call(ins) || is_kfunc_call(ins) || is_func_ref(ins) {
             continue;
         }
 
@@ -150,23 +194,32 @@ fn relocate_maps<'a, I: Iterator<Item = &'a Relocation>>(
 
         let section_index = match sym.section_index {
             Some(index) => index,
-            // this is not a map relocation
-            None => continue,
+            None => {
+                if let Some(ext) = externs.get(&rel.symbol_index) {
+                    relocate_extern(&mut instructions[ins_index..], ext, kconfig_map)?;
+                }
+                // otherwise this is not a map relocation
+                continue;
+            }
         };
 
-        let (name, map) =
-            maps_by_section
-                .get(&section_index.0)
-                .ok_or(RelocationError::SectionNotFound {
-                    symbol_index: rel.symbol_index,
-                    symbol_name: sym.name.clone(),
-                    section_index: section_index.0,
-                })?;
+        // BTF maps are referenced through their own symbol, legacy and data maps through the
+        // symbol of the section they're defined in
+        let (name, map) = maps_by_symbol
+            .get(&rel.symbol_index)
+            .or_else(|| maps_by_section.get(&section_index.0))
+            .ok_or(RelocationError::SectionNotFound {
+                symbol_index: rel.symbol_index,
+                symbol_name: sym.name.clone(),
+                section_index: section_index.0,
+            })?;
 
-        let map_fd = map.fd.ok_or_else(|| RelocationError::MapNotCreated {
-            name: (*name).into(),
-            section_index: section_index.0,
-        })?;
+        let map_fd = map
+            .fd_or_err()
+            .map_err(|_| RelocationError::MapNotCreated {
+                name: (*name).into(),
+                section_index: section_index.0,
+            })?;
 
         if !map.obj.data.is_empty() {
             instructions[ins_index].set_src_reg(BPF_PSEUDO_MAP_VALUE as u8);
@@ -180,11 +233,92 @@ fn relocate_maps<'a, I: Iterator<Item = &'a Relocation>>(
     Ok(())
 }
 
+fn relocate_extern(
+    instructions: &mut [bpf_insn],
+    ext: &Extern,
+    kconfig_map: Option<&Map>,
+) -> Result<(), RelocationError> {
+    match &ext.kind {
+        ExternKind::Kconfig { offset, .. } => {
+            let map_fd = kconfig_map
+                .and_then(|map| map.fd_or_err().ok())
+                .ok_or_else(|| RelocationError::MapNotCreated {
+                    name: KCONFIG_SECTION.to_string(),
+                    section_index: 0,
+                })?;
+            instructions[0].set_src_reg(BPF_PSEUDO_MAP_VALUE as u8);
+            instructions[1].imm = instructions[0].imm + *offset as i32;
+            instructions[0].imm = map_fd;
+        }
+        ExternKind::Ksym {
+            value: Some(KsymValue::BtfId(id)),
+            ..
+        } => {
+            instructions[0].set_src_reg(BPF_PSEUDO_BTF_ID as u8);
+            instructions[0].imm = *id as i32;
+            // the fd of the BTF object the id belongs to, 0 for vmlinux
+            instructions[1].imm = 0;
+        }
+        ExternKind::Ksym {
+            value: Some(KsymValue::Address(addr)),
+            ..
+        } => {
+            instructions[0].imm = *addr as u32 as i32;
+            instructions[1].imm = (*addr >> 32) as u32 as i32;
+        }
+        // weak externs that can't be resolved are NULL
+        ExternKind::Ksym { value: None, .. } if ext.is_weak => {
+            instructions[0].imm = 0;
+            instructions[1].imm = 0;
+        }
+        ExternKind::Ksym { value: None, .. } => {
+            return Err(RelocationError::UnresolvedExtern {
+                name: ext.name.clone(),
+            })
+        }
+        // the address of a kfunc is only used to check whether it exists
+        ExternKind::Kfunc { value, .. } => {
+            instructions[0].imm = value.is_some() as i32;
+            instructions[1].imm = 0;
+        }
+    }
+
+    Ok(())
+}
+
+fn relocate_kfunc_call(ins: &mut bpf_insn, ext: &Extern) -> Result<(), RelocationError> {
+    match &ext.kind {
+        ExternKind::Kfunc {
+            value: Some(KfuncValue { btf_id, fd_index }),
+            ..
+        } => {
+            ins.set_src_reg(BPF_PSEUDO_KFUNC_CALL as u8);
+            ins.imm = *btf_id as i32;
+            ins.off = *fd_index;
+        }
+        // calls to weak kfuncs that can't be resolved must be dead code, the verifier removes
+        // them
+        ExternKind::Kfunc { value: None, .. } if ext.is_weak => {
+            ins.set_src_reg(BPF_PSEUDO_KFUNC_CALL as u8);
+            ins.imm = 0;
+            ins.off = 0;
+        }
+        _ => {
+            return Err(RelocationError::UnresolvedExtern {
+                name: ext.name.clone(),
+            })
+        }
+    }
+
+    Ok(())
+}
+
 struct FunctionLinker<'a> {
     functions: &'a HashMap<u64, Function>,
     linked_functions: HashMap<u64, usize>,
     relocations: &'a HashMap<SectionIndex, HashMap<u64, Relocation>>,
     symbol_table: &'a HashMap<usize, Symbol>,
+    externs: &'a HashMap<usize, Extern>,
 }
 
 impl<'a> FunctionLinker<'a> {
@@ -192,16 +326,20 @@ impl<'a> FunctionLinker<'a> {
         functions: &'a HashMap<u64, Function>,
         relocations: &'a HashMap<SectionIndex, HashMap<u64, Relocation>>,
         symbol_table: &'a HashMap<usize, Symbol>,
+        externs: &'a HashMap<usize, Extern>,
     ) -> FunctionLinker<'a> {
         FunctionLinker {
             functions,
             linked_functions: HashMap::new(),
             relocations,
             symbol_table,
+            externs,
         }
     }
 
-    fn link(mut self, program: &mut Program) -> Result<(), RelocationError> {
+    /// Links the functions called by `program` into it, returning the instruction index at which
+    /// each function was inserted, by function address.
+    fn link(mut self, program: &mut Program) -> Result<HashMap<u64, usize>, RelocationError> {
         let mut fun = program.function.clone();
         // relocate calls in the program's main function. As relocation happens,
         // it will trigger linking in all the callees.
@@ -211,7 +349,7 @@ impl<'a> FunctionLinker<'a> {
         // execution
         program.function = fun;
 
-        Ok(())
+        Ok(self.linked_functions)
     }
 
     fn link_function(
@@ -229,6 +367,21 @@ impl<'a> FunctionLinker<'a> {
         program.instructions.extend(&fun.instructions);
         self.linked_functions.insert(fun.address, start_ins);
 
+        // the func and line info of `fun` are relative to its first instruction, make them
+        // relative to the start of the program
+        program
+            .func_info
+            .extend(fun.func_info.iter().map(|info| bpf_func_info {
+                insn_off: info.insn_off + start_ins as u32,
+                ..*info
+            }));
+        program
+            .line_info
+            .extend(fun.line_info.iter().map(|info| bpf_line_info {
+                insn_off: info.insn_off + start_ins as u32,
+                ..*info
+            }));
+
         // relocate `fun`, recursively linking in all the callees
         self.relocate(program, fun)?;
 
@@ -255,16 +408,34 @@ impl<'a> FunctionLinker<'a> {
         // process all the instructions. We can't only loop over relocations since we need to
         // patch pc-relative calls too.
         for ins_index in start_ins..start_ins + n_instructions {
-            if !is_call(&program.instructions[ins_index]) {
+            // callbacks passed to helpers like bpf_loop are referenced by ld_imm64 instructions,
+            // they're linked and patched like calls
+            let ins = &program.instructions[ins_index];
+            let is_func_ref = is_func_ref(ins);
+            if !is_call(ins) && !is_func_ref {
                 continue;
             }
 
-            let callee_address = if let Some(rel) =
-                rel_info((fun.section_offset + (ins_index - start_ins) * INS_SIZE) as u64)
-            {
+            let rel = rel_info((fun.section_offset + (ins_index - start_ins) * INS_SIZE) as u64);
+            // calls to kfuncs are resolved by the kernel
+            if !is_func_ref {
+                if let Some(ext) = rel.and_then(|rel| self.externs.get(&rel.symbol_index)) {
+                    relocate_kfunc_call(&mut program.instructions[ins_index], ext)?;
+                    continue;
+                }
+            }
+
+            let callee_address = if let Some(rel) = rel {
                 // We have a relocation entry for the instruction at `ins_index`, the address of
                 // the callee is the address of the relocation's target symbol.
-                rel_target_address(rel, self.symbol_table)?
+                let address = rel_target_address(rel, self.symbol_table)?;
+                if is_func_ref {
+                    // static functions are referenced through the section symbol, the offset of
+                    // the function within the section is in imm
+                    (address as i64 + program.instructions[ins_index].imm as i64) as u64
+                } else {
+                    address
+                }
             } else {
                 // The caller and the callee are in the same ELF section and this is a pc-relative
                 // call. Resolve the pc-relative imm to an absolute address.
@@ -298,7 +469,12 @@ impl<'a> FunctionLinker<'a> {
     }
 }
 
-fn is_call(ins: &bpf_insn) -> bool {
+fn is_func_ref(ins: &bpf_insn) -> bool {
+    // ld_imm64, BPF_IMM is 0
+    ins.code as u32 == BPF_LD | BPF_DW && ins.src_reg() as u32 == BPF_PSEUDO_FUNC
+}
+
+pub(crate) fn is_call(ins: &bpf_insn) -> bool {
     let klass = (ins.code & 0x07) as u32;
     let op = (ins.code & 0xF0) as u32;
     let src = (ins.code & 0x08) as u32;
@@ -310,3 +486,322 @@ fn is_call(ins: &bpf_insn) -> bool {
         && ins.dst_reg() == 0
         && ins.off == 0
 }
+
+fn is_kfunc_call(ins: &bpf_insn) -> bool {
+    ins.code as u32 == BPF_JMP | BPF_CALL && ins.src_reg() as u32 == BPF_PSEUDO_KFUNC_CALL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generated::BPF_EXIT,
+        obj::{fake_function, fake_obj, fake_program},
+    };
+
+    fn ins(code: u32, src_reg: u8, imm: i32) -> bpf_insn {
+        bpf_insn {
+            code: code as u8,
+            _bitfield_align_1: [],
+            _bitfield_1: bpf_insn::new_bitfield_1(0, src_reg),
+            off: 0,
+            imm,
+        }
+    }
+
+    #[test]
+    fn test_link_btf_info() {
+        let call = ins(BPF_JMP | BPF_CALL, BPF_PSEUDO_CALL as u8, -1);
+        let exit = ins(BPF_JMP | BPF_EXIT, 0, 0);
+
+        let mut prog_fun = fake_function(0, 1, vec![call, call, exit]);
+        prog_fun.func_info = vec![bpf_func_info {
+            insn_off: 0,
+            type_id: 1,
+        }];
+        let mut program = fake_program("prog", prog_fun);
+
+        let mut callee = fake_function(0x100, 2, vec![ins(0, 0, 0), exit]);
+        callee.func_info = vec![bpf_func_info {
+            insn_off: 0,
+            type_id: 2,
+        }];
+        callee.line_info = vec![bpf_line_info {
+            insn_off: 1,
+            file_name_off: 0,
+            line_off: 0,
+            line_col: 42,
+        }];
+        let functions = vec![(callee.address, callee)].into_iter().collect();
+
+        // both calls in the program are relocated to the same callee
+        let relocations = vec![(
+            SectionIndex(1),
+            (0..2)
+                .map(|i| {
+                    let offset = (i * INS_SIZE) as u64;
+                    (
+                        offset,
+                        Relocation {
+                            offset,
+                            symbol_index: 1,
+                        },
+                    )
+                })
+                .collect(),
+        )]
+        .into_iter()
+        .collect();
+        let symbol_table = vec![(
+            1,
+            Symbol {
+                index: 1,
+                section_index: Some(SectionIndex(2)),
+                name: Some("callee".to_string()),
+                address: 0x100,
+                size: 2 * INS_SIZE as u64,
+                is_definition: true,
+                is_text: true,
+                is_weak: false,
+            },
+        )]
+        .into_iter()
+        .collect();
+
+        FunctionLinker::new(&functions, &relocations, &symbol_table, &HashMap::new())
+            .link(&mut program)
+            .unwrap();
+
+        let fun = &program.function;
+        assert_eq!(fun.instructions.len(), 5);
+        assert_eq!(
+            fun.func_info
+                .iter()
+                .map(|info| (info.insn_off, info.type_id))
+                .collect::<Vec<_>>(),
+            vec![(0, 1), (3, 2)]
+        );
+        assert_eq!(fun.line_info.len(), 1);
+        assert_eq!(fun.line_info[0].insn_off, 4);
+        assert_eq!(fun.line_info[0].line_col, 42);
+    }
+
+    #[test]
+    fn test_relocate_programs_errors() {
+        let call = ins(BPF_JMP | BPF_CALL, BPF_PSEUDO_CALL as u8, 5);
+        let exit = ins(BPF_JMP | BPF_EXIT, 0, 0);
+
+        let mut obj = fake_obj();
+        for (name, instructions) in [("good", vec![exit]), ("bad", vec![call, exit])] {
+            let mut function = fake_function(0, 1, instructions);
+            function.name = name.to_string();
+            obj.programs
+                .insert(name.to_string(), fake_program(name, function));
+        }
+
+        // the call in `bad` targets a function that doesn't exist, which doesn't prevent `good`
+        // from being relocated
+        let errors = obj.relocate_programs(std::iter::empty());
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors["bad"],
+            (function, RelocationError::UnknownFunction { .. }) if function == "bad"
+        ));
+        assert_eq!(obj.programs["good"].function.instructions.len(), 1);
+    }
+
+    #[test]
+    fn test_relocate_kfunc_calls() {
+        let call = ins(BPF_JMP | BPF_CALL, BPF_PSEUDO_CALL as u8, -1);
+        let exit = ins(BPF_JMP | BPF_EXIT, 0, 0);
+
+        let mut obj = fake_obj();
+        obj.programs.insert(
+            "prog".to_string(),
+            fake_program("prog", fake_function(0, 1, vec![call, call, exit])),
+        );
+        for (i, name) in ["bpf_foo", "bpf_bar"].iter().enumerate() {
+            obj.symbols_by_index.insert(
+                i + 1,
+                Symbol {
+                    index: i + 1,
+                    section_index: None,
+                    name: Some(name.to_string()),
+                    address: 0,
+                    size: 0,
+                    is_definition: false,
+                    is_text: false,
+                    is_weak: false,
+                },
+            );
+        }
+        obj.relocations.insert(
+            SectionIndex(1),
+            (0..2)
+                .map(|i| {
+                    let offset = (i * INS_SIZE) as u64;
+                    (
+                        offset,
+                        Relocation {
+                            offset,
+                            symbol_index: i + 1,
+                        },
+                    )
+                })
+                .collect(),
+        );
+
+        // without BTF, calls to undefined functions are calls to kfuncs
+        obj.collect_externs().unwrap();
+        assert_eq!(obj.externs.len(), 2);
+        for ext in obj.externs.values_mut() {
+            assert!(matches!(
+                ext.kind,
+                ExternKind::Kfunc {
+                    type_id: None,
+                    value: None
+                }
+            ));
+            if ext.name == "bpf_foo" {
+                ext.kind = ExternKind::Kfunc {
+                    type_id: None,
+                    value: Some(KfuncValue {
+                        btf_id: 42,
+                        fd_index: 1,
+                    }),
+                };
+            }
+        }
+
+        // bpf_bar is not weak and can't be resolved
+        let errors = obj.relocate_programs(std::iter::empty());
+        assert!(matches!(
+            &errors["prog"],
+            (_, RelocationError::UnresolvedExtern { name }) if name == "bpf_bar"
+        ));
+
+        obj.symbols_by_index.get_mut(&2).unwrap().is_weak = true;
+        obj.externs.get_mut(&2).unwrap().is_weak = true;
+        let errors = obj.relocate_programs(std::iter::empty());
+        assert!(errors.is_empty());
+        let instructions = &obj.programs["prog"].function.instructions;
+        assert_eq!(
+            instructions[..2]
+                .iter()
+                .map(|ins| (ins.src_reg() as u32, ins.imm, ins.off))
+                .collect::<Vec<_>>(),
+            vec![
+                (BPF_PSEUDO_KFUNC_CALL, 42, 1),
+                (BPF_PSEUDO_KFUNC_CALL, 0, 0)
+            ]
+        );
+    }
+
+    fn ld_func_ref(imm: i32) -> [bpf_insn; 2] {
+        [
+            ins(BPF_LD | BPF_DW, BPF_PSEUDO_FUNC as u8, imm),
+            ins(0, 0, 0),
+        ]
+    }
+
+    fn symbol(index: usize, section_index: usize, name: &str, address: u64) -> Symbol {
+        Symbol {
+            index,
+            section_index: Some(SectionIndex(section_index)),
+            name: Some(name.to_string()),
+            address,
+            size: 0,
+            is_definition: true,
+            is_text: true,
+            is_weak: false,
+        }
+    }
+
+    #[test]
+    fn test_link_callbacks() {
+        let exit = ins(BPF_JMP | BPF_EXIT, 0, 0);
+
+        // the first callback is a static function referenced through the section symbol, the
+        // second one is in the same section as the program and referenced pc-relative
+        let mut instructions = ld_func_ref(0x100).to_vec();
+        instructions.extend_from_slice(&ld_func_ref(1));
+        instructions.push(exit);
+        let mut program = fake_program("prog", fake_function(0, 1, instructions));
+
+        let functions = vec![
+            fake_function(0x100, 2, vec![ins(0, 0, 0), exit]),
+            fake_function(4 * INS_SIZE as u64, 1, vec![exit]),
+        ]
+        .into_iter()
+        .map(|fun| (fun.address, fun))
+        .collect();
+        let relocations = vec![(
+            SectionIndex(1),
+            vec![(
+                0,
+                Relocation {
+                    offset: 0,
+                    symbol_index: 1,
+                },
+            )]
+            .into_iter()
+            .collect(),
+        )]
+        .into_iter()
+        .collect();
+        let symbol_table = vec![(1, symbol(1, 2, ".text", 0))].into_iter().collect();
+
+        let linked_functions =
+            FunctionLinker::new(&functions, &relocations, &symbol_table, &HashMap::new())
+                .link(&mut program)
+                .unwrap();
+
+        assert_eq!(linked_functions[&0x100], 5);
+        assert_eq!(linked_functions[&(4 * INS_SIZE as u64)], 7);
+        let instructions = &program.function.instructions;
+        assert_eq!(instructions.len(), 8);
+        assert_eq!(instructions[0].imm, 4);
+        assert_eq!(instructions[2].imm, 4);
+        assert_eq!(instructions[0].src_reg() as u32, BPF_PSEUDO_FUNC);
+    }
+
+    #[test]
+    fn test_relocate_callbacks() {
+        let exit = ins(BPF_JMP | BPF_EXIT, 0, 0);
+        // bpf_loop(1, callback, NULL, 0)
+        let mut instructions = ld_func_ref(0).to_vec();
+        instructions.push(ins(BPF_JMP | BPF_CALL, 0, 181));
+        instructions.push(exit);
+
+        let mut obj = fake_obj();
+        obj.programs.insert(
+            "prog".to_string(),
+            fake_program("prog", fake_function(0, 1, instructions)),
+        );
+        let callback = fake_function(0x100, 2, vec![ins(0, 0, 0), exit]);
+        obj.functions.insert(callback.address, callback);
+        obj.symbols_by_index
+            .insert(1, symbol(1, 2, "callback", 0x100));
+        obj.relocations.insert(
+            SectionIndex(1),
+            vec![(
+                0,
+                Relocation {
+                    offset: 0,
+                    symbol_index: 1,
+                },
+            )]
+            .into_iter()
+            .collect(),
+        );
+
+        // the callback is linked in and the ld_imm64 isn't mistaken for a map reference
+        let errors = obj.relocate_programs(std::iter::empty());
+        assert!(errors.is_empty());
+        let instructions = &obj.programs["prog"].function.instructions;
+        assert_eq!(instructions.len(), 6);
+        assert_eq!(instructions[0].imm, 3);
+        assert_eq!(instructions[0].src_reg() as u32, BPF_PSEUDO_FUNC);
+        assert_eq!(instructions[2].imm, 181);
+    }
+}
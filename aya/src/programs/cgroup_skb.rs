@@ -1,12 +1,17 @@
-use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::{
+    os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    path::Path,
+};
 
 use crate::{
+    features::features,
     generated::{
         bpf_attach_type::{BPF_CGROUP_INET_EGRESS, BPF_CGROUP_INET_INGRESS},
         bpf_prog_type::BPF_PROG_TYPE_CGROUP_SKB,
     },
-    programs::{load_program, LinkRef, ProgAttachLink, ProgramData, ProgramError},
-    sys::{bpf_link_create, bpf_prog_attach, kernel_version},
+    obj::ProgramSection,
+    programs::{dup_fd, load_program, LinkRef, ProgAttachLink, ProgramData, ProgramError},
+    sys::{bpf_link_create, bpf_prog_attach},
 };
 
 use super::FdLink;
@@ -56,6 +61,27 @@ pub struct CgroupSkb {
 }
 
 impl CgroupSkb {
+    /// Opens a program pinned at `path`, for example with
+    /// [`Program::pin`](crate::programs::Program::pin) by an earlier run.
+    ///
+    /// The kernel doesn't report the expected attach type of the program, so it must be given as
+    /// `expected_attach_type`. See also [`Program::from_pin`](crate::programs::Program::from_pin).
+    pub fn from_pin<P: AsRef<Path>>(
+        path: P,
+        expected_attach_type: CgroupSkbAttachType,
+    ) -> Result<CgroupSkb, ProgramError> {
+        let data = ProgramData::from_pin(path.as_ref(), BPF_PROG_TYPE_CGROUP_SKB, |name| {
+            match expected_attach_type {
+                CgroupSkbAttachType::Ingress => ProgramSection::CgroupSkbIngress { name },
+                CgroupSkbAttachType::Egress => ProgramSection::CgroupSkbEgress { name },
+            }
+        })?;
+        Ok(CgroupSkb {
+            data,
+            expected_attach_type: Some(expected_attach_type),
+        })
+    }
+
     /// Loads the program inside the kernel.
     ///
     /// See also [`Program::load`](crate::programs::Program::load).
@@ -86,8 +112,7 @@ impl CgroupSkb {
             CgroupSkbAttachType::Ingress => BPF_CGROUP_INET_INGRESS,
             CgroupSkbAttachType::Egress => BPF_CGROUP_INET_EGRESS,
         };
-        let k_ver = kernel_version().unwrap();
-        if k_ver >= (5, 7, 0) {
+        if features().bpf_link() {
             let link_fd =
                 bpf_link_create(prog_fd, cgroup_fd, attach_type, 0).map_err(|(_, io_error)| {
                     ProgramError::SyscallError {
@@ -95,8 +120,11 @@ impl CgroupSkb {
                         io_error,
                     }
                 })? as RawFd;
-            Ok(self.data.link(FdLink { fd: Some(link_fd) }))
+            // Safety: bpf_link_create returned a new fd, the link is its only owner
+            let link_fd = unsafe { OwnedFd::from_raw_fd(link_fd) };
+            Ok(self.data.link(FdLink::new(link_fd)))
         } else {
+            let (link_prog_fd, link_target_fd) = (dup_fd(prog_fd)?, dup_fd(cgroup_fd)?);
             bpf_prog_attach(prog_fd, cgroup_fd, attach_type).map_err(|(_, io_error)| {
                 ProgramError::SyscallError {
                     call: "bpf_prog_attach".to_owned(),
@@ -104,9 +132,11 @@ impl CgroupSkb {
                 }
             })?;
 
-            Ok(self
-                .data
-                .link(ProgAttachLink::new(prog_fd, cgroup_fd, attach_type)))
+            Ok(self.data.link(ProgAttachLink::new(
+                link_prog_fd,
+                link_target_fd,
+                attach_type,
+            )))
         }
     }
 }
@@ -119,3 +149,64 @@ pub enum CgroupSkbAttachType {
     /// Attach to egress.
     Egress,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io, os::unix::io::IntoRawFd};
+
+    use libc::{EBADF, EINVAL};
+
+    use super::*;
+    use crate::{
+        generated::bpf_cmd,
+        programs::tests::program_data,
+        sys::{fake_fd, override_syscall, SysResult, Syscall},
+    };
+
+    // the bpf_link probe fails with `link_error`, and so does attaching with bpf_link
+    fn cgroup_syscall(call: Syscall, link_error: i32) -> SysResult {
+        match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_LOAD,
+                ..
+            } => Ok(fake_fd().into_raw_fd() as i64),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_LINK_CREATE,
+                ..
+            } => Err((-1, io::Error::from_raw_os_error(link_error))),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_ATTACH | bpf_cmd::BPF_PROG_DETACH,
+                ..
+            } => Ok(0),
+            _ => Err((-1, io::Error::from_raw_os_error(libc::EFAULT))),
+        }
+    }
+
+    fn attach() -> Result<LinkRef, ProgramError> {
+        let mut data = program_data();
+        data.fd = Some(fake_fd());
+        let mut prog = CgroupSkb {
+            data,
+            expected_attach_type: None,
+        };
+        let cgroup = File::open("/dev/null").unwrap();
+        prog.attach(cgroup, CgroupSkbAttachType::Ingress)
+    }
+
+    #[test]
+    fn test_attach_bpf_link() {
+        override_syscall(|call| cgroup_syscall(call, EBADF));
+        assert!(matches!(
+            attach(),
+            Err(ProgramError::SyscallError { call, .. }) if call == "bpf_link_create"
+        ));
+    }
+
+    #[test]
+    fn test_attach_prog_attach() {
+        override_syscall(|call| cgroup_syscall(call, EINVAL));
+        let link = attach().unwrap();
+        assert!(!features().bpf_link());
+        assert!(link.inner.lock().fd_link().is_none());
+    }
+}
@@ -1,8 +1,14 @@
 //! fexit programs.
+use std::{os::unix::io::RawFd, sync::Arc};
+
 use crate::{
     generated::{bpf_attach_type::BPF_TRACE_FEXIT, bpf_prog_type::BPF_PROG_TYPE_TRACING},
-    obj::btf::{Btf, BtfKind},
-    programs::{load_program, utils::attach_raw_tracepoint, LinkRef, ProgramData, ProgramError},
+    obj::btf::Btf,
+    programs::{
+        load_program,
+        utils::{attach_raw_tracepoint, find_kernel_func},
+        LinkRef, ProgramData, ProgramError,
+    },
 };
 
 /// A program that can be attached to the exit point of (almost) anny kernel
@@ -31,9 +37,9 @@ use crate::{
 /// # }
 /// # let mut bpf = Bpf::load_file("ebpf_programs.o")?;
 /// use aya::{Bpf, programs::FExit, BtfError, Btf};
-/// use std::convert::TryInto;
+/// use std::{convert::TryInto, sync::Arc};
 ///
-/// let btf = Btf::from_sys_fs()?;
+/// let btf = Arc::new(Btf::from_sys_fs()?);
 /// let program: &mut FExit = bpf.program_mut("filename_lookup").unwrap().try_into()?;
 /// program.load("filename_lookup", &btf)?;
 /// program.attach()?;
@@ -53,11 +59,19 @@ impl FExit {
     ///
     /// Loads the program so it's executed when the kernel function `fn_name`
     /// is exited. The `btf` argument must contain the BTF info for the running
-    /// kernel.
-    pub fn load(&mut self, fn_name: &str, btf: &Btf) -> Result<(), ProgramError> {
+    /// kernel. Functions defined by kernel modules are looked up in the module
+    /// BTF found in `/sys/kernel/btf`.
+    pub fn load(&mut self, fn_name: &str, btf: &Arc<Btf>) -> Result<(), ProgramError> {
         self.data.expected_attach_type = Some(BPF_TRACE_FEXIT);
-        self.data.attach_btf_id = Some(btf.id_by_type_name_kind(fn_name, BtfKind::Func)?);
-        load_program(BPF_PROG_TYPE_TRACING, &mut self.data)
+        let (attach_btf_id, attach_btf_obj_fd) = find_kernel_func(btf, fn_name)?;
+        self.data.attach_btf_id = Some(attach_btf_id);
+        self.data.attach_btf_obj_fd = attach_btf_obj_fd.map(|fd| fd as u32);
+        let ret = load_program(BPF_PROG_TYPE_TRACING, &mut self.data);
+        // once loaded, the program holds a reference to the module BTF
+        if let Some(fd) = self.data.attach_btf_obj_fd.take() {
+            unsafe { libc::close(fd as RawFd) };
+        }
+        ret
     }
 
     /// Attaches the program
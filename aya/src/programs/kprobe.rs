@@ -1,9 +1,10 @@
 //! Kernel space probes.
-use std::io;
+use std::{io, path::Path};
 use thiserror::Error;
 
 use crate::{
     generated::bpf_prog_type::BPF_PROG_TYPE_KPROBE,
+    obj::ProgramSection,
     programs::{
         load_program,
         probe::{attach, ProbeKind},
@@ -43,6 +44,22 @@ pub struct KProbe {
 }
 
 impl KProbe {
+    /// Opens a program pinned at `path`, for example with
+    /// [`Program::pin`](crate::programs::Program::pin) by an earlier run.
+    ///
+    /// The kernel doesn't record whether the program is a kprobe or a kretprobe, so `kind` must be
+    /// [`ProbeKind::KProbe`] or [`ProbeKind::KRetProbe`]. See also
+    /// [`Program::from_pin`](crate::programs::Program::from_pin).
+    pub fn from_pin<P: AsRef<Path>>(path: P, kind: ProbeKind) -> Result<KProbe, ProgramError> {
+        let section = match kind {
+            ProbeKind::KProbe => |name| ProgramSection::KProbe { name },
+            ProbeKind::KRetProbe => |name| ProgramSection::KRetProbe { name },
+            _ => return Err(ProgramError::UnexpectedProgramType),
+        };
+        let data = ProgramData::from_pin(path.as_ref(), BPF_PROG_TYPE_KPROBE, section)?;
+        Ok(KProbe { data, kind })
+    }
+
     /// Loads the program inside the kernel.
     ///
     /// See also [`Program::load`](crate::programs::Program::load).
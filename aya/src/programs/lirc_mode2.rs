@@ -1,13 +1,13 @@
-use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 
 use crate::{
     generated::{bpf_attach_type::BPF_LIRC_MODE2, bpf_prog_type::BPF_PROG_TYPE_LIRC_MODE2},
-    programs::{load_program, query, Link, LinkRef, ProgramData, ProgramError, ProgramInfo},
+    programs::{
+        dup_fd, load_program, query, Link, LinkRef, ProgramData, ProgramError, ProgramInfo,
+    },
     sys::{bpf_obj_get_info_by_fd, bpf_prog_attach, bpf_prog_detach, bpf_prog_get_fd_by_id},
 };
 
-use libc::{close, dup};
-
 /// A program used to decode IR into key events for a lirc device.
 ///
 /// [`LircMode2`] programs can be used to inspect infrared pulses, spaces,
@@ -64,6 +64,7 @@ impl LircMode2 {
         let prog_fd = self.data.fd_or_err()?;
         let lircdev_fd = lircdev.as_raw_fd();
 
+        let link = LircLink::new(dup_fd(prog_fd)?, dup_fd(lircdev_fd)?);
         bpf_prog_attach(prog_fd, lircdev_fd, BPF_LIRC_MODE2).map_err(|(_, io_error)| {
             ProgramError::SyscallError {
                 call: "bpf_prog_attach".to_owned(),
@@ -71,51 +72,49 @@ impl LircMode2 {
             }
         })?;
 
-        Ok(self.data.link(LircLink::new(prog_fd, lircdev_fd)))
+        Ok(self.data.link(link))
     }
 
     /// Queries the lirc device for attached programs.
     pub fn query<T: AsRawFd>(target_fd: T) -> Result<Vec<LircLink>, ProgramError> {
         let prog_ids = query(target_fd.as_raw_fd(), BPF_LIRC_MODE2, 0, &mut None)?;
 
-        let mut prog_fds = Vec::with_capacity(prog_ids.len());
+        let mut links = Vec::with_capacity(prog_ids.len());
 
         for id in prog_ids {
             let fd = bpf_prog_get_fd_by_id(id).map_err(|io_error| ProgramError::SyscallError {
                 call: "bpf_prog_get_fd_by_id".to_owned(),
                 io_error,
             })?;
+            // Safety: the fd was just returned by the kernel and nothing else owns it
+            let prog_fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
 
-            prog_fds.push(fd as RawFd);
+            links.push(LircLink::new(prog_fd, dup_fd(target_fd.as_raw_fd())?));
         }
 
-        Ok(prog_fds
-            .into_iter()
-            .map(|prog_fd| LircLink {
-                prog_fd: Some(prog_fd),
-                target_fd: Some(unsafe { dup(target_fd.as_raw_fd()) }),
-            })
-            .collect())
+        Ok(links)
     }
 }
 
 #[derive(Debug)]
 pub struct LircLink {
-    prog_fd: Option<RawFd>,
-    target_fd: Option<RawFd>,
+    prog_fd: Option<OwnedFd>,
+    target_fd: Option<OwnedFd>,
 }
 
 impl LircLink {
-    pub(crate) fn new(prog_fd: RawFd, target_fd: RawFd) -> LircLink {
+    // the link owns copies of the fds of the program and of the device, since it can outlive
+    // the program
+    pub(crate) fn new(prog_fd: OwnedFd, target_fd: OwnedFd) -> LircLink {
         LircLink {
             prog_fd: Some(prog_fd),
-            target_fd: Some(unsafe { dup(target_fd) }),
+            target_fd: Some(target_fd),
         }
     }
 
     pub fn info(&self) -> Result<ProgramInfo, ProgramError> {
-        if let Some(fd) = self.prog_fd {
-            match bpf_obj_get_info_by_fd(fd) {
+        if let Some(fd) = &self.prog_fd {
+            match bpf_obj_get_info_by_fd(fd.as_raw_fd()) {
                 Ok(info) => Ok(ProgramInfo(info)),
                 Err(io_error) => Err(ProgramError::SyscallError {
                     call: "bpf_obj_get_info_by_fd".to_owned(),
@@ -132,19 +131,10 @@ impl Link for LircLink {
     fn detach(&mut self) -> Result<(), ProgramError> {
         if let Some(prog_fd) = self.prog_fd.take() {
             let target_fd = self.target_fd.take().unwrap();
-            let _ = bpf_prog_detach(prog_fd, target_fd, BPF_LIRC_MODE2);
-            unsafe { close(target_fd) };
+            let _ = bpf_prog_detach(prog_fd.as_raw_fd(), target_fd.as_raw_fd(), BPF_LIRC_MODE2);
             Ok(())
         } else {
             Err(ProgramError::AlreadyDetached)
         }
     }
 }
-
-impl Drop for LircLink {
-    fn drop(&mut self) {
-        if let Some(target_fd) = self.target_fd.take() {
-            unsafe { close(target_fd) };
-        }
-    }
-}
@@ -10,6 +10,10 @@
 //! automatically though, since often you will need to do some application
 //! specific setup before you can actually load them.
 //!
+//! Relocation errors are reported by the `load()` method of the program that
+//! failed, so that one broken program doesn't prevent the others from being
+//! used. To only prepare some of the programs, see [`BpfLoader::programs`].
+//!
 //! In order to load and attach a program, you need to retrieve it using [`Bpf::program_mut`],
 //! then call the `load()` and `attach()` methods, for example:
 //!
@@ -31,6 +35,7 @@
 //! program you're trying to attach.
 //!
 //! [`Bpf::load_file`]: crate::Bpf::load_file
+//! [`BpfLoader::programs`]: crate::BpfLoader::programs
 //! [`Bpf::load`]: crate::Bpf::load
 //! [`Bpf::programs`]: crate::Bpf::programs
 //! [`Bpf::program`]: crate::Bpf::program
@@ -54,22 +59,30 @@ pub mod tc;
 mod tp_btf;
 mod trace_point;
 mod uprobe;
-mod utils;
+pub(crate) mod utils;
+mod verifier;
 mod xdp;
 
-use libc::{close, dup, ENOSPC};
+use libc::ENOSPC;
+use parking_lot::Mutex;
 use std::{
-    cell::RefCell,
     cmp,
     convert::TryFrom,
+    error::Error,
     ffi::{CStr, CString},
-    io,
-    os::unix::io::{AsRawFd, RawFd},
-    path::Path,
-    rc::Rc,
+    fs, io, iter, mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 use thiserror::Error;
 
+pub use crate::obj::btf::{
+    CoreRelocation, CoreRelocationKind, CoreRelocationResult, CORE_POISON_IMM,
+};
 pub use cgroup_skb::{CgroupSkb, CgroupSkbAttachType};
 pub use fentry::FEntry;
 pub use fexit::FExit;
@@ -88,13 +101,19 @@ pub use tc::{SchedClassifier, TcAttachType, TcError};
 pub use tp_btf::BtfTracePoint;
 pub use trace_point::{TracePoint, TracePointError};
 pub use uprobe::{UProbe, UProbeError};
+pub(crate) use verifier::SourceMap;
+pub use verifier::{InstructionState, VerifierError, VerifierLogLevel, VerifierOutput};
 pub use xdp::{Xdp, XdpError, XdpFlags};
 
 use crate::{
-    generated::{bpf_attach_type, bpf_prog_info, bpf_prog_type},
+    features::features,
+    generated::{bpf_attach_type, bpf_func_info, bpf_line_info, bpf_prog_info, bpf_prog_type},
     maps::MapError,
-    obj::{self, btf::BtfError, Function, KernelVersion},
-    sys::{bpf_load_program, bpf_pin_object, bpf_prog_detach, bpf_prog_query, BpfLoadProgramAttrs},
+    obj::{self, btf::BtfError, Function, KernelVersion, ProgramSection, RelocationError},
+    sys::{
+        bpf_get_object, bpf_load_program, bpf_obj_get_info_by_fd, bpf_pin_object, bpf_prog_detach,
+        bpf_prog_get_fd_by_id, bpf_prog_query, BpfLoadProgramAttrs,
+    },
 };
 
 /// Error type returned when working with programs.
@@ -147,6 +166,29 @@ pub enum ProgramError {
     #[error("invalid pin path `{error}`")]
     InvalidPinPath { error: String },
 
+    /// The type of the program can't be determined from the information provided by the kernel.
+    #[error("the program type is ambiguous, use the constructor of the program type")]
+    AmbiguousProgramType,
+
+    /// The link wasn't created with `bpf_link_create` or `bpf_raw_tracepoint_open`.
+    #[error("the link can't be pinned")]
+    UnpinnableLink,
+
+    /// The program has no instructions to load, which is the case of programs opened with
+    /// [`Program::from_pin`] or [`Program::from_id`] once they're unloaded.
+    #[error("the program has no instructions and can't be loaded")]
+    NotLoadable,
+
+    /// Relocating the program failed.
+    #[error("error relocating `{function}`")]
+    RelocationError {
+        /// The name of the function that failed to relocate.
+        function: String,
+        /// The relocation error.
+        #[source]
+        error: Box<dyn Error + Send + Sync>,
+    },
+
     /// A map error occurred while loading or attaching a program.
     #[error(transparent)]
     MapError(#[from] MapError),
@@ -207,6 +249,91 @@ pub enum Program {
 }
 
 impl Program {
+    /// Opens a program pinned at `path`, for example with [`Program::pin`] by an earlier run.
+    ///
+    /// The type and the name of the program are read from the kernel. The program can then be
+    /// attached, detached and pinned again, but since its instructions aren't known it can't be
+    /// loaded again once unloaded.
+    ///
+    /// # Errors
+    ///
+    /// The kernel doesn't distinguish kprobes from uprobes, the kinds of [`SkSkb`] programs, the
+    /// expected attach types of [`CgroupSkb`] programs nor [`FEntry`], [`FExit`] and
+    /// [`BtfTracePoint`] programs. For those
+    /// [`ProgramError::AmbiguousProgramType`] is returned, and the constructors of the program
+    /// types, for example [`KProbe::from_pin`], must be used instead.
+    pub fn from_pin<P: AsRef<Path>>(path: P) -> Result<Program, ProgramError> {
+        Program::from_fd(pinned_program_fd(path.as_ref())?)
+    }
+
+    /// Opens the loaded program with the given id.
+    ///
+    /// See [`Program::from_pin`].
+    pub fn from_id(id: u32) -> Result<Program, ProgramError> {
+        let fd = bpf_prog_get_fd_by_id(id).map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_prog_get_fd_by_id".to_owned(),
+            io_error,
+        })?;
+        // Safety: BPF_PROG_GET_FD_BY_ID opens a new fd, which nothing else owns
+        Program::from_fd(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    fn from_fd(fd: OwnedFd) -> Result<Program, ProgramError> {
+        use crate::generated::bpf_prog_type::*;
+
+        let info = program_info(fd.as_raw_fd())?;
+        let program = match info.0.type_ {
+            t if t == BPF_PROG_TYPE_TRACEPOINT as u32 => Program::TracePoint(TracePoint {
+                data: ProgramData::from_info(fd, &info, |name| ProgramSection::TracePoint { name }),
+            }),
+            t if t == BPF_PROG_TYPE_SOCKET_FILTER as u32 => Program::SocketFilter(SocketFilter {
+                data: ProgramData::from_info(fd, &info, |name| ProgramSection::SocketFilter {
+                    name,
+                }),
+            }),
+            t if t == BPF_PROG_TYPE_XDP as u32 => Program::Xdp(Xdp {
+                data: ProgramData::from_info(fd, &info, |name| ProgramSection::Xdp { name }),
+            }),
+            t if t == BPF_PROG_TYPE_SK_MSG as u32 => Program::SkMsg(SkMsg {
+                data: ProgramData::from_info(fd, &info, |name| ProgramSection::SkMsg { name }),
+            }),
+            t if t == BPF_PROG_TYPE_SOCK_OPS as u32 => Program::SockOps(SockOps {
+                data: ProgramData::from_info(fd, &info, |name| ProgramSection::SockOps { name }),
+            }),
+            t if t == BPF_PROG_TYPE_SCHED_CLS as u32 => {
+                let data = ProgramData::from_info(fd, &info, |name| {
+                    ProgramSection::SchedClassifier { name }
+                });
+                Program::SchedClassifier(SchedClassifier::from_data(data))
+            }
+            t if t == BPF_PROG_TYPE_LIRC_MODE2 as u32 => Program::LircMode2(LircMode2 {
+                data: ProgramData::from_info(fd, &info, |name| ProgramSection::LircMode2 { name }),
+            }),
+            t if t == BPF_PROG_TYPE_PERF_EVENT as u32 => Program::PerfEvent(PerfEvent {
+                data: ProgramData::from_info(fd, &info, |name| ProgramSection::PerfEvent { name }),
+            }),
+            t if t == BPF_PROG_TYPE_RAW_TRACEPOINT as u32 => {
+                Program::RawTracePoint(RawTracePoint {
+                    data: ProgramData::from_info(fd, &info, |name| ProgramSection::RawTracePoint {
+                        name,
+                    }),
+                })
+            }
+            t if t == BPF_PROG_TYPE_LSM as u32 => Program::Lsm(Lsm {
+                data: ProgramData::from_info(fd, &info, |name| ProgramSection::Lsm { name }),
+            }),
+            t if t == BPF_PROG_TYPE_KPROBE as u32
+                || t == BPF_PROG_TYPE_SK_SKB as u32
+                || t == BPF_PROG_TYPE_CGROUP_SKB as u32
+                || t == BPF_PROG_TYPE_TRACING as u32 =>
+            {
+                return Err(ProgramError::AmbiguousProgramType)
+            }
+            _ => return Err(ProgramError::UnexpectedProgramType),
+        };
+        Ok(program)
+    }
+
     /// Loads the program in the kernel.
     ///
     /// # Errors
@@ -221,6 +348,22 @@ impl Program {
         load_program(self.prog_type(), self.data_mut())
     }
 
+    /// Unloads the program from the kernel.
+    ///
+    /// All the links created by attaching the program are detached first, including the ones
+    /// held as [`LinkRef`]. The program can then be loaded again, for example after changing its
+    /// configuration.
+    ///
+    /// Dropping a program closes its file descriptor too, but leaves the links that are still
+    /// referenced attached until they're dropped.
+    ///
+    /// # Errors
+    ///
+    /// If the program is not loaded, [`ProgramError::NotLoaded`] is returned.
+    pub fn unload(&mut self) -> Result<(), ProgramError> {
+        self.data_mut().unload()
+    }
+
     /// Returns the low level program type.
     pub fn prog_type(&self) -> bpf_prog_type {
         use crate::generated::bpf_prog_type::*;
@@ -250,6 +393,32 @@ impl Program {
         self.data_mut().pin(path)
     }
 
+    /// Sets the level of the log produced by the verifier when the program is loaded.
+    ///
+    /// By default the log is only requested when loading fails. When a level is set, the log is
+    /// requested from the first attempt and kept even if the program is loaded successfully, see
+    /// [verifier_output](Self::verifier_output).
+    pub fn set_verifier_log_level(&mut self, level: VerifierLogLevel) {
+        self.data_mut().verifier_log_level = Some(level);
+    }
+
+    /// Returns the output of the verifier from the last time the program was loaded.
+    ///
+    /// The output is available if loading the program failed, or if a log level was set with
+    /// [set_verifier_log_level](Self::set_verifier_log_level).
+    pub fn verifier_output(&self) -> Option<&VerifierOutput> {
+        self.data().verifier_output.as_ref()
+    }
+
+    /// Returns the CO-RE relocations that were applied to the program.
+    ///
+    /// Relocations for which no matching type could be found in the kernel are
+    /// [poisoned](CoreRelocationResult::Poisoned): guarding them with checks like
+    /// `bpf_core_field_exists` makes them unreachable and lets the program load.
+    pub fn core_relocations(&self) -> &[CoreRelocation] {
+        &self.data().core_relocations
+    }
+
     fn data(&self) -> &ProgramData {
         match self {
             Program::KProbe(p) => &p.data,
@@ -298,21 +467,115 @@ impl Program {
 #[derive(Debug)]
 pub(crate) struct ProgramData {
     pub(crate) obj: obj::Program,
-    pub(crate) fd: Option<RawFd>,
-    pub(crate) links: Vec<Rc<RefCell<dyn Link>>>,
+    pub(crate) fd: Option<OwnedFd>,
+    pub(crate) links: Vec<Arc<Mutex<dyn Link>>>,
     pub(crate) expected_attach_type: Option<bpf_attach_type>,
     pub(crate) attach_btf_obj_fd: Option<u32>,
     pub(crate) attach_btf_id: Option<u32>,
+    pub(crate) btf_fd: Option<Arc<OwnedFd>>,
+    // relocation errors are reported when the program is loaded, so that a program that can't be
+    // relocated doesn't prevent the others from being used
+    pub(crate) relocation_error: Option<(String, RelocationError)>,
+    pub(crate) verifier_log_level: Option<VerifierLogLevel>,
+    pub(crate) verifier_output: Option<VerifierOutput>,
+    pub(crate) source_map: SourceMap,
+    // the fds of the module BTF objects that define the kfuncs called by the program. The calls
+    // reference them by their index in the fd_array passed to the kernel, which is built at load
+    // time: index 0 is vmlinux and is never read by the kernel, so the fds start at index 1.
+    pub(crate) fd_array: Vec<Arc<OwnedFd>>,
+    pub(crate) core_relocations: Vec<CoreRelocation>,
 }
 
 impl ProgramData {
     fn fd_or_err(&self) -> Result<RawFd, ProgramError> {
-        self.fd.ok_or(ProgramError::NotLoaded)
+        self.raw_fd().ok_or(ProgramError::NotLoaded)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.fd.as_ref().map(|fd| fd.as_raw_fd())
+    }
+
+    // Creates the data of a program pinned at `path`, checking that it is of type `prog_type`.
+    pub(crate) fn from_pin(
+        path: &Path,
+        prog_type: bpf_prog_type,
+        section: impl FnOnce(String) -> ProgramSection,
+    ) -> Result<ProgramData, ProgramError> {
+        let fd = pinned_program_fd(path)?;
+        let info = program_info(fd.as_raw_fd())?;
+        if info.0.type_ != prog_type as u32 {
+            return Err(ProgramError::UnexpectedProgramType);
+        }
+        Ok(ProgramData::from_info(fd, &info, section))
+    }
+
+    // Creates the data of a program that was loaded by another process or by an earlier run.
+    // Its instructions aren't known, so it can't be loaded again once unloaded.
+    fn from_info(
+        fd: OwnedFd,
+        info: &ProgramInfo,
+        section: impl FnOnce(String) -> ProgramSection,
+    ) -> ProgramData {
+        let name = String::from_utf8_lossy(info.name()).into_owned();
+        let license = if info.0.gpl_compatible() != 0 {
+            "GPL"
+        } else {
+            "Proprietary"
+        };
+        let function = Function {
+            address: 0,
+            name: name.clone(),
+            section_index: object::SectionIndex(0),
+            section_offset: 0,
+            instructions: Vec::new(),
+            func_info: Vec::new(),
+            line_info: Vec::new(),
+        };
+        ProgramData {
+            source_map: SourceMap::new(&function, None),
+            obj: obj::Program {
+                license: CString::new(license).unwrap(),
+                kernel_version: KernelVersion::Any,
+                section: section(name),
+                function,
+            },
+            fd: Some(fd),
+            links: Vec::new(),
+            expected_attach_type: None,
+            attach_btf_obj_fd: None,
+            attach_btf_id: None,
+            btf_fd: None,
+            relocation_error: None,
+            verifier_log_level: None,
+            verifier_output: None,
+            fd_array: Vec::new(),
+            core_relocations: Vec::new(),
+        }
+    }
+
+    pub(crate) fn unload(&mut self) -> Result<(), ProgramError> {
+        if self.fd.is_none() {
+            return Err(ProgramError::NotLoaded);
+        }
+        // links that fail to detach are kept, so that unloading can be retried
+        let mut result = Ok(());
+        self.links.retain(|link| match link.lock().detach() {
+            Ok(()) | Err(ProgramError::AlreadyDetached) => false,
+            Err(e) => {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+                true
+            }
+        });
+        result?;
+        self.fd = None;
+        Ok(())
     }
 
     pub fn link<T: Link + 'static>(&mut self, link: T) -> LinkRef {
-        let link: Rc<RefCell<dyn Link>> = Rc::new(RefCell::new(link));
-        self.links.push(Rc::clone(&link));
+        let link: Arc<Mutex<dyn Link>> = Arc::new(Mutex::new(link));
+        self.links.push(Arc::clone(&link));
         LinkRef::new(link)
     }
 
@@ -342,7 +605,7 @@ pub(crate) struct VerifierLog {
 }
 
 impl VerifierLog {
-    fn new() -> VerifierLog {
+    pub(crate) fn new() -> VerifierLog {
         VerifierLog { buf: Vec::new() }
     }
 
@@ -350,7 +613,7 @@ impl VerifierLog {
         &mut self.buf
     }
 
-    fn grow(&mut self) {
+    pub(crate) fn grow(&mut self) {
         let len = cmp::max(
             MIN_LOG_BUF_SIZE,
             cmp::min(MAX_LOG_BUF_SIZE, self.buf.capacity() * 10),
@@ -365,7 +628,7 @@ impl VerifierLog {
         }
     }
 
-    fn truncate(&mut self) {
+    pub(crate) fn truncate(&mut self) {
         if self.buf.is_empty() {
             return;
         }
@@ -389,16 +652,41 @@ impl VerifierLog {
 }
 
 fn load_program(prog_type: bpf_prog_type, data: &mut ProgramData) -> Result<(), ProgramError> {
-    let ProgramData { obj, fd, .. } = data;
+    let ProgramData {
+        obj,
+        fd,
+        relocation_error,
+        verifier_log_level,
+        verifier_output,
+        source_map,
+        fd_array,
+        ..
+    } = data;
     if fd.is_some() {
         return Err(ProgramError::AlreadyLoaded);
     }
+    if let Some((function, error)) = relocation_error {
+        return Err(ProgramError::RelocationError {
+            function: function.clone(),
+            error: Box::new(error.clone()),
+        });
+    }
     let crate::obj::Program {
-        function: Function { instructions, .. },
+        function:
+            Function {
+                name,
+                instructions,
+                func_info,
+                line_info,
+                ..
+            },
         license,
         kernel_version,
         ..
     } = obj;
+    if instructions.is_empty() {
+        return Err(ProgramError::NotLoadable);
+    }
 
     let target_kernel_version = match *kernel_version {
         KernelVersion::Any => {
@@ -408,11 +696,35 @@ fn load_program(prog_type: bpf_prog_type, data: &mut ProgramData) -> Result<(),
         _ => (*kernel_version).into(),
     };
 
+    // program names were added in 4.15
+    let prog_name = if features().bpf_name() {
+        CString::new(name.as_str()).ok()
+    } else {
+        None
+    };
+
+    let fd_array = if fd_array.is_empty() {
+        Vec::new()
+    } else {
+        iter::once(0)
+            .chain(fd_array.iter().map(|fd| fd.as_raw_fd()))
+            .collect()
+    };
+
     let mut log_buf = VerifierLog::new();
+    // without an explicit level, the log is only requested after the first attempt fails
+    let log_level = match verifier_log_level {
+        Some(level) => {
+            log_buf.grow();
+            *level
+        }
+        None => VerifierLogLevel::all(),
+    };
     let mut retries = 0;
     let mut ret;
     loop {
         let attr = BpfLoadProgramAttrs {
+            name: prog_name.as_deref(),
             ty: prog_type,
             insns: instructions,
             license,
@@ -421,14 +733,33 @@ fn load_program(prog_type: bpf_prog_type, data: &mut ProgramData) -> Result<(),
             attach_btf_obj_fd: data.attach_btf_obj_fd,
             attach_btf_id: data.attach_btf_id,
             log: &mut log_buf,
+            log_level: log_level.bits(),
+            prog_btf_fd: data.btf_fd.as_ref().map(|fd| fd.as_raw_fd()),
+            func_info_rec_size: mem::size_of::<bpf_func_info>(),
+            func_info,
+            line_info_rec_size: mem::size_of::<bpf_line_info>(),
+            line_info,
+            fd_array: &fd_array,
         };
         ret = bpf_load_program(attr);
         match &ret {
             Ok(prog_fd) => {
-                *fd = Some(*prog_fd as RawFd);
+                // Safety: the fd was just returned by the kernel and nothing else owns it
+                *fd = Some(unsafe { OwnedFd::from_raw_fd(*prog_fd as RawFd) });
+                if verifier_log_level.is_some() {
+                    log_buf.truncate();
+                    *verifier_output = Some(VerifierOutput::parse(
+                        verifier_log_string(&log_buf),
+                        source_map,
+                        false,
+                    ));
+                }
                 return Ok(());
             }
-            Err((_, io_error)) if retries == 0 || io_error.raw_os_error() == Some(ENOSPC) => {
+            Err((_, io_error))
+                if (retries == 0 && verifier_log_level.is_none())
+                    || io_error.raw_os_error() == Some(ENOSPC) =>
+            {
                 if retries == 10 {
                     break;
                 }
@@ -441,18 +772,28 @@ fn load_program(prog_type: bpf_prog_type, data: &mut ProgramData) -> Result<(),
 
     if let Err((_, io_error)) = ret {
         log_buf.truncate();
+        let verifier_log = verifier_log_string(&log_buf);
+        *verifier_output = Some(VerifierOutput::parse(
+            verifier_log.clone(),
+            source_map,
+            true,
+        ));
         return Err(ProgramError::LoadError {
             io_error,
-            verifier_log: log_buf
-                .as_c_str()
-                .map(|s| s.to_string_lossy().to_string())
-                .unwrap_or_else(|| "[none]".to_owned()),
+            verifier_log,
         });
     }
 
     Ok(())
 }
 
+fn verifier_log_string(log_buf: &VerifierLog) -> String {
+    log_buf
+        .as_c_str()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "[none]".to_owned())
+}
+
 pub(crate) fn query<T: AsRawFd>(
     target_fd: T,
     attach_type: bpf_attach_type,
@@ -492,8 +833,16 @@ pub(crate) fn query<T: AsRawFd>(
 }
 
 /// Detach an attached program.
-pub trait Link: std::fmt::Debug {
+///
+/// Links are shared between the program and the [`LinkRef`] returned when attaching it, which can
+/// both be moved to other threads.
+pub trait Link: std::fmt::Debug + Send {
     fn detach(&mut self) -> Result<(), ProgramError>;
+
+    /// Returns the underlying [`FdLink`] if the program was attached with a `bpf_link`.
+    fn fd_link(&mut self) -> Option<&mut FdLink> {
+        None
+    }
 }
 
 /// The return type of `program.attach(...)`.
@@ -502,35 +851,96 @@ pub trait Link: std::fmt::Debug {
 /// program.
 #[derive(Debug)]
 pub struct LinkRef {
-    inner: Rc<RefCell<dyn Link>>,
+    inner: Arc<Mutex<dyn Link>>,
 }
 
 impl LinkRef {
-    fn new(link: Rc<RefCell<dyn Link>>) -> LinkRef {
+    fn new(link: Arc<Mutex<dyn Link>>) -> LinkRef {
         LinkRef { inner: link }
     }
 }
 
+impl LinkRef {
+    /// Pins the link to the BPF filesystem at `path` and takes ownership of it.
+    ///
+    /// A pinned link stays attached after the process exits, until the pin is removed. The link
+    /// is no longer detached when the program is unloaded or dropped; use
+    /// [`PinnedLink::from_pin`] to reopen it, for example after restarting, and
+    /// [`PinnedLink::unpin`] to detach it.
+    ///
+    /// # Errors
+    ///
+    /// Only links created with `bpf_link_create` or `bpf_raw_tracepoint_open` can be pinned, for
+    /// other links [`ProgramError::UnpinnableLink`] is returned.
+    pub fn pin<P: AsRef<Path>>(&self, path: P) -> Result<PinnedLink, ProgramError> {
+        let path = path.as_ref();
+        let mut inner = self.inner.lock();
+        let link = inner.fd_link().ok_or(ProgramError::UnpinnableLink)?;
+        let fd = link.fd.as_ref().ok_or(ProgramError::AlreadyDetached)?;
+        bpf_pin_object(fd.as_raw_fd(), &pin_path(path)?).map_err(|(_, io_error)| {
+            ProgramError::SyscallError {
+                call: "BPF_OBJ_PIN".to_owned(),
+                io_error,
+            }
+        })?;
+        Ok(PinnedLink {
+            inner: FdLink { fd: link.fd.take() },
+            path: path.to_owned(),
+        })
+    }
+
+    /// Takes ownership of the link, so that it isn't detached when the program is unloaded or
+    /// dropped.
+    ///
+    /// The link is detached when the returned [`FdLink`] is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Only links created with `bpf_link_create` or `bpf_raw_tracepoint_open` can be taken, for
+    /// other links [`ProgramError::UnpinnableLink`] is returned.
+    pub fn take_link(&self) -> Result<FdLink, ProgramError> {
+        let mut inner = self.inner.lock();
+        let link = inner.fd_link().ok_or(ProgramError::UnpinnableLink)?;
+        match link.fd.take() {
+            Some(fd) => Ok(FdLink::new(fd)),
+            None => Err(ProgramError::AlreadyDetached),
+        }
+    }
+}
+
 impl Link for LinkRef {
     fn detach(&mut self) -> Result<(), ProgramError> {
-        self.inner.borrow_mut().detach()
+        self.inner.lock().detach()
     }
 }
 
+/// A link created with `bpf_link_create` or `bpf_raw_tracepoint_open`.
+///
+/// The program is detached when the link is dropped.
 #[derive(Debug)]
-pub(crate) struct FdLink {
-    fd: Option<RawFd>,
+pub struct FdLink {
+    fd: Option<OwnedFd>,
+}
+
+impl FdLink {
+    pub(crate) fn new(fd: OwnedFd) -> FdLink {
+        FdLink { fd: Some(fd) }
+    }
 }
 
 impl Link for FdLink {
     fn detach(&mut self) -> Result<(), ProgramError> {
-        if let Some(fd) = self.fd.take() {
-            unsafe { close(fd) };
+        // closing the last reference to the link detaches the program
+        if self.fd.take().is_some() {
             Ok(())
         } else {
             Err(ProgramError::AlreadyDetached)
         }
     }
+
+    fn fd_link(&mut self) -> Option<&mut FdLink> {
+        Some(self)
+    }
 }
 
 impl Drop for FdLink {
@@ -539,22 +949,75 @@ impl Drop for FdLink {
     }
 }
 
+/// A link pinned to the BPF filesystem.
+///
+/// Dropping a pinned link closes its file descriptor but leaves the program attached, see
+/// [`unpin`](Self::unpin) to detach it.
+#[derive(Debug)]
+pub struct PinnedLink {
+    inner: FdLink,
+    path: PathBuf,
+}
+
+impl PinnedLink {
+    /// Opens the link pinned at `path`, for example by [`LinkRef::pin`] in an earlier run.
+    pub fn from_pin<P: AsRef<Path>>(path: P) -> Result<PinnedLink, ProgramError> {
+        let path = path.as_ref();
+        let fd = bpf_get_object(&pin_path(path)?).map_err(|(_, io_error)| {
+            ProgramError::SyscallError {
+                call: "BPF_OBJ_GET".to_owned(),
+                io_error,
+            }
+        })? as RawFd;
+        Ok(PinnedLink {
+            // Safety: BPF_OBJ_GET opens a new fd for the pinned object, which nothing else owns
+            inner: FdLink::new(unsafe { OwnedFd::from_raw_fd(fd) }),
+            path: path.to_owned(),
+        })
+    }
+
+    /// Returns the path the link is pinned at.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Removes the pin.
+    ///
+    /// The program is detached when the returned [`FdLink`] is dropped, unless the link is
+    /// pinned somewhere else or still referenced by another process.
+    pub fn unpin(self) -> Result<FdLink, ProgramError> {
+        fs::remove_file(&self.path).map_err(|io_error| ProgramError::SyscallError {
+            call: "unlink".to_owned(),
+            io_error,
+        })?;
+        Ok(self.inner)
+    }
+}
+
+fn pin_path(path: &Path) -> Result<CString, ProgramError> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|e| ProgramError::InvalidPinPath {
+        error: e.to_string(),
+    })
+}
+
 #[derive(Debug)]
 struct ProgAttachLink {
-    prog_fd: Option<RawFd>,
-    target_fd: Option<RawFd>,
+    prog_fd: Option<OwnedFd>,
+    target_fd: Option<OwnedFd>,
     attach_type: bpf_attach_type,
 }
 
 impl ProgAttachLink {
+    // the link owns copies of the fds of the program and of the target, made with dup_fd before
+    // attaching, since it can outlive the program
     pub(crate) fn new(
-        prog_fd: RawFd,
-        target_fd: RawFd,
+        prog_fd: OwnedFd,
+        target_fd: OwnedFd,
         attach_type: bpf_attach_type,
     ) -> ProgAttachLink {
         ProgAttachLink {
             prog_fd: Some(prog_fd),
-            target_fd: Some(unsafe { dup(target_fd) }),
+            target_fd: Some(target_fd),
             attach_type,
         }
     }
@@ -564,8 +1027,7 @@ impl Link for ProgAttachLink {
     fn detach(&mut self) -> Result<(), ProgramError> {
         if let Some(prog_fd) = self.prog_fd.take() {
             let target_fd = self.target_fd.take().unwrap();
-            let _ = bpf_prog_detach(prog_fd, target_fd, self.attach_type);
-            unsafe { close(target_fd) };
+            let _ = bpf_prog_detach(prog_fd.as_raw_fd(), target_fd.as_raw_fd(), self.attach_type);
             Ok(())
         } else {
             Err(ProgramError::AlreadyDetached)
@@ -579,9 +1041,39 @@ impl Drop for ProgAttachLink {
     }
 }
 
+fn pinned_program_fd(path: &Path) -> Result<OwnedFd, ProgramError> {
+    let fd =
+        bpf_get_object(&pin_path(path)?).map_err(|(_, io_error)| ProgramError::SyscallError {
+            call: "BPF_OBJ_GET".to_owned(),
+            io_error,
+        })? as RawFd;
+    // Safety: BPF_OBJ_GET opens a new fd for the pinned program, which nothing else owns
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn program_info(fd: RawFd) -> Result<ProgramInfo, ProgramError> {
+    bpf_obj_get_info_by_fd(fd)
+        .map(ProgramInfo)
+        .map_err(|io_error| ProgramError::SyscallError {
+            call: "bpf_obj_get_info_by_fd".to_owned(),
+            io_error,
+        })
+}
+
+/// Duplicates `fd`, for links that need the fd of the program or of the target to be detached.
+pub(crate) fn dup_fd(fd: RawFd) -> Result<OwnedFd, ProgramError> {
+    // Safety: the callers only pass fds that are open for the duration of the call
+    unsafe { BorrowedFd::borrow_raw(fd) }
+        .try_clone_to_owned()
+        .map_err(|io_error| ProgramError::SyscallError {
+            call: "dup".to_owned(),
+            io_error,
+        })
+}
+
 impl ProgramFd for Program {
     fn fd(&self) -> Option<RawFd> {
-        self.data().fd
+        self.data().raw_fd()
     }
 }
 
@@ -596,7 +1088,7 @@ macro_rules! impl_program_fd {
         $(
             impl ProgramFd for $struct_name {
                 fn fd(&self) -> Option<RawFd> {
-                    self.data.fd
+                    self.data.raw_fd()
                 }
             }
         )+
@@ -622,6 +1114,77 @@ impl_program_fd!(
     FExit,
 );
 
+macro_rules! impl_program_unload {
+    ($($struct_name:ident),+ $(,)?) => {
+        $(
+            impl $struct_name {
+                /// Unloads the program from the kernel, detaching all its links first.
+                ///
+                /// See [`Program::unload`].
+                pub fn unload(&mut self) -> Result<(), ProgramError> {
+                    self.data.unload()
+                }
+            }
+        )+
+    }
+}
+
+impl_program_unload!(
+    KProbe,
+    UProbe,
+    TracePoint,
+    SocketFilter,
+    Xdp,
+    SkMsg,
+    SkSkb,
+    SockOps,
+    SchedClassifier,
+    CgroupSkb,
+    LircMode2,
+    PerfEvent,
+    Lsm,
+    RawTracePoint,
+    BtfTracePoint,
+    FEntry,
+    FExit,
+);
+
+macro_rules! impl_from_pin {
+    ($($struct_name:ident => $prog_type:ident, $section:ident),+ $(,)?) => {
+        $(
+            impl $struct_name {
+                /// Opens a program pinned at `path`, for example with [`Program::pin`] by an
+                /// earlier run.
+                ///
+                /// See [`Program::from_pin`].
+                pub fn from_pin<P: AsRef<Path>>(path: P) -> Result<$struct_name, ProgramError> {
+                    let data = ProgramData::from_pin(
+                        path.as_ref(),
+                        bpf_prog_type::$prog_type,
+                        |name| ProgramSection::$section { name },
+                    )?;
+                    Ok($struct_name { data })
+                }
+            }
+        )+
+    }
+}
+
+impl_from_pin!(
+    TracePoint => BPF_PROG_TYPE_TRACEPOINT, TracePoint,
+    SocketFilter => BPF_PROG_TYPE_SOCKET_FILTER, SocketFilter,
+    Xdp => BPF_PROG_TYPE_XDP, Xdp,
+    SkMsg => BPF_PROG_TYPE_SK_MSG, SkMsg,
+    SockOps => BPF_PROG_TYPE_SOCK_OPS, SockOps,
+    LircMode2 => BPF_PROG_TYPE_LIRC_MODE2, LircMode2,
+    PerfEvent => BPF_PROG_TYPE_PERF_EVENT, PerfEvent,
+    RawTracePoint => BPF_PROG_TYPE_RAW_TRACEPOINT, RawTracePoint,
+    Lsm => BPF_PROG_TYPE_LSM, Lsm,
+    BtfTracePoint => BPF_PROG_TYPE_TRACING, BtfTracePoint,
+    FEntry => BPF_PROG_TYPE_TRACING, FEntry,
+    FExit => BPF_PROG_TYPE_TRACING, FExit,
+);
+
 macro_rules! impl_try_from_program {
     ($($ty:ident),+ $(,)?) => {
         $(
@@ -699,3 +1262,302 @@ impl ProgramInfo {
         self.0.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::unix::io::IntoRawFd,
+        slice,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::{
+        generated::{bpf_cmd, bpf_insn, bpf_prog_type::*},
+        obj::{fake_function, fake_program},
+        sys::{fake_fd, override_syscall, SysResult, Syscall},
+    };
+
+    #[derive(Debug)]
+    struct TestLink {
+        detached: Arc<AtomicBool>,
+    }
+
+    impl Link for TestLink {
+        fn detach(&mut self) -> Result<(), ProgramError> {
+            if self.detached.swap(true, Ordering::SeqCst) {
+                Err(ProgramError::AlreadyDetached)
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    // fails to detach the first time
+    #[derive(Debug)]
+    struct FlakyLink {
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl Link for FlakyLink {
+        fn detach(&mut self) -> Result<(), ProgramError> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(ProgramError::SyscallError {
+                    call: "detach".to_owned(),
+                    io_error: io::Error::from_raw_os_error(libc::EBUSY),
+                })
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    pub(super) fn program_data() -> ProgramData {
+        let obj = fake_program("prog", fake_function(0, 1, Vec::new()));
+        ProgramData {
+            source_map: SourceMap::new(&obj.function, None),
+            obj,
+            fd: None,
+            links: Vec::new(),
+            expected_attach_type: None,
+            attach_btf_obj_fd: None,
+            attach_btf_id: None,
+            btf_fd: None,
+            relocation_error: None,
+            verifier_log_level: None,
+            verifier_output: None,
+            fd_array: Vec::new(),
+            core_relocations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_unload() {
+        let mut prog = Xdp {
+            data: program_data(),
+        };
+        assert!(matches!(prog.unload(), Err(ProgramError::NotLoaded)));
+
+        prog.data.fd = Some(fake_fd());
+        let first = Arc::new(AtomicBool::new(false));
+        let second = Arc::new(AtomicBool::new(false));
+        let _first_link = prog.data.link(TestLink {
+            detached: Arc::clone(&first),
+        });
+        let mut second_link = prog.data.link(TestLink {
+            detached: Arc::clone(&second),
+        });
+        // links that were already detached don't make unloading fail
+        second_link.detach().unwrap();
+
+        prog.unload().unwrap();
+        assert!(first.load(Ordering::SeqCst));
+        assert!(second.load(Ordering::SeqCst));
+        assert!(prog.data.links.is_empty());
+        assert_eq!(prog.fd(), None);
+        assert!(matches!(prog.unload(), Err(ProgramError::NotLoaded)));
+    }
+
+    #[test]
+    fn test_unload_retry() {
+        let mut prog = Xdp {
+            data: program_data(),
+        };
+        prog.data.fd = Some(fake_fd());
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let detached = Arc::new(AtomicBool::new(false));
+        let _flaky_link = prog.data.link(FlakyLink {
+            attempts: Arc::clone(&attempts),
+        });
+        let _link = prog.data.link(TestLink {
+            detached: Arc::clone(&detached),
+        });
+
+        // the other links are still detached, the failed one is kept
+        assert!(matches!(
+            prog.unload(),
+            Err(ProgramError::SyscallError { .. })
+        ));
+        assert!(detached.load(Ordering::SeqCst));
+        assert_eq!(prog.data.links.len(), 1);
+        assert!(prog.fd().is_some());
+
+        prog.unload().unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert!(prog.data.links.is_empty());
+        assert_eq!(prog.fd(), None);
+    }
+
+    #[test]
+    fn test_link_pin() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_PIN,
+                ..
+            } => Ok(0),
+            _ => Err((-1, io::Error::from_raw_os_error(libc::EFAULT))),
+        });
+
+        let mut data = program_data();
+        data.fd = Some(fake_fd());
+        let link = data.link(FdLink::new(fake_fd()));
+        let pinned = link.pin("/sys/fs/bpf/link").unwrap();
+        assert_eq!(pinned.path(), Path::new("/sys/fs/bpf/link"));
+        assert!(pinned.inner.fd.is_some());
+
+        // the program doesn't own the link anymore
+        assert!(matches!(
+            link.pin("/sys/fs/bpf/link"),
+            Err(ProgramError::AlreadyDetached)
+        ));
+        data.unload().unwrap();
+    }
+
+    #[test]
+    fn test_link_pin_unpinnable() {
+        let mut data = program_data();
+        let link = data.link(TestLink {
+            detached: Arc::new(AtomicBool::new(false)),
+        });
+        assert!(matches!(
+            link.pin("/sys/fs/bpf/link"),
+            Err(ProgramError::UnpinnableLink)
+        ));
+        assert!(matches!(
+            link.take_link(),
+            Err(ProgramError::UnpinnableLink)
+        ));
+    }
+
+    #[test]
+    fn test_take_link() {
+        let mut data = program_data();
+        let link = data.link(FdLink::new(fake_fd()));
+        let mut owned = link.take_link().unwrap();
+        assert!(matches!(
+            link.take_link(),
+            Err(ProgramError::AlreadyDetached)
+        ));
+        owned.detach().unwrap();
+    }
+
+    #[test]
+    fn test_pinned_link_from_pin() {
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET,
+                ..
+            } => Ok(fake_fd().into_raw_fd() as i64),
+            _ => Err((-1, io::Error::from_raw_os_error(libc::EFAULT))),
+        });
+
+        let path = std::env::temp_dir().join(format!("aya-test-link-{}", std::process::id()));
+        fs::write(&path, b"").unwrap();
+        let pinned = PinnedLink::from_pin(&path).unwrap();
+        assert_eq!(pinned.path(), path);
+
+        let mut link = pinned.unpin().unwrap();
+        assert!(!path.exists());
+        link.detach().unwrap();
+    }
+
+    fn prog_syscall(call: Syscall, prog_type: bpf_prog_type) -> SysResult {
+        match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET | bpf_cmd::BPF_PROG_GET_FD_BY_ID,
+                ..
+            } => Ok(fake_fd().into_raw_fd() as i64),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_OBJ_GET_INFO_BY_FD,
+                attr,
+            } => {
+                let info = unsafe { &mut *(attr.info.info as *mut bpf_prog_info) };
+                info.type_ = prog_type as u32;
+                info.id = 42;
+                for (dst, src) in info.name.iter_mut().zip(b"prog_name") {
+                    *dst = *src as _;
+                }
+                Ok(0)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(libc::EFAULT))),
+        }
+    }
+
+    #[test]
+    fn test_program_from_id() {
+        override_syscall(|call| prog_syscall(call, BPF_PROG_TYPE_XDP));
+
+        let program = Program::from_id(42).unwrap();
+        assert!(matches!(&program, Program::Xdp(_)));
+        assert_eq!(program.data().obj.function.name, "prog_name");
+        assert!(program.fd().is_some());
+    }
+
+    #[test]
+    fn test_load_from_pin_after_unload() {
+        override_syscall(|call| prog_syscall(call, BPF_PROG_TYPE_XDP));
+
+        let mut prog = Xdp::from_pin("/sys/fs/bpf/prog").unwrap();
+        assert!(matches!(prog.load(), Err(ProgramError::AlreadyLoaded)));
+        prog.unload().unwrap();
+        assert!(matches!(prog.load(), Err(ProgramError::NotLoadable)));
+    }
+
+    #[test]
+    fn test_program_from_pin_ambiguous() {
+        override_syscall(|call| prog_syscall(call, BPF_PROG_TYPE_KPROBE));
+
+        assert!(matches!(
+            Program::from_pin("/sys/fs/bpf/prog"),
+            Err(ProgramError::AmbiguousProgramType)
+        ));
+        let kprobe = KProbe::from_pin("/sys/fs/bpf/prog", ProbeKind::KRetProbe).unwrap();
+        assert!(matches!(kprobe.kind(), ProbeKind::KRetProbe));
+    }
+
+    #[test]
+    fn test_load_fd_array() {
+        // the features are probed with programs that don't have an fd_array
+        features();
+        override_syscall(|call| match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_LOAD,
+                attr,
+            } => {
+                let u = unsafe { &attr.__bindgen_anon_3 };
+                let fd_array = unsafe { slice::from_raw_parts(u.fd_array as *const RawFd, 2) };
+                // index 0 is vmlinux, the module BTF fds follow
+                assert_eq!(fd_array[0], 0);
+                assert!(fd_array[1] > 0);
+                Ok(fake_fd().into_raw_fd() as i64)
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(libc::EFAULT))),
+        });
+
+        let mut data = program_data();
+        // exit
+        data.obj.function.instructions.push(bpf_insn {
+            code: 0x95,
+            ..unsafe { mem::zeroed() }
+        });
+        let module_btf = Arc::new(fake_fd());
+        data.fd_array.push(Arc::clone(&module_btf));
+        let mut prog = Xdp { data };
+        prog.load().unwrap();
+
+        // the program keeps the module BTF open until it's dropped
+        assert_eq!(Arc::strong_count(&module_btf), 2);
+        drop(prog);
+        assert_eq!(Arc::strong_count(&module_btf), 1);
+    }
+
+    #[test]
+    fn test_from_pin_unexpected_type() {
+        override_syscall(|call| prog_syscall(call, BPF_PROG_TYPE_KPROBE));
+
+        assert!(matches!(
+            Xdp::from_pin("/sys/fs/bpf/prog"),
+            Err(ProgramError::UnexpectedProgramType)
+        ));
+    }
+}
@@ -6,12 +6,13 @@ use std::{
 };
 
 use crate::{
+    features::features,
     programs::{
         kprobe::KProbeError, perf_attach, perf_attach_debugfs,
         trace_point::read_sys_fs_trace_point_id, uprobe::UProbeError, LinkRef, ProgramData,
         ProgramError,
     },
-    sys::{kernel_version, perf_event_open_probe, perf_event_open_trace_point},
+    sys::{perf_event_open_probe, perf_event_open_trace_point},
 };
 
 #[derive(Debug, Copy, Clone)]
@@ -43,9 +44,12 @@ pub(crate) fn attach(
     pid: Option<pid_t>,
 ) -> Result<LinkRef, ProgramError> {
     // https://github.com/torvalds/linux/commit/e12f03d7031a977356e3d7b75a68c2185ff8d155
-    // Use debugfs to create probe
-    let k_ver = kernel_version().unwrap();
-    if k_ver < (4, 17, 0) {
+    // Use debugfs to create probe when the kernel doesn't provide a kprobe/uprobe PMU
+    let pmu = match kind {
+        ProbeKind::KProbe | ProbeKind::KRetProbe => features().kprobe_pmu(),
+        ProbeKind::UProbe | ProbeKind::URetProbe => features().uprobe_pmu(),
+    };
+    if !pmu {
         let (fd, event_alias) = create_as_trace_point(kind, fn_name, offset, pid)?;
 
         return perf_attach_debugfs(program_data, fd, kind, event_alias);
@@ -1,7 +1,7 @@
 use crate::{
     generated::{bpf_attach_type::BPF_SK_MSG_VERDICT, bpf_prog_type::BPF_PROG_TYPE_SK_MSG},
     maps::sock::SocketMap,
-    programs::{load_program, LinkRef, ProgAttachLink, ProgramData, ProgramError},
+    programs::{dup_fd, load_program, LinkRef, ProgAttachLink, ProgramData, ProgramError},
     sys::bpf_prog_attach,
 };
 
@@ -72,14 +72,17 @@ impl SkMsg {
         let prog_fd = self.data.fd_or_err()?;
         let map_fd = map.fd_or_err()?;
 
+        let (link_prog_fd, link_target_fd) = (dup_fd(prog_fd)?, dup_fd(map_fd)?);
         bpf_prog_attach(prog_fd, map_fd, BPF_SK_MSG_VERDICT).map_err(|(_, io_error)| {
             ProgramError::SyscallError {
                 call: "bpf_prog_attach".to_owned(),
                 io_error,
             }
         })?;
-        Ok(self
-            .data
-            .link(ProgAttachLink::new(prog_fd, map_fd, BPF_SK_MSG_VERDICT)))
+        Ok(self.data.link(ProgAttachLink::new(
+            link_prog_fd,
+            link_target_fd,
+            BPF_SK_MSG_VERDICT,
+        )))
     }
 }
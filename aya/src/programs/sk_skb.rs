@@ -1,10 +1,13 @@
+use std::path::Path;
+
 use crate::{
     generated::{
         bpf_attach_type::{BPF_SK_SKB_STREAM_PARSER, BPF_SK_SKB_STREAM_VERDICT},
         bpf_prog_type::BPF_PROG_TYPE_SK_SKB,
     },
     maps::sock::SocketMap,
-    programs::{load_program, LinkRef, ProgAttachLink, ProgramData, ProgramError},
+    obj::ProgramSection,
+    programs::{dup_fd, load_program, LinkRef, ProgAttachLink, ProgramData, ProgramError},
     sys::bpf_prog_attach,
 };
 
@@ -51,6 +54,19 @@ pub struct SkSkb {
 }
 
 impl SkSkb {
+    /// Opens a program pinned at `path`, for example with
+    /// [`Program::pin`](crate::programs::Program::pin) by an earlier run.
+    ///
+    /// The kernel doesn't record the kind of the program, so it must be given as `kind`. See also
+    /// [`Program::from_pin`](crate::programs::Program::from_pin).
+    pub fn from_pin<P: AsRef<Path>>(path: P, kind: SkSkbKind) -> Result<SkSkb, ProgramError> {
+        let data = ProgramData::from_pin(path.as_ref(), BPF_PROG_TYPE_SK_SKB, |name| match kind {
+            SkSkbKind::StreamParser => ProgramSection::SkSkbStreamParser { name },
+            SkSkbKind::StreamVerdict => ProgramSection::SkSkbStreamVerdict { name },
+        })?;
+        Ok(SkSkb { data, kind })
+    }
+
     /// Loads the program inside the kernel.
     ///
     /// See also [`Program::load`](crate::programs::Program::load).
@@ -67,14 +83,17 @@ impl SkSkb {
             SkSkbKind::StreamParser => BPF_SK_SKB_STREAM_PARSER,
             SkSkbKind::StreamVerdict => BPF_SK_SKB_STREAM_VERDICT,
         };
+        let (link_prog_fd, link_target_fd) = (dup_fd(prog_fd)?, dup_fd(map_fd)?);
         bpf_prog_attach(prog_fd, map_fd, attach_type).map_err(|(_, io_error)| {
             ProgramError::SyscallError {
                 call: "bpf_prog_attach".to_owned(),
                 io_error,
             }
         })?;
-        Ok(self
-            .data
-            .link(ProgAttachLink::new(prog_fd, map_fd, attach_type)))
+        Ok(self.data.link(ProgAttachLink::new(
+            link_prog_fd,
+            link_target_fd,
+            attach_type,
+        )))
     }
 }
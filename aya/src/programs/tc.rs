@@ -5,12 +5,14 @@ use std::{
     ffi::{CStr, CString},
     io,
     os::unix::io::RawFd,
+    path::Path,
 };
 
 use crate::{
     generated::{
         bpf_prog_type::BPF_PROG_TYPE_SCHED_CLS, TC_H_CLSACT, TC_H_MIN_EGRESS, TC_H_MIN_INGRESS,
     },
+    obj::ProgramSection,
     programs::{load_program, Link, LinkRef, ProgramData, ProgramError},
     sys::{
         netlink_find_filter_with_name, netlink_qdisc_add_clsact, netlink_qdisc_attach,
@@ -106,6 +108,25 @@ impl TcAttachType {
 }
 
 impl SchedClassifier {
+    /// Opens a program pinned at `path`, for example with
+    /// [`Program::pin`](crate::programs::Program::pin) by an earlier run.
+    ///
+    /// See also [`Program::from_pin`](crate::programs::Program::from_pin).
+    pub fn from_pin<P: AsRef<Path>>(path: P) -> Result<SchedClassifier, ProgramError> {
+        let data = ProgramData::from_pin(path.as_ref(), BPF_PROG_TYPE_SCHED_CLS, |name| {
+            ProgramSection::SchedClassifier { name }
+        })?;
+        Ok(SchedClassifier::from_data(data))
+    }
+
+    // the filters are named after the program
+    pub(crate) fn from_data(data: ProgramData) -> SchedClassifier {
+        let name = CString::new(data.obj.function.name.clone())
+            .unwrap_or_default()
+            .into_boxed_c_str();
+        SchedClassifier { data, name }
+    }
+
     /// Loads the program inside the kernel.
     ///
     /// See also [`Program::load`](crate::programs::Program::load).
@@ -15,6 +15,7 @@ use thiserror::Error;
 
 use crate::{
     generated::bpf_prog_type::BPF_PROG_TYPE_KPROBE,
+    obj::ProgramSection,
     programs::{
         load_program,
         probe::{attach, ProbeKind},
@@ -45,6 +46,22 @@ pub struct UProbe {
 }
 
 impl UProbe {
+    /// Opens a program pinned at `path`, for example with
+    /// [`Program::pin`](crate::programs::Program::pin) by an earlier run.
+    ///
+    /// The kernel doesn't record whether the program is a uprobe or a uretprobe, so `kind` must be
+    /// [`ProbeKind::UProbe`] or [`ProbeKind::URetProbe`]. See also
+    /// [`Program::from_pin`](crate::programs::Program::from_pin).
+    pub fn from_pin<P: AsRef<Path>>(path: P, kind: ProbeKind) -> Result<UProbe, ProgramError> {
+        let section = match kind {
+            ProbeKind::UProbe => |name| ProgramSection::UProbe { name },
+            ProbeKind::URetProbe => |name| ProgramSection::URetProbe { name },
+            _ => return Err(ProgramError::UnexpectedProgramType),
+        };
+        let data = ProgramData::from_pin(path.as_ref(), BPF_PROG_TYPE_KPROBE, section)?;
+        Ok(UProbe { data, kind })
+    }
+
     /// Loads the program inside the kernel.
     ///
     /// See also [`Program::load`](crate::programs::Program::load).
@@ -1,9 +1,17 @@
 //! Common functions shared between multiple eBPF program types.
-use std::{ffi::CStr, os::unix::io::RawFd};
+use std::{
+    ffi::CStr,
+    io,
+    os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    sync::Arc,
+};
 
 use crate::{
+    obj::btf::{Btf, BtfError, BtfKind},
     programs::{FdLink, LinkRef, ProgramData, ProgramError},
-    sys::bpf_raw_tracepoint_open,
+    sys::{
+        bpf_btf_get_fd_by_id, bpf_btf_get_info_by_fd, bpf_btf_get_next_id, bpf_raw_tracepoint_open,
+    },
 };
 
 /// Attaches the program to a raw tracepoint.
@@ -19,6 +27,65 @@ pub(crate) fn attach_raw_tracepoint(
             io_error,
         }
     })? as RawFd;
+    // Safety: the raw tracepoint fd is new and owned by nothing but the link
+    let pfd = unsafe { OwnedFd::from_raw_fd(pfd) };
+
+    Ok(program_data.link(FdLink::new(pfd)))
+}
+
+/// Finds the BTF id of the kernel function `fn_name`.
+///
+/// The function is looked up in `btf`, which must be the BTF of the running kernel, and then in
+/// the BTF of the loaded kernel modules. When the function is defined by a module, the fd of the
+/// module's BTF object is returned as well. The caller is responsible for closing it.
+pub(crate) fn find_kernel_func(
+    btf: &Arc<Btf>,
+    fn_name: &str,
+) -> Result<(u32, Option<RawFd>), ProgramError> {
+    let err = match btf.id_by_type_name_kind(fn_name, BtfKind::Func) {
+        Ok(type_id) => return Ok((type_id, None)),
+        Err(e @ BtfError::UnknownBtfTypeName { .. }) => e,
+        Err(e) => return Err(e.into()),
+    };
+
+    for module in Btf::modules_from_sys_fs(Arc::clone(btf))? {
+        if let Some(type_id) = module
+            .btf
+            .split_id_by_type_name_kind(fn_name, BtfKind::Func)?
+        {
+            let fd =
+                module_btf_fd(&module.name).map_err(|io_error| ProgramError::SyscallError {
+                    call: "bpf_btf_get_fd_by_id".to_owned(),
+                    io_error,
+                })?;
+            return Ok((type_id, Some(fd.into_raw_fd())));
+        }
+    }
+
+    Err(err.into())
+}
+
+// Returns the fd of the kernel BTF object of the module `name`.
+pub(crate) fn module_btf_fd(name: &str) -> Result<OwnedFd, io::Error> {
+    let mut id = 0;
+    while let Some(next_id) = bpf_btf_get_next_id(id)? {
+        id = next_id;
+        let fd = match bpf_btf_get_fd_by_id(id) {
+            // Safety: the fd was just returned by the kernel and nothing else owns it
+            Ok(fd) => unsafe { OwnedFd::from_raw_fd(fd) },
+            // the object was unloaded in the meantime
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => continue,
+            Err(e) => return Err(e),
+        };
+
+        // module names are at most MODULE_NAME_LEN (64 - sizeof(unsigned long)) bytes
+        let mut buf = [0u8; 64];
+        let info = bpf_btf_get_info_by_fd(fd.as_raw_fd(), &mut buf)?;
+        let len = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+        if info.kernel_btf != 0 && &buf[..len] == name.as_bytes() {
+            return Ok(fd);
+        }
+    }
 
-    Ok(program_data.link(FdLink { fd: Some(pfd) }))
+    Err(io::Error::from_raw_os_error(libc::ENOENT))
 }
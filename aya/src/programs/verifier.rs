@@ -0,0 +1,273 @@
+//! Verifier log options and parsing.
+use crate::obj::{btf::Btf, Function};
+
+bitflags! {
+    /// The level of detail of the log produced by the kernel verifier.
+    pub struct VerifierLogLevel: u32 {
+        /// Log the instructions and register states where verification fails.
+        const DEBUG = 1;
+        /// Log the register states of every instruction.
+        const VERBOSE = 2;
+        /// Log statistics like the number of processed instructions and the stack depth.
+        const STATS = 4;
+    }
+}
+
+impl Default for VerifierLogLevel {
+    fn default() -> Self {
+        VerifierLogLevel::DEBUG | VerifierLogLevel::STATS
+    }
+}
+
+/// The output of the kernel verifier for a program.
+#[derive(Debug, Clone, Default)]
+pub struct VerifierOutput {
+    /// The log as returned by the kernel.
+    pub log: String,
+    /// The register states logged for each instruction, in the order they appear in the log.
+    pub states: Vec<InstructionState>,
+    /// The number of instructions processed by the verifier.
+    pub processed_insns: Option<u32>,
+    /// The stack depth of each function of the program, starting with the main function.
+    pub stack_depth: Vec<u32>,
+    /// Why the program was rejected, if it was.
+    pub error: Option<VerifierError>,
+}
+
+/// The state of the registers and stack before an instruction is executed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionState {
+    /// The index of the instruction.
+    pub insn: usize,
+    /// The state as printed by the verifier, for example `R1=ctx(off=0,imm=0) R10=fp0`.
+    pub state: String,
+}
+
+/// The reason why a program was rejected by the verifier.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifierError {
+    /// The message logged by the verifier.
+    pub message: String,
+    /// The index of the instruction that was rejected.
+    pub insn: Option<usize>,
+    /// The function containing the instruction.
+    pub function: Option<String>,
+    /// The source file containing the instruction, when the program has line info.
+    pub file: Option<String>,
+    /// The source line of the instruction, when the program has line info.
+    pub line: Option<u32>,
+}
+
+/// Maps instructions of a linked program back to functions and source lines.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SourceMap {
+    // sorted by instruction index
+    functions: Vec<(usize, String)>,
+    lines: Vec<(usize, String, u32)>,
+}
+
+impl SourceMap {
+    pub(crate) fn new(function: &Function, btf: Option<&Btf>) -> SourceMap {
+        let mut functions = Vec::new();
+        let mut lines = Vec::new();
+        if let Some(btf) = btf {
+            for info in &function.func_info {
+                let name = btf
+                    .type_by_id(info.type_id)
+                    .and_then(|ty| btf.type_name(ty))
+                    .ok()
+                    .flatten();
+                if let Some(name) = name {
+                    functions.push((info.insn_off as usize, name.into_owned()));
+                }
+            }
+            for info in &function.line_info {
+                if let Ok(file) = btf.string_at(info.file_name_off) {
+                    lines.push((
+                        info.insn_off as usize,
+                        file.into_owned(),
+                        info.line_col >> 10,
+                    ));
+                }
+            }
+        }
+        if functions.is_empty() {
+            functions.push((0, function.name.clone()));
+        }
+        functions.sort_by_key(|(insn, _)| *insn);
+        lines.sort_by_key(|(insn, _, _)| *insn);
+
+        SourceMap { functions, lines }
+    }
+
+    fn function(&self, insn: usize) -> Option<&str> {
+        self.functions
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= insn)
+            .map(|(_, name)| name.as_str())
+    }
+
+    fn line(&self, insn: usize) -> Option<(&str, u32)> {
+        self.lines
+            .iter()
+            .rev()
+            .find(|(start, _, _)| *start <= insn)
+            .map(|(_, file, line)| (file.as_str(), *line))
+    }
+}
+
+impl VerifierOutput {
+    /// Parses the log of a program. `failed` is true if the program was rejected.
+    pub(crate) fn parse(log: String, source_map: &SourceMap, failed: bool) -> VerifierOutput {
+        let mut states = Vec::new();
+        let mut processed_insns = None;
+        let mut stack_depth = Vec::new();
+        let mut last_insn = None;
+        let mut error = None;
+
+        for line in log.lines().map(str::trim_end) {
+            if let Some(rest) = line.strip_prefix("processed ") {
+                processed_insns = rest.split(' ').next().and_then(|n| n.parse().ok());
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("stack depth ") {
+                stack_depth = rest.split('+').filter_map(|n| n.parse().ok()).collect();
+                continue;
+            }
+            if line.is_empty() || line.starts_with(';') || line.starts_with("verification time") {
+                continue;
+            }
+            // "from 4 to 6: R0=..." is the state at the target of a jump
+            let (insn, rest) = match line.strip_prefix("from ") {
+                Some(rest) => match rest.split_once(": ") {
+                    Some((jump, state)) => {
+                        (jump.rsplit(' ').next().and_then(|n| n.parse().ok()), state)
+                    }
+                    None => (None, rest),
+                },
+                None => match line.split_once(':') {
+                    Some((insn, rest)) => (insn.parse::<usize>().ok(), rest.trim_start()),
+                    None => (None, line),
+                },
+            };
+            let insn = match insn {
+                Some(insn) => insn,
+                None => {
+                    // anything else is a message, the last one explains why the program was
+                    // rejected
+                    error = Some(line.to_owned());
+                    continue;
+                }
+            };
+            if rest.starts_with('(') {
+                // "1: (b7) r0 = 0 ; R0_w=0", the state is after the instruction on newer kernels
+                last_insn = Some(insn);
+                if let Some((_, state)) = rest.split_once(" ; ") {
+                    states.push(InstructionState {
+                        insn,
+                        state: state.to_owned(),
+                    });
+                }
+            } else {
+                states.push(InstructionState {
+                    insn,
+                    state: rest.to_owned(),
+                });
+            }
+        }
+
+        let error = if failed {
+            let line = last_insn.and_then(|insn| source_map.line(insn));
+            Some(VerifierError {
+                message: error.unwrap_or_default(),
+                insn: last_insn,
+                function: last_insn
+                    .and_then(|insn| source_map.function(insn))
+                    .map(str::to_owned),
+                file: line.map(|(file, _)| file.to_owned()),
+                line: line.map(|(_, line)| line),
+            })
+        } else {
+            None
+        };
+
+        VerifierOutput {
+            log,
+            states,
+            processed_insns,
+            stack_depth,
+            error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source_map() -> SourceMap {
+        SourceMap {
+            functions: vec![(0, "prog".to_owned()), (4, "helper".to_owned())],
+            lines: vec![(0, "prog.c".to_owned(), 10), (5, "prog.c".to_owned(), 20)],
+        }
+    }
+
+    #[test]
+    fn test_parse_rejected() {
+        let log = "\
+func#0 @0
+0: R1=ctx(off=0,imm=0) R10=fp0
+0: (b7) r0 = 0                        ; R0_w=0
+1: (85) call pc+2
+caller:
+ R10=fp0
+callee:
+ frame1: R1=ctx(off=0,imm=0) R10=fp0
+4: (b7) r1 = 0
+5: (79) r0 = *(u64 *)(r1 +0)
+R1 invalid mem access 'scalar'
+processed 5 insns (limit 1000000) max_states_per_insn 0 total_states 0 peak_states 0 mark_read 0
+";
+        let output = VerifierOutput::parse(log.to_owned(), &source_map(), true);
+        assert_eq!(output.processed_insns, Some(5));
+        assert_eq!(
+            output.states,
+            vec![
+                InstructionState {
+                    insn: 0,
+                    state: "R1=ctx(off=0,imm=0) R10=fp0".to_owned()
+                },
+                InstructionState {
+                    insn: 0,
+                    state: "R0_w=0".to_owned()
+                },
+            ]
+        );
+        assert_eq!(
+            output.error,
+            Some(VerifierError {
+                message: "R1 invalid mem access 'scalar'".to_owned(),
+                insn: Some(5),
+                function: Some("helper".to_owned()),
+                file: Some("prog.c".to_owned()),
+                line: Some(20),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        let log = "\
+from 2 to 4: R0=inv0 R10=fp0
+processed 12 insns (limit 1000000) max_states_per_insn 0 total_states 1 peak_states 1 mark_read 1
+stack depth 8+16
+verification time 40 usec
+";
+        let output = VerifierOutput::parse(log.to_owned(), &source_map(), false);
+        assert_eq!(output.processed_insns, Some(12));
+        assert_eq!(output.stack_depth, vec![8, 16]);
+        assert_eq!(output.states[0].insn, 4);
+        assert!(output.error.is_none());
+    }
+}
@@ -1,15 +1,20 @@
 use bitflags;
-use libc::if_nametoindex;
-use std::{ffi::CString, io, os::unix::io::RawFd};
+use libc::{if_nametoindex, EINVAL, EOPNOTSUPP};
+use std::{
+    ffi::CString,
+    io,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+};
 use thiserror::Error;
 
 use crate::{
+    features::features,
     generated::{
         bpf_attach_type::BPF_XDP, bpf_prog_type::BPF_PROG_TYPE_XDP, XDP_FLAGS_DRV_MODE,
         XDP_FLAGS_HW_MODE, XDP_FLAGS_REPLACE, XDP_FLAGS_SKB_MODE, XDP_FLAGS_UPDATE_IF_NOEXIST,
     },
-    programs::{load_program, FdLink, Link, LinkRef, ProgramData, ProgramError},
-    sys::{bpf_link_create, kernel_version, netlink_set_xdp_fd},
+    programs::{dup_fd, load_program, FdLink, Link, LinkRef, ProgramData, ProgramError},
+    sys::{bpf_link_create, netlink_set_xdp_fd},
 };
 
 /// The type returned when attaching an [`Xdp`] program fails on kernels `< 5.9`.
@@ -83,9 +88,9 @@ impl Xdp {
     /// [`ProgramError::UnknownInterface`] is returned.
     ///
     /// When attaching fails, [`ProgramError::SyscallError`] is returned for
-    /// kernels `>= 5.9.0`, and instead
-    /// [`XdpError::NetlinkError`] is returned for older
-    /// kernels.
+    /// kernels that support attaching XDP programs with `bpf_link` (usually
+    /// `>= 5.9.0`), and instead [`XdpError::NetlinkError`] is returned for
+    /// older kernels.
     pub fn attach(&mut self, interface: &str, flags: XdpFlags) -> Result<LinkRef, ProgramError> {
         let prog_fd = self.data.fd_or_err()?;
 
@@ -97,24 +102,25 @@ impl Xdp {
             });
         }
 
-        let k_ver = kernel_version().unwrap();
-        if k_ver >= (5, 9, 0) {
+        if features().bpf_link_xdp() {
             let link_fd = bpf_link_create(prog_fd, if_index, BPF_XDP, flags.bits).map_err(
                 |(_, io_error)| ProgramError::SyscallError {
                     call: "bpf_link_create".to_owned(),
                     io_error,
                 },
             )? as RawFd;
-            Ok(self
-                .data
-                .link(XdpLink::FdLink(FdLink { fd: Some(link_fd) })))
+            // Safety: bpf_link_create returned a new fd, the link is its only owner
+            let link_fd = unsafe { OwnedFd::from_raw_fd(link_fd) };
+            Ok(self.data.link(XdpLink::FdLink(FdLink::new(link_fd))))
         } else {
+            // the link keeps its own copy of the program fd, since it can outlive the program
+            let link_prog_fd = dup_fd(prog_fd)?;
             unsafe { netlink_set_xdp_fd(if_index, prog_fd, None, flags.bits) }
                 .map_err(|io_error| XdpError::NetlinkError { io_error })?;
 
             Ok(self.data.link(XdpLink::NlLink(NlLink {
                 if_index,
-                prog_fd: Some(prog_fd),
+                prog_fd: Some(link_prog_fd),
                 flags,
             })))
         }
@@ -124,20 +130,25 @@ impl Xdp {
 #[derive(Debug)]
 struct NlLink {
     if_index: i32,
-    prog_fd: Option<RawFd>,
+    prog_fd: Option<OwnedFd>,
     flags: XdpFlags,
 }
 
 impl Link for NlLink {
     fn detach(&mut self) -> Result<(), ProgramError> {
         if let Some(fd) = self.prog_fd.take() {
-            let k_ver = kernel_version().unwrap();
-            let flags = if k_ver >= (5, 7, 0) {
-                self.flags.bits | XDP_FLAGS_REPLACE
-            } else {
-                self.flags.bits
-            };
-            let _ = unsafe { netlink_set_xdp_fd(self.if_index, -1, Some(fd), flags) };
+            let fd = fd.as_raw_fd();
+            // XDP_FLAGS_REPLACE makes sure we only detach our own program. Kernels older than
+            // 5.7 don't support the flag and reject the request, so retry without it. Any other
+            // error, like EEXIST when another program replaced ours, must not be retried or the
+            // other program would be detached.
+            let flags = self.flags.bits | XDP_FLAGS_REPLACE;
+            if let Err(e) = unsafe { netlink_set_xdp_fd(self.if_index, -1, Some(fd), flags) } {
+                if matches!(e.raw_os_error(), Some(EINVAL) | Some(EOPNOTSUPP)) {
+                    let _ =
+                        unsafe { netlink_set_xdp_fd(self.if_index, -1, Some(fd), self.flags.bits) };
+                }
+            }
             Ok(())
         } else {
             Err(ProgramError::AlreadyDetached)
@@ -164,4 +175,129 @@ impl Link for XdpLink {
             XdpLink::NlLink(link) => link.detach(),
         }
     }
+
+    fn fd_link(&mut self) -> Option<&mut FdLink> {
+        match self {
+            XdpLink::FdLink(link) => Some(link),
+            XdpLink::NlLink(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io, os::unix::io::IntoRawFd};
+
+    use libc::{EEXIST, ENODEV};
+
+    use super::*;
+    use crate::{
+        generated::bpf_cmd,
+        programs::tests::program_data,
+        sys::{fake_fd, override_netlink_set_xdp_fd, override_syscall, SysResult, Syscall},
+    };
+
+    thread_local! {
+        static NETLINK_FLAGS: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn netlink_set_xdp_fd(
+        _if_index: i32,
+        _fd: RawFd,
+        _old_fd: Option<RawFd>,
+        flags: u32,
+        error: i32,
+    ) -> Result<(), io::Error> {
+        NETLINK_FLAGS.with(|calls| calls.borrow_mut().push(flags));
+        Err(io::Error::from_raw_os_error(error))
+    }
+
+    fn detach() -> Vec<u32> {
+        NETLINK_FLAGS.with(|calls| calls.borrow_mut().clear());
+        let mut link = NlLink {
+            if_index: 1,
+            prog_fd: Some(fake_fd()),
+            flags: XdpFlags::SKB_MODE,
+        };
+        link.detach().unwrap();
+        NETLINK_FLAGS.with(|calls| calls.take())
+    }
+
+    #[test]
+    fn test_nl_link_detach_retries_without_replace() {
+        override_netlink_set_xdp_fd(|if_index, fd, old_fd, flags| {
+            netlink_set_xdp_fd(if_index, fd, old_fd, flags, EINVAL)
+        });
+        let skb_mode = XdpFlags::SKB_MODE.bits;
+        assert_eq!(detach(), vec![skb_mode | XDP_FLAGS_REPLACE, skb_mode]);
+    }
+
+    #[test]
+    fn test_nl_link_detach_replaced() {
+        // another program replaced ours, it must not be detached
+        override_netlink_set_xdp_fd(|if_index, fd, old_fd, flags| {
+            netlink_set_xdp_fd(if_index, fd, old_fd, flags, EEXIST)
+        });
+        assert_eq!(detach(), vec![XdpFlags::SKB_MODE.bits | XDP_FLAGS_REPLACE]);
+    }
+
+    // Loads programs and answers the bpf_link probe, which links to an invalid ifindex, with
+    // `probe_error`. Attaching with bpf_link succeeds.
+    fn link_syscall(call: Syscall, probe_error: i32) -> SysResult {
+        match call {
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_PROG_LOAD,
+                ..
+            } => Ok(fake_fd().into_raw_fd() as i64),
+            Syscall::Bpf {
+                cmd: bpf_cmd::BPF_LINK_CREATE,
+                attr,
+            } => {
+                if unsafe { attr.link_create.__bindgen_anon_1.target_fd } as RawFd == -1 {
+                    Err((-1, io::Error::from_raw_os_error(probe_error)))
+                } else {
+                    Ok(fake_fd().into_raw_fd() as i64)
+                }
+            }
+            _ => Err((-1, io::Error::from_raw_os_error(libc::EFAULT))),
+        }
+    }
+
+    fn attach() -> (Xdp, LinkRef, Vec<u32>) {
+        override_netlink_set_xdp_fd(|_, _, _, flags| {
+            NETLINK_FLAGS.with(|calls| calls.borrow_mut().push(flags));
+            Ok(())
+        });
+        NETLINK_FLAGS.with(|calls| calls.borrow_mut().clear());
+        let mut data = program_data();
+        data.fd = Some(fake_fd());
+        let mut prog = Xdp { data };
+        let link = prog.attach("lo", XdpFlags::SKB_MODE).unwrap();
+        (prog, link, NETLINK_FLAGS.with(|calls| calls.take()))
+    }
+
+    #[test]
+    fn test_attach_bpf_link() {
+        override_syscall(|call| link_syscall(call, ENODEV));
+        let (_prog, link, netlink_flags) = attach();
+        assert!(link.inner.lock().fd_link().is_some());
+        assert!(netlink_flags.is_empty());
+    }
+
+    #[test]
+    fn test_attach_netlink() {
+        // kernels without XDP links reject the attach type
+        override_syscall(|call| link_syscall(call, EINVAL));
+        let (mut prog, link, netlink_flags) = attach();
+        assert!(!features().bpf_link_xdp());
+        assert!(link.inner.lock().fd_link().is_none());
+        assert_eq!(netlink_flags, vec![XdpFlags::SKB_MODE.bits]);
+
+        NETLINK_FLAGS.with(|calls| calls.borrow_mut().clear());
+        prog.data.unload().unwrap();
+        assert_eq!(
+            NETLINK_FLAGS.with(|calls| calls.take()),
+            vec![XdpFlags::SKB_MODE.bits | XDP_FLAGS_REPLACE]
+        );
+    }
 }
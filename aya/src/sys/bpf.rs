@@ -1,26 +1,41 @@
 use std::{
     cmp,
-    ffi::CStr,
+    ffi::{CStr, CString},
     io,
     mem::{self, MaybeUninit},
     os::unix::io::RawFd,
     slice,
 };
 
-use libc::{c_long, ENOENT};
+use libc::{c_long, EBADF, ENODEV, ENOENT};
 
 use crate::{
     bpf_map_def,
-    generated::{bpf_attach_type, bpf_attr, bpf_cmd, bpf_insn, bpf_prog_info, bpf_prog_type},
+    features::features,
+    generated::{
+        bpf_attach_type, bpf_attr, bpf_btf_info, bpf_cmd, bpf_func_info, bpf_insn, bpf_line_info,
+        bpf_map_info, bpf_map_type, bpf_prog_info, bpf_prog_type, btf_func_linkage, btf_member,
+        btf_param, btf_var_secinfo, BPF_ALU64, BPF_CALL, BPF_EXIT, BPF_F_MMAPABLE,
+        BPF_F_NO_PREALLOC, BPF_JMP, BPF_K, BPF_MOV, BTF_INT_SIGNED, BTF_VAR_STATIC,
+    },
     maps::PerCpuValues,
-    programs::VerifierLog,
+    obj::btf::{Btf, BtfType},
+    programs::{VerifierLog, VerifierLogLevel},
     sys::{kernel_version, SysResult},
+    util::page_size,
     Pod, BPF_OBJ_NAME_LEN,
 };
 
+// not exported by libc, this is the kernel internal error code for "operation not supported"
+const ENOTSUPP: i32 = 524;
+
 use super::{syscall, Syscall};
 
-pub(crate) fn bpf_create_map(name: &CStr, def: &bpf_map_def) -> SysResult {
+pub(crate) fn bpf_create_map(
+    name: &CStr,
+    def: &bpf_map_def,
+    inner_map_fd: Option<RawFd>,
+) -> SysResult {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
 
     let u = unsafe { &mut attr.__bindgen_anon_1 };
@@ -29,12 +44,14 @@ pub(crate) fn bpf_create_map(name: &CStr, def: &bpf_map_def) -> SysResult {
     u.value_size = def.value_size;
     u.max_entries = def.max_entries;
     u.map_flags = def.map_flags;
+    if let Some(fd) = inner_map_fd {
+        u.inner_map_fd = fd as u32;
+    }
 
     // https://github.com/torvalds/linux/commit/ad5b177bd73f5107d97c36f56395c4281fb6f089
     // The map name was added as a parameter in kernel 4.15+ so we skip adding it on
     // older kernels for compatibility
-    let k_ver = kernel_version().unwrap();
-    if k_ver >= (4, 15, 0) {
+    if features().bpf_name() {
         // u.map_name is 16 bytes max and must be NULL terminated
         let name_len = cmp::min(name.to_bytes().len(), BPF_OBJ_NAME_LEN - 1);
         u.map_name[..name_len]
@@ -60,6 +77,7 @@ pub(crate) fn bpf_get_object(path: &CStr) -> SysResult {
 }
 
 pub(crate) struct BpfLoadProgramAttrs<'a> {
+    pub(crate) name: Option<&'a CStr>,
     pub(crate) ty: bpf_prog_type,
     pub(crate) insns: &'a [bpf_insn],
     pub(crate) license: &'a CStr,
@@ -68,12 +86,27 @@ pub(crate) struct BpfLoadProgramAttrs<'a> {
     pub(crate) attach_btf_obj_fd: Option<u32>,
     pub(crate) attach_btf_id: Option<u32>,
     pub(crate) log: &'a mut VerifierLog,
+    pub(crate) log_level: u32,
+    pub(crate) prog_btf_fd: Option<RawFd>,
+    pub(crate) func_info_rec_size: usize,
+    pub(crate) func_info: &'a [bpf_func_info],
+    pub(crate) line_info_rec_size: usize,
+    pub(crate) line_info: &'a [bpf_line_info],
+    pub(crate) fd_array: &'a [RawFd],
 }
 
 pub(crate) fn bpf_load_program(aya_attr: BpfLoadProgramAttrs) -> SysResult {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
 
     let u = unsafe { &mut attr.__bindgen_anon_3 };
+
+    if let Some(name) = aya_attr.name {
+        // u.prog_name is 16 bytes max and must be NULL terminated
+        let name_len = cmp::min(name.to_bytes().len(), BPF_OBJ_NAME_LEN - 1);
+        u.prog_name[..name_len]
+            .copy_from_slice(unsafe { slice::from_raw_parts(name.as_ptr(), name_len) });
+    }
+
     u.prog_type = aya_attr.ty as u32;
     if let Some(v) = aya_attr.expected_attach_type {
         u.expected_attach_type = v as u32;
@@ -84,7 +117,7 @@ pub(crate) fn bpf_load_program(aya_attr: BpfLoadProgramAttrs) -> SysResult {
     u.kern_version = aya_attr.kernel_version;
     let log_buf = aya_attr.log.buf();
     if log_buf.capacity() > 0 {
-        u.log_level = 7;
+        u.log_level = aya_attr.log_level;
         u.log_buf = log_buf.as_mut_ptr() as u64;
         u.log_size = log_buf.capacity() as u32;
     }
@@ -94,10 +127,40 @@ pub(crate) fn bpf_load_program(aya_attr: BpfLoadProgramAttrs) -> SysResult {
     if let Some(v) = aya_attr.attach_btf_id {
         u.attach_btf_id = v;
     }
+    if let Some(btf_fd) = aya_attr.prog_btf_fd {
+        u.prog_btf_fd = btf_fd as u32;
+        if !aya_attr.func_info.is_empty() {
+            u.func_info_rec_size = aya_attr.func_info_rec_size as u32;
+            u.func_info = aya_attr.func_info.as_ptr() as u64;
+            u.func_info_cnt = aya_attr.func_info.len() as u32;
+        }
+        if !aya_attr.line_info.is_empty() {
+            u.line_info_rec_size = aya_attr.line_info_rec_size as u32;
+            u.line_info = aya_attr.line_info.as_ptr() as u64;
+            u.line_info_cnt = aya_attr.line_info.len() as u32;
+        }
+    }
+    if !aya_attr.fd_array.is_empty() {
+        u.fd_array = aya_attr.fd_array.as_ptr() as u64;
+    }
 
     sys_bpf(bpf_cmd::BPF_PROG_LOAD, &attr)
 }
 
+pub(crate) fn bpf_load_btf(raw_btf: &[u8], log: &mut VerifierLog) -> SysResult {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let u = unsafe { &mut attr.__bindgen_anon_7 };
+    u.btf = raw_btf.as_ptr() as u64;
+    u.btf_size = raw_btf.len() as u32;
+    let log_buf = log.buf();
+    if log_buf.capacity() > 0 {
+        u.btf_log_level = 1;
+        u.btf_log_buf = log_buf.as_mut_ptr() as u64;
+        u.btf_log_size = log_buf.capacity() as u32;
+    }
+    sys_bpf(bpf_cmd::BPF_BTF_LOAD, &attr)
+}
+
 fn lookup<K: Pod, V: Pod>(
     fd: RawFd,
     key: Option<&K>,
@@ -359,6 +422,64 @@ pub(crate) fn bpf_obj_get_info_by_fd(prog_fd: RawFd) -> Result<bpf_prog_info, io
     }
 }
 
+pub(crate) fn bpf_map_get_info_by_fd(map_fd: RawFd) -> Result<bpf_map_info, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    // info gets entirely populated by the kernel
+    let mut info = unsafe { mem::zeroed::<bpf_map_info>() };
+
+    attr.info.bpf_fd = map_fd as u32;
+    attr.info.info = &mut info as *mut _ as u64;
+    attr.info.info_len = mem::size_of::<bpf_map_info>() as u32;
+
+    match sys_bpf(bpf_cmd::BPF_OBJ_GET_INFO_BY_FD, &attr) {
+        Ok(_) => Ok(info),
+        Err((_, err)) => Err(err),
+    }
+}
+
+pub(crate) fn bpf_btf_get_next_id(start_id: u32) -> Result<Option<u32>, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    attr.__bindgen_anon_6.__bindgen_anon_1.start_id = start_id;
+
+    match sys_bpf(bpf_cmd::BPF_BTF_GET_NEXT_ID, &attr) {
+        Ok(_) => Ok(Some(unsafe { attr.__bindgen_anon_6.next_id })),
+        Err((_, err)) if err.raw_os_error() == Some(ENOENT) => Ok(None),
+        Err((_, err)) => Err(err),
+    }
+}
+
+pub(crate) fn bpf_btf_get_fd_by_id(btf_id: u32) -> Result<RawFd, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+
+    attr.__bindgen_anon_6.__bindgen_anon_1.btf_id = btf_id;
+
+    match sys_bpf(bpf_cmd::BPF_BTF_GET_FD_BY_ID, &attr) {
+        Ok(v) => Ok(v as RawFd),
+        Err((_, err)) => Err(err),
+    }
+}
+
+/// Returns the info of the BTF object `btf_fd`, copying its name into `name`.
+pub(crate) fn bpf_btf_get_info_by_fd(
+    btf_fd: RawFd,
+    name: &mut [u8],
+) -> Result<bpf_btf_info, io::Error> {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let mut info = unsafe { mem::zeroed::<bpf_btf_info>() };
+    info.name = name.as_mut_ptr() as u64;
+    info.name_len = name.len() as u32;
+
+    attr.info.bpf_fd = btf_fd as u32;
+    attr.info.info = &info as *const _ as u64;
+    attr.info.info_len = mem::size_of::<bpf_btf_info>() as u32;
+
+    match sys_bpf(bpf_cmd::BPF_OBJ_GET_INFO_BY_FD, &attr) {
+        Ok(_) => Ok(info),
+        Err((_, err)) => Err(err),
+    }
+}
+
 pub(crate) fn bpf_raw_tracepoint_open(name: Option<&CStr>, prog_fd: RawFd) -> SysResult {
     let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
 
@@ -371,6 +492,470 @@ pub(crate) fn bpf_raw_tracepoint_open(name: Option<&CStr>, prog_fd: RawFd) -> Sy
     sys_bpf(bpf_cmd::BPF_RAW_TRACEPOINT_OPEN, &attr)
 }
 
+pub(crate) fn is_prog_name_supported() -> bool {
+    let name = CString::new("aya_name_check").unwrap();
+    probe_prog_load(
+        bpf_prog_type::BPF_PROG_TYPE_SOCKET_FILTER,
+        Some(&name),
+        None,
+        None,
+        &probe_insns(),
+        &mut VerifierLog::new(),
+    )
+}
+
+pub(crate) fn is_prog_type_supported(prog_type: bpf_prog_type) -> bool {
+    use bpf_attach_type::*;
+    use bpf_prog_type::*;
+
+    // some program types can't be loaded without a valid attach target. For those we load the
+    // program with a bogus target and check that the verifier rejected the target rather than
+    // the program type.
+    let (expected_attach_type, attach_btf_id, expected_msg) = match prog_type {
+        BPF_PROG_TYPE_CGROUP_SOCK_ADDR => (Some(BPF_CGROUP_INET4_CONNECT), None, None),
+        BPF_PROG_TYPE_CGROUP_SOCKOPT => (Some(BPF_CGROUP_GETSOCKOPT), None, None),
+        BPF_PROG_TYPE_SK_LOOKUP => (Some(BPF_SK_LOOKUP), None, None),
+        BPF_PROG_TYPE_TRACING => (
+            Some(BPF_TRACE_FENTRY),
+            Some(1),
+            Some("attach_btf_id 1 is not a function"),
+        ),
+        BPF_PROG_TYPE_LSM => (
+            Some(BPF_LSM_MAC),
+            Some(1),
+            Some("attach_btf_id 1 is not a function"),
+        ),
+        BPF_PROG_TYPE_EXT => (None, Some(1), Some("Cannot replace kernel functions")),
+        BPF_PROG_TYPE_STRUCT_OPS => (
+            None,
+            Some(1),
+            Some("attach_btf_id 1 is not a supported struct"),
+        ),
+        BPF_PROG_TYPE_SYSCALL => (None, None, Some("Syscall programs can only be sleepable")),
+        _ => (None, None, None),
+    };
+
+    let mut log = VerifierLog::new();
+    if expected_msg.is_some() {
+        log.grow();
+    }
+    if probe_prog_load(
+        prog_type,
+        None,
+        expected_attach_type,
+        attach_btf_id,
+        &probe_insns(),
+        &mut log,
+    ) {
+        return true;
+    }
+
+    match expected_msg {
+        Some(msg) => log_contains(&log, msg),
+        None => false,
+    }
+}
+
+pub(crate) fn is_attach_type_supported(
+    prog_type: bpf_prog_type,
+    attach_type: bpf_attach_type,
+) -> bool {
+    probe_prog_load(
+        prog_type,
+        None,
+        Some(attach_type),
+        None,
+        &probe_insns(),
+        &mut VerifierLog::new(),
+    )
+}
+
+pub(crate) fn is_helper_supported(prog_type: bpf_prog_type, helper: u32) -> bool {
+    let insns = [
+        // call helper
+        bpf_insn {
+            code: (BPF_JMP | BPF_CALL) as u8,
+            _bitfield_align_1: [],
+            _bitfield_1: bpf_insn::new_bitfield_1(0, 0),
+            off: 0,
+            imm: helper as i32,
+        },
+        // exit
+        probe_insns()[1],
+    ];
+
+    let mut log = VerifierLog::new();
+    log.grow();
+    if probe_prog_load(prog_type, None, None, None, &insns, &mut log) {
+        return true;
+    }
+
+    // The verifier rejects unknown helpers with "invalid func unknown#<id>" and helpers that
+    // can't be used by the program type with "unknown func <name>#<id>". Any other error, for
+    // example invalid arguments, means that the helper exists. An empty log means that the
+    // program type itself isn't supported.
+    log.truncate();
+    match log.as_c_str() {
+        Some(log) if !log.to_bytes().is_empty() => {
+            let log = log.to_string_lossy();
+            !(log.contains("invalid func ") || log.contains("unknown func "))
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn is_map_type_supported(map_type: bpf_map_type) -> bool {
+    use bpf_map_type::*;
+
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let u = unsafe { &mut attr.__bindgen_anon_1 };
+    u.map_type = map_type as u32;
+    u.key_size = 4;
+    u.value_size = 4;
+    u.max_entries = 1;
+
+    let mut fds = Vec::new();
+    let mut expected_err = None;
+    match map_type {
+        BPF_MAP_TYPE_LPM_TRIE => {
+            // struct bpf_lpm_trie_key with a 4 byte address
+            u.key_size = 8;
+            u.map_flags = BPF_F_NO_PREALLOC;
+        }
+        BPF_MAP_TYPE_QUEUE | BPF_MAP_TYPE_STACK | BPF_MAP_TYPE_BLOOM_FILTER => u.key_size = 0,
+        BPF_MAP_TYPE_CGROUP_STORAGE | BPF_MAP_TYPE_PERCPU_CGROUP_STORAGE => {
+            // struct bpf_cgroup_storage_key
+            u.key_size = 12;
+            u.max_entries = 0;
+        }
+        BPF_MAP_TYPE_ARRAY_OF_MAPS | BPF_MAP_TYPE_HASH_OF_MAPS => {
+            let mut inner_attr = unsafe { mem::zeroed::<bpf_attr>() };
+            let inner = unsafe { &mut inner_attr.__bindgen_anon_1 };
+            inner.map_type = BPF_MAP_TYPE_ARRAY as u32;
+            inner.key_size = 4;
+            inner.value_size = 4;
+            inner.max_entries = 1;
+            match sys_bpf(bpf_cmd::BPF_MAP_CREATE, &inner_attr) {
+                Ok(fd) => {
+                    fds.push(fd as RawFd);
+                    u.inner_map_fd = fd as u32;
+                }
+                Err(_) => return false,
+            }
+        }
+        BPF_MAP_TYPE_SK_STORAGE | BPF_MAP_TYPE_INODE_STORAGE | BPF_MAP_TYPE_TASK_STORAGE => {
+            // local storage maps require BTF for their key and value
+            let btf_fd = match load_local_storage_btf() {
+                Some(fd) => fd,
+                None => return false,
+            };
+            fds.push(btf_fd);
+            u.value_size = 8;
+            u.max_entries = 0;
+            u.map_flags = BPF_F_NO_PREALLOC;
+            u.btf_fd = btf_fd as u32;
+            u.btf_key_type_id = 1;
+            u.btf_value_type_id = 3;
+        }
+        BPF_MAP_TYPE_RINGBUF => {
+            u.key_size = 0;
+            u.value_size = 0;
+            u.max_entries = page_size() as u32;
+        }
+        BPF_MAP_TYPE_STRUCT_OPS => {
+            // struct_ops maps need a valid vmlinux type, the kernel returns ENOTSUPP when the
+            // type is invalid but the map type is supported
+            u.btf_vmlinux_value_type_id = 1;
+            expected_err = Some(ENOTSUPP);
+        }
+        _ => {}
+    }
+
+    let ret = sys_bpf(bpf_cmd::BPF_MAP_CREATE, &attr);
+    for fd in fds {
+        unsafe { libc::close(fd) };
+    }
+    match ret {
+        Ok(fd) => {
+            unsafe { libc::close(fd as RawFd) };
+            true
+        }
+        Err((_, io_error)) => expected_err.is_some() && io_error.raw_os_error() == expected_err,
+    }
+}
+
+/// Probes support for array maps that can be mapped into memory with `mmap`.
+pub(crate) fn is_mmapable_array_supported() -> bool {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let u = unsafe { &mut attr.__bindgen_anon_1 };
+    u.map_type = bpf_map_type::BPF_MAP_TYPE_ARRAY as u32;
+    u.key_size = 4;
+    u.value_size = 4;
+    u.max_entries = 1;
+    u.map_flags = BPF_F_MMAPABLE;
+
+    match sys_bpf(bpf_cmd::BPF_MAP_CREATE, &attr) {
+        Ok(fd) => {
+            unsafe { libc::close(fd as RawFd) };
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Probes support for attaching programs of type `prog_type` with `attach_type` using
+/// `bpf_link`.
+///
+/// Like libbpf, a minimal program is loaded and linked to an invalid target. Kernels that support
+/// the link only fail to find the target: `EBADF` for the fd of a cgroup or perf event, `ENODEV`
+/// for the ifindex of XDP. Kernels that don't reject the attach type with `EINVAL`. A valid
+/// program fd is needed since kernels 5.7 and 5.8 check it before the attach type, and would
+/// report `EBADF` for XDP even though XDP links were only added in 5.9.
+pub(crate) fn is_bpf_link_supported(
+    prog_type: bpf_prog_type,
+    attach_type: bpf_attach_type,
+) -> bool {
+    let mut attr = unsafe { mem::zeroed::<bpf_attr>() };
+    let u = unsafe { &mut attr.__bindgen_anon_3 };
+    let insns = probe_insns();
+    let license = CString::new("GPL").unwrap();
+    u.prog_type = prog_type as u32;
+    u.insns = insns.as_ptr() as u64;
+    u.insn_cnt = insns.len() as u32;
+    u.license = license.as_ptr() as u64;
+
+    let prog_fd = match sys_bpf(bpf_cmd::BPF_PROG_LOAD, &attr) {
+        Ok(fd) => fd as RawFd,
+        Err(_) => return false,
+    };
+    let ret = bpf_link_create(prog_fd, -1, attach_type, 0);
+    unsafe { libc::close(prog_fd) };
+    match ret {
+        Ok(fd) => {
+            unsafe { libc::close(fd as RawFd) };
+            true
+        }
+        Err((_, io_error)) => matches!(io_error.raw_os_error(), Some(EBADF) | Some(ENODEV)),
+    }
+}
+
+// r0 = 0; exit
+fn probe_insns() -> [bpf_insn; 2] {
+    [
+        bpf_insn {
+            code: (BPF_ALU64 | BPF_MOV | BPF_K) as u8,
+            _bitfield_align_1: [],
+            _bitfield_1: bpf_insn::new_bitfield_1(0, 0),
+            off: 0,
+            imm: 0,
+        },
+        bpf_insn {
+            code: (BPF_JMP | BPF_EXIT) as u8,
+            _bitfield_align_1: [],
+            _bitfield_1: bpf_insn::new_bitfield_1(0, 0),
+            off: 0,
+            imm: 0,
+        },
+    ]
+}
+
+fn probe_prog_load(
+    prog_type: bpf_prog_type,
+    name: Option<&CStr>,
+    expected_attach_type: Option<bpf_attach_type>,
+    attach_btf_id: Option<u32>,
+    insns: &[bpf_insn],
+    log: &mut VerifierLog,
+) -> bool {
+    let license = CString::new("GPL").unwrap();
+    // kprobes need the running kernel version on older kernels
+    let kernel_version = match kernel_version() {
+        Ok((major, minor, patch)) => (major << 16) + (minor << 8) + cmp::min(patch, 255),
+        Err(_) => 0,
+    };
+    let attr = BpfLoadProgramAttrs {
+        name,
+        ty: prog_type,
+        insns,
+        license: &license,
+        kernel_version,
+        expected_attach_type,
+        attach_btf_obj_fd: None,
+        attach_btf_id,
+        log,
+        log_level: VerifierLogLevel::all().bits(),
+        prog_btf_fd: None,
+        func_info_rec_size: 0,
+        func_info: &[],
+        line_info_rec_size: 0,
+        line_info: &[],
+        fd_array: &[],
+    };
+    match bpf_load_program(attr) {
+        Ok(fd) => {
+            unsafe { libc::close(fd as RawFd) };
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+fn log_contains(log: &VerifierLog, msg: &str) -> bool {
+    log.as_c_str()
+        .map(|log| log.to_string_lossy().contains(msg))
+        .unwrap_or(false)
+}
+
+// the BTF used by libbpf to probe local storage maps:
+// [1] int
+// [2] struct bpf_spin_lock { int val; }
+// [3] struct val { int cnt; struct bpf_spin_lock l; }
+fn load_local_storage_btf() -> Option<RawFd> {
+    let mut btf = Btf::new();
+    let name_offset = btf.add_string("int".to_string());
+    let int_type_id = btf.add_type(BtfType::new_int(name_offset, 4, BTF_INT_SIGNED, 0));
+
+    let name_offset = btf.add_string("bpf_spin_lock".to_string());
+    let val_offset = btf.add_string("val".to_string());
+    let spin_lock_type_id = btf.add_type(BtfType::new_struct(
+        name_offset,
+        vec![btf_member {
+            name_off: val_offset,
+            type_: int_type_id,
+            offset: 0,
+        }],
+        4,
+    ));
+
+    let name_offset = btf.add_string("val".to_string());
+    let cnt_offset = btf.add_string("cnt".to_string());
+    let l_offset = btf.add_string("l".to_string());
+    btf.add_type(BtfType::new_struct(
+        name_offset,
+        vec![
+            btf_member {
+                name_off: cnt_offset,
+                type_: int_type_id,
+                offset: 0,
+            },
+            btf_member {
+                name_off: l_offset,
+                type_: spin_lock_type_id,
+                offset: 32,
+            },
+        ],
+        8,
+    ));
+
+    bpf_load_btf(&btf.to_bytes(), &mut VerifierLog::new())
+        .ok()
+        .map(|fd| fd as RawFd)
+}
+
+pub(crate) fn is_btf_supported() -> bool {
+    let mut btf = Btf::new();
+    let name_offset = btf.add_string("int".to_string());
+    btf.add_type(BtfType::new_int(name_offset, 4, BTF_INT_SIGNED, 0));
+    probe_btf(btf)
+}
+
+pub(crate) fn is_btf_func_supported() -> bool {
+    probe_btf_func(btf_func_linkage::BTF_FUNC_STATIC)
+}
+
+pub(crate) fn is_btf_func_global_supported() -> bool {
+    probe_btf_func(btf_func_linkage::BTF_FUNC_GLOBAL)
+}
+
+fn probe_btf_func(linkage: btf_func_linkage) -> bool {
+    let mut btf = Btf::new();
+    let name_offset = btf.add_string("int".to_string());
+    let int_type_id = btf.add_type(BtfType::new_int(name_offset, 4, BTF_INT_SIGNED, 0));
+
+    let a_name = btf.add_string("a".to_string());
+    let b_name = btf.add_string("b".to_string());
+    let params = vec![
+        btf_param {
+            name_off: a_name,
+            type_: int_type_id,
+        },
+        btf_param {
+            name_off: b_name,
+            type_: int_type_id,
+        },
+    ];
+    let func_proto_type_id = btf.add_type(BtfType::new_func_proto(params, int_type_id));
+
+    let name_offset = btf.add_string("add".to_string());
+    btf.add_type(BtfType::new_func(name_offset, func_proto_type_id, linkage));
+
+    probe_btf(btf)
+}
+
+pub(crate) fn is_btf_datasec_supported() -> bool {
+    let mut btf = Btf::new();
+    let name_offset = btf.add_string("int".to_string());
+    let int_type_id = btf.add_type(BtfType::new_int(name_offset, 4, BTF_INT_SIGNED, 0));
+
+    let name_offset = btf.add_string("foo".to_string());
+    let var_type_id = btf.add_type(BtfType::new_var(name_offset, int_type_id, BTF_VAR_STATIC));
+
+    let name_offset = btf.add_string(".data".to_string());
+    let variables = vec![btf_var_secinfo {
+        type_: var_type_id,
+        offset: 0,
+        size: 4,
+    }];
+    btf.add_type(BtfType::new_datasec(name_offset, variables, 4));
+
+    probe_btf(btf)
+}
+
+pub(crate) fn is_btf_float_supported() -> bool {
+    let mut btf = Btf::new();
+    let name_offset = btf.add_string("float".to_string());
+    btf.add_type(BtfType::new_float(name_offset, 16));
+
+    probe_btf(btf)
+}
+
+pub(crate) fn is_btf_decl_tag_supported() -> bool {
+    let mut btf = Btf::new();
+    let name_offset = btf.add_string("int".to_string());
+    let int_type_id = btf.add_type(BtfType::new_int(name_offset, 4, BTF_INT_SIGNED, 0));
+
+    let name_offset = btf.add_string("foo".to_string());
+    let var_type_id = btf.add_type(BtfType::new_var(name_offset, int_type_id, BTF_VAR_STATIC));
+
+    let name_offset = btf.add_string("decl_tag".to_string());
+    btf.add_type(BtfType::new_decl_tag(name_offset, var_type_id, -1));
+
+    probe_btf(btf)
+}
+
+pub(crate) fn is_btf_type_tag_supported() -> bool {
+    let mut btf = Btf::new();
+    let name_offset = btf.add_string("int".to_string());
+    let int_type_id = btf.add_type(BtfType::new_int(name_offset, 4, BTF_INT_SIGNED, 0));
+
+    let name_offset = btf.add_string("type_tag".to_string());
+    let type_tag_type_id = btf.add_type(BtfType::new_type_tag(name_offset, int_type_id));
+    btf.add_type(BtfType::new_ptr(0, type_tag_type_id));
+
+    probe_btf(btf)
+}
+
+fn probe_btf(btf: Btf) -> bool {
+    let mut log_buf = VerifierLog::new();
+    match bpf_load_btf(&btf.to_bytes(), &mut log_buf) {
+        Ok(fd) => {
+            unsafe { libc::close(fd as RawFd) };
+            true
+        }
+        Err(_) => false,
+    }
+}
+
 fn sys_bpf(cmd: bpf_cmd, attr: &bpf_attr) -> SysResult {
     syscall(Syscall::Bpf { cmd, attr })
 }
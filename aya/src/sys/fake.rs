@@ -1,15 +1,24 @@
-use std::{cell::RefCell, io, ptr};
+use std::{
+    cell::RefCell,
+    fs::File,
+    io,
+    os::unix::io::{OwnedFd, RawFd},
+    ptr,
+};
 
 use libc::c_void;
 
 use super::{SysResult, Syscall};
 
 type SyscallFn = unsafe fn(Syscall) -> SysResult;
+type NetlinkSetXdpFdFn = fn(i32, RawFd, Option<RawFd>, u32) -> Result<(), io::Error>;
 
 #[cfg(test)]
 thread_local! {
     pub(crate) static TEST_SYSCALL: RefCell<SyscallFn> = RefCell::new(test_syscall);
     pub(crate) static TEST_MMAP_RET: RefCell<*mut c_void> = RefCell::new(ptr::null_mut());
+    pub(crate) static TEST_NETLINK_SET_XDP_FD: RefCell<NetlinkSetXdpFdFn> =
+        RefCell::new(test_netlink_set_xdp_fd);
 }
 
 #[cfg(test)]
@@ -21,3 +30,25 @@ unsafe fn test_syscall(_call: Syscall) -> SysResult {
 pub(crate) fn override_syscall(call: unsafe fn(Syscall) -> SysResult) {
     TEST_SYSCALL.with(|test_impl| *test_impl.borrow_mut() = call);
 }
+
+#[cfg(test)]
+fn test_netlink_set_xdp_fd(
+    _if_index: i32,
+    _fd: RawFd,
+    _old_fd: Option<RawFd>,
+    _flags: u32,
+) -> Result<(), io::Error> {
+    Err(io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+#[cfg(test)]
+pub(crate) fn override_netlink_set_xdp_fd(call: NetlinkSetXdpFdFn) {
+    TEST_NETLINK_SET_XDP_FD.with(|test_impl| *test_impl.borrow_mut() = call);
+}
+
+// Owned fds are closed when dropped, which aborts in debug builds if the fd isn't open. Tests
+// use /dev/null instead of made up fds for the maps and programs they create.
+#[cfg(test)]
+pub(crate) fn fake_fd() -> OwnedFd {
+    File::open("/dev/null").unwrap().into()
+}
@@ -77,6 +77,25 @@ unsafe fn syscall_impl(call: Syscall) -> SysResult {
     Ok(ret)
 }
 
+#[cfg(test)]
+pub(crate) fn kernel_release() -> Result<String, ()> {
+    Ok("255.255.255".to_string())
+}
+
+#[cfg(not(test))]
+pub(crate) fn kernel_release() -> Result<String, ()> {
+    unsafe {
+        let mut v = mem::zeroed::<utsname>();
+        if libc::uname(&mut v as *mut _) != 0 {
+            return Err(());
+        }
+
+        Ok(std::ffi::CStr::from_ptr(v.release.as_ptr())
+            .to_string_lossy()
+            .into_owned())
+    }
+}
+
 #[cfg(test)]
 pub(crate) fn kernel_version() -> Result<(u32, u32, u32), ()> {
     Ok((0xff, 0xff, 0xff))
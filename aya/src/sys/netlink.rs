@@ -28,6 +28,21 @@ pub(crate) unsafe fn netlink_set_xdp_fd(
     fd: RawFd,
     old_fd: Option<RawFd>,
     flags: u32,
+) -> Result<(), io::Error> {
+    #[cfg(not(test))]
+    return set_xdp_fd(if_index, fd, old_fd, flags);
+
+    #[cfg(test)]
+    return crate::sys::TEST_NETLINK_SET_XDP_FD
+        .with(|test_impl| test_impl.borrow()(if_index, fd, old_fd, flags));
+}
+
+#[cfg_attr(test, allow(dead_code))]
+unsafe fn set_xdp_fd(
+    if_index: i32,
+    fd: RawFd,
+    old_fd: Option<RawFd>,
+    flags: u32,
 ) -> Result<(), io::Error> {
     let sock = NetlinkSocket::open()?;
 
@@ -1,6 +1,6 @@
 //! Utility functions.
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     ffi::CString,
     fs::{self, File},
     io::{self, BufReader},
@@ -8,7 +8,12 @@ use std::{
     str::FromStr,
 };
 
-use crate::generated::{TC_H_MAJ_MASK, TC_H_MIN_MASK};
+use crate::{
+    generated::{TC_H_MAJ_MASK, TC_H_MIN_MASK},
+    sys::kernel_release,
+};
+
+use flate2::read::GzDecoder;
 
 use libc::{if_nametoindex, sysconf, _SC_PAGESIZE};
 
@@ -92,6 +97,45 @@ fn parse_kernel_symbols(reader: impl BufRead) -> Result<BTreeMap<u64, String>, i
     Ok(syms)
 }
 
+/// Loads the configuration of the running kernel.
+///
+/// The configuration is read from `/proc/config.gz` if the kernel exposes it, from
+/// `/boot/config-$(uname -r)` otherwise.
+pub(crate) fn kernel_config() -> Result<HashMap<String, String>, io::Error> {
+    match File::open("/proc/config.gz") {
+        Ok(file) => parse_kernel_config(BufReader::new(GzDecoder::new(file))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            // uname sets errno on failure
+            let release = kernel_release().map_err(|_| io::Error::last_os_error())?;
+            let file = File::open(format!("/boot/config-{}", release))?;
+            parse_kernel_config(BufReader::new(file))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn parse_kernel_config(reader: impl BufRead) -> Result<HashMap<String, String>, io::Error> {
+    let mut config = HashMap::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        // options that are not set appear as comments
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) if name.starts_with("CONFIG_") => {
+                config.insert(name.to_owned(), value.to_owned());
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, line.to_owned())),
+        }
+    }
+
+    Ok(config)
+}
+
 pub(crate) fn ifindex_from_ifname(if_name: &str) -> Result<u32, io::Error> {
     let c_str_if_name = CString::new(if_name)?;
     let c_if_name = c_str_if_name.as_ptr();
@@ -194,4 +238,26 @@ mod tests {
         );
         assert_eq!(syms.get(&0x6000u64).unwrap().as_str(), "cpu_tss_rw");
     }
+
+    #[test]
+    fn test_parse_kernel_config() {
+        let data = "#\n\
+                    # Automatically generated file; DO NOT EDIT.\n\
+                    CONFIG_BPF=y\n\
+                    CONFIG_BPF_JIT=m\n\
+                    # CONFIG_BPF_PRELOAD is not set\n\
+                    \n\
+                    CONFIG_HZ=250\n\
+                    CONFIG_LOCALVERSION=\"-foo=bar\"\n"
+            .as_bytes();
+        let config = parse_kernel_config(BufReader::new(data)).unwrap();
+        assert_eq!(config.len(), 4);
+        assert_eq!(config["CONFIG_BPF"], "y");
+        assert_eq!(config["CONFIG_BPF_JIT"], "m");
+        assert_eq!(config["CONFIG_HZ"], "250");
+        assert_eq!(config["CONFIG_LOCALVERSION"], "\"-foo=bar\"");
+        assert!(!config.contains_key("CONFIG_BPF_PRELOAD"));
+
+        assert!(parse_kernel_config(BufReader::new("FOO".as_bytes())).is_err());
+    }
 }
@@ -0,0 +1,41 @@
+/// Declares kernel functions (kfuncs) that can be called by the program.
+///
+/// Kfuncs are kernel functions exported to BPF programs. Unlike helpers they don't have a fixed
+/// id: the calls are left unresolved in the object file, and the loader looks the functions up
+/// by name in the BTF of the running kernel and of its modules when the program is loaded.
+///
+/// The macro generates an `unsafe` wrapper for each function, with the same name and signature.
+/// The signatures must match the kernel's, or the program is rejected by the verifier.
+///
+/// # Example
+///
+/// ```no_run
+/// use aya_bpf::{cty::c_void, kfuncs};
+///
+/// kfuncs! {
+///     fn bpf_rcu_read_lock();
+///     fn bpf_rcu_read_unlock();
+///     pub fn bpf_task_acquire(task: *mut c_void) -> *mut c_void;
+/// }
+///
+/// unsafe fn in_rcu_section() {
+///     bpf_rcu_read_lock();
+///     // ...
+///     bpf_rcu_read_unlock();
+/// }
+/// ```
+#[macro_export]
+macro_rules! kfuncs {
+    ($($(#[$attr:meta])* $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*) => {
+        $(
+            $(#[$attr])*
+            #[inline(always)]
+            $vis unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+                extern "C" {
+                    fn $name($($arg: $ty),*) $(-> $ret)?;
+                }
+                $name($($arg),*)
+            }
+        )*
+    };
+}
@@ -6,6 +6,7 @@ pub use aya_bpf_bindings::bindings;
 
 mod args;
 pub mod helpers;
+mod kfuncs;
 pub mod maps;
 pub mod programs;
 
@@ -65,6 +65,7 @@ fn codegen_bindings(opts: &Options) -> Result<(), anyhow::Error> {
         "bpf_prog_type",
         "bpf_attach_type",
         "bpf_prog_info",
+        "bpf_map_info",
         "bpf_btf_info",
         "bpf_func_info",
         "bpf_line_info",
@@ -79,6 +80,7 @@ fn codegen_bindings(opts: &Options) -> Result<(), anyhow::Error> {
         "btf_param",
         "btf_var",
         "btf_var_secinfo",
+        "btf_decl_tag",
         "btf_func_linkage",
         // PERF
         "perf_event_attr",
@@ -107,6 +109,7 @@ fn codegen_bindings(opts: &Options) -> Result<(), anyhow::Error> {
         "BPF_STX",
         "BPF_LD",
         "BPF_K",
+        "BPF_MOV",
         "BPF_DW",
         "BPF_W",
         "BPF_H",
@@ -114,6 +117,7 @@ fn codegen_bindings(opts: &Options) -> Result<(), anyhow::Error> {
         "BPF_F_.*",
         "BPF_JMP",
         "BPF_CALL",
+        "BPF_EXIT",
         "SO_ATTACH_BPF",
         "SO_DETACH_BPF",
         // BTF